@@ -0,0 +1,14 @@
+// htmx does not swap 4xx/5xx responses by default. The server retargets error
+// fragments to #error-message, so let those through and clear the previous
+// error when a new request starts. Loaded on every page.
+document.addEventListener("htmx:beforeSwap", (evt) => {
+  const xhr = evt.detail.xhr;
+  if (xhr.status >= 400 && xhr.getResponseHeader("HX-Retarget")) {
+    evt.detail.shouldSwap = true;
+    evt.detail.isError = false;
+  }
+});
+document.addEventListener("htmx:beforeRequest", () => {
+  const errorMessage = document.getElementById("error-message");
+  if (errorMessage) errorMessage.innerHTML = "";
+});
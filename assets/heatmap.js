@@ -0,0 +1,210 @@
+const labelWidth = 44;
+const trackWidth = 60;
+const headerHeight = 20;
+const cellWidth = 22;
+const baseCellHeight = 16;
+
+// Canvas heatmap for whole proteins: the matrix comes from /api/matrix in one
+// request, and only the rows inside the viewport are drawn.
+function canvasHeatmap() {
+  return {
+    matrix: null,
+    zoomLevel: 1,
+    tooltip: "",
+    hovered: null,
+
+    query() {
+      const params = new URLSearchParams();
+      document.querySelectorAll(this.$root.dataset.include).forEach((el) => {
+        if (el.name && el.value !== undefined && el.value !== "") {
+          params.append(el.name, el.value);
+        }
+      });
+      return params;
+    },
+
+    load() {
+      fetch(`/api/matrix?${this.query()}`)
+        .then((response) => response.json())
+        .then((matrix) => {
+          if (matrix.error) {
+            this.tooltip = matrix.error;
+            return;
+          }
+          this.matrix = matrix;
+          this.resize();
+          this.colorStructure();
+        });
+    },
+
+    exportSvg() {
+      window.location.href = `/api/matrix.svg?${this.query()}`;
+    },
+
+    get rows() {
+      return this.matrix.end - this.matrix.start + 1;
+    },
+
+    get cellHeight() {
+      return baseCellHeight * this.zoomLevel;
+    },
+
+    get gridLeft() {
+      return labelWidth + (this.matrix.track ? trackWidth : 0);
+    },
+
+    zoom(factor) {
+      if (!this.matrix) return;
+      const viewport = this.$refs.viewport;
+      // Keep the row at the top of the viewport in place.
+      const topRow = viewport.scrollTop / this.cellHeight;
+      this.zoomLevel = Math.min(4, Math.max(1 / 16, this.zoomLevel * factor));
+      this.resize();
+      viewport.scrollTop = topRow * this.cellHeight;
+      this.draw();
+    },
+
+    resize() {
+      const viewport = this.$refs.viewport;
+      const canvas = this.$refs.canvas;
+      const width = this.gridLeft + this.matrix.amino_acids.length * cellWidth;
+      this.$refs.spacer.style.height = `${headerHeight + this.rows * this.cellHeight}px`;
+      this.$refs.spacer.style.width = `${width}px`;
+      const ratio = window.devicePixelRatio || 1;
+      canvas.width = width * ratio;
+      canvas.height = viewport.clientHeight * ratio;
+      canvas.style.width = `${width}px`;
+      canvas.style.height = `${viewport.clientHeight}px`;
+      canvas.getContext("2d").setTransform(ratio, 0, 0, ratio, 0, 0);
+      this.draw();
+    },
+
+    draw() {
+      if (!this.matrix) return;
+      const m = this.matrix;
+      const viewport = this.$refs.viewport;
+      const ctx = this.$refs.canvas.getContext("2d");
+      const height = viewport.clientHeight;
+      const cols = m.amino_acids.length;
+      const cellHeight = this.cellHeight;
+      const first = Math.floor(viewport.scrollTop / cellHeight);
+      const last = Math.min(
+        this.rows - 1,
+        Math.ceil((viewport.scrollTop + height) / cellHeight),
+      );
+      const offset = headerHeight - (viewport.scrollTop - first * cellHeight);
+
+      ctx.clearRect(0, 0, this.$refs.canvas.width, height);
+      ctx.font = "11px sans-serif";
+      ctx.textBaseline = "middle";
+      for (let row = first; row <= last; row++) {
+        const y = offset + (row - first) * cellHeight;
+        if (cellHeight >= 8) {
+          ctx.fillStyle = "#333";
+          ctx.textAlign = "right";
+          ctx.fillText(m.start + row, labelWidth - 4, y + cellHeight / 2);
+        }
+        if (m.track) {
+          const value = m.track.values[row];
+          if (value !== null && m.track.scale > 0) {
+            const w = Math.min(1, Math.abs(value) / m.track.scale) * (trackWidth - 8);
+            ctx.fillStyle = value < 0 ? "#D7301F" : "#4A6FA5";
+            ctx.fillRect(labelWidth + 4, y + 1, w, Math.max(1, cellHeight - 2));
+          }
+        }
+        for (let col = 0; col < cols; col++) {
+          const color = m.colors[row * cols + col];
+          ctx.fillStyle = color === 255 ? "#f1f1f1" : m.palette[color];
+          ctx.fillRect(this.gridLeft + col * cellWidth, y, cellWidth - 1, Math.max(1, cellHeight - 1));
+        }
+      }
+
+      // Column header stays on top of the rows.
+      ctx.fillStyle = "#fff";
+      ctx.fillRect(0, 0, this.$refs.canvas.width, headerHeight);
+      ctx.fillStyle = "#333";
+      ctx.textAlign = "center";
+      m.amino_acids.forEach((aa, col) => {
+        ctx.fillText(aa, this.gridLeft + col * cellWidth + cellWidth / 2, headerHeight / 2);
+      });
+      if (m.track) {
+        ctx.fillText("Tol.", labelWidth + trackWidth / 2, headerHeight / 2);
+      }
+    },
+
+    cellAt(event) {
+      const rect = this.$refs.canvas.getBoundingClientRect();
+      const x = event.clientX - rect.left;
+      const y = event.clientY - rect.top;
+      if (y < headerHeight) return null;
+      const row = Math.floor(
+        (y - headerHeight + this.$refs.viewport.scrollTop) / this.cellHeight,
+      );
+      if (row < 0 || row >= this.rows) return null;
+      if (x < this.gridLeft) return { row, col: null };
+      const col = Math.floor((x - this.gridLeft) / cellWidth);
+      if (col >= this.matrix.amino_acids.length) return null;
+      return { row, col };
+    },
+
+    hover(event) {
+      if (!this.matrix) return;
+      const cell = this.cellAt(event);
+      if (!cell) {
+        this.tooltip = "";
+        return;
+      }
+      const m = this.matrix;
+      const pos = m.start + cell.row;
+      if (cell.col === null) {
+        const value = m.track ? m.track.values[cell.row] : null;
+        this.tooltip = m.track
+          ? `${pos} ${m.track.label}: ${value === null ? "N/A" : value.toFixed(3)}`
+          : `${pos}`;
+        return;
+      }
+      const index = cell.row * m.amino_acids.length + cell.col;
+      const value = m.values[index];
+      this.tooltip = `${pos}${m.amino_acids[cell.col]}: ${value === null ? "N/A" : value.toFixed(3)}`;
+      const id = m.ids[index];
+      if (id !== null && id !== this.hovered) {
+        this.hovered = id;
+        htmx.ajax("GET", `/variant/${id}`, {
+          target: "#variant-view-body",
+          values: { color: m.palette[m.colors[index]] },
+        });
+      }
+    },
+
+    // Color each residue by its most extreme cell, or by its most common
+    // non-neutral class when painting by classification.
+    colorStructure() {
+      const m = this.matrix;
+      const cols = m.amino_acids.length;
+      const categorical = m.palette.length === 3;
+      const residues = [];
+      for (let row = 0; row < this.rows; row++) {
+        let best = null;
+        const counts = [0, 0, 0];
+        for (let col = 0; col < cols; col++) {
+          const color = m.colors[row * cols + col];
+          if (color === 255) continue;
+          if (categorical) {
+            counts[color] += 1;
+          } else if (best === null || Math.abs(color - 127) > Math.abs(best - 127)) {
+            best = color;
+          }
+        }
+        if (categorical) {
+          // Palette order is deleterious, neutral, gain-of-function.
+          if (counts[0] + counts[2] === 0) best = counts[1] > 0 ? 1 : null;
+          else best = counts[0] >= counts[2] ? 0 : 2;
+        }
+        if (best !== null) {
+          residues.push({ pos: m.start + row, color: m.palette[best] });
+        }
+      }
+      colorVariants(residues);
+    },
+  };
+}
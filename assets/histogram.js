@@ -0,0 +1,180 @@
+const seriesColors = {
+  Missense: "#4A6FA5",
+  Nonsense: "#D7301F",
+  Synonymous: "#33A02C",
+};
+
+// Mirrors the threshold filter in `get_variants`: which variants the heatmap
+// keeps for a given threshold.
+function passesThreshold(metric, value, threshold) {
+  switch (metric) {
+    case "p_value":
+    case "statistic":
+      return value < threshold;
+    default:
+      return value >= 0 ? value < threshold : value > threshold;
+  }
+}
+
+function histogramPlot() {
+  return {
+    svg: null,
+    x: null,
+    width: null,
+    height: null,
+    histogram: null,
+
+    initHistogram(histogram, threshold) {
+      this.histogram = histogram;
+      var margin = { top: 30, right: 30, bottom: 60, left: 60 };
+
+      var container = d3.select("#histogram");
+      var width =
+        parseInt(container.style("width")) - margin.left - margin.right;
+      var height =
+        parseInt(container.style("height")) - margin.top - margin.bottom;
+      this.width = width;
+      this.height = height;
+
+      var svg = d3
+        .select("#histogram")
+        .append("svg")
+        .attr("width", width + margin.left + margin.right)
+        .attr("height", height + margin.top + margin.bottom)
+        .append("g")
+        .attr("transform", "translate(" + margin.left + "," + margin.top + ")");
+      this.svg = svg;
+
+      const edges = histogram.edges;
+      var x = d3
+        .scaleLinear()
+        .domain([edges[0], edges[edges.length - 1]])
+        .range([0, width]);
+      this.x = x;
+      const maxCount = d3.max(histogram.series, (s) => d3.max(s.counts)) || 1;
+      var y = d3.scaleLinear().domain([0, maxCount]).range([height, 0]);
+
+      svg
+        .append("g")
+        .attr("transform", "translate(0," + height + ")")
+        .call(d3.axisBottom(x));
+      svg.append("g").call(d3.axisLeft(y));
+      svg
+        .append("text")
+        .attr("x", width / 2)
+        .attr("y", height + margin.bottom - 20)
+        .style("text-anchor", "middle")
+        .text(histogram.metric);
+      svg
+        .append("text")
+        .attr("transform", "rotate(-90)")
+        .attr("y", 0 - margin.left)
+        .attr("x", 0 - height / 2)
+        .attr("dy", "1em")
+        .style("text-anchor", "middle")
+        .text("Variants");
+
+      // One translucent step outline per mutation type
+      histogram.series
+        .filter((s) => s.total > 0)
+        .forEach((s, i) => {
+          const points = s.counts.flatMap((count, bin) => [
+            [edges[bin], count],
+            [edges[bin + 1], count],
+          ]);
+          svg
+            .append("path")
+            .datum(points)
+            .attr("fill", seriesColors[s.mutation_type])
+            .attr("fill-opacity", 0.25)
+            .attr("stroke", seriesColors[s.mutation_type])
+            .attr(
+              "d",
+              d3
+                .area()
+                .x((d) => x(d[0]))
+                .y0(height)
+                .y1((d) => y(d[1])),
+            );
+          svg
+            .append("text")
+            .attr("x", width - 10)
+            .attr("y", 12 + i * 14)
+            .style("text-anchor", "end")
+            .style("fill", seriesColors[s.mutation_type])
+            .text(`${s.mutation_type} (${s.total})`);
+        });
+
+      this.initThresholdLine(threshold);
+    },
+
+    // Draggable threshold line; dropping it moves the threshold slider.
+    initThresholdLine(threshold) {
+      const slider = document.getElementById("threshold");
+      if (threshold === null && !slider) return;
+      const edges = this.histogram.edges;
+      const start =
+        threshold !== null
+          ? threshold
+          : slider
+            ? parseFloat(slider.value)
+            : edges[edges.length - 1];
+
+      const line = this.svg.append("g").attr("class", "threshold-line");
+      line
+        .append("line")
+        .attr("y1", 0)
+        .attr("y2", this.height)
+        .style("stroke", "#000")
+        .style("stroke-width", 2)
+        .style("cursor", "ew-resize");
+      const label = line
+        .append("text")
+        .attr("y", -8)
+        .style("text-anchor", "middle");
+
+      const move = (value) => {
+        const cx = this.x(value);
+        line.select("line").attr("x1", cx).attr("x2", cx);
+        label
+          .attr("x", cx)
+          .text(
+            `${value.toFixed(3)}: ${(this.fractionRemoved(value) * 100).toFixed(1)}% removed`,
+          );
+      };
+      move(start);
+
+      line.call(
+        d3
+          .drag()
+          .on("drag", (event) => {
+            const cx = Math.max(0, Math.min(this.width, event.x));
+            move(this.x.invert(cx));
+          })
+          .on("end", (event) => {
+            if (!slider) return;
+            const cx = Math.max(0, Math.min(this.width, event.x));
+            slider.value = this.x.invert(cx);
+            slider.dispatchEvent(new Event("input", { bubbles: true }));
+          }),
+      );
+    },
+
+    // Share of variants the threshold filter drops, judged at bin centers
+    fractionRemoved(threshold) {
+      const edges = this.histogram.edges;
+      let total = 0;
+      let kept = 0;
+      this.histogram.series.forEach((s) => {
+        s.counts.forEach((count, bin) => {
+          const center = (edges[bin] + edges[bin + 1]) / 2;
+          total += count;
+          if (passesThreshold(this.histogram.metric, center, threshold)) {
+            kept += count;
+          }
+        });
+      });
+      return total > 0 ? 1 - kept / total : 0;
+    },
+  };
+}
@@ -9,17 +9,24 @@ function scatterPlot() {
     height: null,
     data: [], // The initial data for the points
 
-    initPlot(xmin, xmax, ymin, ymax) {
+    initPlot(axes) {
       // Initialize the scatter plot once
-      const { svg, x, y } = this.initScatter(xmin, xmax, ymin, ymax);
+      const { svg, x, y } = this.initScatter(
+        axes.x_min,
+        axes.x_max,
+        0,
+        axes.y_max,
+        axes.x_label,
+      );
       this.svg = svg;
       this.x = x;
       this.y = y;
 
+      this.initGuides(axes);
       this.initBrush();
     },
 
-    initScatter(xmin, xmax, ymin, ymax) {
+    initScatter(xmin, xmax, ymin, ymax, xlabel) {
       var margin = { top: 20, right: 30, bottom: 60, left: 60 };
 
       // Get the width and height of the container dynamically
@@ -54,7 +61,7 @@ function scatterPlot() {
         .attr("x", 0 + width / 2)
         .attr("y", height + margin.bottom - 20)
         .style("text-anchor", "middle")
-        .text("Log2 Fold Change");
+        .text(xlabel);
 
       svg
         .append("text")
@@ -66,6 +73,32 @@ function scatterPlot() {
         .text("-log10(p value)");
       return { svg, x, y };
     },
+    // Dashed effect size and significance cutoff lines
+    initGuides(axes) {
+      const guides = this.svg
+        .append("g")
+        .attr("class", "volcano-guides")
+        .style("stroke", "#888")
+        .style("stroke-dasharray", "4,4");
+      const yLine = this.y(axes.significance_line);
+      guides
+        .append("line")
+        .attr("x1", 0)
+        .attr("x2", this.width)
+        .attr("y1", yLine)
+        .attr("y2", yLine);
+      if (axes.effect_cutoff !== null) {
+        [-axes.effect_cutoff, axes.effect_cutoff].forEach((cutoff) => {
+          guides
+            .append("line")
+            .attr("x1", this.x(cutoff))
+            .attr("x2", this.x(cutoff))
+            .attr("y1", 0)
+            .attr("y2", this.height);
+        });
+      }
+    },
+
     // Initialize the brush for selection
     initBrush() {
       const brush = d3
@@ -91,8 +124,8 @@ function scatterPlot() {
 
       // Find the points within the selection area
       const selectedPoints = this.data.filter((d) => {
-        const cx = this.x(d.log2_fold_change);
-        const cy = this.y(d.p_value);
+        const cx = this.x(d.x);
+        const cy = this.y(d.y);
         return cx >= x0 && cx <= x1 && cy >= y0 && cy <= y1;
       });
       colorVariants(selectedPoints);
@@ -108,7 +141,7 @@ function scatterPlot() {
       if (!this.svg) return;
 
       // Bind the new data to the circles
-      const points = this.svg.selectAll("circle").data(this.data, (d) => d.pos);
+      const points = this.svg.selectAll("circle").data(this.data, (d) => d.id);
 
       // Remove old points with a fade-out transition
       points
@@ -123,8 +156,8 @@ function scatterPlot() {
       points
         .transition()
         .duration(750)
-        .attr("cx", (d) => this.x(d.log2_fold_change))
-        .attr("cy", (d) => this.y(d.p_value))
+        .attr("cx", (d) => this.x(d.x))
+        .attr("cy", (d) => this.y(d.y))
         .attr("r", point_radius)
         .style("fill", (d) => d.color);
 
@@ -132,8 +165,8 @@ function scatterPlot() {
       points
         .enter()
         .append("circle")
-        .attr("cx", (d) => this.x(d.log2_fold_change))
-        .attr("cy", (d) => this.y(d.p_value))
+        .attr("cx", (d) => this.x(d.x))
+        .attr("cy", (d) => this.y(d.y))
         .attr("r", 0) // Start with radius 0 for fade-in effect
         .style("fill", (d) => d.color)
         .style("opacity", 0) // Start with opacity 0 for fade-in effect
@@ -141,6 +174,30 @@ function scatterPlot() {
         .duration(750)
         .attr("r", point_radius)
         .style("opacity", 1);
+
+      this.updateLabels();
+    },
+
+    // Text labels for the top hits, which the server marks with `label`
+    updateLabels() {
+      const labels = this.svg
+        .selectAll("text.volcano-label")
+        .data(
+          this.data.filter((d) => d.label),
+          (d) => d.id,
+        );
+      labels.exit().remove();
+      labels
+        .enter()
+        .append("text")
+        .attr("class", "volcano-label")
+        .attr("font-size", "10px")
+        .attr("dx", point_radius + 2)
+        .attr("dy", -point_radius)
+        .merge(labels)
+        .attr("x", (d) => this.x(d.x))
+        .attr("y", (d) => this.y(d.y))
+        .text((d) => d.label);
     },
 
     // Method to set new data and update the points
@@ -43,3 +43,4 @@ function focusVariant(pos) {
     },
   ]);
 }
+
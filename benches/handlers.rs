@@ -0,0 +1,256 @@
+//! Latency of the hot-path handlers against seeded synthetic data.
+//!
+//! Needs a migrated Postgres at `DATABASE_URL` and the server running against
+//! the same database at `BENCH_URL` (default `http://localhost:3000`):
+//!
+//! ```bash
+//! cargo bench --bench handlers
+//! ```
+//!
+//! A synthetic protein `BENCH` with `BENCH_POSITIONS` positions (default 1000)
+//! is seeded once and reused. Each handler is requested `BENCH_ITERATIONS`
+//! times (default 20) and the latencies are written to
+//! `target/bench/handlers.tsv`. Point `BENCH_BASELINE` at an earlier results
+//! file to fail when a handler's median gets more than `BENCH_TOLERANCE`
+//! (default 0.2) slower.
+use std::collections::HashMap;
+use std::env;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context};
+use sqlx::PgPool;
+
+const PROTEIN: &str = "BENCH";
+const CONDITIONS: [&str; 2] = ["bench_a", "bench_b"];
+const WARMUP: usize = 2;
+const RESULTS: &str = "target/bench/handlers.tsv";
+
+struct Latency {
+    handler: String,
+    median: Duration,
+    p95: Duration,
+    max: Duration,
+}
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Seeds `PROTEIN` unless it is already there with the requested size.
+async fn seed(pool: &PgPool, positions: i32) -> anyhow::Result<()> {
+    let seeded: Option<i32> = sqlx::query_scalar(
+        r#"
+        SELECT max(variant_summary.max_pos) FROM variant_summary
+        JOIN protein ON variant_summary.protein_id = protein.id
+        WHERE protein.name = $1
+        "#,
+    )
+    .bind(PROTEIN)
+    .fetch_one(pool)
+    .await?;
+    if seeded == Some(positions) {
+        return Ok(());
+    }
+    println!("seeding {PROTEIN} with {positions} positions");
+    let mut txn = pool.begin().await?;
+    sqlx::query("DELETE FROM protein WHERE name = $1")
+        .bind(PROTEIN)
+        .execute(&mut *txn)
+        .await?;
+    sqlx::query(
+        r#"
+        INSERT INTO protein (name, sequence)
+        SELECT $1, string_agg(substr('ACDEFGHIKLMNPQRSTVWY', pos % 20 + 1, 1), '' ORDER BY pos)
+        FROM generate_series(1, $2) pos
+        "#,
+    )
+    .bind(PROTEIN)
+    .bind(positions)
+    .execute(&mut *txn)
+    .await?;
+    // Same synthetic data on every run
+    sqlx::query("SELECT setseed(0.42)")
+        .execute(&mut *txn)
+        .await?;
+    sqlx::query(
+        r#"
+        INSERT INTO variant (
+            chunk, pos, condition, aa, log2_fold_change, log2_std_error,
+            statistic, p_value, version, protein_id, created_on
+        )
+        SELECT
+            pos / 50 + 1, pos, condition, aa, effect, 0.25,
+            effect / 0.25, p_value, 'bench', protein_id, now()
+        FROM (
+            SELECT
+                protein.id AS protein_id,
+                pos,
+                condition,
+                aa,
+                (random() - 0.6) * 3 AS effect,
+                greatest(random() * random(), 1e-12) AS p_value
+            FROM protein,
+                generate_series(1, $2) pos,
+                unnest($3::VARCHAR[]) condition,
+                unnest($4::VARCHAR[]) aa
+            WHERE protein.name = $1
+        ) AS synthetic
+        "#,
+    )
+    .bind(PROTEIN)
+    .bind(positions)
+    .bind(&CONDITIONS[..])
+    .bind(&dms_viewer::GROUPED_AMINO_ACIDS[..])
+    .execute(&mut *txn)
+    .await?;
+    txn.commit().await?;
+    Ok(())
+}
+
+fn cases() -> Vec<(&'static str, String)> {
+    let table = format!(
+        "protein={PROTEIN}&condition={}&paint=log2_fold_change&position_filter=NoOrder",
+        CONDITIONS[0]
+    );
+    let ranked = format!(
+        "protein={PROTEIN}&condition={}&paint=p_value&position_filter=MostSignificantPValue",
+        CONDITIONS[0]
+    );
+    vec![
+        ("proteins", "/proteins".to_string()),
+        ("conditions", format!("/conditions?protein={PROTEIN}")),
+        ("threshold", format!("/threshold?{table}")),
+        ("variants", format!("/variants?{table}&page=1")),
+        (
+            "variants_threshold",
+            format!("/variants?{table}&page=1&threshold=1"),
+        ),
+        ("variants_ranked", format!("/variants?{ranked}&page=1")),
+        ("matrix", format!("/api/matrix?{table}")),
+        ("matrix_svg", format!("/api/matrix.svg?{table}")),
+        ("volcano", format!("/plot?{table}&plot=Scatter")),
+        ("substitutions", format!("/plot?{table}&plot=Substitution")),
+        ("distribution", format!("/plot?{table}&plot=Distribution")),
+        ("tolerance", format!("/tolerance?{table}&format=Json")),
+        ("classification", format!("/classification?{table}")),
+    ]
+}
+
+async fn measure(
+    client: &reqwest::Client,
+    url: &str,
+    iterations: usize,
+) -> anyhow::Result<Vec<Duration>> {
+    let mut latencies = Vec::with_capacity(iterations);
+    for i in 0..WARMUP + iterations {
+        let start = Instant::now();
+        let response = client.get(url).send().await?;
+        let status = response.status();
+        response.bytes().await?;
+        if !status.is_success() {
+            bail!("{url} returned {status}");
+        }
+        if i >= WARMUP {
+            latencies.push(start.elapsed());
+        }
+    }
+    latencies.sort();
+    Ok(latencies)
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let index = ((sorted.len() - 1) as f64 * p).round() as usize;
+    sorted[index]
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+fn write_results(latencies: &[Latency]) -> anyhow::Result<()> {
+    std::fs::create_dir_all(Path::new(RESULTS).parent().unwrap())?;
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(b'\t')
+        .from_path(RESULTS)?;
+    writer.write_record(["handler", "median_ms", "p95_ms", "max_ms"])?;
+    for latency in latencies {
+        writer.write_record([
+            latency.handler.clone(),
+            format!("{:.3}", millis(latency.median)),
+            format!("{:.3}", millis(latency.p95)),
+            format!("{:.3}", millis(latency.max)),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn read_baseline(path: &str) -> anyhow::Result<HashMap<String, f64>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .from_path(path)
+        .with_context(|| format!("reading baseline {path}"))?;
+    let mut medians = HashMap::new();
+    for record in reader.records() {
+        let record = record?;
+        medians.insert(record[0].to_string(), record[1].parse()?);
+    }
+    Ok(medians)
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    dotenvy::dotenv().ok();
+    let database_url = env::var("DATABASE_URL").context("DATABASE_URL is not set")?;
+    let base_url: String = env_or("BENCH_URL", "http://localhost:3000".to_string());
+    let positions = env_or("BENCH_POSITIONS", 1000);
+    let iterations = env_or("BENCH_ITERATIONS", 20usize).max(1);
+    let tolerance = env_or("BENCH_TOLERANCE", 0.2);
+
+    let pool = PgPool::connect(&database_url).await?;
+    seed(&pool, positions).await?;
+
+    let client = reqwest::Client::new();
+    let mut latencies = vec![];
+    for (handler, path) in cases() {
+        let sorted = measure(&client, &format!("{base_url}{path}"), iterations).await?;
+        let latency = Latency {
+            handler: handler.to_string(),
+            median: percentile(&sorted, 0.5),
+            p95: percentile(&sorted, 0.95),
+            max: *sorted.last().unwrap(),
+        };
+        println!(
+            "{:<20} median {:>9.3} ms  p95 {:>9.3} ms  max {:>9.3} ms",
+            latency.handler,
+            millis(latency.median),
+            millis(latency.p95),
+            millis(latency.max)
+        );
+        latencies.push(latency);
+    }
+    write_results(&latencies)?;
+    println!("results written to {RESULTS}");
+
+    if let Ok(path) = env::var("BENCH_BASELINE") {
+        let baseline = read_baseline(&path)?;
+        let regressions: Vec<String> = latencies
+            .iter()
+            .filter_map(|latency| {
+                let before = *baseline.get(&latency.handler)?;
+                let now = millis(latency.median);
+                (now > before * (1.0 + tolerance))
+                    .then(|| format!("{}: {:.3} ms -> {:.3} ms", latency.handler, before, now))
+            })
+            .collect();
+        if !regressions.is_empty() {
+            bail!("median latency regressed:\n{}", regressions.join("\n"));
+        }
+        println!("no regressions against {path}");
+    }
+    Ok(())
+}
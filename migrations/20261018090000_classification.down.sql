@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP TABLE protein_domain;
+
+ALTER TABLE protein DROP COLUMN sequence;
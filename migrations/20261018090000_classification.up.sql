@@ -0,0 +1,10 @@
+-- Add up migration script here
+ALTER TABLE protein ADD COLUMN sequence TEXT;
+
+CREATE TABLE protein_domain (
+    id SERIAL PRIMARY KEY,
+    protein_id INTEGER NOT NULL REFERENCES protein (id) ON DELETE CASCADE,
+    name VARCHAR(60) NOT NULL,
+    start_pos INTEGER NOT NULL,
+    end_pos INTEGER NOT NULL
+);
@@ -0,0 +1,10 @@
+-- Add down migration script here
+DROP TRIGGER variant_summary_after_delete ON variant;
+DROP TRIGGER variant_summary_after_update ON variant;
+DROP TRIGGER variant_summary_after_insert ON variant;
+
+DROP FUNCTION refresh_variant_summary_after_change();
+DROP FUNCTION refresh_variant_summary(INTEGER, VARCHAR);
+
+DROP TABLE variant_metric_summary;
+DROP TABLE variant_summary;
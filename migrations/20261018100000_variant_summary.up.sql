@@ -0,0 +1,122 @@
+-- Add up migration script here
+CREATE TABLE variant_summary (
+    protein_id INTEGER NOT NULL REFERENCES protein (id) ON DELETE CASCADE,
+    condition VARCHAR(30) NOT NULL,
+    version VARCHAR(30) NOT NULL,
+    variant_count INTEGER NOT NULL,
+    position_count INTEGER NOT NULL,
+    min_pos INTEGER NOT NULL,
+    max_pos INTEGER NOT NULL,
+    -- Share of the min_pos..max_pos × 21 amino acid grid that has a measurement
+    coverage DOUBLE PRECISION NOT NULL,
+    refreshed_on TIMESTAMP NOT NULL,
+    PRIMARY KEY (protein_id, condition, version)
+);
+
+-- One row per metric, named like the `Paint` values the handlers pass in
+CREATE TABLE variant_metric_summary (
+    protein_id INTEGER NOT NULL REFERENCES protein (id) ON DELETE CASCADE,
+    condition VARCHAR(30) NOT NULL,
+    version VARCHAR(30) NOT NULL,
+    metric VARCHAR(30) NOT NULL,
+    min_value DOUBLE PRECISION NOT NULL,
+    max_value DOUBLE PRECISION NOT NULL,
+    max_abs DOUBLE PRECISION NOT NULL,
+    q05 DOUBLE PRECISION NOT NULL,
+    q25 DOUBLE PRECISION NOT NULL,
+    median DOUBLE PRECISION NOT NULL,
+    q75 DOUBLE PRECISION NOT NULL,
+    q95 DOUBLE PRECISION NOT NULL,
+    PRIMARY KEY (protein_id, condition, version, metric)
+);
+
+CREATE FUNCTION refresh_variant_summary(target_protein_id INTEGER, target_condition VARCHAR)
+RETURNS VOID AS $$
+BEGIN
+    DELETE FROM variant_summary
+    WHERE protein_id = target_protein_id AND condition = target_condition;
+    DELETE FROM variant_metric_summary
+    WHERE protein_id = target_protein_id AND condition = target_condition;
+
+    INSERT INTO variant_summary
+    SELECT
+        protein_id,
+        condition,
+        version,
+        count(*),
+        count(DISTINCT pos),
+        min(pos),
+        max(pos),
+        count(DISTINCT (pos, aa))::DOUBLE PRECISION / ((max(pos) - min(pos) + 1) * 21),
+        now()
+    FROM variant
+    WHERE protein_id = target_protein_id AND condition = target_condition
+    GROUP BY protein_id, condition, version;
+
+    INSERT INTO variant_metric_summary
+    SELECT
+        variant.protein_id,
+        variant.condition,
+        variant.version,
+        metrics.metric,
+        min(metrics.value),
+        max(metrics.value),
+        max(abs(metrics.value)),
+        percentile_cont(0.05) WITHIN GROUP (ORDER BY metrics.value),
+        percentile_cont(0.25) WITHIN GROUP (ORDER BY metrics.value),
+        percentile_cont(0.5) WITHIN GROUP (ORDER BY metrics.value),
+        percentile_cont(0.75) WITHIN GROUP (ORDER BY metrics.value),
+        percentile_cont(0.95) WITHIN GROUP (ORDER BY metrics.value)
+    FROM variant
+    CROSS JOIN LATERAL (
+        VALUES
+            ('p_value', variant.p_value),
+            ('log2_fold_change', variant.log2_fold_change),
+            ('statistic', variant.statistic)
+    ) AS metrics (metric, value)
+    WHERE variant.protein_id = target_protein_id AND variant.condition = target_condition
+    GROUP BY variant.protein_id, variant.condition, variant.version, metrics.metric;
+END;
+$$ LANGUAGE plpgsql;
+
+-- Refresh every (protein, condition) a statement touched, once per statement,
+-- so bulk ingest pays for a single pass.
+CREATE FUNCTION refresh_variant_summary_after_change()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        PERFORM refresh_variant_summary(changed.protein_id, changed.condition)
+        FROM (SELECT DISTINCT protein_id, condition FROM old_rows) AS changed;
+    ELSIF TG_OP = 'INSERT' THEN
+        PERFORM refresh_variant_summary(changed.protein_id, changed.condition)
+        FROM (SELECT DISTINCT protein_id, condition FROM new_rows) AS changed;
+    ELSE
+        PERFORM refresh_variant_summary(changed.protein_id, changed.condition)
+        FROM (
+            SELECT protein_id, condition FROM old_rows
+            UNION
+            SELECT protein_id, condition FROM new_rows
+        ) AS changed;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER variant_summary_after_insert
+AFTER INSERT ON variant
+REFERENCING NEW TABLE AS new_rows
+FOR EACH STATEMENT EXECUTE FUNCTION refresh_variant_summary_after_change();
+
+CREATE TRIGGER variant_summary_after_update
+AFTER UPDATE ON variant
+REFERENCING OLD TABLE AS old_rows NEW TABLE AS new_rows
+FOR EACH STATEMENT EXECUTE FUNCTION refresh_variant_summary_after_change();
+
+CREATE TRIGGER variant_summary_after_delete
+AFTER DELETE ON variant
+REFERENCING OLD TABLE AS old_rows
+FOR EACH STATEMENT EXECUTE FUNCTION refresh_variant_summary_after_change();
+
+-- Backfill what is already loaded
+SELECT refresh_variant_summary(pairs.protein_id, pairs.condition)
+FROM (SELECT DISTINCT protein_id, condition FROM variant) AS pairs;
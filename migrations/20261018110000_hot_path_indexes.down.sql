@@ -0,0 +1,6 @@
+-- Add down migration script here
+DROP INDEX protein_domain_protein_start_idx;
+
+DROP INDEX variant_protein_condition_pos_aa_idx;
+
+ALTER TABLE protein DROP CONSTRAINT protein_name_key;
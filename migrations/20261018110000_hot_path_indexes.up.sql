@@ -0,0 +1,47 @@
+-- Add up migration script here
+-- Names were never unique, so fold duplicates into the lowest id first: their
+-- variants and domains move over, and the kept row fills its missing pdb id
+-- and sequence from them. The summary triggers follow the moved variants.
+CREATE TEMPORARY TABLE protein_merge AS
+SELECT id, min(id) OVER (PARTITION BY name) AS keep_id
+FROM protein;
+
+DELETE FROM protein_merge WHERE id = keep_id;
+
+UPDATE variant
+SET protein_id = protein_merge.keep_id
+FROM protein_merge
+WHERE variant.protein_id = protein_merge.id;
+
+UPDATE protein_domain
+SET protein_id = protein_merge.keep_id
+FROM protein_merge
+WHERE protein_domain.protein_id = protein_merge.id;
+
+UPDATE protein
+SET pdb_id = coalesce(protein.pdb_id, merged.pdb_id),
+    sequence = coalesce(protein.sequence, merged.sequence)
+FROM (
+    SELECT
+        protein_merge.keep_id,
+        max(duplicate.pdb_id) AS pdb_id,
+        max(duplicate.sequence) AS sequence
+    FROM protein_merge
+    JOIN protein AS duplicate ON duplicate.id = protein_merge.id
+    GROUP BY protein_merge.keep_id
+) AS merged
+WHERE protein.id = merged.keep_id;
+
+DELETE FROM protein USING protein_merge WHERE protein.id = protein_merge.id;
+
+DROP TABLE protein_merge;
+
+ALTER TABLE protein ADD CONSTRAINT protein_name_key UNIQUE (name);
+
+-- Every variant query filters on protein and condition, then on a position
+-- range ordered by (pos, aa); the ranked query partitions by pos.
+CREATE INDEX variant_protein_condition_pos_aa_idx
+    ON variant (protein_id, condition, pos, aa);
+
+CREATE INDEX protein_domain_protein_start_idx
+    ON protein_domain (protein_id, start_pos);
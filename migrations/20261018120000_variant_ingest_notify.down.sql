@@ -0,0 +1,23 @@
+-- Add down migration script here
+CREATE OR REPLACE FUNCTION refresh_variant_summary_after_change()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        PERFORM refresh_variant_summary(changed.protein_id, changed.condition)
+        FROM (SELECT DISTINCT protein_id, condition FROM old_rows) AS changed;
+    ELSIF TG_OP = 'INSERT' THEN
+        PERFORM refresh_variant_summary(changed.protein_id, changed.condition)
+        FROM (SELECT DISTINCT protein_id, condition FROM new_rows) AS changed;
+    ELSE
+        PERFORM refresh_variant_summary(changed.protein_id, changed.condition)
+        FROM (
+            SELECT protein_id, condition FROM old_rows
+            UNION
+            SELECT protein_id, condition FROM new_rows
+        ) AS changed;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP FUNCTION refresh_variant_summary_and_notify(INTEGER, VARCHAR);
@@ -0,0 +1,37 @@
+-- Add up migration script here
+-- Tell listening servers which (protein, condition) changed, so they can drop
+-- cached responses. The protein is NULL once it has been deleted.
+CREATE FUNCTION refresh_variant_summary_and_notify(target_protein_id INTEGER, target_condition VARCHAR)
+RETURNS VOID AS $$
+BEGIN
+    PERFORM refresh_variant_summary(target_protein_id, target_condition);
+    PERFORM pg_notify(
+        'variant_ingest',
+        json_build_object(
+            'protein', (SELECT name FROM protein WHERE id = target_protein_id),
+            'condition', target_condition
+        )::text
+    );
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION refresh_variant_summary_after_change()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        PERFORM refresh_variant_summary_and_notify(changed.protein_id, changed.condition)
+        FROM (SELECT DISTINCT protein_id, condition FROM old_rows) AS changed;
+    ELSIF TG_OP = 'INSERT' THEN
+        PERFORM refresh_variant_summary_and_notify(changed.protein_id, changed.condition)
+        FROM (SELECT DISTINCT protein_id, condition FROM new_rows) AS changed;
+    ELSE
+        PERFORM refresh_variant_summary_and_notify(changed.protein_id, changed.condition)
+        FROM (
+            SELECT protein_id, condition FROM old_rows
+            UNION
+            SELECT protein_id, condition FROM new_rows
+        ) AS changed;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
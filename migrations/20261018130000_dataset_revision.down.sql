@@ -0,0 +1,20 @@
+-- Add down migration script here
+DROP TRIGGER protein_domain_change_notify ON protein_domain;
+DROP TRIGGER protein_change_notify ON protein;
+DROP FUNCTION notify_protein_change();
+
+CREATE OR REPLACE FUNCTION refresh_variant_summary_and_notify(target_protein_id INTEGER, target_condition VARCHAR)
+RETURNS VOID AS $$
+BEGIN
+    PERFORM refresh_variant_summary(target_protein_id, target_condition);
+    PERFORM pg_notify(
+        'variant_ingest',
+        json_build_object(
+            'protein', (SELECT name FROM protein WHERE id = target_protein_id),
+            'condition', target_condition
+        )::text
+    );
+END;
+$$ LANGUAGE plpgsql;
+
+DROP SEQUENCE dataset_revision;
@@ -0,0 +1,43 @@
+-- Add up migration script here
+-- Bumped on every change to the data the viewer shows; servers derive ETags
+-- from it. A NULL condition in a notification means "anything may have changed".
+CREATE SEQUENCE dataset_revision;
+
+CREATE OR REPLACE FUNCTION refresh_variant_summary_and_notify(target_protein_id INTEGER, target_condition VARCHAR)
+RETURNS VOID AS $$
+BEGIN
+    PERFORM refresh_variant_summary(target_protein_id, target_condition);
+    PERFORM pg_notify(
+        'variant_ingest',
+        json_build_object(
+            'protein', (SELECT name FROM protein WHERE id = target_protein_id),
+            'condition', target_condition,
+            'revision', nextval('dataset_revision')
+        )::text
+    );
+END;
+$$ LANGUAGE plpgsql;
+
+-- Sequences and domains feed the substitution, tolerance and classification views
+CREATE FUNCTION notify_protein_change()
+RETURNS TRIGGER AS $$
+BEGIN
+    PERFORM pg_notify(
+        'variant_ingest',
+        json_build_object(
+            'protein', NULL,
+            'condition', NULL,
+            'revision', nextval('dataset_revision')
+        )::text
+    );
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER protein_change_notify
+AFTER INSERT OR UPDATE OR DELETE ON protein
+FOR EACH STATEMENT EXECUTE FUNCTION notify_protein_change();
+
+CREATE TRIGGER protein_domain_change_notify
+AFTER INSERT OR UPDATE OR DELETE ON protein_domain
+FOR EACH STATEMENT EXECUTE FUNCTION notify_protein_change();
@@ -0,0 +1,30 @@
+-- Add up migration script here
+-- Local accounts. Passwords are stored as argon2 PHC strings; session and API
+-- token secrets only as their SHA-256, so a copy of the database cannot be
+-- used to sign in.
+CREATE TABLE app_user (
+    id SERIAL PRIMARY KEY,
+    username VARCHAR(60) NOT NULL UNIQUE,
+    password_hash TEXT NOT NULL,
+    is_admin BOOLEAN NOT NULL DEFAULT FALSE,
+    disabled BOOLEAN NOT NULL DEFAULT FALSE,
+    created_on TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc')
+);
+
+CREATE TABLE user_session (
+    token_hash CHAR(64) PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES app_user (id) ON DELETE CASCADE,
+    expires_on TIMESTAMP NOT NULL
+);
+
+CREATE INDEX user_session_user_id ON user_session (user_id);
+
+CREATE TABLE api_token (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES app_user (id) ON DELETE CASCADE,
+    name VARCHAR(60) NOT NULL,
+    token_hash CHAR(64) NOT NULL UNIQUE,
+    created_on TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+    last_used_on TIMESTAMP,
+    UNIQUE (user_id, name)
+);
@@ -0,0 +1,5 @@
+-- Add down migration script here
+DROP TRIGGER project_member_change_notify ON project_member;
+ALTER TABLE protein DROP COLUMN public, DROP COLUMN project_id;
+DROP TABLE project_member;
+DROP TABLE project;
@@ -0,0 +1,32 @@
+-- Add up migration script here
+-- Proteins belong to a project. Members see a project's proteins with a role;
+-- everyone else, signed in or not, only sees proteins marked public.
+CREATE TABLE project (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR(60) NOT NULL UNIQUE,
+    created_on TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc')
+);
+
+CREATE TABLE project_member (
+    project_id INTEGER NOT NULL REFERENCES project (id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES app_user (id) ON DELETE CASCADE,
+    role VARCHAR(10) NOT NULL CHECK (role IN ('viewer', 'uploader', 'admin')),
+    PRIMARY KEY (project_id, user_id)
+);
+
+CREATE INDEX project_member_user_id ON project_member (user_id);
+
+ALTER TABLE protein
+    ADD COLUMN project_id INTEGER REFERENCES project (id) ON DELETE SET NULL,
+    ADD COLUMN public BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Everything loaded before projects existed stays visible to everyone
+INSERT INTO project (name) SELECT 'default' WHERE EXISTS (SELECT 1 FROM protein);
+UPDATE protein SET
+    project_id = (SELECT id FROM project WHERE name = 'default'),
+    public = TRUE;
+
+-- Membership decides what a signed-in user sees, so it moves ETags on too
+CREATE TRIGGER project_member_change_notify
+AFTER INSERT OR UPDATE OR DELETE ON project_member
+FOR EACH STATEMENT EXECUTE FUNCTION notify_protein_change();
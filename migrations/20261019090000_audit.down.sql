@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP TABLE audit_log;
+ALTER TABLE variant DROP COLUMN batch_id;
+DROP TABLE upload_batch;
@@ -0,0 +1,41 @@
+-- Add up migration script here
+-- Who changed what. Every ingest stores the file it came from as an upload
+-- batch that its variants point back to; ingests, deletes and protein edits
+-- each append an audit_log entry. Entries keep the protein's name at the time
+-- and outlive the protein, the batch and the user they mention.
+CREATE TABLE upload_batch (
+    id SERIAL PRIMARY KEY,
+    protein_id INTEGER NOT NULL REFERENCES protein (id) ON DELETE CASCADE,
+    file_name VARCHAR(255) NOT NULL,
+    sha256 CHAR(64) NOT NULL,
+    rows_inserted BIGINT NOT NULL,
+    rows_rejected BIGINT NOT NULL,
+    -- File header -> variant field, null for ignored columns
+    columns JSONB NOT NULL,
+    user_id INTEGER REFERENCES app_user (id) ON DELETE SET NULL,
+    actor VARCHAR(60) NOT NULL,
+    created_on TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc')
+);
+
+CREATE INDEX upload_batch_protein_id ON upload_batch (protein_id);
+
+-- Variants loaded before batches existed have none
+ALTER TABLE variant
+    ADD COLUMN batch_id INTEGER REFERENCES upload_batch (id) ON DELETE CASCADE;
+
+CREATE INDEX variant_batch_id ON variant (batch_id);
+
+CREATE TABLE audit_log (
+    id BIGSERIAL PRIMARY KEY,
+    created_on TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+    user_id INTEGER REFERENCES app_user (id) ON DELETE SET NULL,
+    actor VARCHAR(60) NOT NULL,
+    action VARCHAR(30) NOT NULL,
+    protein_id INTEGER REFERENCES protein (id) ON DELETE SET NULL,
+    protein VARCHAR(30) NOT NULL,
+    batch_id INTEGER,
+    details JSONB NOT NULL DEFAULT '{}'
+);
+
+CREATE INDEX audit_log_protein_id ON audit_log (protein_id);
+CREATE INDEX audit_log_batch_id ON audit_log (batch_id);
@@ -0,0 +1,26 @@
+-- Add up migration script here
+-- Archived variants leave `variant` for `archived_variant`, so the summaries,
+-- notifications and every query treat them as deleted; restoring moves them
+-- back with their ids. An archived protein keeps its variants and is only
+-- left out of the protein list.
+ALTER TABLE protein ADD COLUMN archived_on TIMESTAMP;
+
+CREATE TABLE archived_variant (
+    id INTEGER PRIMARY KEY,
+    chunk INTEGER NOT NULL,
+    pos INTEGER NOT NULL,
+    condition VARCHAR(30) NOT NULL,
+    aa VARCHAR(30) NOT NULL,
+    log2_fold_change DOUBLE PRECISION NOT NULL,
+    log2_std_error DOUBLE PRECISION NOT NULL,
+    statistic DOUBLE PRECISION NOT NULL,
+    p_value DOUBLE PRECISION NOT NULL,
+    version VARCHAR(30) NOT NULL,
+    protein_id INTEGER NOT NULL REFERENCES protein (id) ON DELETE CASCADE,
+    created_on TIMESTAMP NOT NULL,
+    batch_id INTEGER REFERENCES upload_batch (id) ON DELETE CASCADE,
+    archived_on TIMESTAMP NOT NULL DEFAULT (now() AT TIME ZONE 'utc')
+);
+
+CREATE INDEX archived_variant_protein_condition ON archived_variant (protein_id, condition);
+CREATE INDEX archived_variant_batch_id ON archived_variant (batch_id);
@@ -0,0 +1,7 @@
+-- Add down migration script here
+DROP TABLE dataset_revision;
+DROP TABLE variant_metric_summary;
+DROP TABLE variant_summary;
+DROP TABLE variant;
+DROP TABLE protein_domain;
+DROP TABLE protein;
@@ -0,0 +1,106 @@
+-- Add up migration script here
+-- The PostgreSQL schema in ../ as of 20261018130000, for single-user SQLite
+-- databases. SQLite has no statement-level triggers, so the repository
+-- refreshes variant_summary and variant_metric_summary after each ingest.
+CREATE TABLE protein (
+    id INTEGER PRIMARY KEY,
+    name VARCHAR(30) NOT NULL UNIQUE,
+    pdb_id VARCHAR(10),
+    sequence TEXT
+);
+
+CREATE TABLE protein_domain (
+    id INTEGER PRIMARY KEY,
+    protein_id INTEGER NOT NULL REFERENCES protein (id) ON DELETE CASCADE,
+    name VARCHAR(60) NOT NULL,
+    start_pos INTEGER NOT NULL,
+    end_pos INTEGER NOT NULL
+);
+
+CREATE TABLE variant (
+    id INTEGER PRIMARY KEY,
+    chunk INTEGER NOT NULL,
+    pos INTEGER NOT NULL,
+    condition VARCHAR(30) NOT NULL,
+    aa VARCHAR(30) NOT NULL,
+    log2_fold_change DOUBLE PRECISION NOT NULL,
+    log2_std_error DOUBLE PRECISION NOT NULL,
+    statistic DOUBLE PRECISION NOT NULL,
+    p_value DOUBLE PRECISION NOT NULL,
+    version VARCHAR(30) NOT NULL,
+    protein_id INTEGER NOT NULL REFERENCES protein (id) ON DELETE CASCADE,
+    created_on TIMESTAMP NOT NULL
+);
+
+CREATE TABLE variant_summary (
+    protein_id INTEGER NOT NULL REFERENCES protein (id) ON DELETE CASCADE,
+    condition VARCHAR(30) NOT NULL,
+    version VARCHAR(30) NOT NULL,
+    variant_count INTEGER NOT NULL,
+    position_count INTEGER NOT NULL,
+    min_pos INTEGER NOT NULL,
+    max_pos INTEGER NOT NULL,
+    coverage DOUBLE PRECISION NOT NULL,
+    refreshed_on TIMESTAMP NOT NULL,
+    PRIMARY KEY (protein_id, condition, version)
+);
+
+CREATE TABLE variant_metric_summary (
+    protein_id INTEGER NOT NULL REFERENCES protein (id) ON DELETE CASCADE,
+    condition VARCHAR(30) NOT NULL,
+    version VARCHAR(30) NOT NULL,
+    metric VARCHAR(30) NOT NULL,
+    min_value DOUBLE PRECISION NOT NULL,
+    max_value DOUBLE PRECISION NOT NULL,
+    max_abs DOUBLE PRECISION NOT NULL,
+    q05 DOUBLE PRECISION NOT NULL,
+    q25 DOUBLE PRECISION NOT NULL,
+    median DOUBLE PRECISION NOT NULL,
+    q75 DOUBLE PRECISION NOT NULL,
+    q95 DOUBLE PRECISION NOT NULL,
+    PRIMARY KEY (protein_id, condition, version, metric)
+);
+
+CREATE INDEX variant_protein_condition_pos_aa_idx
+    ON variant (protein_id, condition, pos, aa);
+
+CREATE INDEX protein_domain_protein_start_idx
+    ON protein_domain (protein_id, start_pos);
+
+-- Stands in for the dataset_revision sequence; servers poll it for changes.
+CREATE TABLE dataset_revision (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    revision INTEGER NOT NULL
+);
+
+INSERT INTO dataset_revision (id, revision) VALUES (1, 0);
+
+CREATE TRIGGER protein_insert_revision AFTER INSERT ON protein
+BEGIN
+    UPDATE dataset_revision SET revision = revision + 1;
+END;
+
+CREATE TRIGGER protein_update_revision AFTER UPDATE ON protein
+BEGIN
+    UPDATE dataset_revision SET revision = revision + 1;
+END;
+
+CREATE TRIGGER protein_delete_revision AFTER DELETE ON protein
+BEGIN
+    UPDATE dataset_revision SET revision = revision + 1;
+END;
+
+CREATE TRIGGER protein_domain_insert_revision AFTER INSERT ON protein_domain
+BEGIN
+    UPDATE dataset_revision SET revision = revision + 1;
+END;
+
+CREATE TRIGGER protein_domain_update_revision AFTER UPDATE ON protein_domain
+BEGIN
+    UPDATE dataset_revision SET revision = revision + 1;
+END;
+
+CREATE TRIGGER protein_domain_delete_revision AFTER DELETE ON protein_domain
+BEGIN
+    UPDATE dataset_revision SET revision = revision + 1;
+END;
@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP TABLE api_token;
+DROP TABLE user_session;
+DROP TABLE app_user;
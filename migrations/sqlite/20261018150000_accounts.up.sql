@@ -0,0 +1,28 @@
+-- Add up migration script here
+-- ../20261018150000_accounts for SQLite.
+CREATE TABLE app_user (
+    id INTEGER PRIMARY KEY,
+    username VARCHAR(60) NOT NULL UNIQUE,
+    password_hash TEXT NOT NULL,
+    is_admin BOOLEAN NOT NULL DEFAULT FALSE,
+    disabled BOOLEAN NOT NULL DEFAULT FALSE,
+    created_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE user_session (
+    token_hash CHAR(64) PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES app_user (id) ON DELETE CASCADE,
+    expires_on TIMESTAMP NOT NULL
+);
+
+CREATE INDEX user_session_user_id ON user_session (user_id);
+
+CREATE TABLE api_token (
+    id INTEGER PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES app_user (id) ON DELETE CASCADE,
+    name VARCHAR(60) NOT NULL,
+    token_hash CHAR(64) NOT NULL UNIQUE,
+    created_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_used_on TIMESTAMP,
+    UNIQUE (user_id, name)
+);
@@ -0,0 +1,5 @@
+-- Add down migration script here
+ALTER TABLE protein DROP COLUMN public;
+ALTER TABLE protein DROP COLUMN project_id;
+DROP TABLE project_member;
+DROP TABLE project;
@@ -0,0 +1,41 @@
+-- Add up migration script here
+-- ../20261018160000_projects for SQLite.
+CREATE TABLE project (
+    id INTEGER PRIMARY KEY,
+    name VARCHAR(60) NOT NULL UNIQUE,
+    created_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE project_member (
+    project_id INTEGER NOT NULL REFERENCES project (id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES app_user (id) ON DELETE CASCADE,
+    role VARCHAR(10) NOT NULL CHECK (role IN ('viewer', 'uploader', 'admin')),
+    PRIMARY KEY (project_id, user_id)
+);
+
+CREATE INDEX project_member_user_id ON project_member (user_id);
+
+-- SQLite cannot drop a column with a foreign key, so protein.project_id has
+-- none here; projects are never deleted.
+ALTER TABLE protein ADD COLUMN project_id INTEGER;
+ALTER TABLE protein ADD COLUMN public BOOLEAN NOT NULL DEFAULT FALSE;
+
+INSERT INTO project (name) SELECT 'default' WHERE EXISTS (SELECT 1 FROM protein);
+UPDATE protein SET
+    project_id = (SELECT id FROM project WHERE name = 'default'),
+    public = TRUE;
+
+CREATE TRIGGER project_member_insert_revision AFTER INSERT ON project_member
+BEGIN
+    UPDATE dataset_revision SET revision = revision + 1;
+END;
+
+CREATE TRIGGER project_member_update_revision AFTER UPDATE ON project_member
+BEGIN
+    UPDATE dataset_revision SET revision = revision + 1;
+END;
+
+CREATE TRIGGER project_member_delete_revision AFTER DELETE ON project_member
+BEGIN
+    UPDATE dataset_revision SET revision = revision + 1;
+END;
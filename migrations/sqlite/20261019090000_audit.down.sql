@@ -0,0 +1,5 @@
+-- Add down migration script here
+DROP TABLE audit_log;
+DROP INDEX variant_batch_id;
+ALTER TABLE variant DROP COLUMN batch_id;
+DROP TABLE upload_batch;
@@ -0,0 +1,37 @@
+-- Add up migration script here
+-- ../20261019090000_audit for SQLite.
+CREATE TABLE upload_batch (
+    id INTEGER PRIMARY KEY,
+    protein_id INTEGER NOT NULL REFERENCES protein (id) ON DELETE CASCADE,
+    file_name VARCHAR(255) NOT NULL,
+    sha256 CHAR(64) NOT NULL,
+    rows_inserted BIGINT NOT NULL,
+    rows_rejected BIGINT NOT NULL,
+    columns TEXT NOT NULL,
+    user_id INTEGER REFERENCES app_user (id) ON DELETE SET NULL,
+    actor VARCHAR(60) NOT NULL,
+    created_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX upload_batch_protein_id ON upload_batch (protein_id);
+
+-- No foreign key, so the column can be dropped again; variants go with their
+-- protein anyway.
+ALTER TABLE variant ADD COLUMN batch_id INTEGER;
+
+CREATE INDEX variant_batch_id ON variant (batch_id);
+
+CREATE TABLE audit_log (
+    id INTEGER PRIMARY KEY,
+    created_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    user_id INTEGER REFERENCES app_user (id) ON DELETE SET NULL,
+    actor VARCHAR(60) NOT NULL,
+    action VARCHAR(30) NOT NULL,
+    protein_id INTEGER REFERENCES protein (id) ON DELETE SET NULL,
+    protein VARCHAR(30) NOT NULL,
+    batch_id INTEGER,
+    details TEXT NOT NULL DEFAULT '{}'
+);
+
+CREATE INDEX audit_log_protein_id ON audit_log (protein_id);
+CREATE INDEX audit_log_batch_id ON audit_log (batch_id);
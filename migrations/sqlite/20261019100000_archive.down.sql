@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TABLE archived_variant;
+ALTER TABLE protein DROP COLUMN archived_on;
@@ -0,0 +1,23 @@
+-- Add up migration script here
+-- ../20261019100000_archive for SQLite.
+ALTER TABLE protein ADD COLUMN archived_on TIMESTAMP;
+
+CREATE TABLE archived_variant (
+    id INTEGER PRIMARY KEY,
+    chunk INTEGER NOT NULL,
+    pos INTEGER NOT NULL,
+    condition VARCHAR(30) NOT NULL,
+    aa VARCHAR(30) NOT NULL,
+    log2_fold_change DOUBLE PRECISION NOT NULL,
+    log2_std_error DOUBLE PRECISION NOT NULL,
+    statistic DOUBLE PRECISION NOT NULL,
+    p_value DOUBLE PRECISION NOT NULL,
+    version VARCHAR(30) NOT NULL,
+    protein_id INTEGER NOT NULL REFERENCES protein (id) ON DELETE CASCADE,
+    created_on TIMESTAMP NOT NULL,
+    batch_id INTEGER,
+    archived_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX archived_variant_protein_condition ON archived_variant (protein_id, condition);
+CREATE INDEX archived_variant_batch_id ON archived_variant (batch_id);
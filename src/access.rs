@@ -0,0 +1,198 @@
+//! Projects and who may see what. Every protein belongs to at most one
+//! project; its members see it with a role, and everyone else only sees it
+//! once it is public. Site administrators see and manage everything.
+
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use crate::auth::{User, MAX_NAME_LENGTH};
+use crate::error::AppError;
+
+/// What a member may do in a project; each role includes the ones before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Sees the project's proteins.
+    Viewer,
+    /// Also uploads files for them.
+    Uploader,
+    /// Also manages members and publishes proteins.
+    Admin,
+}
+
+impl Role {
+    pub const ALL: [Role; 3] = [Role::Viewer, Role::Uploader, Role::Admin];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Uploader => "uploader",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(role: &str) -> Result<Self, String> {
+        Role::ALL
+            .into_iter()
+            .find(|candidate| candidate.as_str() == role)
+            .ok_or_else(|| format!("unknown role {role:?}, expected viewer, uploader or admin"))
+    }
+}
+
+impl TryFrom<String> for Role {
+    type Error = String;
+
+    fn try_from(role: String) -> Result<Self, String> {
+        role.parse()
+    }
+}
+
+/// Which proteins a request may see. Set for every request from the caller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Scope {
+    /// Public proteins only: anonymous visitors.
+    #[default]
+    Public,
+    /// Public proteins and those of the user's projects.
+    Member(i32),
+    /// Every protein: site administrators and the admin CLI.
+    Everything,
+}
+
+impl Scope {
+    pub fn of(user: Option<&User>) -> Self {
+        match user {
+            Some(user) if user.is_admin => Scope::Everything,
+            Some(user) => Scope::Member(user.id),
+            None => Scope::Public,
+        }
+    }
+
+    pub fn sees_everything(&self) -> bool {
+        *self == Scope::Everything
+    }
+
+    /// The member whose projects are visible besides the public proteins.
+    pub fn user_id(&self) -> Option<i32> {
+        match self {
+            Scope::Member(id) => Some(*id),
+            _ => None,
+        }
+    }
+
+    /// Whether `protein` is visible to a caller with `role` in its project.
+    pub fn can_see(&self, protein: &ProteinAccess, role: Option<Role>) -> bool {
+        !protein.archived && (self.sees_everything() || protein.public || role.is_some())
+    }
+
+    /// Tells responses for different scopes apart, e.g. in ETags. Empty for
+    /// `Public`.
+    pub fn tag(&self) -> String {
+        match self {
+            Scope::Public => String::new(),
+            Scope::Member(id) => format!("-u{id}"),
+            Scope::Everything => "-all".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, FromRow)]
+pub struct Project {
+    pub id: i32,
+    pub name: String,
+    pub created_on: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, FromRow)]
+pub struct Member {
+    pub user_id: i32,
+    pub username: String,
+    #[sqlx(try_from = "String")]
+    pub role: Role,
+}
+
+/// Who can see a protein: its project, if any, and whether it is public.
+/// Archived proteins are hidden from everyone until they are restored.
+#[derive(Debug, Clone, PartialEq, Serialize, FromRow)]
+pub struct ProteinAccess {
+    pub name: String,
+    pub project_id: Option<i32>,
+    pub public: bool,
+    pub archived: bool,
+}
+
+pub fn check_project_name(name: &str) -> Result<(), AppError> {
+    if name.trim().is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return Err(AppError::BadRequest(format!(
+            "project names have 1 to {MAX_NAME_LENGTH} characters"
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(id: i32, is_admin: bool) -> User {
+        User {
+            id,
+            username: format!("user{id}"),
+            is_admin,
+            disabled: false,
+            created_on: NaiveDateTime::default(),
+        }
+    }
+
+    #[test]
+    fn roles_parse_and_include_each_other() {
+        for role in Role::ALL {
+            assert_eq!(role.as_str().parse(), Ok(role));
+        }
+        assert!("owner".parse::<Role>().is_err());
+        assert!(Role::Viewer < Role::Uploader && Role::Uploader < Role::Admin);
+    }
+
+    #[test]
+    fn scopes_follow_the_caller() {
+        let private = ProteinAccess {
+            name: "P1".to_string(),
+            project_id: Some(1),
+            public: false,
+            archived: false,
+        };
+        let public = ProteinAccess {
+            public: true,
+            ..private.clone()
+        };
+        assert_eq!(Scope::of(None), Scope::Public);
+        assert_eq!(Scope::of(Some(&user(3, false))), Scope::Member(3));
+        assert_eq!(Scope::of(Some(&user(3, true))), Scope::Everything);
+        assert!(Scope::Public.can_see(&public, None));
+        assert!(!Scope::Public.can_see(&private, None));
+        assert!(!Scope::Member(3).can_see(&private, None));
+        assert!(Scope::Member(3).can_see(&private, Some(Role::Viewer)));
+        assert!(Scope::Everything.can_see(&private, None));
+        let archived = ProteinAccess {
+            archived: true,
+            ..public.clone()
+        };
+        assert!(!Scope::Everything.can_see(&archived, Some(Role::Admin)));
+        assert_eq!(Scope::Public.tag(), "");
+        assert_ne!(Scope::Member(3).tag(), Scope::Member(4).tag());
+    }
+}
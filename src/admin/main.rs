@@ -0,0 +1,1152 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use anyhow::{bail, Context};
+use clap::{Args, Parser, Subcommand};
+use dms_viewer::access::{self, Role, Scope};
+use dms_viewer::audit::{self, Action, Actor, Change};
+use dms_viewer::auth::{self, UserUpdate};
+use dms_viewer::classify;
+use dms_viewer::config::{self, Backend, Config, ConfigArgs};
+use dms_viewer::datasets::{Operation, Selection};
+use dms_viewer::ingest::{self, SourceFile, DEFAULT_BATCH_SIZE};
+use dms_viewer::migrate;
+use dms_viewer::repository::{PostgresRepository, Repository, SqliteRepository};
+use dms_viewer::validate;
+use dms_viewer::Variant;
+use serde::Serialize;
+use serde_json::{json, Value};
+use sqlx::{PgPool, SqlitePool};
+use tracing::Level;
+
+/// Manage DeepScan proteins and datasets from the command line.
+#[derive(Parser, Debug)]
+#[command(version)]
+struct Cli {
+    #[command(flatten)]
+    config: ConfigArgs,
+    /// Log what the library is doing, not just warnings
+    #[arg(long, short, global = true)]
+    verbose: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Create, list and rename proteins
+    #[command(subcommand)]
+    Protein(ProteinCommand),
+    /// Load TSV files for a protein, the same way the upload form does
+    Import {
+        protein: String,
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Rows per insert statement
+        #[arg(long, default_value_t = DEFAULT_BATCH_SIZE)]
+        batch_size: usize,
+        /// Refuse a file with unparseable rows instead of skipping them
+        #[arg(long)]
+        strict: bool,
+    },
+    /// Delete a protein, or only its variants for a condition, version or
+    /// upload batch, archived ones included
+    Delete(Target),
+    /// Hide a protein, or only its variants for a condition, version or
+    /// upload batch, until they are restored
+    Archive(Target),
+    /// Bring back archived data; restoring a protein brings back everything
+    /// archived under it
+    Restore(Target),
+    /// Write variants as TSV, with BH-adjusted p values per condition
+    Export {
+        protein: String,
+        #[arg(long)]
+        condition: Option<String>,
+        #[arg(long)]
+        version: Option<String>,
+        /// Output file, standard output if omitted
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Rebuild the summary tables and notify running servers
+    Refresh {
+        /// Only this protein
+        protein: Option<String>,
+    },
+    /// Variant and position coverage per condition and version
+    Coverage {
+        /// Only this protein
+        protein: Option<String>,
+    },
+    /// Create and manage login accounts
+    #[command(subcommand)]
+    User(UserCommand),
+    /// Create projects and manage their members and proteins
+    #[command(subcommand)]
+    Project(ProjectCommand),
+}
+
+/// What `delete`, `archive` and `restore` apply to.
+#[derive(Args, Debug)]
+struct Target {
+    protein: String,
+    #[arg(long)]
+    condition: Option<String>,
+    #[arg(long)]
+    version: Option<String>,
+    /// Upload batch id, as shown on the audit page
+    #[arg(long)]
+    batch: Option<i32>,
+    /// Go ahead; without it only the affected rows are counted
+    #[arg(long)]
+    yes: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum ProteinCommand {
+    /// Add a protein so files can be imported for it
+    Create {
+        name: String,
+        #[arg(long)]
+        pdb_id: Option<String>,
+        /// Wild-type amino acid sequence, one letter per residue
+        #[arg(long)]
+        sequence: Option<String>,
+        /// Project the protein belongs to; without one only administrators
+        /// see it until it is public
+        #[arg(long)]
+        project: Option<String>,
+        /// Show the protein to everyone, including anonymous visitors
+        #[arg(long)]
+        public: bool,
+    },
+    List,
+    Rename {
+        from: String,
+        to: String,
+    },
+    /// Show a protein to everyone, including anonymous visitors
+    Publish {
+        name: String,
+    },
+    /// Show a protein only to the members of its project
+    Unpublish {
+        name: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ProjectCommand {
+    /// Add a project
+    Create {
+        name: String,
+    },
+    List,
+    /// Give a user a role in a project: viewer, uploader or admin
+    Member {
+        project: String,
+        username: String,
+        role: Role,
+    },
+    /// Take a user out of a project
+    Remove {
+        project: String,
+        username: String,
+    },
+    /// Move proteins into a project; whether they are public stays as it was
+    Assign {
+        project: String,
+        #[arg(required = true)]
+        proteins: Vec<String>,
+    },
+}
+
+/// Passwords are read from the terminal, or from the first line of standard
+/// input when it is not one.
+#[derive(Subcommand, Debug)]
+enum UserCommand {
+    /// Add an account
+    Create {
+        username: String,
+        /// May manage users in the web interface
+        #[arg(long)]
+        admin: bool,
+    },
+    List,
+    /// Set a new password and end the user's sessions
+    Password {
+        username: String,
+    },
+    /// Block logins, sessions and API tokens of a user
+    Disable {
+        username: String,
+    },
+    Enable {
+        username: String,
+    },
+    /// Remove an account with its sessions and API tokens
+    Delete {
+        username: String,
+    },
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    config::load_dotenv()?;
+    let cli = Cli::parse();
+    tracing_subscriber::fmt()
+        .with_max_level(if cli.verbose {
+            Level::INFO
+        } else {
+            Level::WARN
+        })
+        .with_writer(io::stderr)
+        .init();
+    let config = Config::resolve(&cli.config)?;
+    config.database.validate()?;
+    if config.database.backend() == Some(Backend::Sqlite) {
+        let pool = config.database.connect_sqlite().await?;
+        migrate::run_sqlite(&pool).await?;
+        return sqlite_command(pool, cli.command).await;
+    }
+    let pool = config.database.connect().await?;
+    match cli.command {
+        Command::Protein(ProteinCommand::Create {
+            name,
+            pdb_id,
+            sequence,
+            project,
+            public,
+        }) => {
+            let repository = PostgresRepository::new(pool.clone());
+            let project_id = match &project {
+                Some(project) => Some(project_id(&repository, project).await?),
+                None => None,
+            };
+            let details = json!({"pdb_id": pdb_id, "project": project, "public": public});
+            create_protein(&pool, &name, pdb_id, sequence, project_id, public).await?;
+            record(&repository, Action::CreateProtein, &name, details).await
+        }
+        Command::Protein(ProteinCommand::List) => list_proteins(&pool).await,
+        Command::Protein(ProteinCommand::Publish { name }) => {
+            set_public(&PostgresRepository::new(pool), &name, true).await
+        }
+        Command::Protein(ProteinCommand::Unpublish { name }) => {
+            set_public(&PostgresRepository::new(pool), &name, false).await
+        }
+        Command::Protein(ProteinCommand::Rename { from, to }) => {
+            rename_protein(&pool, &from, &to).await
+        }
+        Command::Import {
+            protein,
+            files,
+            batch_size,
+            strict,
+        } => import(&pool, &protein, &files, batch_size, strict).await,
+        Command::Delete(target) => {
+            let repository = PostgresRepository::new(pool);
+            change_dataset(&repository, Operation::Delete, target).await
+        }
+        Command::Archive(target) => {
+            let repository = PostgresRepository::new(pool);
+            change_dataset(&repository, Operation::Archive, target).await
+        }
+        Command::Restore(target) => {
+            let repository = PostgresRepository::new(pool);
+            change_dataset(&repository, Operation::Restore, target).await
+        }
+        Command::Export {
+            protein,
+            condition,
+            version,
+            output,
+        } => export(&pool, &protein, condition, version, output).await,
+        Command::Refresh { protein } => refresh(&pool, protein).await,
+        Command::Coverage { protein } => coverage(&pool, protein).await,
+        Command::User(command) => user_command(&PostgresRepository::new(pool), command).await,
+        Command::Project(command) => project_command(&PostgresRepository::new(pool), command).await,
+    }
+}
+
+/// Id of `name`, or an error suggesting the closest existing proteins.
+async fn protein_id(pool: &PgPool, name: &str) -> anyhow::Result<i32> {
+    if let Some(id) = sqlx::query_scalar!("SELECT id FROM protein WHERE name = $1", name)
+        .fetch_optional(pool)
+        .await?
+    {
+        return Ok(id);
+    }
+    let names = sqlx::query_scalar!("SELECT name FROM protein ORDER BY name")
+        .fetch_all(pool)
+        .await?;
+    bail!(validate::unknown("protein", name, names.iter().map(String::as_str)).message())
+}
+
+async fn create_protein(
+    pool: &PgPool,
+    name: &str,
+    pdb_id: Option<String>,
+    sequence: Option<String>,
+    project_id: Option<i32>,
+    public: bool,
+) -> anyhow::Result<()> {
+    validate::required("protein name", name)?;
+    let sequence = sequence.map(|sequence| sequence.trim().to_uppercase());
+    let id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO protein (name, pdb_id, sequence, project_id, public)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (name) DO NOTHING
+        RETURNING id
+        "#,
+        name,
+        pdb_id,
+        sequence,
+        project_id,
+        public
+    )
+    .fetch_optional(pool)
+    .await?;
+    match id {
+        Some(id) => println!("created protein {name} with id {id}"),
+        None => bail!("protein {name} already exists"),
+    }
+    Ok(())
+}
+
+async fn list_proteins(pool: &PgPool) -> anyhow::Result<()> {
+    let proteins = sqlx::query!(
+        r#"
+        SELECT
+            protein.name,
+            protein.pdb_id,
+            length(protein.sequence) as sequence_length,
+            project.name as "project?",
+            protein.public,
+            protein.archived_on IS NOT NULL as "archived!",
+            count(DISTINCT variant_summary.condition) as "conditions!",
+            coalesce(sum(variant_summary.variant_count), 0)::BIGINT as "variants!"
+        FROM protein
+        LEFT JOIN project ON project.id = protein.project_id
+        LEFT JOIN variant_summary ON variant_summary.protein_id = protein.id
+        GROUP BY protein.id, project.name
+        ORDER BY protein.name
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+    print_table(
+        &[
+            "protein",
+            "pdb",
+            "length",
+            "project",
+            "visible to",
+            "conditions",
+            "variants",
+        ],
+        proteins
+            .into_iter()
+            .map(|protein| {
+                vec![
+                    protein.name,
+                    protein.pdb_id.unwrap_or_default(),
+                    protein
+                        .sequence_length
+                        .map_or(String::new(), |length| length.to_string()),
+                    protein.project.unwrap_or_default(),
+                    listed_as(protein.public, protein.archived).to_string(),
+                    protein.conditions.to_string(),
+                    protein.variants.to_string(),
+                ]
+            })
+            .collect(),
+    );
+    Ok(())
+}
+
+async fn rename_protein(pool: &PgPool, from: &str, to: &str) -> anyhow::Result<()> {
+    validate::required("protein name", to)?;
+    let id = protein_id(pool, from).await?;
+    let result = sqlx::query!("UPDATE protein SET name = $2 WHERE id = $1", id, to)
+        .execute(pool)
+        .await;
+    match result {
+        Ok(_) => println!("renamed protein {from} to {to}"),
+        Err(sqlx::Error::Database(err)) if err.is_unique_violation() => {
+            bail!("protein {to} already exists")
+        }
+        Err(err) => return Err(err.into()),
+    }
+    record(
+        &PostgresRepository::new(pool.clone()),
+        Action::RenameProtein,
+        to,
+        json!({"from": from, "to": to}),
+    )
+    .await
+}
+
+async fn import(
+    pool: &PgPool,
+    protein: &str,
+    files: &[PathBuf],
+    batch_size: usize,
+    strict: bool,
+) -> anyhow::Result<()> {
+    if batch_size == 0 {
+        bail!("--batch-size must be at least 1");
+    }
+    protein_id(pool, protein).await?;
+    let actor = cli_actor();
+    for path in files {
+        let (variants, source) = read_file(path, protein, strict)?;
+        let progress = Progress::new(&path.display().to_string(), variants.len() as u64);
+        let batch = ingest::insert_batches(
+            pool,
+            &variants,
+            protein,
+            &source,
+            &actor,
+            batch_size,
+            |done| progress.update(done),
+        )
+        .await
+        .with_context(|| format!("could not import {}", path.display()))?;
+        progress.finish();
+        print_imported(path, &batch);
+    }
+    Ok(())
+}
+
+fn print_imported(path: &Path, batch: &audit::UploadBatch) {
+    println!(
+        "{}: {} rows imported as batch {}, {} skipped",
+        path.display(),
+        batch.rows_inserted,
+        batch.id,
+        batch.rows_rejected
+    );
+}
+
+/// Parses one import file, reporting its first few bad rows. Returns the
+/// variants and what the upload batch records about the file.
+fn read_file(
+    path: &Path,
+    protein: &str,
+    strict: bool,
+) -> anyhow::Result<(Vec<Variant>, SourceFile)> {
+    let data = std::fs::read(path).with_context(|| format!("could not open {}", path.display()))?;
+    let name = path.file_name().map_or_else(
+        || path.display().to_string(),
+        |name| name.to_string_lossy().into_owned(),
+    );
+    let (variants, errors, source) = ingest::read_source(&name, &data, protein);
+    for error in errors.iter().take(5) {
+        eprintln!("{}: {error}", path.display());
+    }
+    if errors.len() > 5 {
+        eprintln!("{}: ... and {} more", path.display(), errors.len() - 5);
+    }
+    if strict && !errors.is_empty() {
+        bail!(
+            "{} has {} unparseable rows, nothing was imported from it",
+            path.display(),
+            errors.len()
+        );
+    }
+    Ok((variants, source))
+}
+
+const NEEDS_POSTGRES: &str = "needs a PostgreSQL database";
+
+/// The commands a local SQLite database supports: creating, listing and
+/// publishing proteins, importing, deleting and archiving data, and managing
+/// users and projects. The rest rely on PostgreSQL functions.
+async fn sqlite_command(pool: SqlitePool, command: Command) -> anyhow::Result<()> {
+    let repository = SqliteRepository::new(pool);
+    match command {
+        Command::Protein(ProteinCommand::Create {
+            name,
+            pdb_id,
+            sequence,
+            project,
+            public,
+        }) => {
+            validate::required("protein name", &name)?;
+            let project_id = match &project {
+                Some(project) => Some(project_id(&repository, project).await?),
+                None => None,
+            };
+            let sequence = sequence.map(|sequence| sequence.trim().to_uppercase());
+            match repository
+                .create_protein(&name, pdb_id.as_deref(), sequence.as_deref())
+                .await?
+            {
+                Some(id) => println!("created protein {name} with id {id}"),
+                None => bail!("protein {name} already exists"),
+            }
+            if project_id.is_some() || public {
+                repository
+                    .set_protein_access(&name, project_id, public)
+                    .await?;
+            }
+            let details = json!({"pdb_id": pdb_id, "project": project, "public": public});
+            record(&repository, Action::CreateProtein, &name, details).await?;
+        }
+        Command::Protein(ProteinCommand::List) => {
+            let projects = repository.projects().await?;
+            let proteins = repository.protein_access().await?;
+            print_table(
+                &["protein", "project", "visible to"],
+                proteins
+                    .into_iter()
+                    .map(|protein| {
+                        vec![
+                            protein.name,
+                            projects
+                                .iter()
+                                .find(|project| Some(project.id) == protein.project_id)
+                                .map(|project| project.name.clone())
+                                .unwrap_or_default(),
+                            listed_as(protein.public, protein.archived).to_string(),
+                        ]
+                    })
+                    .collect(),
+            );
+        }
+        Command::Protein(ProteinCommand::Publish { name }) => {
+            set_public(&repository, &name, true).await?
+        }
+        Command::Protein(ProteinCommand::Unpublish { name }) => {
+            set_public(&repository, &name, false).await?
+        }
+        Command::Import {
+            protein,
+            files,
+            batch_size,
+            strict,
+        } => {
+            if batch_size == 0 {
+                bail!("--batch-size must be at least 1");
+            }
+            let proteins = repository.list_proteins(Scope::Everything).await?;
+            if !proteins.contains(&protein) {
+                bail!(
+                    validate::unknown("protein", &protein, proteins.iter().map(String::as_str))
+                        .message()
+                );
+            }
+            let actor = cli_actor();
+            for path in &files {
+                let (variants, source) = read_file(path, &protein, strict)?;
+                let progress = Progress::new(&path.display().to_string(), variants.len() as u64);
+                let batch = ingest::insert_sqlite_batches(
+                    repository.pool(),
+                    &variants,
+                    &protein,
+                    &source,
+                    &actor,
+                    batch_size,
+                    |done| progress.update(done),
+                )
+                .await
+                .with_context(|| format!("could not import {}", path.display()))?;
+                progress.finish();
+                print_imported(path, &batch);
+            }
+        }
+        Command::User(command) => user_command(&repository, command).await?,
+        Command::Project(command) => project_command(&repository, command).await?,
+        Command::Protein(ProteinCommand::Rename { .. }) => bail!("protein rename {NEEDS_POSTGRES}"),
+        Command::Delete(target) => change_dataset(&repository, Operation::Delete, target).await?,
+        Command::Archive(target) => change_dataset(&repository, Operation::Archive, target).await?,
+        Command::Restore(target) => change_dataset(&repository, Operation::Restore, target).await?,
+        Command::Export { .. } => bail!("export {NEEDS_POSTGRES}"),
+        Command::Refresh { .. } => bail!("refresh {NEEDS_POSTGRES}"),
+        Command::Coverage { .. } => bail!("coverage {NEEDS_POSTGRES}"),
+    }
+    Ok(())
+}
+
+/// Account commands, the same for both backends.
+async fn user_command(repository: &dyn Repository, command: UserCommand) -> anyhow::Result<()> {
+    match command {
+        UserCommand::Create { username, admin } => {
+            auth::check_username(&username)?;
+            let password = read_password()?;
+            let hash = auth::hash_password(&password)?;
+            match repository.create_user(&username, &hash, admin).await? {
+                Some(id) => println!("created user {username} with id {id}"),
+                None => bail!("user {username} already exists"),
+            }
+        }
+        UserCommand::List => {
+            let users = repository.users().await?;
+            print_table(
+                &["user", "role", "status", "created"],
+                users
+                    .into_iter()
+                    .map(|user| {
+                        vec![
+                            user.username,
+                            if user.is_admin {
+                                "administrator"
+                            } else {
+                                "user"
+                            }
+                            .to_string(),
+                            if user.disabled { "disabled" } else { "active" }.to_string(),
+                            user.created_on.format("%Y-%m-%d").to_string(),
+                        ]
+                    })
+                    .collect(),
+            );
+        }
+        UserCommand::Password { username } => {
+            let id = user_id(repository, &username).await?;
+            let password = read_password()?;
+            let update = UserUpdate {
+                password_hash: Some(auth::hash_password(&password)?),
+                ..UserUpdate::default()
+            };
+            repository.update_user(id, &update).await?;
+            println!("changed the password of {username}");
+        }
+        UserCommand::Disable { username } => {
+            set_disabled(repository, &username, true).await?;
+            println!("disabled user {username}");
+        }
+        UserCommand::Enable { username } => {
+            set_disabled(repository, &username, false).await?;
+            println!("enabled user {username}");
+        }
+        UserCommand::Delete { username } => {
+            let id = user_id(repository, &username).await?;
+            repository.delete_user(id).await?;
+            println!("deleted user {username}");
+        }
+    }
+    Ok(())
+}
+
+/// Id of `username`, or an error suggesting the closest existing users.
+async fn user_id(repository: &dyn Repository, username: &str) -> anyhow::Result<i32> {
+    let users = repository.users().await?;
+    match users.iter().find(|user| user.username == username) {
+        Some(user) => Ok(user.id),
+        None => bail!(validate::unknown(
+            "user",
+            username,
+            users.iter().map(|user| user.username.as_str())
+        )
+        .message()),
+    }
+}
+
+async fn set_disabled(
+    repository: &dyn Repository,
+    username: &str,
+    disabled: bool,
+) -> anyhow::Result<()> {
+    let id = user_id(repository, username).await?;
+    let update = UserUpdate {
+        disabled: Some(disabled),
+        ..UserUpdate::default()
+    };
+    repository.update_user(id, &update).await?;
+    Ok(())
+}
+
+/// Project commands, the same for both backends.
+async fn project_command(
+    repository: &dyn Repository,
+    command: ProjectCommand,
+) -> anyhow::Result<()> {
+    match command {
+        ProjectCommand::Create { name } => {
+            access::check_project_name(&name)?;
+            match repository.create_project(&name).await? {
+                Some(id) => println!("created project {name} with id {id}"),
+                None => bail!("project {name} already exists"),
+            }
+        }
+        ProjectCommand::List => {
+            let proteins = repository.protein_access().await?;
+            let mut rows = vec![];
+            for project in repository.projects().await? {
+                let members = repository.members(project.id).await?;
+                rows.push(vec![
+                    project.name,
+                    members
+                        .iter()
+                        .map(|member| format!("{} ({})", member.username, member.role))
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    proteins
+                        .iter()
+                        .filter(|protein| protein.project_id == Some(project.id))
+                        .map(|protein| protein.name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                ]);
+            }
+            print_table(&["project", "members", "proteins"], rows);
+        }
+        ProjectCommand::Member {
+            project,
+            username,
+            role,
+        } => {
+            let project_id = project_id(repository, &project).await?;
+            let user_id = user_id(repository, &username).await?;
+            repository
+                .set_member(project_id, user_id, Some(role))
+                .await?;
+            println!("{username} is now {role} of {project}");
+        }
+        ProjectCommand::Remove { project, username } => {
+            let project_id = project_id(repository, &project).await?;
+            let user_id = user_id(repository, &username).await?;
+            if !repository.set_member(project_id, user_id, None).await? {
+                bail!("{username} is not a member of {project}");
+            }
+            println!("removed {username} from {project}");
+        }
+        ProjectCommand::Assign { project, proteins } => {
+            let project_id = project_id(repository, &project).await?;
+            for name in &proteins {
+                let protein = protein_access(repository, name).await?;
+                repository
+                    .set_protein_access(name, Some(project_id), protein.public)
+                    .await?;
+                record(
+                    repository,
+                    Action::ProteinAccess,
+                    name,
+                    json!({"project": project}),
+                )
+                .await?;
+                println!("moved {name} to {project}");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Id of the project called `name`, or an error suggesting the closest
+/// existing projects.
+async fn project_id(repository: &dyn Repository, name: &str) -> anyhow::Result<i32> {
+    let projects = repository.projects().await?;
+    match projects.iter().find(|project| project.name == name) {
+        Some(project) => Ok(project.id),
+        None => bail!(validate::unknown(
+            "project",
+            name,
+            projects.iter().map(|project| project.name.as_str())
+        )
+        .message()),
+    }
+}
+
+/// Project and public flag of protein `name`, or an error suggesting the
+/// closest existing proteins.
+async fn protein_access(
+    repository: &dyn Repository,
+    name: &str,
+) -> anyhow::Result<access::ProteinAccess> {
+    let proteins = repository.protein_access().await?;
+    match proteins.iter().find(|protein| protein.name == name) {
+        Some(protein) => Ok(protein.clone()),
+        None => bail!(validate::unknown(
+            "protein",
+            name,
+            proteins.iter().map(|protein| protein.name.as_str())
+        )
+        .message()),
+    }
+}
+
+async fn set_public(repository: &dyn Repository, name: &str, public: bool) -> anyhow::Result<()> {
+    let protein = protein_access(repository, name).await?;
+    repository
+        .set_protein_access(name, protein.project_id, public)
+        .await?;
+    record(
+        repository,
+        Action::ProteinAccess,
+        name,
+        json!({"public": public}),
+    )
+    .await?;
+    println!("{name} is now visible to {}", visible_to(public));
+    Ok(())
+}
+
+/// Whoever runs the CLI, by their operating system login.
+fn cli_actor() -> Actor {
+    let login = std::env::var("USER")
+        .or_else(|_| std::env::var("LOGNAME"))
+        .unwrap_or_else(|_| "unknown".to_string());
+    Actor::command_line(&login)
+}
+
+/// Records a change made from the command line in the audit log.
+async fn record(
+    repository: &dyn Repository,
+    action: Action,
+    protein: &str,
+    details: Value,
+) -> anyhow::Result<()> {
+    repository
+        .record(&cli_actor(), &Change::new(action, protein, details))
+        .await?;
+    Ok(())
+}
+
+fn visible_to(public: bool) -> &'static str {
+    if public {
+        "everyone"
+    } else {
+        "members"
+    }
+}
+
+/// `visible_to` for the protein lists, where archived proteins are shown too.
+fn listed_as(public: bool, archived: bool) -> &'static str {
+    if archived {
+        "nobody (archived)"
+    } else {
+        visible_to(public)
+    }
+}
+
+fn read_password() -> anyhow::Result<String> {
+    let password = if io::stdin().is_terminal() {
+        let password = rpassword::prompt_password("password: ")?;
+        if rpassword::prompt_password("repeat password: ")? != password {
+            bail!("the passwords do not match");
+        }
+        password
+    } else {
+        let mut line = String::new();
+        io::stdin().read_line(&mut line)?;
+        line.trim_end_matches(['\r', '\n']).to_string()
+    };
+    auth::check_password(&password)?;
+    Ok(password)
+}
+
+/// Deletes, archives or restores `target`, or with `--yes` missing only
+/// says how many variants that would affect.
+async fn change_dataset(
+    repository: &dyn Repository,
+    operation: Operation,
+    target: Target,
+) -> anyhow::Result<()> {
+    let proteins = repository.protein_access().await?;
+    if !proteins
+        .iter()
+        .any(|protein| protein.name == target.protein)
+    {
+        bail!(validate::unknown(
+            "protein",
+            &target.protein,
+            proteins.iter().map(|protein| protein.name.as_str())
+        )
+        .message());
+    }
+    let selection = Selection {
+        protein: target.protein,
+        condition: target.condition,
+        version: target.version,
+        batch: target.batch,
+    };
+    let what = if selection.whole_protein() && operation == Operation::Delete {
+        format!("{selection} and all its variants")
+    } else {
+        selection.to_string()
+    };
+    if !target.yes {
+        let count = repository.affected(&selection).await?.by(operation);
+        bail!("would {operation} {what} ({count} variants), pass --yes to go ahead");
+    }
+    let affected = repository
+        .apply(operation, &selection, &cli_actor())
+        .await?;
+    println!(
+        "{} {what} ({} variants)",
+        operation.past_tense(),
+        affected.by(operation)
+    );
+    Ok(())
+}
+
+/// One exported row. The columns are the ones `read_tsv` expects, so an export
+/// can be imported again; `p_adjusted` is ignored on the way back in.
+#[derive(Serialize)]
+struct ExportRow<'a> {
+    protein: &'a str,
+    condition: &'a str,
+    version: &'a str,
+    chunk: i32,
+    pos: i32,
+    aa: &'a str,
+    log2_fold_change: f64,
+    log2_std_error: f64,
+    statistic: f64,
+    p_value: f64,
+    /// Benjamini-Hochberg over the whole condition, as the viewer computes it.
+    p_adjusted: f64,
+}
+
+async fn export(
+    pool: &PgPool,
+    protein: &str,
+    condition: Option<String>,
+    version: Option<String>,
+    output: Option<PathBuf>,
+) -> anyhow::Result<()> {
+    let id = protein_id(pool, protein).await?;
+    // Adjusted p values depend on every variant of the condition, so fetch
+    // all versions and filter afterwards.
+    let variants = sqlx::query_as!(
+        Variant,
+        r#"SELECT
+            variant.id,
+            variant.chunk,
+            variant.pos,
+            variant.p_value,
+            variant.created_on,
+            variant.log2_fold_change,
+            variant.log2_std_error,
+            variant.statistic,
+            variant.condition,
+            variant.aa,
+            variant.version,
+            protein.name as protein
+        FROM variant
+        JOIN protein ON variant.protein_id = protein.id
+        WHERE protein.id = $1
+        AND ($2::VARCHAR IS NULL OR variant.condition = $2)
+        ORDER BY variant.condition, variant.pos, variant.aa, variant.version
+        "#,
+        id,
+        condition,
+    )
+    .fetch_all(pool)
+    .await?;
+    let mut by_condition: BTreeMap<&str, Vec<&Variant>> = BTreeMap::new();
+    for variant in &variants {
+        by_condition
+            .entry(&variant.condition)
+            .or_default()
+            .push(variant);
+    }
+    let out: Box<dyn Write> = match &output {
+        Some(path) => {
+            Box::new(BufWriter::new(File::create(path).with_context(|| {
+                format!("could not create {}", path.display())
+            })?))
+        }
+        None => Box::new(io::stdout().lock()),
+    };
+    let mut writer = csv::WriterBuilder::new().delimiter(b'\t').from_writer(out);
+    let mut rows = 0;
+    for variants in by_condition.values() {
+        let p_values: Vec<f64> = variants.iter().map(|variant| variant.p_value).collect();
+        let adjusted = classify::benjamini_hochberg(&p_values);
+        for (variant, p_adjusted) in variants.iter().zip(adjusted) {
+            if version
+                .as_ref()
+                .is_some_and(|version| *version != variant.version)
+            {
+                continue;
+            }
+            writer.serialize(ExportRow {
+                protein: &variant.protein,
+                condition: &variant.condition,
+                version: &variant.version,
+                chunk: variant.chunk,
+                pos: variant.pos,
+                aa: &variant.aa,
+                log2_fold_change: variant.log2_fold_change,
+                log2_std_error: variant.log2_std_error,
+                statistic: variant.statistic,
+                p_value: variant.p_value,
+                p_adjusted,
+            })?;
+            rows += 1;
+        }
+    }
+    writer.flush()?;
+    if let Some(path) = output {
+        eprintln!("wrote {rows} rows to {}", path.display());
+    }
+    Ok(())
+}
+
+async fn refresh(pool: &PgPool, protein: Option<String>) -> anyhow::Result<()> {
+    let id = match &protein {
+        Some(protein) => Some(protein_id(pool, protein).await?),
+        None => None,
+    };
+    // Include pairs that only survive in the summaries, so stale rows go too.
+    let refreshed = sqlx::query_scalar!(
+        r#"
+        SELECT count(refresh_variant_summary_and_notify(pairs.protein_id, pairs.condition)) as "count!"
+        FROM (
+            SELECT DISTINCT protein_id, condition FROM variant
+            WHERE $1::INTEGER IS NULL OR protein_id = $1
+            UNION
+            SELECT protein_id, condition FROM variant_summary
+            WHERE $1::INTEGER IS NULL OR protein_id = $1
+        ) AS pairs
+        "#,
+        id
+    )
+    .fetch_one(pool)
+    .await?;
+    println!("refreshed summaries for {refreshed} protein/condition pairs");
+    Ok(())
+}
+
+async fn coverage(pool: &PgPool, protein: Option<String>) -> anyhow::Result<()> {
+    let id = match &protein {
+        Some(protein) => Some(protein_id(pool, protein).await?),
+        None => None,
+    };
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            protein.name,
+            length(protein.sequence) as sequence_length,
+            variant_summary.condition,
+            variant_summary.version,
+            variant_summary.variant_count,
+            variant_summary.position_count,
+            variant_summary.min_pos,
+            variant_summary.max_pos,
+            variant_summary.coverage
+        FROM variant_summary
+        JOIN protein ON variant_summary.protein_id = protein.id
+        WHERE $1::INTEGER IS NULL OR protein.id = $1
+        ORDER BY protein.name, variant_summary.condition, variant_summary.version
+        "#,
+        id
+    )
+    .fetch_all(pool)
+    .await?;
+    print_table(
+        &[
+            "protein",
+            "condition",
+            "version",
+            "variants",
+            "positions",
+            "range",
+            "grid coverage",
+            "sequence coverage",
+        ],
+        rows.into_iter()
+            .map(|row| {
+                vec![
+                    row.name,
+                    row.condition,
+                    row.version,
+                    row.variant_count.to_string(),
+                    row.position_count.to_string(),
+                    format!("{}-{}", row.min_pos, row.max_pos),
+                    format!("{:.1}%", row.coverage * 100.0),
+                    row.sequence_length.filter(|length| *length > 0).map_or(
+                        String::new(),
+                        |length| {
+                            format!("{:.1}%", row.position_count as f64 / length as f64 * 100.0)
+                        },
+                    ),
+                ]
+            })
+            .collect(),
+    );
+    Ok(())
+}
+
+fn print_table(headers: &[&str], rows: Vec<Vec<String>>) {
+    let mut widths: Vec<usize> = headers.iter().map(|header| header.len()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let line = |cells: Vec<&str>| {
+        let padded: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect();
+        println!("{}", padded.join("  ").trim_end());
+    };
+    line(headers.to_vec());
+    for row in &rows {
+        line(row.iter().map(String::as_str).collect());
+    }
+}
+
+/// Row counter drawn on stderr while importing, only when it is a terminal so
+/// scripted runs get clean logs.
+struct Progress {
+    label: String,
+    total: u64,
+    start: Instant,
+    draw: bool,
+}
+
+impl Progress {
+    const WIDTH: u64 = 30;
+
+    fn new(label: &str, total: u64) -> Self {
+        let progress = Self {
+            label: label.to_string(),
+            total,
+            start: Instant::now(),
+            draw: io::stderr().is_terminal(),
+        };
+        progress.update(0);
+        progress
+    }
+
+    fn update(&self, done: u64) {
+        if !self.draw {
+            return;
+        }
+        let filled = (done * Self::WIDTH)
+            .checked_div(self.total)
+            .unwrap_or(Self::WIDTH);
+        let bar = format!(
+            "{}{}",
+            "#".repeat(filled as usize),
+            " ".repeat((Self::WIDTH - filled) as usize)
+        );
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let rate = if elapsed > 0.0 {
+            done as f64 / elapsed
+        } else {
+            0.0
+        };
+        eprint!(
+            "\r{} [{bar}] {done}/{} rows, {rate:.0} rows/s",
+            self.label, self.total
+        );
+        let _ = io::stderr().flush();
+    }
+
+    fn finish(&self) {
+        if self.draw {
+            eprintln!();
+        }
+    }
+}
@@ -0,0 +1,357 @@
+//! Who changed which data, when and from which file. Ingests, deletes,
+//! archives and protein edits each append an entry to the audit log; an ingest also stores
+//! its file as an upload batch that the variants it created point back to.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::types::Json;
+use sqlx::{FromRow, PgConnection, SqliteConnection};
+
+use crate::auth::User;
+use crate::datasets::{Affected, Operation, Selection};
+use crate::{empty_string_as_none, Variant};
+
+/// Entries per page when a filter does not say.
+pub const DEFAULT_LIMIT: i64 = 100;
+pub const MAX_LIMIT: i64 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    /// A file was loaded; the entry points at its upload batch.
+    Ingest,
+    /// Variants, or a whole protein, were deleted.
+    Delete,
+    /// Variants, or a whole protein, were hidden.
+    Archive,
+    Restore,
+    CreateProtein,
+    RenameProtein,
+    /// A protein moved to another project or was published or hidden.
+    ProteinAccess,
+}
+
+impl Action {
+    pub const ALL: [Action; 7] = [
+        Action::Ingest,
+        Action::Delete,
+        Action::Archive,
+        Action::Restore,
+        Action::CreateProtein,
+        Action::RenameProtein,
+        Action::ProteinAccess,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::Ingest => "ingest",
+            Action::Delete => "delete",
+            Action::Archive => "archive",
+            Action::Restore => "restore",
+            Action::CreateProtein => "create_protein",
+            Action::RenameProtein => "rename_protein",
+            Action::ProteinAccess => "protein_access",
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(action: &str) -> Result<Self, String> {
+        Action::ALL
+            .into_iter()
+            .find(|candidate| candidate.as_str() == action)
+            .ok_or_else(|| format!("unknown audit action {action:?}"))
+    }
+}
+
+impl TryFrom<String> for Action {
+    type Error = String;
+
+    fn try_from(action: String) -> Result<Self, String> {
+        action.parse()
+    }
+}
+
+/// Who made a change: a signed-in user, or someone running the admin CLI.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Actor {
+    pub user_id: Option<i32>,
+    pub name: String,
+}
+
+impl Actor {
+    pub fn user(user: &User) -> Self {
+        Self {
+            user_id: Some(user.id),
+            name: user.username.clone(),
+        }
+    }
+
+    /// The admin CLI, run by the operating system user `login`.
+    pub fn command_line(login: &str) -> Self {
+        Self {
+            user_id: None,
+            name: format!("cli:{login}"),
+        }
+    }
+}
+
+/// A change about to be recorded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub action: Action,
+    pub protein: String,
+    pub batch_id: Option<i32>,
+    pub details: Value,
+}
+
+impl Change {
+    pub fn new(action: Action, protein: &str, details: Value) -> Self {
+        Self {
+            action,
+            protein: protein.to_string(),
+            batch_id: None,
+            details,
+        }
+    }
+
+    /// The entry for loading `variants` as `batch`.
+    pub fn ingest(batch: &UploadBatch, variants: &[Variant]) -> Self {
+        let conditions: BTreeSet<&str> = variants.iter().map(|v| v.condition.as_str()).collect();
+        let versions: BTreeSet<&str> = variants.iter().map(|v| v.version.as_str()).collect();
+        Self {
+            action: Action::Ingest,
+            protein: batch.protein.clone(),
+            batch_id: Some(batch.id),
+            details: json!({
+                "file_name": batch.file_name,
+                "sha256": batch.sha256,
+                "rows_inserted": batch.rows_inserted,
+                "rows_rejected": batch.rows_rejected,
+                "columns": batch.columns.0,
+                "conditions": conditions,
+                "versions": versions,
+            }),
+        }
+    }
+
+    /// The entry for applying `operation` to `selection`.
+    pub fn dataset(operation: Operation, selection: &Selection, affected: Affected) -> Self {
+        let action = match operation {
+            Operation::Delete => Action::Delete,
+            Operation::Archive => Action::Archive,
+            Operation::Restore => Action::Restore,
+        };
+        Self {
+            action,
+            protein: selection.protein.clone(),
+            batch_id: selection.batch,
+            details: json!({
+                "condition": selection.condition,
+                "version": selection.version,
+                "whole_protein": selection.whole_protein(),
+                "variants": affected.by(operation),
+            }),
+        }
+    }
+}
+
+/// One ingested file. Every variant it created has its `batch_id`.
+#[derive(Debug, Clone, PartialEq, Serialize, FromRow)]
+pub struct UploadBatch {
+    pub id: i32,
+    pub protein: String,
+    pub file_name: String,
+    pub sha256: String,
+    pub rows_inserted: i64,
+    pub rows_rejected: i64,
+    /// Each header of the file and the variant field it was read into,
+    /// `None` for ignored columns.
+    pub columns: Json<BTreeMap<String, Option<String>>>,
+    pub actor: String,
+    pub created_on: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, FromRow)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub created_on: NaiveDateTime,
+    /// `None` for the admin CLI, or once the user is deleted.
+    pub user_id: Option<i32>,
+    pub actor: String,
+    #[sqlx(try_from = "String")]
+    pub action: Action,
+    /// The protein's name when the change was made.
+    pub protein: String,
+    pub batch_id: Option<i32>,
+    pub details: Json<Value>,
+}
+
+/// Which entries to browse, newest first. Empty form fields match anything.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AuditFilter {
+    /// Matches the name at the time of the change or the current one.
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub protein: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub action: Option<Action>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub actor: Option<String>,
+    pub batch: Option<i32>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub since: Option<NaiveDate>,
+    /// Inclusive.
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub until: Option<NaiveDate>,
+    /// Only entries older than this id, for the next page.
+    pub before: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+impl AuditFilter {
+    pub fn limit(&self) -> i64 {
+        self.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT)
+    }
+
+    /// Start and end of the period, the end exclusive.
+    pub fn period(&self) -> (Option<NaiveDateTime>, Option<NaiveDateTime>) {
+        (
+            self.since.and_then(|day| day.and_hms_opt(0, 0, 0)),
+            self.until
+                .and_then(|day| day.succ_opt())
+                .and_then(|day| day.and_hms_opt(0, 0, 0)),
+        )
+    }
+
+    /// Whether `entry` passes every filter but the protein's current name,
+    /// which only the database knows.
+    pub fn matches(&self, entry: &AuditEntry) -> bool {
+        let (start, end) = self.period();
+        self.protein.as_ref().is_none_or(|p| *p == entry.protein)
+            && self.action.is_none_or(|action| action == entry.action)
+            && self
+                .actor
+                .as_ref()
+                .is_none_or(|actor| *actor == entry.actor)
+            && self.batch.is_none_or(|batch| Some(batch) == entry.batch_id)
+            && start.is_none_or(|start| entry.created_on >= start)
+            && end.is_none_or(|end| entry.created_on < end)
+            && self.before.is_none_or(|before| entry.id < before)
+    }
+}
+
+/// Appends an entry on a Postgres connection, typically inside the
+/// transaction making the change.
+pub async fn record_postgres(
+    conn: &mut PgConnection,
+    actor: &Actor,
+    change: &Change,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO audit_log (user_id, actor, action, protein_id, protein, batch_id, details)
+        VALUES ($1, $2, $3, (SELECT id FROM protein WHERE name = $4), $4, $5, $6)
+        "#,
+        actor.user_id,
+        actor.name,
+        change.action.as_str(),
+        change.protein,
+        change.batch_id,
+        change.details
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// `record_postgres` for a SQLite connection.
+pub async fn record_sqlite(
+    conn: &mut SqliteConnection,
+    actor: &Actor,
+    change: &Change,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO audit_log (user_id, actor, action, protein_id, protein, batch_id, details)
+        VALUES (?1, ?2, ?3, (SELECT id FROM protein WHERE name = ?4), ?4, ?5, ?6)
+        "#,
+    )
+    .bind(actor.user_id)
+    .bind(&actor.name)
+    .bind(change.action.as_str())
+    .bind(&change.protein)
+    .bind(change.batch_id)
+    .bind(Json(&change.details))
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(id: i64, action: Action, created_on: &str) -> AuditEntry {
+        AuditEntry {
+            id,
+            created_on: NaiveDateTime::parse_from_str(created_on, "%Y-%m-%d %H:%M").unwrap(),
+            user_id: None,
+            actor: "cli:ada".to_string(),
+            action,
+            protein: "P1".to_string(),
+            batch_id: Some(7),
+            details: Json(json!({})),
+        }
+    }
+
+    #[test]
+    fn actions_round_trip() {
+        for action in Action::ALL {
+            assert_eq!(action.as_str().parse(), Ok(action));
+            assert_eq!(
+                serde_json::to_value(action).unwrap(),
+                Value::from(action.as_str())
+            );
+        }
+        assert!("upload".parse::<Action>().is_err());
+    }
+
+    #[test]
+    fn filters_match_inclusive_days() {
+        let filter: AuditFilter = serde_json::from_value(json!({
+            "protein": "P1",
+            "action": "ingest",
+            "actor": "",
+            "since": "2026-10-01",
+            "until": "2026-10-02",
+            "before": 5,
+        }))
+        .unwrap();
+        assert_eq!(filter.actor, None);
+        assert!(filter.matches(&entry(4, Action::Ingest, "2026-10-02 23:59")));
+        assert!(filter.matches(&entry(4, Action::Ingest, "2026-10-01 00:00")));
+        assert!(!filter.matches(&entry(4, Action::Ingest, "2026-10-03 00:00")));
+        assert!(!filter.matches(&entry(5, Action::Ingest, "2026-10-02 12:00")));
+        assert!(!filter.matches(&entry(4, Action::Delete, "2026-10-02 12:00")));
+        assert_eq!(filter.limit(), DEFAULT_LIMIT);
+        let everything = AuditFilter {
+            limit: Some(1_000_000),
+            ..AuditFilter::default()
+        };
+        assert!(everything.matches(&entry(9, Action::RenameProtein, "2020-01-01 00:00")));
+        assert_eq!(everything.limit(), MAX_LIMIT);
+    }
+}
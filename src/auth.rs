@@ -0,0 +1,234 @@
+//! Local accounts: argon2 password hashes, session cookies and personal API
+//! tokens. Everything is checked against the database; there is no external
+//! identity provider.
+
+use std::sync::OnceLock;
+
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use axum::http::{header, HeaderMap};
+use chrono::NaiveDateTime;
+use rand::RngCore;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::FromRow;
+
+use crate::error::AppError;
+
+/// Cookie holding the session secret.
+pub const SESSION_COOKIE: &str = "dms_session";
+/// Start of every API token, so a leaked one is easy to recognise.
+pub const TOKEN_PREFIX: &str = "dms_";
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+pub const MAX_NAME_LENGTH: usize = 60;
+
+#[derive(Debug, Clone, PartialEq, Serialize, FromRow)]
+pub struct User {
+    pub id: i32,
+    pub username: String,
+    pub is_admin: bool,
+    pub disabled: bool,
+    pub created_on: NaiveDateTime,
+}
+
+/// A user and the hash to check their password against.
+#[derive(Debug, Clone, FromRow)]
+pub struct Credentials {
+    #[sqlx(flatten)]
+    pub user: User,
+    pub password_hash: String,
+}
+
+/// A login session; it is over once `expires_on` has passed.
+#[derive(Debug, Clone, FromRow)]
+pub struct Session {
+    #[sqlx(flatten)]
+    pub user: User,
+    pub expires_on: NaiveDateTime,
+}
+
+/// A personal API token. The token itself is only shown once, on creation.
+#[derive(Debug, Clone, PartialEq, Serialize, FromRow)]
+pub struct ApiToken {
+    pub id: i32,
+    pub name: String,
+    pub created_on: NaiveDateTime,
+    pub last_used_on: Option<NaiveDateTime>,
+}
+
+/// Changes to an account; `None` leaves a field alone.
+#[derive(Debug, Clone, Default)]
+pub struct UserUpdate {
+    pub password_hash: Option<String>,
+    pub is_admin: Option<bool>,
+    pub disabled: Option<bool>,
+}
+
+impl UserUpdate {
+    /// Whether the change ends the user's sessions: a new password or a
+    /// disabled account.
+    pub fn revokes_sessions(&self) -> bool {
+        self.password_hash.is_some() || self.disabled == Some(true)
+    }
+}
+
+/// Argon2id PHC string for `password`, with a random salt. Slow on purpose;
+/// call it from `spawn_blocking` in async code.
+pub fn hash_password(password: &str) -> anyhow::Result<String> {
+    let mut salt = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut salt);
+    let salt = SaltString::encode_b64(&salt).map_err(|err| anyhow::anyhow!("{err}"))?;
+    Ok(Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|err| anyhow::anyhow!("could not hash password: {err}"))?
+        .to_string())
+}
+
+/// Whether `password` matches `hash`. An unreadable hash matches nothing.
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|hash| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    })
+}
+
+/// Spends as long as `verify_password` on a real account, so a failed login
+/// takes the same time whether or not the username exists.
+pub fn verify_nothing(password: &str) {
+    static DUMMY: OnceLock<String> = OnceLock::new();
+    let hash = DUMMY.get_or_init(|| hash_password("not a password").unwrap_or_default());
+    verify_password(password, hash);
+}
+
+/// 256 random bits, hex encoded: a session secret or the body of a token.
+pub fn new_secret() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex(&bytes)
+}
+
+pub fn new_api_token() -> String {
+    format!("{TOKEN_PREFIX}{}", new_secret())
+}
+
+/// What the database stores instead of a session secret or token. Secrets
+/// are random, so a plain SHA-256 is enough.
+pub fn secret_hash(secret: &str) -> String {
+    hex(&Sha256::digest(secret.as_bytes()))
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+pub fn check_username(username: &str) -> Result<(), AppError> {
+    if username.is_empty() || username.chars().count() > MAX_NAME_LENGTH {
+        return Err(AppError::BadRequest(format!(
+            "usernames have 1 to {MAX_NAME_LENGTH} characters"
+        )));
+    }
+    if !username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-' | '@'))
+    {
+        return Err(AppError::BadRequest(
+            "usernames may only contain letters, digits and . _ - @".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+pub fn check_password(password: &str) -> Result<(), AppError> {
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(AppError::BadRequest(format!(
+            "passwords need at least {MIN_PASSWORD_LENGTH} characters"
+        )));
+    }
+    Ok(())
+}
+
+/// Value of cookie `name` in the request headers.
+pub fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+/// Token of an `Authorization: Bearer` header.
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+/// `Set-Cookie` value for a session lasting `max_age_secs`; 0 ends it.
+pub fn session_cookie(secret: &str, max_age_secs: u64, secure: bool) -> String {
+    format!(
+        "{SESSION_COOKIE}={secret}; Path=/; Max-Age={max_age_secs}; HttpOnly; SameSite=Lax{}",
+        if secure { "; Secure" } else { "" }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn passwords_verify_against_their_hash_only() {
+        let hash = hash_password("correct horse").unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify_password("correct horse", &hash));
+        assert!(!verify_password("correct horse ", &hash));
+        assert!(!verify_password("correct horse", "not a hash"));
+        assert_ne!(hash, hash_password("correct horse").unwrap());
+    }
+
+    #[test]
+    fn secrets_are_random_and_hashed() {
+        let token = new_api_token();
+        assert!(token.starts_with(TOKEN_PREFIX));
+        assert_eq!(token.len(), TOKEN_PREFIX.len() + 64);
+        assert_ne!(token, new_api_token());
+        assert_eq!(
+            secret_hash("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn reads_cookies_and_bearer_tokens() {
+        let mut headers = HeaderMap::new();
+        headers.append(header::COOKIE, HeaderValue::from_static("theme=dark"));
+        headers.append(
+            header::COOKIE,
+            HeaderValue::from_static("a=1; dms_session=abc; b=2"),
+        );
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer dms_xyz"),
+        );
+        assert_eq!(cookie(&headers, SESSION_COOKIE), Some("abc"));
+        assert_eq!(cookie(&headers, "missing"), None);
+        assert_eq!(bearer_token(&headers), Some("dms_xyz"));
+    }
+
+    #[test]
+    fn names_and_passwords_are_checked() {
+        assert!(check_username("ada.lovelace@lab").is_ok());
+        assert!(check_username("").is_err());
+        assert!(check_username("two words").is_err());
+        assert!(check_password("short").is_err());
+        assert!(check_password("long enough").is_ok());
+    }
+}
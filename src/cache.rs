@@ -0,0 +1,275 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use axum::body::Bytes;
+use axum::http::{HeaderMap, StatusCode};
+use serde::Serialize;
+
+/// A cached entry belongs to one protein and condition so ingest can drop
+/// exactly the entries it makes stale. `request` is the route plus the
+/// normalized query.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    pub protein: String,
+    pub condition: String,
+    pub request: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct CachedResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub invalidations: u64,
+    pub entries: usize,
+    pub bytes: usize,
+    pub max_bytes: usize,
+}
+
+struct Entry {
+    response: CachedResponse,
+    inserted: Instant,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct Entries {
+    by_key: HashMap<CacheKey, Entry>,
+    /// Last-use tick → key, oldest first.
+    recency: BTreeMap<u64, CacheKey>,
+    tick: u64,
+    bytes: usize,
+}
+
+impl Entries {
+    fn remove(&mut self, key: &CacheKey) -> Option<Entry> {
+        let entry = self.by_key.remove(key)?;
+        self.recency.remove(&entry.last_used);
+        self.bytes -= entry.response.body.len();
+        Some(entry)
+    }
+}
+
+/// In-memory LRU cache of rendered responses with a time to live and a limit
+/// on the total body size. A limit of zero turns caching off.
+pub struct ResponseCache {
+    entries: Mutex<Entries>,
+    max_bytes: usize,
+    ttl: Duration,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    invalidations: AtomicU64,
+}
+
+impl ResponseCache {
+    pub fn new(max_bytes: usize, ttl: Duration) -> Self {
+        Self {
+            entries: Mutex::new(Entries::default()),
+            max_bytes,
+            ttl,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+            invalidations: AtomicU64::new(0),
+        }
+    }
+
+    pub fn get(&self, key: &CacheKey) -> Option<CachedResponse> {
+        let mut entries = self.entries.lock().unwrap();
+        let expired = match entries.by_key.get(key) {
+            Some(entry) => entry.inserted.elapsed() > self.ttl,
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                return None;
+            }
+        };
+        if expired {
+            entries.remove(key);
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        entries.tick += 1;
+        let tick = entries.tick;
+        let entry = entries.by_key.get_mut(key).unwrap();
+        let previous = std::mem::replace(&mut entry.last_used, tick);
+        let response = entry.response.clone();
+        entries.recency.remove(&previous);
+        entries.recency.insert(tick, key.clone());
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(response)
+    }
+
+    /// Responses bigger than the whole cache are not stored.
+    pub fn insert(&self, key: CacheKey, response: CachedResponse) {
+        let size = response.body.len();
+        if self.max_bytes == 0 || size > self.max_bytes {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        entries.remove(&key);
+        while entries.bytes + size > self.max_bytes {
+            let Some((_, oldest)) = entries.recency.pop_first() else {
+                break;
+            };
+            if let Some(entry) = entries.by_key.remove(&oldest) {
+                entries.bytes -= entry.response.body.len();
+                self.evictions.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        entries.tick += 1;
+        let tick = entries.tick;
+        entries.bytes += size;
+        entries.recency.insert(tick, key.clone());
+        entries.by_key.insert(
+            key,
+            Entry {
+                response,
+                inserted: Instant::now(),
+                last_used: tick,
+            },
+        );
+    }
+
+    /// Drops every entry for `protein` and `condition`; with no protein,
+    /// every entry for the condition. See `clear` for everything.
+    pub fn invalidate(&self, protein: Option<&str>, condition: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        let stale: Vec<CacheKey> = entries
+            .by_key
+            .keys()
+            .filter(|key| {
+                key.condition == condition && protein.is_none_or(|protein| key.protein == protein)
+            })
+            .cloned()
+            .collect();
+        for key in &stale {
+            entries.remove(key);
+        }
+        self.invalidations
+            .fetch_add(stale.len() as u64, Ordering::Relaxed);
+    }
+
+    pub fn clear(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        self.invalidations
+            .fetch_add(entries.by_key.len() as u64, Ordering::Relaxed);
+        *entries = Entries::default();
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let entries = self.entries.lock().unwrap();
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            invalidations: self.invalidations.load(Ordering::Relaxed),
+            entries: entries.by_key.len(),
+            bytes: entries.bytes,
+            max_bytes: self.max_bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(protein: &str, condition: &str, request: &str) -> CacheKey {
+        CacheKey {
+            protein: protein.to_string(),
+            condition: condition.to_string(),
+            request: request.to_string(),
+        }
+    }
+
+    fn response(size: usize) -> CachedResponse {
+        CachedResponse {
+            status: StatusCode::OK,
+            headers: HeaderMap::new(),
+            body: Bytes::from(vec![b'x'; size]),
+        }
+    }
+
+    #[test]
+    fn least_recently_used_entries_go_first() {
+        let cache = ResponseCache::new(30, Duration::from_secs(60));
+        cache.insert(key("P1", "c1", "a"), response(10));
+        cache.insert(key("P1", "c1", "b"), response(10));
+        cache.insert(key("P1", "c1", "c"), response(10));
+        // Using `a` makes `b` the oldest
+        assert!(cache.get(&key("P1", "c1", "a")).is_some());
+        cache.insert(key("P1", "c1", "d"), response(15));
+
+        assert!(cache.get(&key("P1", "c1", "b")).is_none());
+        assert!(cache.get(&key("P1", "c1", "c")).is_none());
+        assert!(cache.get(&key("P1", "c1", "a")).is_some());
+        assert!(cache.get(&key("P1", "c1", "d")).is_some());
+        let stats = cache.stats();
+        assert_eq!(stats.evictions, 2);
+        assert_eq!((stats.entries, stats.bytes), (2, 25));
+
+        // Too big to ever fit, and replacing a key does not count it twice
+        cache.insert(key("P1", "c1", "e"), response(31));
+        assert!(cache.get(&key("P1", "c1", "e")).is_none());
+        cache.insert(key("P1", "c1", "a"), response(5));
+        assert_eq!(cache.stats().bytes, 20);
+    }
+
+    #[test]
+    fn expired_entries_are_misses() {
+        let cache = ResponseCache::new(100, Duration::from_millis(1));
+        cache.insert(key("P1", "c1", "a"), response(10));
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(cache.get(&key("P1", "c1", "a")).is_none());
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (0, 1));
+        assert_eq!((stats.entries, stats.bytes), (0, 0));
+    }
+
+    #[test]
+    fn invalidate_drops_a_protein_or_a_whole_condition() {
+        let cache = ResponseCache::new(100, Duration::from_secs(60));
+        for (protein, condition) in [("P1", "c1"), ("P1", "c2"), ("P2", "c1")] {
+            cache.insert(key(protein, condition, "a"), response(10));
+        }
+        cache.invalidate(Some("P1"), "c1");
+        assert!(cache.get(&key("P1", "c1", "a")).is_none());
+        assert!(cache.get(&key("P1", "c2", "a")).is_some());
+        assert!(cache.get(&key("P2", "c1", "a")).is_some());
+
+        cache.insert(key("P1", "c1", "a"), response(10));
+        cache.invalidate(None, "c1");
+        assert!(cache.get(&key("P1", "c1", "a")).is_none());
+        assert!(cache.get(&key("P2", "c1", "a")).is_none());
+        assert!(cache.get(&key("P1", "c2", "a")).is_some());
+        let stats = cache.stats();
+        assert_eq!(stats.invalidations, 3);
+        assert_eq!((stats.entries, stats.bytes), (1, 10));
+    }
+
+    #[test]
+    fn clear_empties_the_cache() {
+        let cache = ResponseCache::new(100, Duration::from_secs(60));
+        cache.insert(key("P1", "c1", "a"), response(10));
+        cache.insert(key("P2", "c2", "b"), response(20));
+        cache.clear();
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.bytes), (0, 0));
+        assert_eq!(stats.invalidations, 2);
+        assert!(cache.get(&key("P1", "c1", "a")).is_none());
+
+        let disabled = ResponseCache::new(0, Duration::from_secs(60));
+        disabled.insert(key("P1", "c1", "a"), response(1));
+        assert_eq!(disabled.stats().entries, 0);
+    }
+}
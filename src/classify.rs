@@ -0,0 +1,498 @@
+use std::collections::BTreeMap;
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+
+use crate::{wild_type_at, MutationType, Variant};
+
+pub const DEFAULT_EFFECT_CUTOFF: f64 = 1.0;
+pub const DEFAULT_P_CUTOFF: f64 = 0.05;
+pub const DEFAULT_POSTERIOR_CUTOFF: f64 = 0.5;
+pub const DEFAULT_GAIN_Z: f64 = 1.96;
+
+/// Categorical call for a single variant.
+///
+/// Negative log2 fold changes are treated as loss of function, which is the
+/// direction nonsense variants take in our screens.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum VariantClass {
+    Deleterious,
+    Neutral,
+    GainOfFunction,
+}
+
+impl VariantClass {
+    pub const ALL: [VariantClass; 3] = [
+        VariantClass::Deleterious,
+        VariantClass::Neutral,
+        VariantClass::GainOfFunction,
+    ];
+
+    pub fn color_hex(&self) -> &'static str {
+        match self {
+            VariantClass::Deleterious => "#D7301F",
+            VariantClass::Neutral => "#E0E0E0",
+            VariantClass::GainOfFunction => "#2B5EA7",
+        }
+    }
+}
+
+impl std::fmt::Display for VariantClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let output = match self {
+            VariantClass::Deleterious => "deleterious",
+            VariantClass::Neutral => "neutral",
+            VariantClass::GainOfFunction => "gain-of-function",
+        };
+        write!(f, "{}", output)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClassifierKind {
+    #[serde(alias = "threshold")]
+    Threshold,
+    #[serde(alias = "mixture")]
+    Mixture,
+}
+
+impl std::fmt::Display for ClassifierKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let output = match self {
+            ClassifierKind::Threshold => "Threshold",
+            ClassifierKind::Mixture => "Mixture",
+        };
+        write!(f, "{}", output)
+    }
+}
+
+/// The per-variant effect the threshold rule's cutoff applies to, which is
+/// also the x axis of the volcano plot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EffectScale {
+    Log2FoldChange,
+    ZStatistic,
+}
+
+impl EffectScale {
+    pub fn of(&self, variant: &Variant) -> f64 {
+        match self {
+            EffectScale::Log2FoldChange => variant.log2_fold_change,
+            EffectScale::ZStatistic => variant.statistic,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ClassificationRule {
+    /// |effect| >= `effect_cutoff` on `scale` and BH-adjusted p < `p_cutoff`;
+    /// the sign of the effect decides between deleterious and
+    /// gain-of-function.
+    Threshold {
+        effect_cutoff: f64,
+        p_cutoff: f64,
+        scale: EffectScale,
+    },
+    /// Two Gaussians, one fit to synonymous and one to nonsense effects, with
+    /// the mixing weight estimated over the remaining variants. A variant is
+    /// deleterious when its posterior for the nonsense component reaches
+    /// `posterior_cutoff`, and gain-of-function when it sits more than `gain_z`
+    /// synonymous standard deviations away on the other side.
+    Mixture { posterior_cutoff: f64, gain_z: f64 },
+}
+
+impl Default for ClassificationRule {
+    fn default() -> Self {
+        ClassificationRule::Threshold {
+            effect_cutoff: DEFAULT_EFFECT_CUTOFF,
+            p_cutoff: DEFAULT_P_CUTOFF,
+            scale: EffectScale::Log2FoldChange,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct ClassCounts {
+    pub deleterious: usize,
+    pub neutral: usize,
+    pub gain_of_function: usize,
+}
+
+impl ClassCounts {
+    pub fn add(&mut self, class: VariantClass) {
+        match class {
+            VariantClass::Deleterious => self.deleterious += 1,
+            VariantClass::Neutral => self.neutral += 1,
+            VariantClass::GainOfFunction => self.gain_of_function += 1,
+        }
+    }
+
+    pub fn merge(&mut self, other: &ClassCounts) {
+        self.deleterious += other.deleterious;
+        self.neutral += other.neutral;
+        self.gain_of_function += other.gain_of_function;
+    }
+
+    pub fn get(&self, class: VariantClass) -> usize {
+        match class {
+            VariantClass::Deleterious => self.deleterious,
+            VariantClass::Neutral => self.neutral,
+            VariantClass::GainOfFunction => self.gain_of_function,
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.deleterious + self.neutral + self.gain_of_function
+    }
+
+    /// The non-neutral class with the most calls, falling back to neutral.
+    pub fn dominant(&self) -> VariantClass {
+        if self.deleterious == 0 && self.gain_of_function == 0 {
+            VariantClass::Neutral
+        } else if self.deleterious >= self.gain_of_function {
+            VariantClass::Deleterious
+        } else {
+            VariantClass::GainOfFunction
+        }
+    }
+}
+
+/// Benjamini-Hochberg adjusted p values, returned in input order.
+pub fn benjamini_hochberg(p_values: &[f64]) -> Vec<f64> {
+    let n = p_values.len();
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|&a, &b| p_values[a].total_cmp(&p_values[b]));
+    let mut adjusted = vec![0.0; n];
+    let mut running_min = 1.0_f64;
+    for (rank, &idx) in order.iter().enumerate().rev() {
+        let value = p_values[idx] * n as f64 / (rank + 1) as f64;
+        running_min = running_min.min(value);
+        adjusted[idx] = running_min;
+    }
+    adjusted
+}
+
+/// Largest raw p value still significant after BH adjustment at `cutoff`.
+pub fn raw_p_cutoff(p_values: &[f64], cutoff: f64) -> Option<f64> {
+    p_values
+        .iter()
+        .zip(benjamini_hochberg(p_values))
+        .filter(|(_, adjusted)| *adjusted < cutoff)
+        .map(|(p, _)| *p)
+        .max_by(f64::total_cmp)
+}
+
+/// Classify every variant of a protein/condition. The whole condition has to
+/// be passed in, since both rules depend on the full set (multiple testing
+/// correction, mixture fit). Labels are returned in input order.
+pub fn classify(
+    variants: &[Variant],
+    rule: ClassificationRule,
+    sequence: Option<&str>,
+) -> anyhow::Result<Vec<VariantClass>> {
+    match rule {
+        ClassificationRule::Threshold {
+            effect_cutoff,
+            p_cutoff,
+            scale,
+        } => {
+            let p_values: Vec<f64> = variants.iter().map(|v| v.p_value).collect();
+            let adjusted = benjamini_hochberg(&p_values);
+            Ok(variants
+                .iter()
+                .zip(adjusted)
+                .map(|(variant, p_adj)| {
+                    let effect = scale.of(variant);
+                    if p_adj >= p_cutoff || effect.abs() < effect_cutoff {
+                        VariantClass::Neutral
+                    } else if effect < 0.0 {
+                        VariantClass::Deleterious
+                    } else {
+                        VariantClass::GainOfFunction
+                    }
+                })
+                .collect())
+        }
+        ClassificationRule::Mixture {
+            posterior_cutoff,
+            gain_z,
+        } => {
+            let Some(sequence) = sequence else {
+                bail!(
+                    "the mixture classifier needs the protein sequence to find synonymous variants"
+                )
+            };
+            let mut synonymous = vec![];
+            let mut nonsense = vec![];
+            let mut other = vec![];
+            for variant in variants {
+                match MutationType::of(&variant.aa, wild_type_at(sequence, variant.pos)) {
+                    MutationType::Synonymous => synonymous.push(variant.log2_fold_change),
+                    MutationType::Nonsense => nonsense.push(variant.log2_fold_change),
+                    MutationType::Missense => other.push(variant.log2_fold_change),
+                }
+            }
+            let neutral = Gaussian::fit(&synonymous)?;
+            let deleterious = Gaussian::fit(&nonsense)?;
+            let weight = mixing_weight(&other, &neutral, &deleterious);
+            // Gain of function is on the opposite side of the neutral mode
+            // from the nonsense mode.
+            let direction = (neutral.mean - deleterious.mean).signum();
+            Ok(variants
+                .iter()
+                .map(|variant| {
+                    let x = variant.log2_fold_change;
+                    let p_del = weight * deleterious.pdf(x);
+                    let p_neu = (1.0 - weight) * neutral.pdf(x);
+                    let posterior = if p_del + p_neu > 0.0 {
+                        p_del / (p_del + p_neu)
+                    } else {
+                        // Far out in both tails; take whichever mode is closer.
+                        f64::from((x - deleterious.mean).abs() < (x - neutral.mean).abs())
+                    };
+                    if posterior >= posterior_cutoff {
+                        VariantClass::Deleterious
+                    } else if direction * (x - neutral.mean) > gain_z * neutral.sd {
+                        VariantClass::GainOfFunction
+                    } else {
+                        VariantClass::Neutral
+                    }
+                })
+                .collect())
+        }
+    }
+}
+
+/// Per-position class counts for an already classified set of variants.
+pub fn counts_by_position(
+    variants: &[Variant],
+    classes: &[VariantClass],
+) -> BTreeMap<i32, ClassCounts> {
+    let mut counts: BTreeMap<i32, ClassCounts> = BTreeMap::new();
+    for (variant, class) in variants.iter().zip(classes) {
+        counts.entry(variant.pos).or_default().add(*class);
+    }
+    counts
+}
+
+struct Gaussian {
+    mean: f64,
+    sd: f64,
+}
+
+impl Gaussian {
+    fn fit(values: &[f64]) -> anyhow::Result<Self> {
+        if values.len() < 2 {
+            bail!("need at least two reference variants to fit a component");
+        }
+        let n = values.len() as f64;
+        let mean = values.iter().sum::<f64>() / n;
+        let var = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0);
+        if var <= 0.0 || !var.is_finite() {
+            bail!("reference variants have no spread");
+        }
+        Ok(Self {
+            mean,
+            sd: var.sqrt(),
+        })
+    }
+
+    fn pdf(&self, x: f64) -> f64 {
+        let z = (x - self.mean) / self.sd;
+        (-0.5 * z * z).exp() / (self.sd * (2.0 * std::f64::consts::PI).sqrt())
+    }
+}
+
+/// EM estimate of the weight of the deleterious component, keeping both
+/// component shapes fixed.
+fn mixing_weight(values: &[f64], neutral: &Gaussian, deleterious: &Gaussian) -> f64 {
+    if values.is_empty() {
+        return 0.5;
+    }
+    let mut weight = 0.5;
+    for _ in 0..100 {
+        let responsibility: f64 = values
+            .iter()
+            .map(|&x| {
+                let d = weight * deleterious.pdf(x);
+                let n = (1.0 - weight) * neutral.pdf(x);
+                if d + n > 0.0 {
+                    d / (d + n)
+                } else {
+                    0.0
+                }
+            })
+            .sum();
+        let next = (responsibility / values.len() as f64).clamp(1e-6, 1.0 - 1e-6);
+        if (next - weight).abs() < 1e-9 {
+            return next;
+        }
+        weight = next;
+    }
+    weight
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEQUENCE: &str = "ACDEFGHIKLMNPQRSTVWY";
+
+    fn variant(pos: i32, aa: &str, log2_fold_change: f64, p_value: f64) -> Variant {
+        Variant {
+            id: Some(pos),
+            chunk: 1,
+            pos,
+            condition: "c1".to_string(),
+            aa: aa.to_string(),
+            log2_fold_change,
+            log2_std_error: 0.1,
+            statistic: log2_fold_change / 0.1,
+            p_value,
+            version: "v1".to_string(),
+            protein: "P1".to_string(),
+            created_on: Default::default(),
+        }
+    }
+
+    fn assert_close(actual: &[f64], expected: &[f64]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-12, "{actual:?} != {expected:?}");
+        }
+    }
+
+    #[test]
+    fn benjamini_hochberg_adjusts_in_input_order() {
+        assert_close(
+            &benjamini_hochberg(&[0.01, 0.04, 0.03, 0.005]),
+            &[0.02, 0.04, 0.04, 0.02],
+        );
+        // 0.02 * 3 would be 0.06, but no adjusted p may exceed the next one
+        assert_close(
+            &benjamini_hochberg(&[0.5, 0.021, 0.02]),
+            &[0.5, 0.0315, 0.0315],
+        );
+        // Tied p values get the same adjusted p
+        assert_close(
+            &benjamini_hochberg(&[0.01, 0.5, 0.01]),
+            &[0.015, 0.5, 0.015],
+        );
+        assert!(benjamini_hochberg(&[]).is_empty());
+
+        let p_values = [0.3, 0.001, 0.04, 0.04, 0.9, 0.012, 0.2];
+        let adjusted = benjamini_hochberg(&p_values);
+        let mut pairs: Vec<(f64, f64)> = p_values.into_iter().zip(adjusted).collect();
+        pairs.sort_by(|a, b| a.0.total_cmp(&b.0));
+        for window in pairs.windows(2) {
+            assert!(window[0].1 <= window[1].1, "{pairs:?}");
+        }
+        assert!(pairs
+            .iter()
+            .all(|(p, adjusted)| adjusted >= p && *adjusted <= 1.0));
+    }
+
+    #[test]
+    fn raw_p_cutoff_is_the_largest_significant_p() {
+        assert_eq!(raw_p_cutoff(&[0.5, 0.021, 0.02], 0.05), Some(0.021));
+        assert_eq!(raw_p_cutoff(&[0.5, 0.9], 0.05), None);
+        assert_eq!(raw_p_cutoff(&[], 0.05), None);
+    }
+
+    #[test]
+    fn threshold_rule_follows_the_sign_of_the_effect() {
+        let variants = [
+            variant(1, "A", -2.0, 0.001),
+            variant(2, "A", 2.0, 0.001),
+            variant(3, "A", 0.5, 0.001),
+            variant(4, "A", -2.0, 0.9),
+        ];
+        let classes = classify(&variants, ClassificationRule::default(), None).unwrap();
+        assert_eq!(
+            classes,
+            [
+                VariantClass::Deleterious,
+                VariantClass::GainOfFunction,
+                VariantClass::Neutral,
+                VariantClass::Neutral,
+            ]
+        );
+
+        // On the z scale the cutoff applies to the statistic, and its sign
+        // decides instead
+        let mut on_z = variants.clone();
+        on_z[0].statistic = -1.0;
+        on_z[2].statistic = 5.0;
+        on_z[3].statistic = -5.0;
+        let rule = ClassificationRule::Threshold {
+            effect_cutoff: 2.0,
+            p_cutoff: DEFAULT_P_CUTOFF,
+            scale: EffectScale::ZStatistic,
+        };
+        let classes = classify(&on_z, rule, None).unwrap();
+        assert_eq!(
+            classes,
+            [
+                VariantClass::Neutral,
+                VariantClass::GainOfFunction,
+                VariantClass::GainOfFunction,
+                VariantClass::Neutral,
+            ]
+        );
+    }
+
+    #[test]
+    fn mixture_needs_sequence_and_references() {
+        let rule = ClassificationRule::Mixture {
+            posterior_cutoff: DEFAULT_POSTERIOR_CUTOFF,
+            gain_z: DEFAULT_GAIN_Z,
+        };
+        let variants = [
+            variant(1, "A", 0.0, 0.5),
+            variant(2, "C", 0.1, 0.5),
+            variant(1, "*", -3.0, 0.001),
+            variant(2, "*", -2.8, 0.001),
+        ];
+        assert!(classify(&variants, rule, Some(SEQUENCE)).is_ok());
+        let error = classify(&variants, rule, None).unwrap_err();
+        assert!(error.to_string().contains("sequence"), "{error}");
+        // One synonymous reference, then one nonsense reference
+        assert!(classify(&variants[1..], rule, Some(SEQUENCE)).is_err());
+        assert!(classify(&variants[..3], rule, Some(SEQUENCE)).is_err());
+    }
+
+    #[test]
+    fn mixture_calls_nonsense_like_variants_deleterious() {
+        let spread = |i: usize| (i % 5) as f64 - 2.0;
+        let mut variants = vec![];
+        for (i, wild_type) in SEQUENCE.chars().enumerate() {
+            let pos = i as i32 + 1;
+            variants.push(variant(pos, &wild_type.to_string(), spread(i) * 0.05, 0.5));
+            variants.push(variant(pos, "*", -3.0 + spread(i) * 0.1, 0.001));
+        }
+        let missense = [
+            variant(3, "A", -2.9, 0.001),
+            variant(4, "A", 0.05, 0.5),
+            variant(5, "A", 3.0, 0.001),
+        ];
+        variants.extend(missense);
+        let rule = ClassificationRule::Mixture {
+            posterior_cutoff: DEFAULT_POSTERIOR_CUTOFF,
+            gain_z: DEFAULT_GAIN_Z,
+        };
+        let classes = classify(&variants, rule, Some(SEQUENCE)).unwrap();
+
+        let (references, calls) = classes.split_at(2 * SEQUENCE.len());
+        for pair in references.chunks(2) {
+            assert_eq!(pair, [VariantClass::Neutral, VariantClass::Deleterious]);
+        }
+        assert_eq!(
+            calls,
+            [
+                VariantClass::Deleterious,
+                VariantClass::Neutral,
+                VariantClass::GainOfFunction,
+            ]
+        );
+    }
+}
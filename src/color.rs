@@ -0,0 +1,224 @@
+use std::fmt;
+
+use crate::NO_DATA;
+
+/// An sRGB color, rendered as `#RRGGBB`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const WHITE: Rgb = Rgb::new(0xFF, 0xFF, 0xFF);
+    pub const RED: Rgb = Rgb::new(0xFF, 0x00, 0x00);
+    pub const BLUE: Rgb = Rgb::new(0x00, 0x00, 0xFF);
+    /// Same grey as `.dms-cell-no-data` and the canvas heatmap's empty cells.
+    pub const MISSING: Rgb = Rgb::new(0xF1, 0xF1, 0xF1);
+
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Rgb { r, g, b }
+    }
+
+    pub fn hex(&self) -> String {
+        self.to_string()
+    }
+
+    /// CSS functional notation, `rgb(r,g,b)`.
+    pub fn css(&self) -> String {
+        format!("rgb({},{},{})", self.r, self.g, self.b)
+    }
+}
+
+impl fmt::Display for Rgb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:02X}{:02X}{:02X}", self.r, self.g, self.b)
+    }
+}
+
+/// Diverging red–white–blue scale for signed scores: white at zero, pure red
+/// at `-max_abs` and below, pure blue at `max_abs` and above. NaN is missing
+/// data and gets `Rgb::MISSING`. A scale whose `max_abs` is zero, negative or
+/// not finite cannot tell values apart and paints every number white.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DivergingScale {
+    max_abs: f64,
+}
+
+impl DivergingScale {
+    pub fn new(max_abs: f64) -> Self {
+        let max_abs = if max_abs.is_finite() && max_abs > 0.0 {
+            max_abs
+        } else {
+            0.0
+        };
+        DivergingScale { max_abs }
+    }
+
+    pub fn max_abs(&self) -> f64 {
+        self.max_abs
+    }
+
+    /// Where `value` falls on the scale, clamped to `[-1, 1]`; `None` for NaN.
+    pub fn normalize(&self, value: f64) -> Option<f64> {
+        if value.is_nan() {
+            None
+        } else if self.max_abs == 0.0 {
+            Some(0.0)
+        } else {
+            Some((value / self.max_abs).clamp(-1.0, 1.0))
+        }
+    }
+
+    pub fn color(&self, value: f64) -> Rgb {
+        match self.normalize(value) {
+            Some(normalized) => Self::color_at(normalized),
+            None => Rgb::MISSING,
+        }
+    }
+
+    /// Color of a normalized position; `normalized` is clamped to `[-1, 1]`.
+    pub fn color_at(normalized: f64) -> Rgb {
+        if normalized.is_nan() {
+            return Rgb::MISSING;
+        }
+        let normalized = normalized.clamp(-1.0, 1.0);
+        // Fade the two channels the hue does not use from full to none as the
+        // value moves away from zero.
+        let fade = ((1.0 - normalized.abs()) * 255.0).round() as u8;
+        if normalized < 0.0 {
+            Rgb::new(0xFF, fade, fade)
+        } else {
+            Rgb::new(fade, fade, 0xFF)
+        }
+    }
+
+    /// `steps + 1` colors evenly spaced from `-max_abs` to `max_abs`, for
+    /// clients that index into a palette instead of receiving colors.
+    pub fn palette(steps: u8) -> Vec<Rgb> {
+        (0..=steps)
+            .map(|step| Self::color_at(f64::from(step) / f64::from(steps) * 2.0 - 1.0))
+            .collect()
+    }
+
+    /// Index of the closest `palette(steps)` entry, or `NO_DATA` for NaN.
+    pub fn palette_index(&self, value: f64, steps: u8) -> u8 {
+        match self.normalize(value) {
+            Some(normalized) => ((normalized + 1.0) / 2.0 * f64::from(steps)).round() as u8,
+            None => NO_DATA,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    /// How far a color is from white, in the channels the hue fades.
+    fn depth(color: Rgb) -> u8 {
+        0xFF - color.g
+    }
+
+    fn any_value() -> impl Strategy<Value = f64> {
+        prop_oneof![
+            -1e6..1e6f64,
+            Just(0.0),
+            Just(-0.0),
+            Just(f64::INFINITY),
+            Just(f64::NEG_INFINITY),
+            Just(f64::MAX),
+            Just(f64::MIN),
+        ]
+    }
+
+    #[test]
+    fn endpoints_are_fixed() {
+        let scale = DivergingScale::new(2.5);
+        assert_eq!(scale.color(-2.5), Rgb::RED);
+        assert_eq!(scale.color(0.0), Rgb::WHITE);
+        assert_eq!(scale.color(2.5), Rgb::BLUE);
+        assert_eq!(scale.color(-0.0), Rgb::WHITE);
+        assert_eq!(scale.color(f64::NEG_INFINITY), Rgb::RED);
+        assert_eq!(scale.color(f64::INFINITY), Rgb::BLUE);
+        assert_eq!(Rgb::RED.hex(), "#FF0000");
+        assert_eq!(Rgb::new(0x4A, 0x6F, 0xA5).css(), "rgb(74,111,165)");
+    }
+
+    #[test]
+    fn palette_matches_colors() {
+        let scale = DivergingScale::new(3.0);
+        let palette = DivergingScale::palette(254);
+        assert_eq!(palette.len(), 255);
+        assert_eq!(palette[0], Rgb::RED);
+        assert_eq!(palette[127], Rgb::WHITE);
+        assert_eq!(palette[254], Rgb::BLUE);
+        assert_eq!(scale.palette_index(-100.0, 254), 0);
+        assert_eq!(scale.palette_index(100.0, 254), 254);
+        assert_eq!(scale.palette_index(f64::NAN, 254), NO_DATA);
+    }
+
+    proptest! {
+        #[test]
+        fn nan_is_missing_on_every_scale(max_abs in any_value()) {
+            let scale = DivergingScale::new(max_abs);
+            prop_assert_eq!(scale.color(f64::NAN), Rgb::MISSING);
+            prop_assert_eq!(scale.palette_index(f64::NAN, 254), NO_DATA);
+        }
+
+        #[test]
+        fn degenerate_scales_are_white(
+            max_abs in prop_oneof![Just(0.0), Just(f64::NAN), Just(f64::INFINITY), -1e6..=0.0f64],
+            value in any_value(),
+        ) {
+            prop_assert_eq!(DivergingScale::new(max_abs).color(value), Rgb::WHITE);
+        }
+
+        #[test]
+        fn out_of_range_values_saturate(max_abs in 1e-6..1e6f64, excess in 1.0..1e6f64) {
+            let scale = DivergingScale::new(max_abs);
+            prop_assert_eq!(scale.color(-max_abs * excess), Rgb::RED);
+            prop_assert_eq!(scale.color(max_abs * excess), Rgb::BLUE);
+        }
+
+        #[test]
+        fn symmetric_around_zero(max_abs in 1e-6..1e6f64, value in any_value()) {
+            let scale = DivergingScale::new(max_abs);
+            let below = scale.color(-value.abs());
+            let above = scale.color(value.abs());
+            prop_assert_eq!((below.r, below.g, below.b), (above.b, above.g, above.r));
+        }
+
+        #[test]
+        fn deeper_with_distance_from_zero(
+            max_abs in 1e-6..1e6f64,
+            a in any_value(),
+            b in any_value(),
+        ) {
+            let scale = DivergingScale::new(max_abs);
+            let (near, far) = if a.abs() <= b.abs() { (a, b) } else { (b, a) };
+            prop_assert!(depth(scale.color(near)) <= depth(scale.color(far)));
+        }
+
+        #[test]
+        fn hue_follows_sign(max_abs in 1e-6..1e6f64, value in any_value()) {
+            let color = DivergingScale::new(max_abs).color(value);
+            if value < 0.0 {
+                prop_assert_eq!(color.r, 0xFF);
+                prop_assert_eq!(color.g, color.b);
+            } else {
+                prop_assert_eq!(color.b, 0xFF);
+                prop_assert_eq!(color.r, color.g);
+            }
+        }
+
+        #[test]
+        fn palette_index_is_monotonic(max_abs in 1e-6..1e6f64, a in any_value(), b in any_value()) {
+            let scale = DivergingScale::new(max_abs);
+            let (low, high) = if a <= b { (a, b) } else { (b, a) };
+            prop_assert!(scale.palette_index(low, 254) <= scale.palette_index(high, 254));
+        }
+    }
+}
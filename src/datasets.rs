@@ -0,0 +1,225 @@
+//! Deleting, archiving and restoring data a protein, a condition, a version
+//! or an upload batch at a time. Archived variants move to
+//! `archived_variant`, so every view, summary and cache treats them as gone
+//! until they are restored with their ids. Archiving a whole protein only
+//! marks it: it drops out of the protein list with its variants in place.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use crate::{empty_string_as_none, Variant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    /// Removes shown and archived variants for good; a whole protein goes
+    /// with its upload batches.
+    Delete,
+    /// Hides variants, or a whole protein, until they are restored.
+    Archive,
+    Restore,
+}
+
+impl Operation {
+    pub const ALL: [Operation; 3] = [Operation::Delete, Operation::Archive, Operation::Restore];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Operation::Delete => "delete",
+            Operation::Archive => "archive",
+            Operation::Restore => "restore",
+        }
+    }
+
+    pub fn past_tense(&self) -> &'static str {
+        match self {
+            Operation::Delete => "deleted",
+            Operation::Archive => "archived",
+            Operation::Restore => "restored",
+        }
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Operation {
+    type Err = String;
+
+    fn from_str(operation: &str) -> Result<Self, String> {
+        Operation::ALL
+            .into_iter()
+            .find(|candidate| candidate.as_str() == operation)
+            .ok_or_else(|| {
+                format!("unknown operation {operation:?}, expected delete, archive or restore")
+            })
+    }
+}
+
+/// The data of one protein an operation applies to. Each of condition,
+/// version and batch narrows it down; with none of them it is the whole
+/// protein.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Selection {
+    pub protein: String,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub condition: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub version: Option<String>,
+    /// Upload batch id.
+    #[serde(default)]
+    pub batch: Option<i32>,
+}
+
+impl Selection {
+    pub fn whole_protein(&self) -> bool {
+        self.condition.is_none() && self.version.is_none() && self.batch.is_none()
+    }
+
+    /// Exactly one upload batch, which a delete then removes too.
+    pub fn whole_batch(&self) -> bool {
+        self.batch.is_some() && self.condition.is_none() && self.version.is_none()
+    }
+
+    /// Whether `variant`, loaded as upload batch `batch`, is selected.
+    pub fn matches(&self, variant: &Variant, batch: Option<i32>) -> bool {
+        variant.protein == self.protein
+            && self
+                .condition
+                .as_ref()
+                .is_none_or(|condition| variant.condition == *condition)
+            && self
+                .version
+                .as_ref()
+                .is_none_or(|version| variant.version == *version)
+            && self.batch.is_none_or(|id| batch == Some(id))
+    }
+}
+
+impl fmt::Display for Selection {
+    /// E.g. "condition c1, version v2 of GLP1R".
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = vec![];
+        if let Some(condition) = &self.condition {
+            parts.push(format!("condition {condition}"));
+        }
+        if let Some(version) = &self.version {
+            parts.push(format!("version {version}"));
+        }
+        if let Some(batch) = self.batch {
+            parts.push(format!("upload batch {batch}"));
+        }
+        if parts.is_empty() {
+            write!(f, "protein {}", self.protein)
+        } else {
+            write!(f, "{} of {}", parts.join(", "), self.protein)
+        }
+    }
+}
+
+/// Variants a selection covers: the dry run of every operation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Affected {
+    /// Variants the viewer shows.
+    pub variants: i64,
+    pub archived: i64,
+}
+
+impl Affected {
+    /// How many variants `operation` deletes, hides or brings back.
+    pub fn by(&self, operation: Operation) -> i64 {
+        match operation {
+            Operation::Delete => self.variants + self.archived,
+            Operation::Archive => self.variants,
+            Operation::Restore => self.archived,
+        }
+    }
+}
+
+/// Shown and archived variants of one condition and version.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, FromRow)]
+pub struct Dataset {
+    pub condition: String,
+    pub version: String,
+    pub variants: i64,
+    pub archived: i64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variant(condition: &str, version: &str) -> Variant {
+        Variant {
+            id: Some(1),
+            chunk: 1,
+            pos: 1,
+            condition: condition.to_string(),
+            aa: "A".to_string(),
+            log2_fold_change: 0.0,
+            log2_std_error: 0.1,
+            statistic: 0.0,
+            p_value: 1.0,
+            version: version.to_string(),
+            protein: "P1".to_string(),
+            created_on: Default::default(),
+        }
+    }
+
+    #[test]
+    fn selections_narrow_down_a_protein() {
+        let protein = Selection {
+            protein: "P1".to_string(),
+            ..Selection::default()
+        };
+        assert!(protein.whole_protein());
+        assert!(protein.matches(&variant("c1", "v1"), None));
+        assert_eq!(protein.to_string(), "protein P1");
+
+        let version = Selection {
+            condition: Some("c1".to_string()),
+            version: Some("v2".to_string()),
+            ..protein.clone()
+        };
+        assert!(!version.whole_protein() && !version.whole_batch());
+        assert!(version.matches(&variant("c1", "v2"), Some(3)));
+        assert!(!version.matches(&variant("c1", "v1"), Some(3)));
+        assert!(!version.matches(&variant("c2", "v2"), Some(3)));
+        assert_eq!(version.to_string(), "condition c1, version v2 of P1");
+
+        let batch = Selection {
+            batch: Some(3),
+            ..protein
+        };
+        assert!(batch.whole_batch());
+        assert!(batch.matches(&variant("c2", "v1"), Some(3)));
+        assert!(!batch.matches(&variant("c2", "v1"), Some(4)));
+        // Variants from before upload batches belong to none
+        assert!(!batch.matches(&variant("c2", "v1"), None));
+    }
+
+    #[test]
+    fn forms_leave_out_empty_fields() {
+        let selection: Selection = serde_json::from_value(serde_json::json!({
+            "protein": "P1",
+            "condition": "",
+            "version": "v1",
+        }))
+        .unwrap();
+        assert_eq!(selection.condition, None);
+        assert_eq!(selection.version.as_deref(), Some("v1"));
+        let affected = Affected {
+            variants: 5,
+            archived: 2,
+        };
+        assert_eq!(affected.by(Operation::Delete), 7);
+        assert_eq!(affected.by(Operation::Archive), 5);
+        assert_eq!(affected.by(Operation::Restore), 2);
+    }
+}
@@ -0,0 +1,186 @@
+use axum::{
+    extract::multipart::MultipartError,
+    http::{HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use maud::html;
+use tracing::{error, warn};
+
+/// Element HTMX swaps error fragments into, whatever the request targeted.
+pub const ERROR_TARGET: &str = "#error-message";
+
+/// Errors a handler can return. HTML routes render them as a fragment for
+/// `ERROR_TARGET`; API routes wrap them in `ApiError` to get JSON instead.
+#[derive(Debug)]
+pub enum AppError {
+    /// A query parameter or form field is missing or malformed.
+    BadRequest(String),
+    /// The request needs a signed-in user and has none.
+    Unauthorized(String),
+    /// The signed-in user may not do this.
+    Forbidden(String),
+    /// The protein, condition or variant asked for does not exist.
+    NotFound(String),
+    /// A protein or condition name that isn't in the database, with the
+    /// closest names that are.
+    Unknown {
+        what: &'static str,
+        name: String,
+        suggestions: Vec<String>,
+    },
+    /// The request is well formed but the stored data cannot answer it, e.g.
+    /// substitutions for a protein without a sequence.
+    Unprocessable(String),
+    Database(sqlx::Error),
+    Internal(anyhow::Error),
+}
+
+impl AppError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::NotFound(_) | AppError::Unknown { .. } => StatusCode::NOT_FOUND,
+            AppError::Unprocessable(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::Database(sqlx::Error::RowNotFound) => StatusCode::NOT_FOUND,
+            AppError::Database(_) | AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// What the client gets to see; server-side details only go to the log.
+    pub fn message(&self) -> String {
+        match self {
+            AppError::BadRequest(message)
+            | AppError::Unauthorized(message)
+            | AppError::Forbidden(message)
+            | AppError::NotFound(message)
+            | AppError::Unprocessable(message) => message.clone(),
+            AppError::Unknown {
+                what,
+                name,
+                suggestions,
+            } => match suggestions.as_slice() {
+                [] => format!("unknown {what} \"{name}\""),
+                _ => format!(
+                    "unknown {what} \"{name}\", did you mean {}?",
+                    suggestions.join(", ")
+                ),
+            },
+            AppError::Database(sqlx::Error::RowNotFound) => "Not found".to_string(),
+            AppError::Database(_) => "The database could not answer this request".to_string(),
+            AppError::Internal(_) => "Something went wrong on the server".to_string(),
+        }
+    }
+
+    pub fn suggestions(&self) -> &[String] {
+        match self {
+            AppError::Unknown { suggestions, .. } => suggestions,
+            _ => &[],
+        }
+    }
+
+    fn log(&self) {
+        let status = self.status();
+        if status.is_server_error() {
+            error!("{status}: {self}");
+        } else {
+            warn!("{status}: {self}");
+        }
+    }
+}
+
+impl std::fmt::Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AppError::BadRequest(message) => write!(f, "bad request: {}", message),
+            AppError::Unauthorized(message) => write!(f, "unauthorized: {}", message),
+            AppError::Forbidden(message) => write!(f, "forbidden: {}", message),
+            AppError::NotFound(message) => write!(f, "not found: {}", message),
+            AppError::Unknown { what, name, .. } => write!(f, "unknown {}: {}", what, name),
+            AppError::Unprocessable(message) => write!(f, "unprocessable: {}", message),
+            AppError::Database(err) => write!(f, "database error: {}", err),
+            AppError::Internal(err) => write!(f, "internal error: {:#}", err),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl From<sqlx::Error> for AppError {
+    fn from(err: sqlx::Error) -> Self {
+        AppError::Database(err)
+    }
+}
+
+impl From<MultipartError> for AppError {
+    fn from(err: MultipartError) -> Self {
+        AppError::BadRequest(format!("could not read the upload: {}", err.body_text()))
+    }
+}
+
+impl From<anyhow::Error> for AppError {
+    fn from(err: anyhow::Error) -> Self {
+        AppError::Internal(err)
+    }
+}
+
+impl From<serde_json::Error> for AppError {
+    fn from(err: serde_json::Error) -> Self {
+        AppError::Internal(err.into())
+    }
+}
+
+impl From<csv::Error> for AppError {
+    fn from(err: csv::Error) -> Self {
+        AppError::Internal(err.into())
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        self.log();
+        let status = self.status();
+        let mut response = (
+            status,
+            html!(
+                div class="error-message" role="alert" {
+                    strong { (status.canonical_reason().unwrap_or("Error")) } " "
+                    (self.message())
+                }
+            ),
+        )
+            .into_response();
+        let headers = response.headers_mut();
+        headers.insert("HX-Retarget", HeaderValue::from_static(ERROR_TARGET));
+        headers.insert("HX-Reswap", HeaderValue::from_static("innerHTML"));
+        response
+    }
+}
+
+/// `AppError` for JSON routes: `{"error": message, "status": code}`, plus
+/// `suggestions` for unknown names.
+#[derive(Debug)]
+pub struct ApiError(pub AppError);
+
+impl<E: Into<AppError>> From<E> for ApiError {
+    fn from(err: E) -> Self {
+        ApiError(err.into())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        self.0.log();
+        let status = self.0.status();
+        let mut body = serde_json::json!({
+            "error": self.0.message(),
+            "status": status.as_u16(),
+        });
+        if !self.0.suggestions().is_empty() {
+            body["suggestions"] = serde_json::json!(self.0.suggestions());
+        }
+        (status, Json(body)).into_response()
+    }
+}
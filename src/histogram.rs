@@ -0,0 +1,178 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{wild_type_at, MutationType, Paint, Variant};
+
+pub const DEFAULT_BINS: usize = 50;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HistogramSeries {
+    pub mutation_type: MutationType,
+    pub counts: Vec<usize>,
+    pub total: usize,
+}
+
+/// Binned counts of one metric, one series per mutation type. `edges` has one
+/// more entry than each `counts`; the last bin is closed on the right.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Histogram {
+    pub metric: String,
+    pub edges: Vec<f64>,
+    pub series: Vec<HistogramSeries>,
+}
+
+impl Histogram {
+    /// Non-finite values are left out. Synonymous variants are only told
+    /// apart from missense ones when `sequence` is known.
+    pub fn compute(
+        variants: &[Variant],
+        paint: Paint,
+        sequence: Option<&str>,
+        bins: usize,
+    ) -> Self {
+        let bins = bins.max(1);
+        let values: Vec<(MutationType, f64)> = variants
+            .iter()
+            .map(|variant| {
+                let wild_type = sequence.and_then(|sequence| wild_type_at(sequence, variant.pos));
+                (
+                    MutationType::of(&variant.aa, wild_type),
+                    paint.value_of(variant),
+                )
+            })
+            .filter(|(_, value)| value.is_finite())
+            .collect();
+        let (min, max) = values
+            .iter()
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), (_, v)| {
+                (min.min(*v), max.max(*v))
+            });
+        let (min, max) = if values.is_empty() {
+            (0.0, 1.0)
+        } else if min == max {
+            (min - 0.5, max + 0.5)
+        } else {
+            (min, max)
+        };
+        let width = (max - min) / bins as f64;
+        let edges = (0..=bins).map(|i| min + width * i as f64).collect();
+        let series = [
+            MutationType::Missense,
+            MutationType::Nonsense,
+            MutationType::Synonymous,
+        ]
+        .into_iter()
+        .map(|mutation_type| {
+            let mut counts = vec![0; bins];
+            for (_, value) in values.iter().filter(|(t, _)| *t == mutation_type) {
+                let bin = (((value - min) / width) as usize).min(bins - 1);
+                counts[bin] += 1;
+            }
+            HistogramSeries {
+                mutation_type,
+                total: counts.iter().sum(),
+                counts,
+            }
+        })
+        .collect();
+        let metric = match paint {
+            Paint::Classification => Paint::Log2FoldChange,
+            paint => paint,
+        };
+        Self {
+            metric: metric.to_string(),
+            edges,
+            series,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variant(pos: i32, aa: &str, log2_fold_change: f64) -> Variant {
+        Variant {
+            id: None,
+            chunk: 1,
+            pos,
+            condition: "c1".to_string(),
+            aa: aa.to_string(),
+            log2_fold_change,
+            log2_std_error: 0.1,
+            statistic: log2_fold_change / 0.1,
+            p_value: 0.5,
+            version: "v1".to_string(),
+            protein: "P1".to_string(),
+            created_on: Default::default(),
+        }
+    }
+
+    fn counts(histogram: &Histogram, mutation_type: MutationType) -> &[usize] {
+        let series = histogram
+            .series
+            .iter()
+            .find(|series| series.mutation_type == mutation_type)
+            .unwrap();
+        &series.counts
+    }
+
+    #[test]
+    fn edges_split_the_range_evenly() {
+        let variants = [
+            variant(1, "A", -1.0),
+            variant(1, "C", 0.0),
+            variant(2, "A", 0.5),
+            variant(2, "C", 1.0),
+            variant(1, "*", -1.0),
+            variant(1, "M", 0.2),
+        ];
+        let histogram = Histogram::compute(&variants, Paint::Log2FoldChange, Some("MK"), 4);
+        assert_eq!(histogram.metric, "log2_fold_change");
+        assert_eq!(histogram.edges, [-1.0, -0.5, 0.0, 0.5, 1.0]);
+        // Each bin is closed on the left; the last also on the right
+        assert_eq!(counts(&histogram, MutationType::Missense), [1, 0, 1, 2]);
+        assert_eq!(counts(&histogram, MutationType::Nonsense), [1, 0, 0, 0]);
+        assert_eq!(counts(&histogram, MutationType::Synonymous), [0, 0, 1, 0]);
+
+        // Without a sequence the synonymous M counts as missense
+        let histogram = Histogram::compute(&variants, Paint::Log2FoldChange, None, 4);
+        assert_eq!(counts(&histogram, MutationType::Missense), [1, 0, 2, 2]);
+    }
+
+    #[test]
+    fn non_finite_values_are_left_out() {
+        let variants = [
+            variant(1, "A", f64::NAN),
+            variant(1, "C", f64::NEG_INFINITY),
+            variant(1, "D", 0.0),
+            variant(1, "E", 2.0),
+            variant(1, "F", f64::INFINITY),
+        ];
+        let histogram = Histogram::compute(&variants, Paint::Log2FoldChange, None, 2);
+        assert_eq!(histogram.edges, [0.0, 1.0, 2.0]);
+        assert_eq!(counts(&histogram, MutationType::Missense), [1, 1]);
+        let total: usize = histogram.series.iter().map(|series| series.total).sum();
+        assert_eq!(total, 2);
+    }
+
+    #[test]
+    fn a_single_value_gets_a_unit_range_around_it() {
+        let histogram = Histogram::compute(&[variant(1, "A", 2.0)], Paint::Log2FoldChange, None, 2);
+        assert_eq!(histogram.edges, [1.5, 2.0, 2.5]);
+        assert_eq!(counts(&histogram, MutationType::Missense), [0, 1]);
+    }
+
+    #[test]
+    fn empty_input_gives_empty_bins() {
+        let histogram = Histogram::compute(&[], Paint::Classification, None, 0);
+        assert_eq!(histogram.metric, "log2_fold_change");
+        assert_eq!(histogram.edges, [0.0, 1.0]);
+        assert!(histogram.edges.iter().all(|edge| edge.is_finite()));
+        for series in &histogram.series {
+            assert_eq!(
+                (series.counts.as_slice(), series.total),
+                ([0].as_slice(), 0)
+            );
+        }
+    }
+}
@@ -0,0 +1,497 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Read;
+use std::time::Instant;
+
+use chrono::{NaiveDateTime, Utc};
+use sha2::{Digest, Sha256};
+use sqlx::types::Json;
+use sqlx::{PgConnection, PgPool, QueryBuilder, Sqlite, SqliteConnection, SqlitePool};
+use tracing::info;
+
+use crate::audit::{self, Actor, Change, UploadBatch};
+use crate::auth;
+use crate::metrics::{TimedQuery, METRICS};
+use crate::Variant;
+
+/// Rows per `INSERT` when a caller does not ask for progress.
+pub const DEFAULT_BATCH_SIZE: usize = 10_000;
+
+/// Rows per SQLite `INSERT`, keeping the twelve bound values per row under
+/// SQLite's limit of 32766 variables per statement.
+const SQLITE_ROWS_PER_STATEMENT: usize = 2_000;
+
+/// Longest file name an upload batch keeps.
+const MAX_FILE_NAME_LENGTH: usize = 255;
+
+/// Variant fields a file column can fill and the headers they are read from,
+/// as the serde aliases on `Variant` have them. Other columns are ignored.
+const COLUMNS: [(&str, &[&str]); 9] = [
+    ("chunk", &["chunk"]),
+    ("pos", &["pos"]),
+    ("condition", &["condition"]),
+    ("aa", &["aa"]),
+    ("log2_fold_change", &["log2_fold_change", "log2FoldChange"]),
+    ("log2_std_error", &["log2_std_error", "log2StdError"]),
+    ("statistic", &["statistic"]),
+    ("p_value", &["p_value", "p.value"]),
+    ("version", &["version"]),
+];
+
+/// What an upload batch records about the file its variants came from.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceFile {
+    pub name: String,
+    /// Hex SHA-256 of the file as received.
+    pub sha256: String,
+    /// Each header and the variant field it was read into, `None` for ignored
+    /// columns.
+    pub columns: BTreeMap<String, Option<String>>,
+    /// Rows that failed to parse and were skipped.
+    pub rows_rejected: u64,
+}
+
+/// `read_tsv` for a whole file, also describing it for its upload batch.
+pub fn read_source(
+    name: &str,
+    data: &[u8],
+    protein: &str,
+) -> (Vec<Variant>, Vec<csv::Error>, SourceFile) {
+    let mut headers = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .has_headers(true)
+        .from_reader(data);
+    let columns = headers
+        .headers()
+        .map(|headers| {
+            headers
+                .iter()
+                .map(|header| {
+                    let field = COLUMNS
+                        .iter()
+                        .find(|(_, names)| names.contains(&header))
+                        .map(|(field, _)| field.to_string());
+                    (header.to_string(), field)
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    let (variants, errors) = read_tsv(data, protein);
+    let source = SourceFile {
+        name: name.chars().take(MAX_FILE_NAME_LENGTH).collect(),
+        sha256: auth::hex(&Sha256::digest(data)),
+        columns,
+        rows_rejected: errors.len() as u64,
+    };
+    (variants, errors, source)
+}
+
+/// Parses a tab separated variant file for `protein`. Rows that fail to parse
+/// are returned alongside the good ones instead of aborting the whole file.
+pub fn read_tsv(reader: impl Read, protein: &str) -> (Vec<Variant>, Vec<csv::Error>) {
+    info!("reading file");
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(b'\t') // Specify TSV format
+        .has_headers(true)
+        .from_reader(reader);
+    let created_on = Utc::now().naive_utc();
+    let mut variants = vec![];
+    let mut errors = vec![];
+    for result in reader.deserialize::<Variant>() {
+        match result {
+            Ok(mut variant) => {
+                variant.protein = protein.to_string();
+                variant.created_on = created_on;
+                variants.push(variant);
+            }
+            Err(e) => {
+                errors.push(e);
+            }
+        }
+    }
+    (variants, errors)
+}
+
+pub async fn insert(
+    pool: &PgPool,
+    variants: &[Variant],
+    protein: &str,
+    source: &SourceFile,
+    actor: &Actor,
+) -> Result<UploadBatch, sqlx::Error> {
+    insert_batches(
+        pool,
+        variants,
+        protein,
+        source,
+        actor,
+        DEFAULT_BATCH_SIZE,
+        |_| {},
+    )
+    .await
+}
+
+/// Inserts `variants` for an existing `protein` in one transaction, `batch_size`
+/// rows per statement, calling `progress` with the running total after each
+/// batch. The variants belong to a new upload batch for `source`, and the
+/// ingest is recorded in the audit log in the same transaction.
+pub async fn insert_batches(
+    pool: &PgPool,
+    variants: &[Variant],
+    protein: &str,
+    source: &SourceFile,
+    actor: &Actor,
+    batch_size: usize,
+    mut progress: impl FnMut(u64),
+) -> Result<UploadBatch, sqlx::Error> {
+    info!("Inserting {} variants into db", variants.len());
+    let start = Instant::now();
+    let mut txn = pool.begin().await?;
+    let protein_id: i32 = sqlx::query_scalar!("SELECT id FROM protein WHERE name = $1", protein)
+        .fetch_one(&mut *txn) // Fetch one record, assuming the protein exists
+        .await?;
+    info!("Found protein {} at id {}", protein, protein_id);
+    // All rows go in or none do, so the batch can be stored with its count up front
+    let batch = sqlx::query!(
+        r#"
+        INSERT INTO upload_batch
+            (protein_id, file_name, sha256, rows_inserted, rows_rejected, columns, user_id, actor)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        RETURNING id, created_on
+        "#,
+        protein_id,
+        source.name,
+        source.sha256,
+        variants.len() as i64,
+        source.rows_rejected as i64,
+        Json(&source.columns) as _,
+        actor.user_id,
+        actor.name
+    )
+    .fetch_one(&mut *txn)
+    .await?;
+    let mut rows_affected = 0;
+    for chunk in variants.chunks(batch_size.max(1)) {
+        rows_affected += insert_batch(&mut txn, chunk, protein_id, batch.id)
+            .timed("insert_variants")
+            .await?;
+        progress(rows_affected);
+    }
+    let batch = upload_batch(
+        protein,
+        source,
+        actor,
+        batch.id,
+        batch.created_on,
+        rows_affected,
+    );
+    audit::record_postgres(&mut txn, actor, &Change::ingest(&batch, variants)).await?;
+    txn.commit().await?;
+    info!("rows affected {}", rows_affected);
+    METRICS.observe_ingest(rows_affected, start.elapsed());
+    Ok(batch)
+}
+
+fn upload_batch(
+    protein: &str,
+    source: &SourceFile,
+    actor: &Actor,
+    id: i32,
+    created_on: NaiveDateTime,
+    rows_inserted: u64,
+) -> UploadBatch {
+    UploadBatch {
+        id,
+        protein: protein.to_string(),
+        file_name: source.name.clone(),
+        sha256: source.sha256.clone(),
+        rows_inserted: rows_inserted as i64,
+        rows_rejected: source.rows_rejected as i64,
+        columns: Json(source.columns.clone()),
+        actor: actor.name.clone(),
+        created_on,
+    }
+}
+
+async fn insert_batch(
+    conn: &mut PgConnection,
+    variants: &[Variant],
+    protein_id: i32,
+    batch_id: i32,
+) -> Result<u64, sqlx::Error> {
+    let chunks: Vec<i32> = variants.iter().map(|v| v.chunk).collect();
+    let positions: Vec<i32> = variants.iter().map(|v| v.pos).collect();
+    let conditions: Vec<String> = variants.iter().map(|v| v.condition.clone()).collect();
+    let aas: Vec<String> = variants.iter().map(|v| v.aa.clone()).collect();
+    let log2_fold_changes: Vec<f64> = variants.iter().map(|v| v.log2_fold_change).collect();
+    let log2_std_errors: Vec<f64> = variants.iter().map(|v| v.log2_std_error).collect();
+    let statistics: Vec<f64> = variants.iter().map(|v| v.statistic).collect();
+    let p_values: Vec<f64> = variants.iter().map(|v| v.p_value).collect();
+    let versions: Vec<String> = variants.iter().map(|v| v.version.clone()).collect();
+    let created_ons: Vec<NaiveDateTime> = variants.iter().map(|v| v.created_on).collect();
+    let sql = r#"
+            INSERT INTO variant
+            (
+                chunk,
+                pos,
+                condition,
+                aa,
+                log2_fold_change,
+                log2_std_error,
+                statistic,
+                p_value,
+                version,
+                protein_id,
+                created_on,
+                batch_id
+            )
+            SELECT * FROM UNNEST(
+                $1::INT8[],
+                $2::INT8[],
+                $3::VARCHAR(30)[],
+                $4::VARCHAR(30)[],
+                $5::DOUBLE PRECISION[],
+                $6::DOUBLE PRECISION[],
+                $7::DOUBLE PRECISION[],
+                $8::DOUBLE PRECISION[],
+                $9::VARCHAR(30)[],
+                $10::INT8[],
+                $11::TIMESTAMP[],
+                $12::INT4[]
+            );
+        "#;
+    let result = sqlx::query(sql)
+        .bind(chunks)
+        .bind(positions)
+        .bind(conditions)
+        .bind(aas)
+        .bind(log2_fold_changes)
+        .bind(log2_std_errors)
+        .bind(statistics)
+        .bind(p_values)
+        .bind(versions)
+        .bind(vec![protein_id; variants.len()]) // A vector filled with the protein_id for all rows
+        .bind(created_ons)
+        .bind(vec![batch_id; variants.len()])
+        .execute(conn)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+/// `insert` for a SQLite database.
+pub async fn insert_sqlite(
+    pool: &SqlitePool,
+    variants: &[Variant],
+    protein: &str,
+    source: &SourceFile,
+    actor: &Actor,
+) -> Result<UploadBatch, sqlx::Error> {
+    insert_sqlite_batches(
+        pool,
+        variants,
+        protein,
+        source,
+        actor,
+        DEFAULT_BATCH_SIZE,
+        |_| {},
+    )
+    .await
+}
+
+/// `insert_batches` for a SQLite database. SQLite has no statement-level
+/// triggers, so this also refreshes the summary tables of every condition it
+/// touched and bumps the dataset revision, inside the same transaction.
+pub async fn insert_sqlite_batches(
+    pool: &SqlitePool,
+    variants: &[Variant],
+    protein: &str,
+    source: &SourceFile,
+    actor: &Actor,
+    batch_size: usize,
+    mut progress: impl FnMut(u64),
+) -> Result<UploadBatch, sqlx::Error> {
+    info!("Inserting {} variants into db", variants.len());
+    let start = Instant::now();
+    let mut txn = pool.begin().await?;
+    let protein_id: i32 = sqlx::query_scalar("SELECT id FROM protein WHERE name = ?1")
+        .bind(protein)
+        .fetch_one(&mut *txn)
+        .await?;
+    info!("Found protein {} at id {}", protein, protein_id);
+    let (batch_id, created_on): (i32, NaiveDateTime) = sqlx::query_as(
+        r#"
+        INSERT INTO upload_batch
+            (protein_id, file_name, sha256, rows_inserted, rows_rejected, columns, user_id, actor)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+        RETURNING id, created_on
+        "#,
+    )
+    .bind(protein_id)
+    .bind(&source.name)
+    .bind(&source.sha256)
+    .bind(variants.len() as i64)
+    .bind(source.rows_rejected as i64)
+    .bind(Json(&source.columns))
+    .bind(actor.user_id)
+    .bind(&actor.name)
+    .fetch_one(&mut *txn)
+    .await?;
+    let mut rows_affected = 0;
+    for chunk in variants.chunks(batch_size.max(1)) {
+        for statement in chunk.chunks(SQLITE_ROWS_PER_STATEMENT) {
+            rows_affected += insert_sqlite_batch(&mut txn, statement, protein_id, batch_id)
+                .timed("insert_variants")
+                .await?;
+        }
+        progress(rows_affected);
+    }
+    let conditions: BTreeSet<&str> = variants.iter().map(|v| v.condition.as_str()).collect();
+    for condition in conditions {
+        refresh_sqlite_summary(&mut txn, protein_id, condition).await?;
+    }
+    sqlx::query("UPDATE dataset_revision SET revision = revision + 1")
+        .execute(&mut *txn)
+        .await?;
+    let batch = upload_batch(protein, source, actor, batch_id, created_on, rows_affected);
+    audit::record_sqlite(&mut txn, actor, &Change::ingest(&batch, variants)).await?;
+    txn.commit().await?;
+    info!("rows affected {}", rows_affected);
+    METRICS.observe_ingest(rows_affected, start.elapsed());
+    Ok(batch)
+}
+
+async fn insert_sqlite_batch(
+    conn: &mut SqliteConnection,
+    variants: &[Variant],
+    protein_id: i32,
+    batch_id: i32,
+) -> Result<u64, sqlx::Error> {
+    let mut query = QueryBuilder::<Sqlite>::new(
+        "INSERT INTO variant (chunk, pos, condition, aa, log2_fold_change, log2_std_error, \
+         statistic, p_value, version, protein_id, created_on, batch_id) ",
+    );
+    query.push_values(variants, |mut row, variant| {
+        row.push_bind(variant.chunk)
+            .push_bind(variant.pos)
+            .push_bind(&variant.condition)
+            .push_bind(&variant.aa)
+            .push_bind(variant.log2_fold_change)
+            .push_bind(variant.log2_std_error)
+            .push_bind(variant.statistic)
+            .push_bind(variant.p_value)
+            .push_bind(&variant.version)
+            .push_bind(protein_id)
+            .push_bind(variant.created_on)
+            .push_bind(batch_id);
+    });
+    Ok(query.build().execute(conn).await?.rows_affected())
+}
+
+/// Rebuilds `variant_summary` and `variant_metric_summary` for one protein and
+/// condition, as `refresh_variant_summary` does in PostgreSQL. The quantiles
+/// interpolate linearly between the two nearest ranks, like `percentile_cont`.
+pub async fn refresh_sqlite_summary(
+    conn: &mut SqliteConnection,
+    protein_id: i32,
+    condition: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM variant_summary WHERE protein_id = ?1 AND condition = ?2")
+        .bind(protein_id)
+        .bind(condition)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query("DELETE FROM variant_metric_summary WHERE protein_id = ?1 AND condition = ?2")
+        .bind(protein_id)
+        .bind(condition)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query(
+        r#"
+        INSERT INTO variant_summary
+        SELECT
+            protein_id,
+            condition,
+            version,
+            count(*),
+            count(DISTINCT pos),
+            min(pos),
+            max(pos),
+            CAST(count(DISTINCT pos || ':' || aa) AS DOUBLE PRECISION) / ((max(pos) - min(pos) + 1) * 21),
+            datetime('now')
+        FROM variant
+        WHERE protein_id = ?1 AND condition = ?2
+        GROUP BY protein_id, condition, version
+        "#,
+    )
+    .bind(protein_id)
+    .bind(condition)
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query(
+        r#"
+        WITH metrics AS (
+            SELECT protein_id, condition, version, 'p_value' AS metric, p_value AS value
+            FROM variant WHERE protein_id = ?1 AND condition = ?2
+            UNION ALL
+            SELECT protein_id, condition, version, 'log2_fold_change', log2_fold_change
+            FROM variant WHERE protein_id = ?1 AND condition = ?2
+            UNION ALL
+            SELECT protein_id, condition, version, 'statistic', statistic
+            FROM variant WHERE protein_id = ?1 AND condition = ?2
+        ),
+        ranked AS (
+            SELECT
+                *,
+                ROW_NUMBER() OVER (PARTITION BY version, metric ORDER BY value) - 1 AS idx,
+                count(*) OVER (PARTITION BY version, metric) - 1 AS last
+            FROM metrics
+        )
+        INSERT INTO variant_metric_summary
+        SELECT
+            protein_id,
+            condition,
+            version,
+            metric,
+            min(value),
+            max(value),
+            max(abs(value)),
+            sum(value * max(0.0, 1.0 - abs(idx - 0.05 * last))),
+            sum(value * max(0.0, 1.0 - abs(idx - 0.25 * last))),
+            sum(value * max(0.0, 1.0 - abs(idx - 0.5 * last))),
+            sum(value * max(0.0, 1.0 - abs(idx - 0.75 * last))),
+            sum(value * max(0.0, 1.0 - abs(idx - 0.95 * last)))
+        FROM ranked
+        GROUP BY protein_id, condition, version, metric
+        "#,
+    )
+    .bind(protein_id)
+    .bind(condition)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sources_describe_their_columns() {
+        let data = b"pos\taa\tcondition\tchunk\tlog2FoldChange\tlog2StdError\tstatistic\tp.value\tversion\tnote\n\
+                     1\tA\tc1\t1\t0.5\t0.1\t5\t0.01\tv1\tok\n\
+                     x\tA\tc1\t1\t0.5\t0.1\t5\t0.01\tv1\tbad\n";
+        let (variants, errors, source) = read_source("run1.tsv", data, "P1");
+        assert_eq!((variants.len(), errors.len()), (1, 1));
+        assert_eq!(variants[0].protein, "P1");
+        assert_eq!(source.name, "run1.tsv");
+        assert_eq!(source.rows_rejected, 1);
+        assert_eq!(source.sha256, auth::hex(&Sha256::digest(data)));
+        assert_eq!(source.sha256.len(), 64);
+        assert_eq!(source.columns.len(), 10);
+        assert_eq!(
+            source.columns["log2FoldChange"].as_deref(),
+            Some("log2_fold_change")
+        );
+        assert_eq!(source.columns["p.value"].as_deref(), Some("p_value"));
+        assert_eq!(source.columns["note"], None);
+    }
+}
@@ -1,12 +1,38 @@
-use std::{borrow::Cow, env};
+use std::sync::{atomic::AtomicU64, Arc};
 
-use anyhow::bail;
+use cache::ResponseCache;
 use chrono::NaiveDateTime;
-use serde::{Deserialize, Serialize};
-use sqlx::{prelude::FromRow, types::chrono::Utc, PgPool};
-use tracing::info;
+use config::Config;
+use repository::Repository;
+use serde::{de::IntoDeserializer, Deserialize, Deserializer, Serialize};
+use sqlx::{prelude::FromRow, types::chrono::Utc};
+use tolerance::ToleranceMetric;
 
-#[derive(Debug, FromRow, Serialize, Deserialize)]
+pub mod access;
+pub mod audit;
+pub mod auth;
+pub mod cache;
+pub mod classify;
+pub mod color;
+pub mod config;
+pub mod datasets;
+pub mod error;
+pub mod histogram;
+pub mod ingest;
+pub mod matrix;
+pub mod metrics;
+pub mod migrate;
+pub mod repository;
+pub mod substitution;
+pub mod tolerance;
+pub mod validate;
+
+use classify::{
+    ClassificationRule, ClassifierKind, EffectScale, DEFAULT_EFFECT_CUTOFF, DEFAULT_GAIN_Z,
+    DEFAULT_POSTERIOR_CUTOFF, DEFAULT_P_CUTOFF,
+};
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
 pub struct Variant {
     pub id: Option<i32>,
     pub chunk: i32,
@@ -44,103 +70,111 @@ pub const GROUPED_AMINO_ACIDS: [&str; 21] = [
     "I", "V", "L", "F", "C", "M", "A", "W", "G", "T", "S", "Y", "P", "H", "N", "D", "Q", "E", "K",
     "R", "*",
 ];
+/// Default number of positions per table page, see `ServerConfig::page_size`.
 pub const PAGE_SIZE: i32 = 500;
 
-pub struct Normalizer {
-    pub max_abs: f64,
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MutationType {
+    Missense,
+    Nonsense,
+    Synonymous,
 }
 
-impl Normalizer {
-    // Normalize a single value and return the RGB color
-    pub fn get_color_rgb(&self, value: f64) -> String {
-        if self.max_abs == 0.0 {
-            return "rgb(255,255,255)".to_string(); // Handle case where max_abs is 0
-        }
-
-        let normalized = value / self.max_abs;
-
-        if normalized < 0.0 {
-            let intensity = ((1.0 + normalized) * 255.0).round() as u8; // Scale [-1, 0] to [0, 255]
-            format!("rgb(255,{},{})", intensity, intensity) // Shades of red
+impl MutationType {
+    /// Without a wild-type residue a non-stop substitution counts as missense.
+    pub fn of(aa: &str, wild_type: Option<char>) -> Self {
+        if aa == "*" {
+            MutationType::Nonsense
+        } else if wild_type.is_some_and(|wt| aa.len() == 1 && aa.starts_with(wt)) {
+            MutationType::Synonymous
         } else {
-            let intensity = ((1.0 - normalized) * 255.0).round() as u8; // Scale [0, 1] to [255, 0]
-            format!("rgb({},{},255)", intensity, intensity) // Shades of blue
+            MutationType::Missense
         }
     }
+}
 
-    // Normalize a single value and return the Hex color
-    pub fn get_color_hex(&self, value: f64) -> String {
-        if self.max_abs == 0.0 {
-            info!("max_abs is 0");
-            return "#FFFFFF".to_string(); // Handle case where max_abs is 0
-        }
-
-        let normalized = value / self.max_abs;
-
-        if normalized < 0.0 {
-            let intensity = ((1.0 + normalized) * 255.0).round() as u8; // Scale [-1, 0] to [0, 255]
-            format!("#FF{:02X}{:02X}", intensity, intensity) // Shades of red in hex
-        } else {
-            let intensity = ((1.0 - normalized) * 255.0).round() as u8; // Scale [0, 1] to [255, 0]
-            format!("#{:02X}{:02X}FF", intensity, intensity) // Shades of blue in hex
-        }
-    }
+/// Wild-type residue at a 1-based position of a protein sequence.
+pub fn wild_type_at(sequence: &str, pos: i32) -> Option<char> {
+    let index = usize::try_from(pos).ok()?.checked_sub(1)?;
+    sequence.chars().nth(index)
 }
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PosColor {
     pub pos: i32,
     pub color: String,
 }
 
+pub const NO_DATA: u8 = u8::MAX;
+
+/// Whole-protein heatmap in one payload, for the canvas renderer. Cells are
+/// stored row-major: the cell for position `pos` and `amino_acids[col]` is at
+/// `(pos - start) * amino_acids.len() + col`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HeatmapMatrix {
+    pub start: i32,
+    pub end: i32,
+    pub amino_acids: Vec<String>,
+    pub values: Vec<Option<f64>>,
+    pub ids: Vec<Option<i32>>,
+    /// Index into `palette`, or `NO_DATA` for empty cells.
+    pub colors: Vec<u8>,
+    pub palette: Vec<String>,
+    pub track: Option<HeatmapTrack>,
+}
+
+/// One value per position, drawn as a bar column next to the heatmap.
 #[derive(Debug, Serialize, Deserialize)]
-pub struct VariantColor {
+pub struct HeatmapTrack {
+    pub label: String,
+    pub values: Vec<Option<f64>>,
+    pub scale: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VolcanoPoint {
     pub id: i32,
     pub pos: i32,
     pub aa: String,
-    pub log2_fold_change: f64,
-    pub log2_std_error: f64,
-    pub statistic: f64,
-    pub p_value: f64,
+    pub x: f64,
+    /// -log10(p), with p = 0 drawn at the top of the axis.
+    pub y: f64,
     pub color: String,
+    pub label: Option<String>,
 }
 
-#[derive(Clone, Debug)]
-pub struct EnvironmentVariables {
-    pub database_url: Cow<'static, str>,
-    pub port: u16,
-}
-
-impl EnvironmentVariables {
-    pub fn from_env() -> anyhow::Result<Self> {
-        dotenvy::dotenv()?;
-        Ok(Self {
-            database_url: match env::var("DATABASE_URL") {
-                Ok(url) => url.into(),
-                Err(err) => bail!("missing DATABASE_URL: {err}"),
-            },
-            port: match env::var("PORT") {
-                Ok(port) => port.parse()?,
-                _ => 3000,
-            },
-        })
-    }
+/// Axes and guide lines of the volcano plot, all in plot units.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VolcanoAxes {
+    pub x_label: String,
+    pub x_min: f64,
+    pub x_max: f64,
+    pub y_max: f64,
+    /// Vertical lines are drawn at ±`effect_cutoff`.
+    pub effect_cutoff: Option<f64>,
+    /// Horizontal line, already on the -log10 scale.
+    pub significance_line: f64,
 }
 
 #[derive(Clone)]
 pub struct AppState {
-    pub pool: PgPool,
-    pub env: EnvironmentVariables,
+    pub repository: Arc<dyn Repository>,
+    pub config: Arc<Config>,
+    pub cache: Arc<ResponseCache>,
+    /// Last value of the `dataset_revision` sequence this server has seen.
+    pub revision: Arc<AtomicU64>,
 }
 impl AppState {
-    pub async fn from_env() -> anyhow::Result<Self> {
-        let env = EnvironmentVariables::from_env()?;
-        Ok(Self {
-            pool: PgPool::connect(&env.database_url).await?,
-            env: EnvironmentVariables::from_env()?,
-        })
+    pub fn new(config: Config, repository: Arc<dyn Repository>) -> Self {
+        Self {
+            repository,
+            cache: Arc::new(ResponseCache::new(config.cache.max_bytes, config.cache.ttl)),
+            revision: Arc::new(AtomicU64::new(0)),
+            config: Arc::new(config),
+        }
     }
 }
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone, Copy)]
 pub enum PositionFilter {
     MostSignificantPValue,
     LargestLog2FoldChange,
@@ -156,6 +190,20 @@ pub enum Paint {
     Log2FoldChange,
     #[serde(alias = "statistic")]
     ZStatistic,
+    #[serde(alias = "classification")]
+    Classification,
+}
+
+impl Paint {
+    /// The metric a variant is painted by; classification falls back to the
+    /// log2 fold change it is mostly derived from.
+    pub fn value_of(&self, variant: &Variant) -> f64 {
+        match self {
+            Paint::PValue => variant.p_value,
+            Paint::Log2FoldChange | Paint::Classification => variant.log2_fold_change,
+            Paint::ZStatistic => variant.statistic,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -171,6 +219,7 @@ impl std::fmt::Display for Paint {
             Paint::Log2FoldChange => "log2_fold_change",
             Paint::PValue => "p_value",
             Paint::ZStatistic => "statistic",
+            Paint::Classification => "classification",
         };
         write!(f, "{}", output)
     }
@@ -198,14 +247,119 @@ pub struct TableParams {
     pub threshold: Option<f64>,
     pub page: Option<i32>,
     pub plot: Option<PlotType>,
+    pub classifier: Option<ClassifierKind>,
+    pub effect_cutoff: Option<f64>,
+    pub p_cutoff: Option<f64>,
+    pub posterior_cutoff: Option<f64>,
+    pub gain_z: Option<f64>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub tolerance_metric: Option<ToleranceMetric>,
+    pub label_top: Option<usize>,
+}
+
+impl TableParams {
+    /// Everything but protein and condition, in a fixed order with defaults
+    /// filled in, so equivalent queries share a cache entry.
+    pub fn cache_key(&self) -> String {
+        format!(
+            "{}|{}|{:?}|{:?}|{}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}",
+            self.position_filter,
+            self.paint,
+            self.operation,
+            self.threshold,
+            self.page.unwrap_or(1),
+            self.plot,
+            self.classifier,
+            self.effect_cutoff,
+            self.p_cutoff,
+            self.posterior_cutoff,
+            self.gain_z,
+            self.tolerance_metric,
+            self.label_top,
+        )
+    }
+
+    /// The one rule the volcano, the heatmap and the classification summary
+    /// all call variants with, so the same query gives the same calls.
+    pub fn classification_rule(&self) -> ClassificationRule {
+        match self.classifier {
+            Some(ClassifierKind::Mixture) => ClassificationRule::Mixture {
+                posterior_cutoff: self.posterior_cutoff.unwrap_or(DEFAULT_POSTERIOR_CUTOFF),
+                gain_z: self.gain_z.unwrap_or(DEFAULT_GAIN_Z),
+            },
+            _ => ClassificationRule::Threshold {
+                effect_cutoff: self.effect_cutoff_or_threshold().unwrap_or(0.0),
+                p_cutoff: self.p_cutoff.unwrap_or(DEFAULT_P_CUTOFF),
+                scale: self.effect_scale(),
+            },
+        }
+    }
+
+    /// The z statistic when painting by it, otherwise the log2 fold change.
+    pub fn effect_scale(&self) -> EffectScale {
+        match self.paint {
+            Paint::ZStatistic => EffectScale::ZStatistic,
+            _ => EffectScale::Log2FoldChange,
+        }
+    }
+
+    /// Effect size cutoff on `effect_scale`, drawn as the volcano's guide
+    /// lines. The threshold slider sets it when it filters the same measure,
+    /// i.e. when painting by log2 fold change or z statistic; otherwise the
+    /// classifier's `effect_cutoff` does. Without either the log2 scale falls
+    /// back to the default and the z scale has none.
+    pub fn effect_cutoff_or_threshold(&self) -> Option<f64> {
+        let threshold = match self.paint {
+            Paint::Log2FoldChange | Paint::ZStatistic => self.threshold.map(f64::abs),
+            Paint::PValue | Paint::Classification => None,
+        };
+        match (threshold.or(self.effect_cutoff), self.effect_scale()) {
+            (Some(cutoff), _) => Some(cutoff),
+            (None, EffectScale::ZStatistic) => None,
+            (None, EffectScale::Log2FoldChange) => Some(DEFAULT_EFFECT_CUTOFF),
+        }
+    }
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct Domain {
+    pub name: String,
+    pub start_pos: i32,
+    pub end_pos: i32,
+}
+
+/// Lets a `<select>` offer a "none" option with an empty value.
+pub fn empty_string_as_none<'de, D, T>(de: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    match Option::<String>::deserialize(de)?.as_deref() {
+        None | Some("") => Ok(None),
+        Some(s) => T::deserialize(s.into_deserializer()).map(Some),
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub enum ExportFormat {
+    #[serde(alias = "json")]
+    Json,
+    #[serde(alias = "tsv")]
+    Tsv,
 }
 
-#[derive(Deserialize, Copy, Clone)]
+#[derive(Deserialize, Debug, Copy, Clone)]
 pub enum PlotType {
-    #[serde(alias = "scatter")]
+    #[serde(alias = "scatter", alias = "volcano")]
     Scatter,
     #[serde(alias = "heatmap")]
     Heatmap,
+    #[serde(alias = "substitution")]
+    Substitution,
+    #[serde(alias = "distribution")]
+    Distribution,
+    #[serde(alias = "table")]
+    Table,
 }
 
 impl std::fmt::Display for PlotType {
@@ -213,7 +367,78 @@ impl std::fmt::Display for PlotType {
         let output = match self {
             PlotType::Heatmap => "heatmap",
             PlotType::Scatter => "scatter",
+            PlotType::Substitution => "substitution",
+            PlotType::Distribution => "distribution",
+            PlotType::Table => "table",
         };
         write!(f, "{}", output)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(paint: Paint, threshold: Option<f64>, effect_cutoff: Option<f64>) -> TableParams {
+        TableParams {
+            protein: "P1".to_string(),
+            condition: "c1".to_string(),
+            position_filter: PositionFilter::NoOrder,
+            paint,
+            operation: None,
+            threshold,
+            page: None,
+            plot: None,
+            classifier: None,
+            effect_cutoff,
+            p_cutoff: None,
+            posterior_cutoff: None,
+            gain_z: None,
+            tolerance_metric: None,
+            label_top: None,
+        }
+    }
+
+    #[test]
+    fn threshold_sets_the_effect_cutoff_only_when_painting_by_an_effect() {
+        let default = Some(DEFAULT_EFFECT_CUTOFF);
+        // paint, threshold, effect_cutoff, expected cutoff
+        let cases = [
+            (Paint::PValue, None, None, default),
+            (Paint::PValue, None, Some(2.0), Some(2.0)),
+            (Paint::PValue, Some(0.05), None, default),
+            (Paint::PValue, Some(0.05), Some(2.0), Some(2.0)),
+            (Paint::Log2FoldChange, None, None, default),
+            (Paint::Log2FoldChange, None, Some(2.0), Some(2.0)),
+            (Paint::Log2FoldChange, Some(-1.5), None, Some(1.5)),
+            (Paint::Log2FoldChange, Some(-1.5), Some(2.0), Some(1.5)),
+            (Paint::ZStatistic, None, None, None),
+            (Paint::ZStatistic, None, Some(2.0), Some(2.0)),
+            (Paint::ZStatistic, Some(3.0), None, Some(3.0)),
+            (Paint::ZStatistic, Some(3.0), Some(2.0), Some(3.0)),
+            (Paint::Classification, None, None, default),
+            (Paint::Classification, None, Some(2.0), Some(2.0)),
+            (Paint::Classification, Some(0.25), None, default),
+            (Paint::Classification, Some(0.25), Some(2.0), Some(2.0)),
+        ];
+        for (paint, threshold, effect_cutoff, expected) in cases {
+            let params = params(paint, threshold, effect_cutoff);
+            let case = format!("{paint} threshold={threshold:?} effect_cutoff={effect_cutoff:?}");
+            assert_eq!(params.effect_cutoff_or_threshold(), expected, "{case}");
+            let ClassificationRule::Threshold {
+                effect_cutoff,
+                scale,
+                ..
+            } = params.classification_rule()
+            else {
+                panic!("{case}: not a threshold rule");
+            };
+            assert_eq!(effect_cutoff, expected.unwrap_or(0.0), "{case}");
+            assert_eq!(
+                scale == EffectScale::ZStatistic,
+                matches!(paint, Paint::ZStatistic),
+                "{case}"
+            );
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use std::ops::RangeInclusive;
+
+use crate::{Variant, GROUPED_AMINO_ACIDS};
+
+/// Column of `aa` in `GROUPED_AMINO_ACIDS`.
+pub fn column_of(aa: &str) -> Option<usize> {
+    GROUPED_AMINO_ACIDS
+        .iter()
+        .position(|candidate| *candidate == aa)
+}
+
+/// Variants indexed once into a dense `positions × GROUPED_AMINO_ACIDS` grid,
+/// stored row by row. Empty cells are `None`.
+#[derive(Debug, Clone)]
+pub struct VariantMatrix<'a> {
+    start: i32,
+    end: i32,
+    cells: Vec<Option<&'a Variant>>,
+    duplicates: usize,
+    skipped: usize,
+}
+
+impl<'a> VariantMatrix<'a> {
+    /// Grid over `start..=end`. Variants outside the range or with an
+    /// unknown residue are skipped; when several variants share a cell the
+    /// first one in `variants` is kept.
+    pub fn build(variants: &'a [Variant], start: i32, end: i32) -> Self {
+        let rows = if end >= start {
+            (end - start + 1) as usize
+        } else {
+            0
+        };
+        let mut cells = vec![None; rows * GROUPED_AMINO_ACIDS.len()];
+        let mut duplicates = 0;
+        let mut skipped = 0;
+        for variant in variants {
+            let Some(index) = Self::index_in(start, end, variant.pos, &variant.aa) else {
+                skipped += 1;
+                continue;
+            };
+            match cells[index] {
+                Some(_) => duplicates += 1,
+                None => cells[index] = Some(variant),
+            }
+        }
+        Self {
+            start,
+            end,
+            cells,
+            duplicates,
+            skipped,
+        }
+    }
+
+    /// Grid spanning the lowest to the highest position in `variants`.
+    pub fn from_variants(variants: &'a [Variant]) -> Self {
+        let start = variants
+            .iter()
+            .map(|variant| variant.pos)
+            .min()
+            .unwrap_or(1);
+        let end = variants
+            .iter()
+            .map(|variant| variant.pos)
+            .max()
+            .unwrap_or(0);
+        Self::build(variants, start, end)
+    }
+
+    fn index_in(start: i32, end: i32, pos: i32, aa: &str) -> Option<usize> {
+        if pos < start || pos > end {
+            return None;
+        }
+        let col = column_of(aa)?;
+        Some((pos - start) as usize * GROUPED_AMINO_ACIDS.len() + col)
+    }
+
+    pub fn start(&self) -> i32 {
+        self.start
+    }
+
+    pub fn end(&self) -> i32 {
+        self.end
+    }
+
+    pub fn positions(&self) -> RangeInclusive<i32> {
+        self.start..=self.end
+    }
+
+    /// Number of cells, empty ones included.
+    pub fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+
+    /// Variants that landed in an already filled cell.
+    pub fn duplicates(&self) -> usize {
+        self.duplicates
+    }
+
+    /// Variants outside the position range or with an unknown residue.
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    pub fn get(&self, pos: i32, aa: &str) -> Option<&'a Variant> {
+        Self::index_in(self.start, self.end, pos, aa).and_then(|index| self.cells[index])
+    }
+
+    /// Cells of one position, in `GROUPED_AMINO_ACIDS` order.
+    pub fn row(&self, pos: i32) -> Option<&[Option<&'a Variant>]> {
+        if pos < self.start || pos > self.end {
+            return None;
+        }
+        let width = GROUPED_AMINO_ACIDS.len();
+        let offset = (pos - self.start) as usize * width;
+        Some(&self.cells[offset..offset + width])
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = (i32, &[Option<&'a Variant>])> {
+        self.positions()
+            .zip(self.cells.chunks(GROUPED_AMINO_ACIDS.len()))
+    }
+
+    /// Filled cells, row by row.
+    pub fn iter(&self) -> impl Iterator<Item = &'a Variant> + '_ {
+        self.cells.iter().flatten().copied()
+    }
+
+    /// One entry per cell, row-major, `None` where the cell is empty.
+    pub fn map<T>(&self, mut f: impl FnMut(&'a Variant) -> T) -> Vec<Option<T>> {
+        self.cells.iter().map(|cell| cell.map(&mut f)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variant(id: i32, pos: i32, aa: &str, log2_fold_change: f64) -> Variant {
+        Variant {
+            id: Some(id),
+            chunk: 1,
+            pos,
+            condition: "c1".to_string(),
+            aa: aa.to_string(),
+            log2_fold_change,
+            log2_std_error: 0.1,
+            statistic: 0.0,
+            p_value: 0.5,
+            version: "v1".to_string(),
+            protein: "P1".to_string(),
+            created_on: Default::default(),
+        }
+    }
+
+    #[test]
+    fn sparse_cells_are_empty() {
+        let variants = vec![variant(1, 2, "A", 0.5), variant(2, 4, "*", -2.0)];
+        let matrix = VariantMatrix::build(&variants, 1, 4);
+
+        assert_eq!(matrix.len(), 4 * GROUPED_AMINO_ACIDS.len());
+        assert_eq!(matrix.get(2, "A").and_then(|v| v.id), Some(1));
+        assert_eq!(matrix.get(4, "*").and_then(|v| v.id), Some(2));
+        assert!(matrix.get(1, "A").is_none());
+        assert!(matrix.get(2, "V").is_none());
+        assert!(matrix.row(3).unwrap().iter().all(Option::is_none));
+        assert_eq!(matrix.iter().count(), 2);
+        assert_eq!(matrix.duplicates(), 0);
+    }
+
+    #[test]
+    fn rows_follow_grouped_amino_acids() {
+        let variants = vec![variant(1, 5, "I", 0.1), variant(2, 5, "*", 0.2)];
+        let matrix = VariantMatrix::from_variants(&variants);
+
+        assert_eq!(matrix.positions(), 5..=5);
+        let (pos, row) = matrix.rows().next().unwrap();
+        assert_eq!(pos, 5);
+        assert_eq!(row.first().unwrap().and_then(|v| v.id), Some(1));
+        assert_eq!(row.last().unwrap().and_then(|v| v.id), Some(2));
+        assert_eq!(
+            matrix.map(|v| v.log2_fold_change)[column_of("*").unwrap()],
+            Some(0.2)
+        );
+    }
+
+    #[test]
+    fn duplicated_cells_keep_the_first_variant() {
+        let variants = vec![
+            variant(1, 3, "L", 1.0),
+            variant(2, 3, "L", -1.0),
+            variant(3, 3, "L", 0.0),
+        ];
+        let matrix = VariantMatrix::build(&variants, 1, 3);
+
+        assert_eq!(matrix.get(3, "L").and_then(|v| v.id), Some(1));
+        assert_eq!(matrix.duplicates(), 2);
+        assert_eq!(matrix.iter().count(), 1);
+    }
+
+    #[test]
+    fn out_of_range_and_unknown_residues_are_skipped() {
+        let variants = vec![
+            variant(1, 0, "A", 0.0),
+            variant(2, 11, "A", 0.0),
+            variant(3, 5, "X", 0.0),
+            variant(4, 5, "A", 0.0),
+        ];
+        let matrix = VariantMatrix::build(&variants, 1, 10);
+
+        assert_eq!(matrix.skipped(), 3);
+        assert!(matrix.get(0, "A").is_none());
+        assert!(matrix.row(11).is_none());
+        assert_eq!(matrix.iter().count(), 1);
+    }
+
+    #[test]
+    fn empty_range_has_no_cells() {
+        let variants = vec![variant(1, 1, "A", 0.0)];
+        let matrix = VariantMatrix::build(&variants, 5, 4);
+        assert!(matrix.is_empty());
+        assert_eq!(matrix.rows().count(), 0);
+        assert_eq!(matrix.skipped(), 1);
+
+        let matrix = VariantMatrix::from_variants(&[]);
+        assert!(matrix.is_empty());
+    }
+}
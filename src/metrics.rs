@@ -0,0 +1,356 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::future::Future;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+/// Upper bounds of the latency histogram buckets, in seconds.
+const BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+/// Process-wide metrics, so database helpers can time their queries without
+/// being handed the application state.
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::default);
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Histogram {
+    /// Cumulative: observations at or below each bound in `BUCKETS`.
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        for (bound, bucket) in BUCKETS.iter().zip(&mut self.buckets) {
+            if seconds <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    /// (route, method, status) → count
+    requests: BTreeMap<(String, String, u16), u64>,
+    /// (route, method) → latency
+    request_duration: BTreeMap<(String, String), Histogram>,
+    query_duration: BTreeMap<&'static str, Histogram>,
+    query_errors: BTreeMap<&'static str, u64>,
+    ingest_rows: u64,
+    ingest_seconds: f64,
+    ingest_last_rows_per_second: f64,
+}
+
+/// Request, query and ingest metrics, rendered in the Prometheus text format
+/// by `render`. Values that already live elsewhere, like pool and cache
+/// statistics, are written by the `/metrics` handler at scrape time.
+#[derive(Default)]
+pub struct Metrics {
+    registry: Mutex<Registry>,
+}
+
+impl Metrics {
+    /// `route` should be the matched route pattern, not the raw path, to keep
+    /// the number of series bounded.
+    pub fn observe_request(&self, route: &str, method: &str, status: u16, elapsed: Duration) {
+        let mut registry = self.registry.lock().unwrap();
+        *registry
+            .requests
+            .entry((route.to_string(), method.to_string(), status))
+            .or_default() += 1;
+        registry
+            .request_duration
+            .entry((route.to_string(), method.to_string()))
+            .or_default()
+            .observe(elapsed.as_secs_f64());
+    }
+
+    pub fn observe_query(&self, name: &'static str, elapsed: Duration, ok: bool) {
+        let mut registry = self.registry.lock().unwrap();
+        registry
+            .query_duration
+            .entry(name)
+            .or_default()
+            .observe(elapsed.as_secs_f64());
+        if !ok {
+            *registry.query_errors.entry(name).or_default() += 1;
+        }
+    }
+
+    /// Runs `query`, recording its duration and whether it failed under `name`.
+    pub async fn time_query<T, E>(
+        &self,
+        name: &'static str,
+        query: impl Future<Output = Result<T, E>>,
+    ) -> Result<T, E> {
+        let start = Instant::now();
+        let result = query.await;
+        self.observe_query(name, start.elapsed(), result.is_ok());
+        result
+    }
+
+    pub fn observe_ingest(&self, rows: u64, elapsed: Duration) {
+        let mut registry = self.registry.lock().unwrap();
+        let seconds = elapsed.as_secs_f64();
+        registry.ingest_rows += rows;
+        registry.ingest_seconds += seconds;
+        if seconds > 0.0 {
+            registry.ingest_last_rows_per_second = rows as f64 / seconds;
+        }
+    }
+
+    pub fn render(&self, out: &mut Exposition) {
+        let registry = self.registry.lock().unwrap();
+
+        out.header(
+            "dms_http_requests_total",
+            "counter",
+            "HTTP requests by route, method and status",
+        );
+        for ((route, method, status), count) in &registry.requests {
+            out.sample(
+                "dms_http_requests_total",
+                &[
+                    ("route", route),
+                    ("method", method),
+                    ("status", &status.to_string()),
+                ],
+                *count as f64,
+            );
+        }
+
+        out.header(
+            "dms_http_request_duration_seconds",
+            "histogram",
+            "HTTP request latency by route and method",
+        );
+        for ((route, method), histogram) in &registry.request_duration {
+            out.histogram(
+                "dms_http_request_duration_seconds",
+                &[("route", route), ("method", method)],
+                histogram,
+            );
+        }
+
+        out.header(
+            "dms_db_query_duration_seconds",
+            "histogram",
+            "Database query latency by query name",
+        );
+        for (query, histogram) in &registry.query_duration {
+            out.histogram(
+                "dms_db_query_duration_seconds",
+                &[("query", query)],
+                histogram,
+            );
+        }
+
+        out.header(
+            "dms_db_query_errors_total",
+            "counter",
+            "Failed database queries by query name",
+        );
+        for (query, count) in &registry.query_errors {
+            out.sample(
+                "dms_db_query_errors_total",
+                &[("query", query)],
+                *count as f64,
+            );
+        }
+
+        out.counter(
+            "dms_ingest_rows_total",
+            "Variant rows inserted through the server",
+            registry.ingest_rows as f64,
+        );
+        out.counter(
+            "dms_ingest_seconds_total",
+            "Time spent inserting variant rows",
+            registry.ingest_seconds,
+        );
+        out.gauge(
+            "dms_ingest_last_rows_per_second",
+            "Insert throughput of the most recent upload",
+            registry.ingest_last_rows_per_second,
+        );
+    }
+}
+
+/// Writer for the Prometheus text exposition format.
+#[derive(Debug, Default)]
+pub struct Exposition {
+    out: String,
+}
+
+impl Exposition {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn header(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.out, "# HELP {name} {help}");
+        let _ = writeln!(self.out, "# TYPE {name} {kind}");
+    }
+
+    pub fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.out.push_str(name);
+        if !labels.is_empty() {
+            self.out.push('{');
+            for (index, (key, value)) in labels.iter().enumerate() {
+                if index > 0 {
+                    self.out.push(',');
+                }
+                let _ = write!(self.out, "{key}=\"{}\"", escape_label(value));
+            }
+            self.out.push('}');
+        }
+        let _ = writeln!(self.out, " {}", format_value(value));
+    }
+
+    pub fn counter(&mut self, name: &str, help: &str, value: f64) {
+        self.header(name, "counter", help);
+        self.sample(name, &[], value);
+    }
+
+    pub fn gauge(&mut self, name: &str, help: &str, value: f64) {
+        self.header(name, "gauge", help);
+        self.sample(name, &[], value);
+    }
+
+    fn histogram(&mut self, name: &str, labels: &[(&str, &str)], histogram: &Histogram) {
+        let bucket_name = format!("{name}_bucket");
+        for (bound, count) in BUCKETS.iter().zip(histogram.buckets) {
+            let bound = bound.to_string();
+            let mut bucket_labels = labels.to_vec();
+            bucket_labels.push(("le", &bound));
+            self.sample(&bucket_name, &bucket_labels, count as f64);
+        }
+        let mut bucket_labels = labels.to_vec();
+        bucket_labels.push(("le", "+Inf"));
+        self.sample(&bucket_name, &bucket_labels, histogram.count as f64);
+        self.sample(&format!("{name}_sum"), labels, histogram.sum);
+        self.sample(&format!("{name}_count"), labels, histogram.count as f64);
+    }
+
+    pub fn finish(self) -> String {
+        self.out
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "+Inf" } else { "-Inf" }.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// `.timed(name)` on query futures, recording them in `METRICS`.
+pub trait TimedQuery<T, E>: Future<Output = Result<T, E>> + Sized {
+    fn timed(self, name: &'static str) -> impl Future<Output = Result<T, E>> {
+        METRICS.time_query(name, self)
+    }
+}
+
+impl<F, T, E> TimedQuery<T, E> for F where F: Future<Output = Result<T, E>> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(metrics: &Metrics) -> String {
+        let mut out = Exposition::new();
+        metrics.render(&mut out);
+        out.finish()
+    }
+
+    fn value(rendered: &str, series: &str) -> f64 {
+        let line = rendered
+            .lines()
+            .find(|line| line.starts_with(series) && line[series.len()..].starts_with(' '))
+            .unwrap_or_else(|| panic!("no {series} in\n{rendered}"));
+        line[series.len() + 1..].parse().unwrap()
+    }
+
+    #[test]
+    fn renders_request_counters_and_latency_histograms() {
+        let metrics = Metrics::default();
+        let fast = Duration::from_millis(3);
+        metrics.observe_request("/variants", "GET", 200, fast);
+        metrics.observe_request("/variants", "GET", 200, fast);
+        metrics.observe_request("/variants", "GET", 404, Duration::from_millis(300));
+        let rendered = render(&metrics);
+
+        assert!(rendered.contains("# TYPE dms_http_requests_total counter\n"));
+        assert!(rendered.contains("# TYPE dms_http_request_duration_seconds histogram\n"));
+        let labels = r#"route="/variants",method="GET""#;
+        assert_eq!(
+            value(
+                &rendered,
+                &format!("dms_http_requests_total{{{labels},status=\"200\"}}")
+            ),
+            2.0
+        );
+        assert_eq!(
+            value(
+                &rendered,
+                &format!("dms_http_requests_total{{{labels},status=\"404\"}}")
+            ),
+            1.0
+        );
+
+        // Buckets are cumulative, ending with every observation at +Inf
+        let bucket = |le: &str| {
+            value(
+                &rendered,
+                &format!("dms_http_request_duration_seconds_bucket{{{labels},le=\"{le}\"}}"),
+            )
+        };
+        assert_eq!(bucket("0.001"), 0.0);
+        assert_eq!(bucket("0.005"), 2.0);
+        assert_eq!(bucket("0.25"), 2.0);
+        assert_eq!(bucket("0.5"), 3.0);
+        assert_eq!(bucket("+Inf"), 3.0);
+        assert_eq!(
+            value(
+                &rendered,
+                &format!("dms_http_request_duration_seconds_count{{{labels}}}")
+            ),
+            3.0
+        );
+        let sum = value(
+            &rendered,
+            &format!("dms_http_request_duration_seconds_sum{{{labels}}}"),
+        );
+        assert!((sum - 0.306).abs() < 1e-9, "{sum}");
+    }
+
+    #[test]
+    fn escapes_label_values() {
+        let metrics = Metrics::default();
+        metrics.observe_request("/a\"b\\c", "GET", 200, Duration::ZERO);
+        let rendered = render(&metrics);
+        assert!(
+            rendered.contains(
+                r#"dms_http_requests_total{route="/a\"b\\c",method="GET",status="200"} 1"#
+            ),
+            "{rendered}"
+        );
+        assert_eq!(format_value(f64::INFINITY), "+Inf");
+        assert_eq!(format_value(f64::NAN), "NaN");
+    }
+}
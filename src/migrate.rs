@@ -0,0 +1,156 @@
+use anyhow::{bail, Context};
+use sqlx::{
+    migrate::{Migrate, Migrator},
+    PgConnection, PgPool, SqlitePool,
+};
+use tracing::{info, warn};
+
+/// The migrations in `migrations/`, embedded at compile time.
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// The same schema for SQLite databases, from `migrations/sqlite/`.
+pub static SQLITE_MIGRATOR: Migrator = sqlx::migrate!("./migrations/sqlite");
+
+/// Last revision of the retired Alembic history. Its schema is exactly the
+/// first sqlx migration, so a database at this revision can adopt the sqlx
+/// history without re-running it.
+const ALEMBIC_HEAD: &str = "8cc733934278";
+
+/// Newest migration this build knows about.
+pub fn latest_version() -> i64 {
+    newest(&MIGRATOR)
+}
+
+/// Newest SQLite migration this build knows about.
+pub fn latest_sqlite_version() -> i64 {
+    newest(&SQLITE_MIGRATOR)
+}
+
+fn newest(migrator: &Migrator) -> i64 {
+    migrator
+        .iter()
+        .map(|migration| migration.version)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Applies every pending migration and returns the versions it applied.
+///
+/// Holds sqlx's per-database advisory lock for the whole run, so when several
+/// servers start at once one migrates and the others wait, then find nothing
+/// left to do.
+pub async fn run(pool: &PgPool) -> anyhow::Result<Vec<i64>> {
+    let mut conn = pool.acquire().await?;
+    conn.lock().await?;
+    let result = run_locked(&mut conn).await;
+    conn.unlock().await?;
+    result
+}
+
+async fn run_locked(conn: &mut PgConnection) -> anyhow::Result<Vec<i64>> {
+    adopt_alembic(conn).await?;
+    conn.ensure_migrations_table().await?;
+    let before = applied_versions(conn).await?;
+    // The lock is already held by `run`; taking it again from the migrator
+    // would deadlock on the same connection.
+    let migrator = Migrator {
+        migrations: MIGRATOR.migrations.clone(),
+        ignore_missing: MIGRATOR.ignore_missing,
+        locking: false,
+        no_tx: MIGRATOR.no_tx,
+    };
+    migrator
+        .run(&mut *conn)
+        .await
+        .context("could not apply migrations")?;
+    let after = applied_versions(conn).await?;
+    Ok(newly_applied(&MIGRATOR, &before, after))
+}
+
+/// Applies every pending SQLite migration and returns the versions it
+/// applied. SQLite serializes writers itself, so there is no lock to take.
+pub async fn run_sqlite(pool: &SqlitePool) -> anyhow::Result<Vec<i64>> {
+    let mut conn = pool.acquire().await?;
+    conn.ensure_migrations_table().await?;
+    let before = applied_versions(&mut *conn).await?;
+    SQLITE_MIGRATOR
+        .run(&mut *conn)
+        .await
+        .context("could not apply migrations")?;
+    let after = applied_versions(&mut *conn).await?;
+    Ok(newly_applied(&SQLITE_MIGRATOR, &before, after))
+}
+
+/// The versions in `after` but not in `before`, each logged.
+fn newly_applied(migrator: &Migrator, before: &[i64], after: Vec<i64>) -> Vec<i64> {
+    let applied: Vec<i64> = after
+        .into_iter()
+        .filter(|version| !before.contains(version))
+        .collect();
+    for version in &applied {
+        let description = migrator
+            .iter()
+            .find(|migration| migration.version == *version)
+            .map_or("", |migration| &migration.description);
+        info!("applied migration {version} {description}");
+    }
+    applied
+}
+
+async fn applied_versions(conn: &mut impl Migrate) -> anyhow::Result<Vec<i64>> {
+    Ok(conn
+        .list_applied_migrations()
+        .await?
+        .into_iter()
+        .map(|migration| migration.version)
+        .collect())
+}
+
+/// Moves a database created by the old Alembic scripts onto the sqlx history:
+/// records the initial migration as applied and drops `alembic_version`.
+/// Fails if the database is at any other Alembic revision, since its schema
+/// can no longer be assumed to match.
+async fn adopt_alembic(conn: &mut PgConnection) -> anyhow::Result<()> {
+    let (alembic, sqlx): (bool, bool) = sqlx::query_as(
+        "SELECT to_regclass('alembic_version') IS NOT NULL, \
+                to_regclass('_sqlx_migrations') IS NOT NULL",
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    if !alembic {
+        return Ok(());
+    }
+    if sqlx {
+        warn!("both alembic_version and _sqlx_migrations exist, ignoring alembic_version");
+        return Ok(());
+    }
+    let revision: Option<String> = sqlx::query_scalar("SELECT version_num FROM alembic_version")
+        .fetch_optional(&mut *conn)
+        .await?;
+    if revision.as_deref() != Some(ALEMBIC_HEAD) {
+        bail!(
+            "database is at Alembic revision {}, expected {ALEMBIC_HEAD}; \
+             its schema may differ from migrations/, migrate it by hand",
+            revision.as_deref().unwrap_or("<none>")
+        );
+    }
+    let initial = MIGRATOR.iter().next().context("no embedded migrations")?;
+    conn.ensure_migrations_table().await?;
+    sqlx::query(
+        "INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time) \
+         VALUES ($1, $2, TRUE, $3, -1)",
+    )
+    .bind(initial.version)
+    .bind(&*initial.description)
+    .bind(&*initial.checksum)
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query("DROP TABLE alembic_version")
+        .execute(&mut *conn)
+        .await?;
+    info!(
+        "adopted Alembic revision {ALEMBIC_HEAD} as migration {} {}",
+        initial.version, initial.description
+    );
+    Ok(())
+}
@@ -0,0 +1,894 @@
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+
+use async_trait::async_trait;
+use chrono::{NaiveDateTime, Utc};
+use sqlx::types::Json;
+
+use super::{passes_threshold, MetricRange, PoolStats, Repository, Result};
+use crate::access::{Member, Project, ProteinAccess, Role, Scope};
+use crate::audit::{Actor, AuditEntry, AuditFilter, Change, UploadBatch};
+use crate::auth::{ApiToken, Credentials, Session, User, UserUpdate};
+use crate::datasets::{Affected, Dataset, Operation, Selection};
+use crate::ingest::SourceFile;
+use crate::{migrate, Domain, Paint, PositionFilter, TableParams, Variant};
+
+#[derive(Debug, Default)]
+struct Protein {
+    sequence: Option<String>,
+    domains: Vec<Domain>,
+    project_id: Option<i32>,
+    public: bool,
+    archived: bool,
+}
+
+#[derive(Debug)]
+struct StoredToken {
+    user_id: i32,
+    token_hash: String,
+    token: ApiToken,
+}
+
+#[derive(Debug, Default)]
+struct Store {
+    proteins: BTreeMap<String, Protein>,
+    variants: Vec<Variant>,
+    archived: Vec<Variant>,
+    revision: u64,
+    users: Vec<Credentials>,
+    /// Keyed by token hash: (user id, expiry).
+    sessions: BTreeMap<String, (i32, NaiveDateTime)>,
+    tokens: Vec<StoredToken>,
+    projects: Vec<Project>,
+    /// Keyed by (project id, user id).
+    members: BTreeMap<(i32, i32), Role>,
+    batches: Vec<UploadBatch>,
+    /// Batch id by variant id.
+    variant_batches: BTreeMap<i32, i32>,
+    audit_log: Vec<AuditEntry>,
+    /// Last id handed out to a user, token, project or batch.
+    last_id: i32,
+}
+
+impl Store {
+    fn next_id(&mut self) -> i32 {
+        self.last_id += 1;
+        self.last_id
+    }
+
+    fn append(&mut self, actor: &Actor, change: &Change) {
+        let entry = AuditEntry {
+            id: self.audit_log.len() as i64 + 1,
+            created_on: Utc::now().naive_utc(),
+            user_id: actor.user_id,
+            actor: actor.name.clone(),
+            action: change.action,
+            protein: change.protein.clone(),
+            batch_id: change.batch_id,
+            details: Json(change.details.clone()),
+        };
+        self.audit_log.push(entry);
+    }
+
+    /// Whether `scope` may see the protein called `name`.
+    fn visible(&self, scope: Scope, name: &str) -> bool {
+        self.proteins.get(name).is_some_and(|protein| {
+            scope.sees_everything()
+                || protein.public
+                || protein
+                    .project_id
+                    .zip(scope.user_id())
+                    .is_some_and(|key| self.members.contains_key(&key))
+        })
+    }
+
+    /// Whether `selection` covers `variant`, by the batch that created it.
+    fn selects(&self, selection: &Selection, variant: &Variant) -> bool {
+        let batch = variant
+            .id
+            .and_then(|id| self.variant_batches.get(&id).copied());
+        selection.matches(variant, batch)
+    }
+
+    fn affected(&self, selection: &Selection) -> Affected {
+        let count = |variants: &[Variant]| {
+            variants
+                .iter()
+                .filter(|variant| self.selects(selection, variant))
+                .count() as i64
+        };
+        Affected {
+            variants: count(&self.variants),
+            archived: count(&self.archived),
+        }
+    }
+
+    /// Splits `variants` into the ones `selection` covers and the rest.
+    fn take(&self, selection: &Selection, variants: Vec<Variant>) -> (Vec<Variant>, Vec<Variant>) {
+        variants
+            .into_iter()
+            .partition(|variant| self.selects(selection, variant))
+    }
+
+    /// An enabled user by id.
+    fn active_user(&self, id: i32) -> Option<User> {
+        self.users
+            .iter()
+            .map(|credentials| &credentials.user)
+            .find(|user| user.id == id && !user.disabled)
+            .cloned()
+    }
+}
+
+/// `Repository` held in memory, for exercising handlers without a database.
+/// Answers like `PostgresRepository` does, ties in the ranked view included:
+/// the first variant by amino acid wins.
+#[derive(Debug, Default)]
+pub struct MemoryRepository {
+    store: RwLock<Store>,
+}
+
+impl MemoryRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a public protein outside any project.
+    pub fn add_protein(&self, name: &str, sequence: Option<&str>) {
+        let mut store = self.store.write().unwrap();
+        store.proteins.insert(
+            name.to_string(),
+            Protein {
+                sequence: sequence.map(str::to_string),
+                public: true,
+                ..Protein::default()
+            },
+        );
+        store.revision += 1;
+    }
+
+    pub fn add_domain(&self, protein: &str, domain: Domain) {
+        let mut store = self.store.write().unwrap();
+        if let Some(protein) = store.proteins.get_mut(protein) {
+            protein.domains.push(domain);
+            protein.domains.sort_by_key(|domain| domain.start_pos);
+        }
+        store.revision += 1;
+    }
+
+    /// Variants of one condition matching `keep`, by position and amino acid.
+    fn select(
+        &self,
+        protein: &str,
+        condition: &str,
+        keep: impl Fn(&Variant) -> bool,
+    ) -> Vec<Variant> {
+        let store = self.store.read().unwrap();
+        let mut variants: Vec<Variant> = store
+            .variants
+            .iter()
+            .filter(|variant| variant.protein == protein && variant.condition == condition)
+            .filter(|variant| keep(variant))
+            .cloned()
+            .collect();
+        variants.sort_by(|a, b| (a.pos, &a.aa).cmp(&(b.pos, &b.aa)));
+        variants
+    }
+}
+
+#[async_trait]
+impl Repository for MemoryRepository {
+    async fn list_proteins(&self, scope: Scope) -> Result<Vec<String>> {
+        let store = self.store.read().unwrap();
+        Ok(store
+            .proteins
+            .iter()
+            .filter(|(name, protein)| !protein.archived && store.visible(scope, name))
+            .map(|(name, _)| name.clone())
+            .collect())
+    }
+
+    async fn protein_visible(&self, scope: Scope, protein: &str) -> Result<bool> {
+        let store = self.store.read().unwrap();
+        Ok(store
+            .proteins
+            .get(protein)
+            .is_some_and(|found| !found.archived && store.visible(scope, protein)))
+    }
+
+    async fn protein_sequence(&self, protein: &str) -> Result<Option<String>> {
+        let store = self.store.read().unwrap();
+        Ok(store
+            .proteins
+            .get(protein)
+            .and_then(|protein| protein.sequence.clone()))
+    }
+
+    async fn domains(&self, protein: &str) -> Result<Vec<Domain>> {
+        let store = self.store.read().unwrap();
+        Ok(store
+            .proteins
+            .get(protein)
+            .map(|protein| protein.domains.clone())
+            .unwrap_or_default())
+    }
+
+    async fn conditions_for(&self, protein: &str) -> Result<Vec<String>> {
+        let store = self.store.read().unwrap();
+        let mut conditions: Vec<String> = store
+            .variants
+            .iter()
+            .filter(|variant| variant.protein == protein)
+            .map(|variant| variant.condition.clone())
+            .collect();
+        conditions.sort();
+        conditions.dedup();
+        Ok(conditions)
+    }
+
+    async fn max_position(&self, protein: &str, condition: &str) -> Result<Option<i32>> {
+        Ok(self
+            .select(protein, condition, |_| true)
+            .iter()
+            .map(|variant| variant.pos)
+            .max())
+    }
+
+    async fn all_variants(&self, protein: &str, condition: &str) -> Result<Vec<Variant>> {
+        Ok(self.select(protein, condition, |_| true))
+    }
+
+    async fn variants_in_range(
+        &self,
+        protein: &str,
+        condition: &str,
+        start: i32,
+        end: i32,
+    ) -> Result<Vec<Variant>> {
+        Ok(self.select(protein, condition, |variant| {
+            (start..=end).contains(&variant.pos)
+        }))
+    }
+
+    async fn variants_page(
+        &self,
+        params: &TableParams,
+        start: i32,
+        end: i32,
+    ) -> Result<Vec<Variant>> {
+        let in_page = |variant: &Variant| (start..=end).contains(&variant.pos);
+        let rank = |variant: &Variant| match params.position_filter {
+            PositionFilter::MostSignificantPValue => variant.p_value,
+            PositionFilter::LargestLog2FoldChange => -variant.log2_fold_change,
+            PositionFilter::LargestZStatistic => -variant.statistic,
+            PositionFilter::NoOrder => 0.0,
+        };
+        let variants = self.select(&params.protein, &params.condition, in_page);
+        Ok(match (&params.position_filter, params.threshold) {
+            (PositionFilter::NoOrder, None) => variants,
+            (PositionFilter::NoOrder, Some(threshold)) => variants
+                .into_iter()
+                .filter(|variant| passes_threshold(variant, params.paint, threshold))
+                .collect(),
+            _ => {
+                let mut best: BTreeMap<i32, Variant> = BTreeMap::new();
+                for variant in variants {
+                    match best.get(&variant.pos) {
+                        Some(current) if rank(current).total_cmp(&rank(&variant)).is_le() => {}
+                        _ => {
+                            best.insert(variant.pos, variant);
+                        }
+                    }
+                }
+                best.into_values().collect()
+            }
+        })
+    }
+
+    async fn variant_by_id(&self, id: i32) -> Result<Option<Variant>> {
+        let store = self.store.read().unwrap();
+        Ok(store
+            .variants
+            .iter()
+            .find(|variant| variant.id == Some(id))
+            .cloned())
+    }
+
+    async fn range_for(
+        &self,
+        protein: &str,
+        condition: &str,
+        paint: Paint,
+    ) -> Result<Option<MetricRange>> {
+        if let Paint::Classification = paint {
+            return Ok(None);
+        }
+        let values: Vec<f64> = self
+            .select(protein, condition, |_| true)
+            .iter()
+            .map(|variant| paint.value_of(variant))
+            .collect();
+        if values.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(MetricRange {
+            min: values.iter().copied().fold(f64::INFINITY, f64::min),
+            max: values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            max_abs: values.iter().map(|value| value.abs()).fold(0.0, f64::max),
+        }))
+    }
+
+    async fn create_protein(
+        &self,
+        name: &str,
+        _pdb_id: Option<&str>,
+        sequence: Option<&str>,
+    ) -> Result<Option<i32>> {
+        let mut store = self.store.write().unwrap();
+        if store.proteins.contains_key(name) {
+            return Ok(None);
+        }
+        store.proteins.insert(
+            name.to_string(),
+            Protein {
+                sequence: sequence.map(str::to_string),
+                ..Protein::default()
+            },
+        );
+        store.revision += 1;
+        Ok(Some(store.proteins.len() as i32))
+    }
+
+    async fn insert_variants(
+        &self,
+        protein: &str,
+        variants: &[Variant],
+        source: &SourceFile,
+        actor: &Actor,
+    ) -> Result<UploadBatch> {
+        let mut store = self.store.write().unwrap();
+        if !store.proteins.contains_key(protein) {
+            return Err(sqlx::Error::RowNotFound);
+        }
+        let batch = UploadBatch {
+            id: store.next_id(),
+            protein: protein.to_string(),
+            file_name: source.name.clone(),
+            sha256: source.sha256.clone(),
+            rows_inserted: variants.len() as i64,
+            rows_rejected: source.rows_rejected as i64,
+            columns: Json(source.columns.clone()),
+            actor: actor.name.clone(),
+            created_on: Utc::now().naive_utc(),
+        };
+        let mut next_id = store
+            .variants
+            .iter()
+            .filter_map(|variant| variant.id)
+            .max()
+            .unwrap_or(0);
+        for variant in variants {
+            next_id += 1;
+            store.variants.push(Variant {
+                id: Some(next_id),
+                protein: protein.to_string(),
+                ..variant.clone()
+            });
+            store.variant_batches.insert(next_id, batch.id);
+        }
+        store.revision += 1;
+        store.append(actor, &Change::ingest(&batch, variants));
+        store.batches.push(batch.clone());
+        Ok(batch)
+    }
+
+    async fn variant_batch(&self, variant_id: i32) -> Result<Option<UploadBatch>> {
+        let store = self.store.read().unwrap();
+        Ok(store
+            .variant_batches
+            .get(&variant_id)
+            .and_then(|id| store.batches.iter().find(|batch| batch.id == *id).cloned()))
+    }
+
+    async fn record(&self, actor: &Actor, change: &Change) -> Result<()> {
+        self.store.write().unwrap().append(actor, change);
+        Ok(())
+    }
+
+    async fn audit_log(&self, filter: &AuditFilter, scope: Scope) -> Result<Vec<AuditEntry>> {
+        let store = self.store.read().unwrap();
+        Ok(store
+            .audit_log
+            .iter()
+            .rev()
+            .filter(|entry| store.visible(scope, &entry.protein) && filter.matches(entry))
+            .take(filter.limit() as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn datasets(&self, protein: &str) -> Result<Vec<Dataset>> {
+        let store = self.store.read().unwrap();
+        let mut counts: BTreeMap<(&str, &str), (i64, i64)> = BTreeMap::new();
+        for variant in store.variants.iter().filter(|v| v.protein == protein) {
+            counts
+                .entry((&variant.condition, &variant.version))
+                .or_default()
+                .0 += 1;
+        }
+        for variant in store.archived.iter().filter(|v| v.protein == protein) {
+            counts
+                .entry((&variant.condition, &variant.version))
+                .or_default()
+                .1 += 1;
+        }
+        Ok(counts
+            .into_iter()
+            .map(|((condition, version), (variants, archived))| Dataset {
+                condition: condition.to_string(),
+                version: version.to_string(),
+                variants,
+                archived,
+            })
+            .collect())
+    }
+
+    async fn upload_batches(&self, protein: &str) -> Result<Vec<UploadBatch>> {
+        let store = self.store.read().unwrap();
+        Ok(store
+            .batches
+            .iter()
+            .rev()
+            .filter(|batch| batch.protein == protein)
+            .cloned()
+            .collect())
+    }
+
+    async fn affected(&self, selection: &Selection) -> Result<Affected> {
+        Ok(self.store.read().unwrap().affected(selection))
+    }
+
+    async fn apply(
+        &self,
+        operation: Operation,
+        selection: &Selection,
+        actor: &Actor,
+    ) -> Result<Affected> {
+        let mut store = self.store.write().unwrap();
+        let affected = store.affected(selection);
+        store.append(actor, &Change::dataset(operation, selection, affected));
+        let name = selection.protein.as_str();
+        let variants = std::mem::take(&mut store.variants);
+        let archived = std::mem::take(&mut store.archived);
+        let (selected, variants) = store.take(selection, variants);
+        let (selected_archived, archived) = store.take(selection, archived);
+        match operation {
+            Operation::Delete if selection.whole_protein() => {
+                store.proteins.remove(name);
+                store.batches.retain(|batch| batch.protein != name);
+                (store.variants, store.archived) = (variants, archived);
+            }
+            Operation::Delete => {
+                if selection.whole_batch() {
+                    store
+                        .batches
+                        .retain(|batch| Some(batch.id) != selection.batch);
+                }
+                (store.variants, store.archived) = (variants, archived);
+            }
+            Operation::Archive if selection.whole_protein() => {
+                if let Some(protein) = store.proteins.get_mut(name) {
+                    protein.archived = true;
+                }
+                store.variants = variants.into_iter().chain(selected).collect();
+                store.archived = archived.into_iter().chain(selected_archived).collect();
+            }
+            Operation::Archive => {
+                store.variants = variants;
+                store.archived = archived
+                    .into_iter()
+                    .chain(selected_archived)
+                    .chain(selected)
+                    .collect();
+            }
+            Operation::Restore => {
+                if selection.whole_protein() {
+                    if let Some(protein) = store.proteins.get_mut(name) {
+                        protein.archived = false;
+                    }
+                }
+                store.variants = variants
+                    .into_iter()
+                    .chain(selected)
+                    .chain(selected_archived)
+                    .collect();
+                store.archived = archived;
+            }
+        }
+        store.revision += 1;
+        Ok(affected)
+    }
+
+    async fn dataset_revision(&self) -> Result<u64> {
+        Ok(self.store.read().unwrap().revision)
+    }
+
+    async fn create_user(
+        &self,
+        username: &str,
+        password_hash: &str,
+        is_admin: bool,
+    ) -> Result<Option<i32>> {
+        let mut store = self.store.write().unwrap();
+        if store
+            .users
+            .iter()
+            .any(|credentials| credentials.user.username == username)
+        {
+            return Ok(None);
+        }
+        let id = store.next_id();
+        store.users.push(Credentials {
+            user: User {
+                id,
+                username: username.to_string(),
+                is_admin,
+                disabled: false,
+                created_on: Utc::now().naive_utc(),
+            },
+            password_hash: password_hash.to_string(),
+        });
+        Ok(Some(id))
+    }
+
+    async fn users(&self) -> Result<Vec<User>> {
+        let store = self.store.read().unwrap();
+        let mut users: Vec<User> = store
+            .users
+            .iter()
+            .map(|credentials| credentials.user.clone())
+            .collect();
+        users.sort_by(|a, b| a.username.cmp(&b.username));
+        Ok(users)
+    }
+
+    async fn credentials(&self, username: &str) -> Result<Option<Credentials>> {
+        let store = self.store.read().unwrap();
+        Ok(store
+            .users
+            .iter()
+            .find(|credentials| credentials.user.username == username)
+            .cloned())
+    }
+
+    async fn update_user(&self, id: i32, update: &UserUpdate) -> Result<bool> {
+        let mut store = self.store.write().unwrap();
+        let Some(credentials) = store
+            .users
+            .iter_mut()
+            .find(|credentials| credentials.user.id == id)
+        else {
+            return Ok(false);
+        };
+        if let Some(password_hash) = &update.password_hash {
+            credentials.password_hash = password_hash.clone();
+        }
+        if let Some(is_admin) = update.is_admin {
+            credentials.user.is_admin = is_admin;
+        }
+        if let Some(disabled) = update.disabled {
+            credentials.user.disabled = disabled;
+        }
+        if update.revokes_sessions() {
+            store.sessions.retain(|_, (user_id, _)| *user_id != id);
+        }
+        Ok(true)
+    }
+
+    async fn delete_user(&self, id: i32) -> Result<bool> {
+        let mut store = self.store.write().unwrap();
+        let before = store.users.len();
+        store.users.retain(|credentials| credentials.user.id != id);
+        store.sessions.retain(|_, (user_id, _)| *user_id != id);
+        store.tokens.retain(|token| token.user_id != id);
+        store.members.retain(|(_, user_id), _| *user_id != id);
+        for entry in &mut store.audit_log {
+            if entry.user_id == Some(id) {
+                entry.user_id = None;
+            }
+        }
+        Ok(store.users.len() < before)
+    }
+
+    async fn create_session(
+        &self,
+        user_id: i32,
+        token_hash: &str,
+        expires_on: NaiveDateTime,
+    ) -> Result<()> {
+        let mut store = self.store.write().unwrap();
+        let now = Utc::now().naive_utc();
+        store
+            .sessions
+            .retain(|_, (_, expires_on)| *expires_on >= now);
+        store
+            .sessions
+            .insert(token_hash.to_string(), (user_id, expires_on));
+        Ok(())
+    }
+
+    async fn session(&self, token_hash: &str) -> Result<Option<Session>> {
+        let store = self.store.read().unwrap();
+        Ok(store
+            .sessions
+            .get(token_hash)
+            .and_then(|&(user_id, expires_on)| {
+                store
+                    .active_user(user_id)
+                    .map(|user| Session { user, expires_on })
+            }))
+    }
+
+    async fn delete_session(&self, token_hash: &str) -> Result<()> {
+        self.store.write().unwrap().sessions.remove(token_hash);
+        Ok(())
+    }
+
+    async fn create_api_token(
+        &self,
+        user_id: i32,
+        name: &str,
+        token_hash: &str,
+    ) -> Result<Option<i32>> {
+        let mut store = self.store.write().unwrap();
+        if store
+            .tokens
+            .iter()
+            .any(|token| token.user_id == user_id && token.token.name == name)
+        {
+            return Ok(None);
+        }
+        let id = store.next_id();
+        store.tokens.push(StoredToken {
+            user_id,
+            token_hash: token_hash.to_string(),
+            token: ApiToken {
+                id,
+                name: name.to_string(),
+                created_on: Utc::now().naive_utc(),
+                last_used_on: None,
+            },
+        });
+        Ok(Some(id))
+    }
+
+    async fn api_tokens(&self, user_id: i32) -> Result<Vec<ApiToken>> {
+        let store = self.store.read().unwrap();
+        Ok(store
+            .tokens
+            .iter()
+            .rev()
+            .filter(|token| token.user_id == user_id)
+            .map(|token| token.token.clone())
+            .collect())
+    }
+
+    async fn token_user(&self, token_hash: &str) -> Result<Option<User>> {
+        let mut store = self.store.write().unwrap();
+        let Some(token) = store
+            .tokens
+            .iter_mut()
+            .find(|token| token.token_hash == token_hash)
+        else {
+            return Ok(None);
+        };
+        token.token.last_used_on = Some(Utc::now().naive_utc());
+        let user_id = token.user_id;
+        Ok(store.active_user(user_id))
+    }
+
+    async fn delete_api_token(&self, user_id: i32, id: i32) -> Result<bool> {
+        let mut store = self.store.write().unwrap();
+        let before = store.tokens.len();
+        store
+            .tokens
+            .retain(|token| token.user_id != user_id || token.token.id != id);
+        Ok(store.tokens.len() < before)
+    }
+
+    async fn protein_access(&self) -> Result<Vec<ProteinAccess>> {
+        let store = self.store.read().unwrap();
+        Ok(store
+            .proteins
+            .iter()
+            .map(|(name, protein)| ProteinAccess {
+                name: name.clone(),
+                project_id: protein.project_id,
+                public: protein.public,
+                archived: protein.archived,
+            })
+            .collect())
+    }
+
+    async fn set_protein_access(
+        &self,
+        protein: &str,
+        project_id: Option<i32>,
+        public: bool,
+    ) -> Result<bool> {
+        let mut store = self.store.write().unwrap();
+        let Some(protein) = store.proteins.get_mut(protein) else {
+            return Ok(false);
+        };
+        protein.project_id = project_id;
+        protein.public = public;
+        store.revision += 1;
+        Ok(true)
+    }
+
+    async fn create_project(&self, name: &str) -> Result<Option<i32>> {
+        let mut store = self.store.write().unwrap();
+        if store.projects.iter().any(|project| project.name == name) {
+            return Ok(None);
+        }
+        let id = store.next_id();
+        store.projects.push(Project {
+            id,
+            name: name.to_string(),
+            created_on: Utc::now().naive_utc(),
+        });
+        store.projects.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(Some(id))
+    }
+
+    async fn projects(&self) -> Result<Vec<Project>> {
+        Ok(self.store.read().unwrap().projects.clone())
+    }
+
+    async fn members(&self, project_id: i32) -> Result<Vec<Member>> {
+        let store = self.store.read().unwrap();
+        let mut members: Vec<Member> = store
+            .members
+            .range((project_id, i32::MIN)..=(project_id, i32::MAX))
+            .filter_map(|(&(_, user_id), &role)| {
+                let user = store.users.iter().find(|user| user.user.id == user_id)?;
+                Some(Member {
+                    user_id,
+                    username: user.user.username.clone(),
+                    role,
+                })
+            })
+            .collect();
+        members.sort_by(|a, b| a.username.cmp(&b.username));
+        Ok(members)
+    }
+
+    async fn roles(&self, user_id: i32) -> Result<BTreeMap<i32, Role>> {
+        let store = self.store.read().unwrap();
+        Ok(store
+            .members
+            .iter()
+            .filter(|((_, member), _)| *member == user_id)
+            .map(|(&(project_id, _), &role)| (project_id, role))
+            .collect())
+    }
+
+    async fn set_member(&self, project_id: i32, user_id: i32, role: Option<Role>) -> Result<bool> {
+        let mut store = self.store.write().unwrap();
+        let changed = match role {
+            Some(role) => {
+                store.members.insert((project_id, user_id), role);
+                true
+            }
+            None => store.members.remove(&(project_id, user_id)).is_some(),
+        };
+        store.revision += u64::from(changed);
+        Ok(changed)
+    }
+
+    async fn applied_migration(&self) -> Result<Option<i64>> {
+        Ok(Some(migrate::latest_version()))
+    }
+
+    fn latest_migration(&self) -> i64 {
+        migrate::latest_version()
+    }
+
+    fn pool_stats(&self) -> PoolStats {
+        PoolStats::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::testing::{p1_variants, params, variant};
+
+    async fn repository() -> MemoryRepository {
+        let repository = MemoryRepository::new();
+        repository.add_protein("P1", Some("MA"));
+        repository
+            .insert_variants(
+                "P1",
+                &p1_variants(),
+                &SourceFile::default(),
+                &Actor::command_line("test"),
+            )
+            .await
+            .unwrap();
+        repository
+    }
+
+    #[tokio::test]
+    async fn ranked_page_keeps_best_variant_per_position() {
+        let repository = repository().await;
+        let by_p = repository
+            .variants_page(
+                &params(PositionFilter::MostSignificantPValue, Paint::PValue, None),
+                1,
+                2,
+            )
+            .await
+            .unwrap();
+        let aas: Vec<(i32, &str)> = by_p.iter().map(|v| (v.pos, v.aa.as_str())).collect();
+        assert_eq!(aas, [(1, "C"), (2, "A")]);
+
+        let by_effect = repository
+            .variants_page(
+                &params(PositionFilter::LargestLog2FoldChange, Paint::PValue, None),
+                1,
+                1,
+            )
+            .await
+            .unwrap();
+        assert_eq!(by_effect.len(), 1);
+        assert_eq!(by_effect[0].aa, "A");
+    }
+
+    #[tokio::test]
+    async fn threshold_filters_unordered_page() {
+        let repository = repository().await;
+        let page = repository
+            .variants_page(
+                &params(PositionFilter::NoOrder, Paint::PValue, Some(0.25)),
+                1,
+                2,
+            )
+            .await
+            .unwrap();
+        let ids: Vec<Option<i32>> = page.iter().map(|v| v.id).collect();
+        assert_eq!(ids, [Some(1), Some(2)]);
+
+        // Classification has no threshold to filter by
+        let page = repository
+            .variants_page(
+                &params(PositionFilter::NoOrder, Paint::Classification, Some(0.25)),
+                1,
+                2,
+            )
+            .await
+            .unwrap();
+        assert_eq!(page.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn insert_needs_an_existing_protein() {
+        let repository = repository().await;
+        let result = repository
+            .insert_variants(
+                "P2",
+                &[variant(1, "A", 0.0, 1.0)],
+                &SourceFile::default(),
+                &Actor::command_line("test"),
+            )
+            .await;
+        assert!(matches!(result, Err(sqlx::Error::RowNotFound)));
+        assert_eq!(repository.conditions_for("P1").await.unwrap(), ["c1"]);
+        let range = repository
+            .range_for("P1", "c1", Paint::Log2FoldChange)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((range.min, range.max, range.max_abs), (-2.0, 1.5, 2.0));
+    }
+}
@@ -0,0 +1,250 @@
+//! Storage behind the handlers. Handlers only see `dyn Repository`, so they
+//! can run against Postgres in production and `MemoryRepository` in tests.
+
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+
+use chrono::NaiveDateTime;
+
+use crate::access::{Member, Project, ProteinAccess, Role, Scope};
+use crate::audit::{Actor, AuditEntry, AuditFilter, Change, UploadBatch};
+use crate::auth::{ApiToken, Credentials, Session, User, UserUpdate};
+use crate::datasets::{Affected, Dataset, Operation, Selection};
+use crate::ingest::SourceFile;
+use crate::{Domain, Paint, TableParams, Variant};
+
+pub mod memory;
+pub mod postgres;
+pub mod sqlite;
+#[cfg(test)]
+mod testing;
+
+pub use memory::MemoryRepository;
+pub use postgres::PostgresRepository;
+pub use sqlite::SqliteRepository;
+
+pub type Result<T> = std::result::Result<T, sqlx::Error>;
+
+/// Smallest, largest and largest absolute value of one metric across every
+/// version of a protein and condition.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MetricRange {
+    pub min: f64,
+    pub max: f64,
+    pub max_abs: f64,
+}
+
+/// Connection pool usage for `/metrics`; all zero for backends without a pool.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PoolStats {
+    pub size: u32,
+    pub idle: u32,
+    pub max: u32,
+}
+
+#[async_trait]
+pub trait Repository: Send + Sync {
+    /// Names of the proteins `scope` may see, sorted, without archived ones.
+    /// Handlers check every protein they are asked about against this list.
+    async fn list_proteins(&self, scope: Scope) -> Result<Vec<String>>;
+
+    /// Whether `protein` is in `list_proteins(scope)`, without listing them.
+    async fn protein_visible(&self, scope: Scope, protein: &str) -> Result<bool>;
+
+    async fn protein_sequence(&self, protein: &str) -> Result<Option<String>>;
+
+    /// Domains of `protein`, by start position.
+    async fn domains(&self, protein: &str) -> Result<Vec<Domain>>;
+
+    /// Conditions with data for `protein`, sorted.
+    async fn conditions_for(&self, protein: &str) -> Result<Vec<String>>;
+
+    /// Highest position with data, `None` if the condition has none.
+    async fn max_position(&self, protein: &str, condition: &str) -> Result<Option<i32>>;
+
+    /// Every variant of a condition, ordered by position and amino acid.
+    async fn all_variants(&self, protein: &str, condition: &str) -> Result<Vec<Variant>>;
+
+    /// Variants at positions `start..=end`, ordered by position and amino acid.
+    async fn variants_in_range(
+        &self,
+        protein: &str,
+        condition: &str,
+        start: i32,
+        end: i32,
+    ) -> Result<Vec<Variant>>;
+
+    /// Variants at positions `start..=end` after the position filter and
+    /// threshold of `params`, as the heatmap and table show them.
+    async fn variants_page(
+        &self,
+        params: &TableParams,
+        start: i32,
+        end: i32,
+    ) -> Result<Vec<Variant>>;
+
+    async fn variant_by_id(&self, id: i32) -> Result<Option<Variant>>;
+
+    /// `None` when the condition has no data for `paint`.
+    async fn range_for(
+        &self,
+        protein: &str,
+        condition: &str,
+        paint: Paint,
+    ) -> Result<Option<MetricRange>>;
+
+    /// Adds a protein and returns its id, `None` if the name is taken.
+    async fn create_protein(
+        &self,
+        name: &str,
+        pdb_id: Option<&str>,
+        sequence: Option<&str>,
+    ) -> Result<Option<i32>>;
+
+    /// Stores `variants` for an existing `protein` as a new upload batch of
+    /// `source` and records the ingest in the audit log.
+    async fn insert_variants(
+        &self,
+        protein: &str,
+        variants: &[Variant],
+        source: &SourceFile,
+        actor: &Actor,
+    ) -> Result<UploadBatch>;
+
+    /// The upload batch that created a variant, `None` for variants loaded
+    /// before batches existed.
+    async fn variant_batch(&self, variant_id: i32) -> Result<Option<UploadBatch>>;
+
+    /// Appends a change to the audit log.
+    async fn record(&self, actor: &Actor, change: &Change) -> Result<()>;
+
+    /// Audit entries passing `filter` about proteins `scope` may see, newest
+    /// first. Entries about deleted proteins only show up for
+    /// `Scope::Everything`.
+    async fn audit_log(&self, filter: &AuditFilter, scope: Scope) -> Result<Vec<AuditEntry>>;
+
+    /// Shown and archived variants of `protein` per condition and version.
+    async fn datasets(&self, protein: &str) -> Result<Vec<Dataset>>;
+
+    /// Upload batches of `protein`, newest first.
+    async fn upload_batches(&self, protein: &str) -> Result<Vec<UploadBatch>>;
+
+    /// How many shown and archived variants `selection` covers.
+    async fn affected(&self, selection: &Selection) -> Result<Affected>;
+
+    /// Deletes, archives or restores `selection` in one transaction and
+    /// records it in the audit log. Returns the variants it covered.
+    async fn apply(
+        &self,
+        operation: Operation,
+        selection: &Selection,
+        actor: &Actor,
+    ) -> Result<Affected>;
+
+    /// Bumped on every change to the stored data; 0 before any.
+    async fn dataset_revision(&self) -> Result<u64>;
+
+    /// Adds an account and returns its id, `None` if the username is taken.
+    async fn create_user(
+        &self,
+        username: &str,
+        password_hash: &str,
+        is_admin: bool,
+    ) -> Result<Option<i32>>;
+
+    /// Every account, by username.
+    async fn users(&self) -> Result<Vec<User>>;
+
+    async fn credentials(&self, username: &str) -> Result<Option<Credentials>>;
+
+    /// Applies `update` and ends the user's sessions when it
+    /// `revokes_sessions`. `false` if there is no such user.
+    async fn update_user(&self, id: i32, update: &UserUpdate) -> Result<bool>;
+
+    /// Removes an account with its sessions and tokens.
+    async fn delete_user(&self, id: i32) -> Result<bool>;
+
+    /// Starts a session and forgets the ones that have expired.
+    async fn create_session(
+        &self,
+        user_id: i32,
+        token_hash: &str,
+        expires_on: NaiveDateTime,
+    ) -> Result<()>;
+
+    /// The session behind a cookie, `None` for an unknown one or a disabled
+    /// user. Expired sessions are returned; the caller checks `expires_on`.
+    async fn session(&self, token_hash: &str) -> Result<Option<Session>>;
+
+    async fn delete_session(&self, token_hash: &str) -> Result<()>;
+
+    /// Adds an API token and returns its id, `None` if the user already has
+    /// one with this name.
+    async fn create_api_token(
+        &self,
+        user_id: i32,
+        name: &str,
+        token_hash: &str,
+    ) -> Result<Option<i32>>;
+
+    /// The user's tokens, newest first.
+    async fn api_tokens(&self, user_id: i32) -> Result<Vec<ApiToken>>;
+
+    /// The owner of a token, recording that it was used. `None` for an unknown
+    /// token or a disabled user.
+    async fn token_user(&self, token_hash: &str) -> Result<Option<User>>;
+
+    async fn delete_api_token(&self, user_id: i32, id: i32) -> Result<bool>;
+
+    /// Project, public and archived flags of every protein, by name.
+    async fn protein_access(&self) -> Result<Vec<ProteinAccess>>;
+
+    /// Moves `protein` to a project and publishes or hides it. `false` if
+    /// there is no such protein.
+    async fn set_protein_access(
+        &self,
+        protein: &str,
+        project_id: Option<i32>,
+        public: bool,
+    ) -> Result<bool>;
+
+    /// Adds a project and returns its id, `None` if the name is taken.
+    async fn create_project(&self, name: &str) -> Result<Option<i32>>;
+
+    /// Every project, by name.
+    async fn projects(&self) -> Result<Vec<Project>>;
+
+    /// Members of a project, by username.
+    async fn members(&self, project_id: i32) -> Result<Vec<Member>>;
+
+    /// The user's role in each of their projects, by project id.
+    async fn roles(&self, user_id: i32) -> Result<BTreeMap<i32, Role>>;
+
+    /// Gives a user a role in a project, or with `None` removes them from
+    /// it. `false` if nothing changed because there was nothing to remove.
+    async fn set_member(&self, project_id: i32, user_id: i32, role: Option<Role>) -> Result<bool>;
+
+    /// Highest migration applied. Errors when the schema was never migrated.
+    async fn applied_migration(&self) -> Result<Option<i64>>;
+
+    /// Newest migration this build has for the backend.
+    fn latest_migration(&self) -> i64;
+
+    fn pool_stats(&self) -> PoolStats;
+}
+
+/// Whether `variant` passes the table threshold for `paint`. A positive log2
+/// fold change passes below the threshold and a negative one above it, as the
+/// SQL behind `variants_page` has it; classification has no threshold.
+pub fn passes_threshold(variant: &Variant, paint: Paint, threshold: f64) -> bool {
+    match paint {
+        Paint::PValue => variant.p_value < threshold,
+        Paint::Log2FoldChange if variant.log2_fold_change >= 0.0 => {
+            variant.log2_fold_change < threshold
+        }
+        Paint::Log2FoldChange => variant.log2_fold_change > threshold,
+        Paint::ZStatistic => variant.statistic < threshold,
+        Paint::Classification => true,
+    }
+}
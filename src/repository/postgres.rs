@@ -0,0 +1,1140 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use chrono::{NaiveDateTime, Utc};
+use serde_json::Value;
+use sqlx::types::Json;
+use sqlx::{PgConnection, PgPool};
+
+use super::{MetricRange, PoolStats, Repository, Result};
+use crate::access::{Member, Project, ProteinAccess, Role, Scope};
+use crate::audit::{self, Actor, AuditEntry, AuditFilter, Change, UploadBatch};
+use crate::auth::{ApiToken, Credentials, Session, User, UserUpdate};
+use crate::datasets::{Affected, Dataset, Operation, Selection};
+use crate::ingest::SourceFile;
+use crate::metrics::TimedQuery;
+use crate::{ingest, migrate, Domain, Paint, PositionFilter, TableParams, Variant};
+
+/// `Repository` over the schema in `migrations/`. Ranges and maximum
+/// positions come from the summary tables the triggers maintain.
+#[derive(Debug, Clone)]
+pub struct PostgresRepository {
+    pool: PgPool,
+}
+
+impl PostgresRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+}
+
+#[async_trait]
+impl Repository for PostgresRepository {
+    async fn list_proteins(&self, scope: Scope) -> Result<Vec<String>> {
+        sqlx::query_scalar!(
+            r#"
+            SELECT name FROM protein
+            WHERE archived_on IS NULL
+            AND ($1 OR public OR project_id IN (
+                SELECT project_id FROM project_member WHERE user_id = $2
+            ))
+            ORDER BY name
+            "#,
+            scope.sees_everything(),
+            scope.user_id()
+        )
+        .fetch_all(&self.pool)
+        .timed("list_proteins")
+        .await
+    }
+
+    async fn protein_visible(&self, scope: Scope, protein: &str) -> Result<bool> {
+        sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM protein
+                WHERE name = $1
+                AND archived_on IS NULL
+                AND ($2 OR public OR project_id IN (
+                    SELECT project_id FROM project_member WHERE user_id = $3
+                ))
+            ) as "visible!"
+            "#,
+            protein,
+            scope.sees_everything(),
+            scope.user_id()
+        )
+        .fetch_one(&self.pool)
+        .timed("protein_visible")
+        .await
+    }
+
+    async fn protein_sequence(&self, protein: &str) -> Result<Option<String>> {
+        Ok(
+            sqlx::query_scalar!("SELECT sequence FROM protein WHERE name = $1", protein)
+                .fetch_optional(&self.pool)
+                .timed("get_protein_sequence")
+                .await?
+                .flatten(),
+        )
+    }
+
+    async fn domains(&self, protein: &str) -> Result<Vec<Domain>> {
+        sqlx::query_as!(
+            Domain,
+            r#"
+            SELECT protein_domain.name, protein_domain.start_pos, protein_domain.end_pos
+            FROM protein_domain
+            JOIN protein ON protein_domain.protein_id = protein.id
+            WHERE protein.name = $1
+            ORDER BY protein_domain.start_pos
+            "#,
+            protein
+        )
+        .fetch_all(&self.pool)
+        .timed("get_domains")
+        .await
+    }
+
+    async fn conditions_for(&self, protein: &str) -> Result<Vec<String>> {
+        sqlx::query_scalar!(
+            r#"
+            SELECT DISTINCT variant_summary.condition FROM variant_summary
+            JOIN protein ON variant_summary.protein_id = protein.id
+            WHERE protein.name = $1
+            ORDER BY variant_summary.condition
+            "#,
+            protein
+        )
+        .fetch_all(&self.pool)
+        .timed("get_conditions")
+        .await
+    }
+
+    async fn max_position(&self, protein: &str, condition: &str) -> Result<Option<i32>> {
+        sqlx::query_scalar!(
+            r#"
+            SELECT max(variant_summary.max_pos) as maximum FROM variant_summary
+            JOIN protein ON variant_summary.protein_id = protein.id
+            WHERE protein.name = $1
+            AND variant_summary.condition = $2
+            "#,
+            protein,
+            condition
+        )
+        .fetch_one(&self.pool)
+        .timed("get_max_position")
+        .await
+    }
+
+    async fn all_variants(&self, protein: &str, condition: &str) -> Result<Vec<Variant>> {
+        sqlx::query_as!(
+            Variant,
+            r#"SELECT
+                variant.id,
+                variant.chunk,
+                variant.pos,
+                variant.p_value,
+                variant.created_on,
+                variant.log2_fold_change,
+                variant.log2_std_error,
+                variant.statistic,
+                variant.condition,
+                variant.aa,
+                variant.version,
+                protein.name as protein
+            FROM variant
+            JOIN protein ON variant.protein_id = protein.id
+            WHERE protein.name = $1
+            AND variant.condition = $2
+            ORDER BY variant.pos, variant.aa
+            "#,
+            protein,
+            condition,
+        )
+        .fetch_all(&self.pool)
+        .timed("get_all_variants")
+        .await
+    }
+
+    async fn variants_in_range(
+        &self,
+        protein: &str,
+        condition: &str,
+        start: i32,
+        end: i32,
+    ) -> Result<Vec<Variant>> {
+        sqlx::query_as!(
+            Variant,
+            r#"SELECT
+                variant.id,
+                variant.chunk,
+                variant.pos,
+                variant.p_value,
+                variant.created_on,
+                variant.log2_fold_change,
+                variant.log2_std_error,
+                variant.statistic,
+                variant.condition,
+                variant.aa,
+                variant.version,
+                protein.name as protein
+            FROM variant
+            JOIN protein ON variant.protein_id = protein.id
+            WHERE protein.name = $1
+            AND variant.condition = $2
+            AND variant.pos >= $3
+            AND variant.pos <= $4
+            ORDER BY variant.pos, variant.aa
+            "#,
+            protein,
+            condition,
+            start,
+            end
+        )
+        .fetch_all(&self.pool)
+        .timed("get_variants_in_range")
+        .await
+    }
+
+    async fn variants_page(
+        &self,
+        params: &TableParams,
+        start: i32,
+        end: i32,
+    ) -> Result<Vec<Variant>> {
+        let TableParams {
+            ref protein,
+            ref condition,
+            ref position_filter,
+            ref paint,
+            ref threshold,
+            ..
+        } = *params;
+        match position_filter {
+            PositionFilter::NoOrder => match threshold {
+                Some(threshold) => {
+                    sqlx::query_as!(
+                        Variant,
+                        r#"SELECT
+                            variant.id,
+                            variant.chunk,
+                            variant.pos,
+                            variant.p_value,
+                            variant.created_on,
+                            variant.log2_fold_change,
+                            variant.log2_std_error,
+                            variant.statistic,
+                            variant.condition,
+                            variant.aa,
+                            variant.version,
+                            protein.name as protein
+                        FROM variant
+                        JOIN protein ON variant.protein_id = protein.id
+                        WHERE protein.name = $1
+                        AND variant.condition = $2
+                        AND case $5
+                            when 'p_value' then variant.p_value < $6
+                            when 'log2_fold_change' then
+                                    (case
+                                        when variant.log2_fold_change >= 0 then variant.log2_fold_change < $6
+                                        else variant.log2_fold_change > $6
+                                    end)
+                            when 'statistic' then variant.statistic < $6
+                            else true
+                        end
+                        AND variant.pos >= $3
+                        AND variant.pos <= $4
+                        ORDER BY variant.pos, variant.aa
+                        "#,
+                        protein,
+                        condition,
+                        start,
+                        end,
+                        paint.to_string(),
+                        *threshold
+                    )
+                    .fetch_all(&self.pool)
+                    .timed("get_filtered_variants")
+                    .await
+                }
+                None => self.variants_in_range(protein, condition, start, end).await,
+            },
+            _ => {
+                sqlx::query_as!(
+                    Variant,
+                    r#"
+                    WITH ranked_variants AS (
+                        SELECT
+                            variant.id,
+                            variant.chunk,
+                            variant.pos,
+                            variant.p_value,
+                            variant.created_on,
+                            variant.log2_fold_change,
+                            variant.log2_std_error,
+                            variant.statistic,
+                            variant.condition,
+                            variant.aa,
+                            variant.version,
+                            protein.name as protein,
+                            ROW_NUMBER() OVER (
+                                PARTITION BY variant.pos
+                                ORDER BY
+                                    CASE $5
+                                        WHEN 'MostSignificantPValue' THEN variant.p_value
+                                        WHEN 'LargestLog2FoldChange' THEN -variant.log2_fold_change
+                                        WHEN 'LargestZStatistic' THEN -variant.statistic
+                                        ELSE NULL
+                                    END ASC
+                            ) AS rn
+                        FROM variant
+                        JOIN protein ON variant.protein_id = protein.id
+                        WHERE protein.name = $1
+                        AND variant.condition = $2
+                    )
+                    SELECT
+                        id,
+                        chunk,
+                        pos,
+                        p_value,
+                        created_on,
+                        log2_fold_change,
+                        log2_std_error,
+                        statistic,
+                        condition,
+                        aa,
+                        version,
+                        protein
+                    FROM ranked_variants
+                    WHERE rn = 1
+                    AND pos >= $3
+                    AND pos <= $4;
+                    "#,
+                    protein,
+                    condition,
+                    start,
+                    end,
+                    position_filter.to_string()
+                )
+                .fetch_all(&self.pool)
+                .timed("get_ranked_variants")
+                .await
+            }
+        }
+    }
+
+    async fn variant_by_id(&self, id: i32) -> Result<Option<Variant>> {
+        sqlx::query_as!(
+            Variant,
+            r#"
+            SELECT
+                variant.id,
+                variant.chunk,
+                variant.pos,
+                variant.p_value,
+                variant.created_on,
+                variant.log2_fold_change,
+                variant.log2_std_error,
+                variant.statistic,
+                variant.condition,
+                variant.aa,
+                variant.version,
+                protein.name as protein
+            FROM variant
+            JOIN protein ON variant.protein_id = protein.id
+            WHERE variant.id = $1;
+            "#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .timed("get_variant_by_id")
+        .await
+    }
+
+    async fn range_for(
+        &self,
+        protein: &str,
+        condition: &str,
+        paint: Paint,
+    ) -> Result<Option<MetricRange>> {
+        let row = sqlx::query!(
+            r#"
+            SELECT
+                min(variant_metric_summary.min_value) as min,
+                max(variant_metric_summary.max_value) as max,
+                max(variant_metric_summary.max_abs) as max_abs
+            FROM variant_metric_summary
+            JOIN protein ON variant_metric_summary.protein_id = protein.id
+            WHERE protein.name = $1
+            AND variant_metric_summary.condition = $2
+            AND variant_metric_summary.metric = $3
+            "#,
+            protein,
+            condition,
+            paint.to_string()
+        )
+        .fetch_one(&self.pool)
+        .timed("get_range_of_variant")
+        .await?;
+        Ok(match (row.min, row.max, row.max_abs) {
+            (Some(min), Some(max), Some(max_abs)) => Some(MetricRange { min, max, max_abs }),
+            _ => None,
+        })
+    }
+
+    async fn create_protein(
+        &self,
+        name: &str,
+        pdb_id: Option<&str>,
+        sequence: Option<&str>,
+    ) -> Result<Option<i32>> {
+        sqlx::query_scalar!(
+            r#"
+            INSERT INTO protein (name, pdb_id, sequence) VALUES ($1, $2, $3)
+            ON CONFLICT (name) DO NOTHING
+            RETURNING id
+            "#,
+            name,
+            pdb_id,
+            sequence
+        )
+        .fetch_optional(&self.pool)
+        .timed("create_protein")
+        .await
+    }
+
+    async fn insert_variants(
+        &self,
+        protein: &str,
+        variants: &[Variant],
+        source: &SourceFile,
+        actor: &Actor,
+    ) -> Result<UploadBatch> {
+        ingest::insert(&self.pool, variants, protein, source, actor).await
+    }
+
+    async fn variant_batch(&self, variant_id: i32) -> Result<Option<UploadBatch>> {
+        sqlx::query_as!(
+            UploadBatch,
+            r#"
+            SELECT
+                upload_batch.id,
+                protein.name as protein,
+                upload_batch.file_name,
+                upload_batch.sha256,
+                upload_batch.rows_inserted,
+                upload_batch.rows_rejected,
+                upload_batch.columns as "columns: Json<BTreeMap<String, Option<String>>>",
+                upload_batch.actor,
+                upload_batch.created_on
+            FROM variant
+            JOIN upload_batch ON variant.batch_id = upload_batch.id
+            JOIN protein ON upload_batch.protein_id = protein.id
+            WHERE variant.id = $1
+            "#,
+            variant_id
+        )
+        .fetch_optional(&self.pool)
+        .timed("variant_batch")
+        .await
+    }
+
+    async fn record(&self, actor: &Actor, change: &Change) -> Result<()> {
+        let mut conn = self.pool.acquire().await?;
+        audit::record_postgres(&mut conn, actor, change)
+            .timed("record_change")
+            .await
+    }
+
+    async fn audit_log(&self, filter: &AuditFilter, scope: Scope) -> Result<Vec<AuditEntry>> {
+        let (start, end) = filter.period();
+        let rows = sqlx::query!(
+            r#"
+            SELECT
+                audit_log.id,
+                audit_log.created_on,
+                audit_log.user_id,
+                audit_log.actor,
+                audit_log.action,
+                audit_log.protein,
+                audit_log.batch_id,
+                audit_log.details as "details: Json<Value>"
+            FROM audit_log
+            LEFT JOIN protein ON audit_log.protein_id = protein.id
+            WHERE ($1 OR protein.public OR protein.project_id IN (
+                SELECT project_id FROM project_member WHERE user_id = $2
+            ))
+            AND ($3::VARCHAR IS NULL OR audit_log.protein = $3 OR protein.name = $3)
+            AND ($4::VARCHAR IS NULL OR audit_log.action = $4)
+            AND ($5::VARCHAR IS NULL OR audit_log.actor = $5)
+            AND ($6::INTEGER IS NULL OR audit_log.batch_id = $6)
+            AND ($7::TIMESTAMP IS NULL OR audit_log.created_on >= $7)
+            AND ($8::TIMESTAMP IS NULL OR audit_log.created_on < $8)
+            AND ($9::BIGINT IS NULL OR audit_log.id < $9)
+            ORDER BY audit_log.id DESC
+            LIMIT $10
+            "#,
+            scope.sees_everything(),
+            scope.user_id(),
+            filter.protein,
+            filter.action.map(|action| action.as_str()),
+            filter.actor,
+            filter.batch,
+            start,
+            end,
+            filter.before,
+            filter.limit()
+        )
+        .fetch_all(&self.pool)
+        .timed("audit_log")
+        .await?;
+        rows.into_iter()
+            .map(|row| {
+                Ok(AuditEntry {
+                    id: row.id,
+                    created_on: row.created_on,
+                    user_id: row.user_id,
+                    actor: row.actor,
+                    action: parse(row.action)?,
+                    protein: row.protein,
+                    batch_id: row.batch_id,
+                    details: row.details,
+                })
+            })
+            .collect()
+    }
+
+    async fn datasets(&self, protein: &str) -> Result<Vec<Dataset>> {
+        sqlx::query_as!(
+            Dataset,
+            r#"
+            SELECT
+                counts.condition as "condition!",
+                counts.version as "version!",
+                sum(counts.variants)::BIGINT as "variants!",
+                sum(counts.archived)::BIGINT as "archived!"
+            FROM (
+                SELECT condition, version, variant_count::BIGINT as variants, 0::BIGINT as archived
+                FROM variant_summary WHERE protein_id = (SELECT id FROM protein WHERE name = $1)
+                UNION ALL
+                SELECT condition, version, 0, count(*)
+                FROM archived_variant WHERE protein_id = (SELECT id FROM protein WHERE name = $1)
+                GROUP BY condition, version
+            ) counts
+            GROUP BY counts.condition, counts.version
+            ORDER BY counts.condition, counts.version
+            "#,
+            protein
+        )
+        .fetch_all(&self.pool)
+        .timed("datasets")
+        .await
+    }
+
+    async fn upload_batches(&self, protein: &str) -> Result<Vec<UploadBatch>> {
+        sqlx::query_as!(
+            UploadBatch,
+            r#"
+            SELECT
+                upload_batch.id,
+                protein.name as protein,
+                upload_batch.file_name,
+                upload_batch.sha256,
+                upload_batch.rows_inserted,
+                upload_batch.rows_rejected,
+                upload_batch.columns as "columns: Json<BTreeMap<String, Option<String>>>",
+                upload_batch.actor,
+                upload_batch.created_on
+            FROM upload_batch
+            JOIN protein ON upload_batch.protein_id = protein.id
+            WHERE protein.name = $1
+            ORDER BY upload_batch.id DESC
+            "#,
+            protein
+        )
+        .fetch_all(&self.pool)
+        .timed("upload_batches")
+        .await
+    }
+
+    async fn affected(&self, selection: &Selection) -> Result<Affected> {
+        let mut conn = self.pool.acquire().await?;
+        count(&mut conn, selection).timed("count_affected").await
+    }
+
+    async fn apply(
+        &self,
+        operation: Operation,
+        selection: &Selection,
+        actor: &Actor,
+    ) -> Result<Affected> {
+        let mut txn = self.pool.begin().await?;
+        let affected = count(&mut txn, selection).await?;
+        audit::record_postgres(
+            &mut txn,
+            actor,
+            &Change::dataset(operation, selection, affected),
+        )
+        .await?;
+        let Selection {
+            protein,
+            condition,
+            version,
+            batch,
+        } = selection;
+        match operation {
+            Operation::Delete if selection.whole_protein() => {
+                sqlx::query!("DELETE FROM protein WHERE name = $1", protein)
+                    .execute(&mut *txn)
+                    .await?;
+            }
+            Operation::Delete => {
+                sqlx::query!(
+                    r#"
+                    DELETE FROM variant USING protein
+                    WHERE variant.protein_id = protein.id AND protein.name = $1
+                    AND ($2::VARCHAR IS NULL OR variant.condition = $2)
+                    AND ($3::VARCHAR IS NULL OR variant.version = $3)
+                    AND ($4::INTEGER IS NULL OR variant.batch_id = $4)
+                    "#,
+                    protein,
+                    condition.as_deref(),
+                    version.as_deref(),
+                    *batch
+                )
+                .execute(&mut *txn)
+                .await?;
+                sqlx::query!(
+                    r#"
+                    DELETE FROM archived_variant USING protein
+                    WHERE archived_variant.protein_id = protein.id AND protein.name = $1
+                    AND ($2::VARCHAR IS NULL OR archived_variant.condition = $2)
+                    AND ($3::VARCHAR IS NULL OR archived_variant.version = $3)
+                    AND ($4::INTEGER IS NULL OR archived_variant.batch_id = $4)
+                    "#,
+                    protein,
+                    condition.as_deref(),
+                    version.as_deref(),
+                    *batch
+                )
+                .execute(&mut *txn)
+                .await?;
+                if selection.whole_batch() {
+                    sqlx::query!(
+                        r#"
+                        DELETE FROM upload_batch USING protein
+                        WHERE upload_batch.protein_id = protein.id AND protein.name = $1
+                        AND upload_batch.id = $2
+                        "#,
+                        protein,
+                        *batch
+                    )
+                    .execute(&mut *txn)
+                    .await?;
+                }
+            }
+            Operation::Archive if selection.whole_protein() => {
+                sqlx::query!(
+                    "UPDATE protein SET archived_on = now() AT TIME ZONE 'utc' WHERE name = $1",
+                    protein
+                )
+                .execute(&mut *txn)
+                .await?;
+            }
+            Operation::Archive => {
+                sqlx::query!(
+                    r#"
+                    WITH moved AS (
+                        DELETE FROM variant USING protein
+                        WHERE variant.protein_id = protein.id AND protein.name = $1
+                        AND ($2::VARCHAR IS NULL OR variant.condition = $2)
+                        AND ($3::VARCHAR IS NULL OR variant.version = $3)
+                        AND ($4::INTEGER IS NULL OR variant.batch_id = $4)
+                        RETURNING variant.*
+                    )
+                    INSERT INTO archived_variant (
+                        id, chunk, pos, condition, aa, log2_fold_change, log2_std_error,
+                        statistic, p_value, version, protein_id, created_on, batch_id
+                    )
+                    SELECT
+                        id, chunk, pos, condition, aa, log2_fold_change, log2_std_error,
+                        statistic, p_value, version, protein_id, created_on, batch_id
+                    FROM moved
+                    "#,
+                    protein,
+                    condition.as_deref(),
+                    version.as_deref(),
+                    *batch
+                )
+                .execute(&mut *txn)
+                .await?;
+            }
+            Operation::Restore => {
+                // Restoring a protein also brings back whatever was archived
+                // under it
+                if selection.whole_protein() {
+                    sqlx::query!(
+                        "UPDATE protein SET archived_on = NULL WHERE name = $1",
+                        protein
+                    )
+                    .execute(&mut *txn)
+                    .await?;
+                }
+                sqlx::query!(
+                    r#"
+                    WITH moved AS (
+                        DELETE FROM archived_variant USING protein
+                        WHERE archived_variant.protein_id = protein.id AND protein.name = $1
+                        AND ($2::VARCHAR IS NULL OR archived_variant.condition = $2)
+                        AND ($3::VARCHAR IS NULL OR archived_variant.version = $3)
+                        AND ($4::INTEGER IS NULL OR archived_variant.batch_id = $4)
+                        RETURNING archived_variant.*
+                    )
+                    INSERT INTO variant (
+                        id, chunk, pos, condition, aa, log2_fold_change, log2_std_error,
+                        statistic, p_value, version, protein_id, created_on, batch_id
+                    )
+                    SELECT
+                        id, chunk, pos, condition, aa, log2_fold_change, log2_std_error,
+                        statistic, p_value, version, protein_id, created_on, batch_id
+                    FROM moved
+                    "#,
+                    protein,
+                    condition.as_deref(),
+                    version.as_deref(),
+                    *batch
+                )
+                .execute(&mut *txn)
+                .await?;
+            }
+        }
+        txn.commit().await?;
+        Ok(affected)
+    }
+
+    async fn dataset_revision(&self) -> Result<u64> {
+        let revision = sqlx::query_scalar!(
+            r#"SELECT CASE WHEN is_called THEN last_value ELSE 0 END as "revision!" FROM dataset_revision"#
+        )
+        .fetch_one(&self.pool)
+        .timed("current_revision")
+        .await?;
+        Ok(revision as u64)
+    }
+
+    async fn create_user(
+        &self,
+        username: &str,
+        password_hash: &str,
+        is_admin: bool,
+    ) -> Result<Option<i32>> {
+        sqlx::query_scalar!(
+            r#"
+            INSERT INTO app_user (username, password_hash, is_admin) VALUES ($1, $2, $3)
+            ON CONFLICT (username) DO NOTHING
+            RETURNING id
+            "#,
+            username,
+            password_hash,
+            is_admin
+        )
+        .fetch_optional(&self.pool)
+        .timed("create_user")
+        .await
+    }
+
+    async fn users(&self) -> Result<Vec<User>> {
+        sqlx::query_as!(
+            User,
+            "SELECT id, username, is_admin, disabled, created_on FROM app_user ORDER BY username"
+        )
+        .fetch_all(&self.pool)
+        .timed("list_users")
+        .await
+    }
+
+    async fn credentials(&self, username: &str) -> Result<Option<Credentials>> {
+        let row = sqlx::query!(
+            r#"
+            SELECT id, username, is_admin, disabled, created_on, password_hash
+            FROM app_user WHERE username = $1
+            "#,
+            username
+        )
+        .fetch_optional(&self.pool)
+        .timed("get_credentials")
+        .await?;
+        Ok(row.map(|row| Credentials {
+            user: User {
+                id: row.id,
+                username: row.username,
+                is_admin: row.is_admin,
+                disabled: row.disabled,
+                created_on: row.created_on,
+            },
+            password_hash: row.password_hash,
+        }))
+    }
+
+    async fn update_user(&self, id: i32, update: &UserUpdate) -> Result<bool> {
+        let mut txn = self.pool.begin().await?;
+        let updated = sqlx::query!(
+            r#"
+            UPDATE app_user SET
+                password_hash = coalesce($2, password_hash),
+                is_admin = coalesce($3, is_admin),
+                disabled = coalesce($4, disabled)
+            WHERE id = $1
+            "#,
+            id,
+            update.password_hash,
+            update.is_admin,
+            update.disabled
+        )
+        .execute(&mut *txn)
+        .timed("update_user")
+        .await?
+        .rows_affected();
+        if update.revokes_sessions() {
+            sqlx::query!("DELETE FROM user_session WHERE user_id = $1", id)
+                .execute(&mut *txn)
+                .timed("revoke_sessions")
+                .await?;
+        }
+        txn.commit().await?;
+        Ok(updated > 0)
+    }
+
+    async fn delete_user(&self, id: i32) -> Result<bool> {
+        let deleted = sqlx::query!("DELETE FROM app_user WHERE id = $1", id)
+            .execute(&self.pool)
+            .timed("delete_user")
+            .await?
+            .rows_affected();
+        Ok(deleted > 0)
+    }
+
+    async fn create_session(
+        &self,
+        user_id: i32,
+        token_hash: &str,
+        expires_on: NaiveDateTime,
+    ) -> Result<()> {
+        let mut txn = self.pool.begin().await?;
+        sqlx::query!(
+            "DELETE FROM user_session WHERE expires_on < $1",
+            Utc::now().naive_utc()
+        )
+        .execute(&mut *txn)
+        .timed("expire_sessions")
+        .await?;
+        sqlx::query!(
+            "INSERT INTO user_session (token_hash, user_id, expires_on) VALUES ($1, $2, $3)",
+            token_hash,
+            user_id,
+            expires_on
+        )
+        .execute(&mut *txn)
+        .timed("create_session")
+        .await?;
+        txn.commit().await
+    }
+
+    async fn session(&self, token_hash: &str) -> Result<Option<Session>> {
+        let row = sqlx::query!(
+            r#"
+            SELECT
+                app_user.id,
+                app_user.username,
+                app_user.is_admin,
+                app_user.disabled,
+                app_user.created_on,
+                user_session.expires_on
+            FROM user_session
+            JOIN app_user ON user_session.user_id = app_user.id
+            WHERE user_session.token_hash = $1
+            AND NOT app_user.disabled
+            "#,
+            token_hash
+        )
+        .fetch_optional(&self.pool)
+        .timed("get_session")
+        .await?;
+        Ok(row.map(|row| Session {
+            user: User {
+                id: row.id,
+                username: row.username,
+                is_admin: row.is_admin,
+                disabled: row.disabled,
+                created_on: row.created_on,
+            },
+            expires_on: row.expires_on,
+        }))
+    }
+
+    async fn delete_session(&self, token_hash: &str) -> Result<()> {
+        sqlx::query!("DELETE FROM user_session WHERE token_hash = $1", token_hash)
+            .execute(&self.pool)
+            .timed("delete_session")
+            .await?;
+        Ok(())
+    }
+
+    async fn create_api_token(
+        &self,
+        user_id: i32,
+        name: &str,
+        token_hash: &str,
+    ) -> Result<Option<i32>> {
+        sqlx::query_scalar!(
+            r#"
+            INSERT INTO api_token (user_id, name, token_hash) VALUES ($1, $2, $3)
+            ON CONFLICT (user_id, name) DO NOTHING
+            RETURNING id
+            "#,
+            user_id,
+            name,
+            token_hash
+        )
+        .fetch_optional(&self.pool)
+        .timed("create_api_token")
+        .await
+    }
+
+    async fn api_tokens(&self, user_id: i32) -> Result<Vec<ApiToken>> {
+        sqlx::query_as!(
+            ApiToken,
+            r#"
+            SELECT id, name, created_on, last_used_on FROM api_token
+            WHERE user_id = $1
+            ORDER BY created_on DESC, id DESC
+            "#,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .timed("list_api_tokens")
+        .await
+    }
+
+    async fn token_user(&self, token_hash: &str) -> Result<Option<User>> {
+        sqlx::query_as!(
+            User,
+            r#"
+            WITH used AS (
+                UPDATE api_token SET last_used_on = $2
+                WHERE token_hash = $1
+                RETURNING user_id
+            )
+            SELECT app_user.id, app_user.username, app_user.is_admin, app_user.disabled, app_user.created_on
+            FROM used
+            JOIN app_user ON used.user_id = app_user.id
+            WHERE NOT app_user.disabled
+            "#,
+            token_hash,
+            Utc::now().naive_utc()
+        )
+        .fetch_optional(&self.pool)
+        .timed("get_token_user")
+        .await
+    }
+
+    async fn delete_api_token(&self, user_id: i32, id: i32) -> Result<bool> {
+        let deleted = sqlx::query!(
+            "DELETE FROM api_token WHERE user_id = $1 AND id = $2",
+            user_id,
+            id
+        )
+        .execute(&self.pool)
+        .timed("delete_api_token")
+        .await?
+        .rows_affected();
+        Ok(deleted > 0)
+    }
+
+    async fn protein_access(&self) -> Result<Vec<ProteinAccess>> {
+        sqlx::query_as!(
+            ProteinAccess,
+            r#"
+            SELECT name, project_id, public, archived_on IS NOT NULL as "archived!"
+            FROM protein
+            ORDER BY name
+            "#
+        )
+        .fetch_all(&self.pool)
+        .timed("protein_access")
+        .await
+    }
+
+    async fn set_protein_access(
+        &self,
+        protein: &str,
+        project_id: Option<i32>,
+        public: bool,
+    ) -> Result<bool> {
+        let updated = sqlx::query!(
+            "UPDATE protein SET project_id = $2, public = $3 WHERE name = $1",
+            protein,
+            project_id,
+            public
+        )
+        .execute(&self.pool)
+        .timed("set_protein_access")
+        .await?
+        .rows_affected();
+        Ok(updated > 0)
+    }
+
+    async fn create_project(&self, name: &str) -> Result<Option<i32>> {
+        sqlx::query_scalar!(
+            "INSERT INTO project (name) VALUES ($1) ON CONFLICT (name) DO NOTHING RETURNING id",
+            name
+        )
+        .fetch_optional(&self.pool)
+        .timed("create_project")
+        .await
+    }
+
+    async fn projects(&self) -> Result<Vec<Project>> {
+        sqlx::query_as!(
+            Project,
+            "SELECT id, name, created_on FROM project ORDER BY name"
+        )
+        .fetch_all(&self.pool)
+        .timed("list_projects")
+        .await
+    }
+
+    async fn members(&self, project_id: i32) -> Result<Vec<Member>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT app_user.id, app_user.username, project_member.role
+            FROM project_member
+            JOIN app_user ON app_user.id = project_member.user_id
+            WHERE project_member.project_id = $1
+            ORDER BY app_user.username
+            "#,
+            project_id
+        )
+        .fetch_all(&self.pool)
+        .timed("list_members")
+        .await?;
+        rows.into_iter()
+            .map(|row| {
+                Ok(Member {
+                    user_id: row.id,
+                    username: row.username,
+                    role: parse(row.role)?,
+                })
+            })
+            .collect()
+    }
+
+    async fn roles(&self, user_id: i32) -> Result<BTreeMap<i32, Role>> {
+        let rows = sqlx::query!(
+            "SELECT project_id, role FROM project_member WHERE user_id = $1",
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .timed("user_roles")
+        .await?;
+        rows.into_iter()
+            .map(|row| Ok((row.project_id, parse(row.role)?)))
+            .collect()
+    }
+
+    async fn set_member(&self, project_id: i32, user_id: i32, role: Option<Role>) -> Result<bool> {
+        let changed = match role {
+            Some(role) => sqlx::query!(
+                r#"
+                INSERT INTO project_member (project_id, user_id, role) VALUES ($1, $2, $3)
+                ON CONFLICT (project_id, user_id) DO UPDATE SET role = excluded.role
+                "#,
+                project_id,
+                user_id,
+                role.as_str()
+            )
+            .execute(&self.pool)
+            .timed("set_member")
+            .await?
+            .rows_affected(),
+            None => sqlx::query!(
+                "DELETE FROM project_member WHERE project_id = $1 AND user_id = $2",
+                project_id,
+                user_id
+            )
+            .execute(&self.pool)
+            .timed("remove_member")
+            .await?
+            .rows_affected(),
+        };
+        Ok(changed > 0)
+    }
+
+    async fn applied_migration(&self) -> Result<Option<i64>> {
+        sqlx::query_scalar("SELECT max(version) FROM _sqlx_migrations WHERE success")
+            .fetch_one(&self.pool)
+            .timed("applied_migration")
+            .await
+    }
+
+    fn latest_migration(&self) -> i64 {
+        migrate::latest_version()
+    }
+
+    fn pool_stats(&self) -> PoolStats {
+        PoolStats {
+            size: self.pool.size(),
+            idle: self.pool.num_idle() as u32,
+            max: self.pool.options().get_max_connections(),
+        }
+    }
+}
+
+/// A role or audit action stored as text.
+/// Shown and archived variants `selection` covers; none for an unknown
+/// protein.
+async fn count(conn: &mut PgConnection, selection: &Selection) -> Result<Affected> {
+    let row = sqlx::query!(
+        r#"
+        SELECT
+            (
+                SELECT count(*) FROM variant
+                WHERE protein_id = protein.id
+                AND ($2::VARCHAR IS NULL OR condition = $2)
+                AND ($3::VARCHAR IS NULL OR version = $3)
+                AND ($4::INTEGER IS NULL OR batch_id = $4)
+            ) as "variants!",
+            (
+                SELECT count(*) FROM archived_variant
+                WHERE protein_id = protein.id
+                AND ($2::VARCHAR IS NULL OR condition = $2)
+                AND ($3::VARCHAR IS NULL OR version = $3)
+                AND ($4::INTEGER IS NULL OR batch_id = $4)
+            ) as "archived!"
+        FROM protein
+        WHERE name = $1
+        "#,
+        selection.protein,
+        selection.condition.as_deref(),
+        selection.version.as_deref(),
+        selection.batch
+    )
+    .fetch_optional(conn)
+    .await?;
+    Ok(row.map_or_else(Affected::default, |row| Affected {
+        variants: row.variants,
+        archived: row.archived,
+    }))
+}
+
+fn parse<T: FromStr<Err = String>>(value: String) -> Result<T> {
+    value
+        .parse()
+        .map_err(|err: String| sqlx::Error::Decode(err.into()))
+}
@@ -0,0 +1,1240 @@
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use chrono::{NaiveDateTime, Utc};
+use sqlx::query::Query;
+use sqlx::sqlite::SqliteArguments;
+use sqlx::{Sqlite, SqliteConnection, SqlitePool};
+
+use super::{MetricRange, PoolStats, Repository, Result};
+use crate::access::{Member, Project, ProteinAccess, Role, Scope};
+use crate::audit::{self, Actor, AuditEntry, AuditFilter, Change, UploadBatch};
+use crate::auth::{ApiToken, Credentials, Session, User, UserUpdate};
+use crate::datasets::{Affected, Dataset, Operation, Selection};
+use crate::ingest::SourceFile;
+use crate::metrics::TimedQuery;
+use crate::{ingest, migrate, Domain, Paint, PositionFilter, TableParams, Variant};
+
+/// Columns of `Variant`, for queries joining `variant` and `protein`.
+const VARIANT_COLUMNS: &str = r#"
+    variant.id,
+    variant.chunk,
+    variant.pos,
+    variant.p_value,
+    variant.created_on,
+    variant.log2_fold_change,
+    variant.log2_std_error,
+    variant.statistic,
+    variant.condition,
+    variant.aa,
+    variant.version,
+    protein.name as protein
+"#;
+
+/// Columns `variant` and `archived_variant` share, for moving rows between
+/// them.
+const MOVED_COLUMNS: &str = "id, chunk, pos, condition, aa, log2_fold_change, log2_std_error, \
+     statistic, p_value, version, protein_id, created_on, batch_id";
+
+/// `Repository` over the schema in `migrations/sqlite/`, for a single user
+/// browsing a local file. Runs the same queries as `PostgresRepository`,
+/// ranked view included; the summary tables are kept up to date by
+/// `ingest::insert_sqlite` instead of triggers.
+#[derive(Debug, Clone)]
+pub struct SqliteRepository {
+    pool: SqlitePool,
+}
+
+impl SqliteRepository {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+
+    pub fn pool(&self) -> &SqlitePool {
+        &self.pool
+    }
+}
+
+#[async_trait]
+impl Repository for SqliteRepository {
+    async fn list_proteins(&self, scope: Scope) -> Result<Vec<String>> {
+        sqlx::query_scalar(
+            r#"
+            SELECT name FROM protein
+            WHERE archived_on IS NULL
+            AND (?1 OR public OR project_id IN (
+                SELECT project_id FROM project_member WHERE user_id = ?2
+            ))
+            ORDER BY name
+            "#,
+        )
+        .bind(scope.sees_everything())
+        .bind(scope.user_id())
+        .fetch_all(&self.pool)
+        .timed("list_proteins")
+        .await
+    }
+
+    async fn protein_visible(&self, scope: Scope, protein: &str) -> Result<bool> {
+        sqlx::query_scalar(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM protein
+                WHERE name = ?1
+                AND archived_on IS NULL
+                AND (?2 OR public OR project_id IN (
+                    SELECT project_id FROM project_member WHERE user_id = ?3
+                ))
+            )
+            "#,
+        )
+        .bind(protein)
+        .bind(scope.sees_everything())
+        .bind(scope.user_id())
+        .fetch_one(&self.pool)
+        .timed("protein_visible")
+        .await
+    }
+
+    async fn protein_sequence(&self, protein: &str) -> Result<Option<String>> {
+        Ok(
+            sqlx::query_scalar::<_, Option<String>>("SELECT sequence FROM protein WHERE name = ?1")
+                .bind(protein)
+                .fetch_optional(&self.pool)
+                .timed("get_protein_sequence")
+                .await?
+                .flatten(),
+        )
+    }
+
+    async fn domains(&self, protein: &str) -> Result<Vec<Domain>> {
+        sqlx::query_as(
+            r#"
+            SELECT protein_domain.name, protein_domain.start_pos, protein_domain.end_pos
+            FROM protein_domain
+            JOIN protein ON protein_domain.protein_id = protein.id
+            WHERE protein.name = ?1
+            ORDER BY protein_domain.start_pos
+            "#,
+        )
+        .bind(protein)
+        .fetch_all(&self.pool)
+        .timed("get_domains")
+        .await
+    }
+
+    async fn conditions_for(&self, protein: &str) -> Result<Vec<String>> {
+        sqlx::query_scalar(
+            r#"
+            SELECT DISTINCT variant_summary.condition FROM variant_summary
+            JOIN protein ON variant_summary.protein_id = protein.id
+            WHERE protein.name = ?1
+            ORDER BY variant_summary.condition
+            "#,
+        )
+        .bind(protein)
+        .fetch_all(&self.pool)
+        .timed("get_conditions")
+        .await
+    }
+
+    async fn max_position(&self, protein: &str, condition: &str) -> Result<Option<i32>> {
+        sqlx::query_scalar(
+            r#"
+            SELECT max(variant_summary.max_pos) as maximum FROM variant_summary
+            JOIN protein ON variant_summary.protein_id = protein.id
+            WHERE protein.name = ?1
+            AND variant_summary.condition = ?2
+            "#,
+        )
+        .bind(protein)
+        .bind(condition)
+        .fetch_one(&self.pool)
+        .timed("get_max_position")
+        .await
+    }
+
+    async fn all_variants(&self, protein: &str, condition: &str) -> Result<Vec<Variant>> {
+        sqlx::query_as(&format!(
+            r#"SELECT {VARIANT_COLUMNS}
+            FROM variant
+            JOIN protein ON variant.protein_id = protein.id
+            WHERE protein.name = ?1
+            AND variant.condition = ?2
+            ORDER BY variant.pos, variant.aa
+            "#
+        ))
+        .bind(protein)
+        .bind(condition)
+        .fetch_all(&self.pool)
+        .timed("get_all_variants")
+        .await
+    }
+
+    async fn variants_in_range(
+        &self,
+        protein: &str,
+        condition: &str,
+        start: i32,
+        end: i32,
+    ) -> Result<Vec<Variant>> {
+        sqlx::query_as(&format!(
+            r#"SELECT {VARIANT_COLUMNS}
+            FROM variant
+            JOIN protein ON variant.protein_id = protein.id
+            WHERE protein.name = ?1
+            AND variant.condition = ?2
+            AND variant.pos >= ?3
+            AND variant.pos <= ?4
+            ORDER BY variant.pos, variant.aa
+            "#
+        ))
+        .bind(protein)
+        .bind(condition)
+        .bind(start)
+        .bind(end)
+        .fetch_all(&self.pool)
+        .timed("get_variants_in_range")
+        .await
+    }
+
+    async fn variants_page(
+        &self,
+        params: &TableParams,
+        start: i32,
+        end: i32,
+    ) -> Result<Vec<Variant>> {
+        let TableParams {
+            ref protein,
+            ref condition,
+            ref position_filter,
+            ref paint,
+            ref threshold,
+            ..
+        } = *params;
+        match position_filter {
+            PositionFilter::NoOrder => match threshold {
+                Some(threshold) => {
+                    sqlx::query_as(&format!(
+                        r#"SELECT {VARIANT_COLUMNS}
+                        FROM variant
+                        JOIN protein ON variant.protein_id = protein.id
+                        WHERE protein.name = ?1
+                        AND variant.condition = ?2
+                        AND case ?5
+                            when 'p_value' then variant.p_value < ?6
+                            when 'log2_fold_change' then
+                                    (case
+                                        when variant.log2_fold_change >= 0 then variant.log2_fold_change < ?6
+                                        else variant.log2_fold_change > ?6
+                                    end)
+                            when 'statistic' then variant.statistic < ?6
+                            else 1
+                        end
+                        AND variant.pos >= ?3
+                        AND variant.pos <= ?4
+                        ORDER BY variant.pos, variant.aa
+                        "#
+                    ))
+                    .bind(protein)
+                    .bind(condition)
+                    .bind(start)
+                    .bind(end)
+                    .bind(paint.to_string())
+                    .bind(*threshold)
+                    .fetch_all(&self.pool)
+                    .timed("get_filtered_variants")
+                    .await
+                }
+                None => self.variants_in_range(protein, condition, start, end).await,
+            },
+            _ => {
+                sqlx::query_as(&format!(
+                    r#"
+                    WITH ranked_variants AS (
+                        SELECT
+                            {VARIANT_COLUMNS},
+                            ROW_NUMBER() OVER (
+                                PARTITION BY variant.pos
+                                ORDER BY
+                                    CASE ?5
+                                        WHEN 'MostSignificantPValue' THEN variant.p_value
+                                        WHEN 'LargestLog2FoldChange' THEN -variant.log2_fold_change
+                                        WHEN 'LargestZStatistic' THEN -variant.statistic
+                                        ELSE NULL
+                                    END ASC
+                            ) AS rn
+                        FROM variant
+                        JOIN protein ON variant.protein_id = protein.id
+                        WHERE protein.name = ?1
+                        AND variant.condition = ?2
+                    )
+                    SELECT
+                        id,
+                        chunk,
+                        pos,
+                        p_value,
+                        created_on,
+                        log2_fold_change,
+                        log2_std_error,
+                        statistic,
+                        condition,
+                        aa,
+                        version,
+                        protein
+                    FROM ranked_variants
+                    WHERE rn = 1
+                    AND pos >= ?3
+                    AND pos <= ?4
+                    ORDER BY pos
+                    "#
+                ))
+                .bind(protein)
+                .bind(condition)
+                .bind(start)
+                .bind(end)
+                .bind(position_filter.to_string())
+                .fetch_all(&self.pool)
+                .timed("get_ranked_variants")
+                .await
+            }
+        }
+    }
+
+    async fn variant_by_id(&self, id: i32) -> Result<Option<Variant>> {
+        sqlx::query_as(&format!(
+            r#"SELECT {VARIANT_COLUMNS}
+            FROM variant
+            JOIN protein ON variant.protein_id = protein.id
+            WHERE variant.id = ?1
+            "#
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .timed("get_variant_by_id")
+        .await
+    }
+
+    async fn range_for(
+        &self,
+        protein: &str,
+        condition: &str,
+        paint: Paint,
+    ) -> Result<Option<MetricRange>> {
+        let (min, max, max_abs): (Option<f64>, Option<f64>, Option<f64>) = sqlx::query_as(
+            r#"
+            SELECT
+                min(variant_metric_summary.min_value) as min,
+                max(variant_metric_summary.max_value) as max,
+                max(variant_metric_summary.max_abs) as max_abs
+            FROM variant_metric_summary
+            JOIN protein ON variant_metric_summary.protein_id = protein.id
+            WHERE protein.name = ?1
+            AND variant_metric_summary.condition = ?2
+            AND variant_metric_summary.metric = ?3
+            "#,
+        )
+        .bind(protein)
+        .bind(condition)
+        .bind(paint.to_string())
+        .fetch_one(&self.pool)
+        .timed("get_range_of_variant")
+        .await?;
+        Ok(match (min, max, max_abs) {
+            (Some(min), Some(max), Some(max_abs)) => Some(MetricRange { min, max, max_abs }),
+            _ => None,
+        })
+    }
+
+    async fn create_protein(
+        &self,
+        name: &str,
+        pdb_id: Option<&str>,
+        sequence: Option<&str>,
+    ) -> Result<Option<i32>> {
+        sqlx::query_scalar(
+            r#"
+            INSERT INTO protein (name, pdb_id, sequence) VALUES (?1, ?2, ?3)
+            ON CONFLICT (name) DO NOTHING
+            RETURNING id
+            "#,
+        )
+        .bind(name)
+        .bind(pdb_id)
+        .bind(sequence)
+        .fetch_optional(&self.pool)
+        .timed("create_protein")
+        .await
+    }
+
+    async fn insert_variants(
+        &self,
+        protein: &str,
+        variants: &[Variant],
+        source: &SourceFile,
+        actor: &Actor,
+    ) -> Result<UploadBatch> {
+        ingest::insert_sqlite(&self.pool, variants, protein, source, actor).await
+    }
+
+    async fn variant_batch(&self, variant_id: i32) -> Result<Option<UploadBatch>> {
+        sqlx::query_as(
+            r#"
+            SELECT
+                upload_batch.id,
+                protein.name as protein,
+                upload_batch.file_name,
+                upload_batch.sha256,
+                upload_batch.rows_inserted,
+                upload_batch.rows_rejected,
+                upload_batch.columns,
+                upload_batch.actor,
+                upload_batch.created_on
+            FROM variant
+            JOIN upload_batch ON variant.batch_id = upload_batch.id
+            JOIN protein ON upload_batch.protein_id = protein.id
+            WHERE variant.id = ?1
+            "#,
+        )
+        .bind(variant_id)
+        .fetch_optional(&self.pool)
+        .timed("variant_batch")
+        .await
+    }
+
+    async fn record(&self, actor: &Actor, change: &Change) -> Result<()> {
+        let mut conn = self.pool.acquire().await?;
+        audit::record_sqlite(&mut conn, actor, change)
+            .timed("record_change")
+            .await
+    }
+
+    async fn audit_log(&self, filter: &AuditFilter, scope: Scope) -> Result<Vec<AuditEntry>> {
+        let (start, end) = filter.period();
+        sqlx::query_as(
+            r#"
+            SELECT
+                audit_log.id,
+                audit_log.created_on,
+                audit_log.user_id,
+                audit_log.actor,
+                audit_log.action,
+                audit_log.protein,
+                audit_log.batch_id,
+                audit_log.details
+            FROM audit_log
+            LEFT JOIN protein ON audit_log.protein_id = protein.id
+            WHERE (?1 OR protein.public OR protein.project_id IN (
+                SELECT project_id FROM project_member WHERE user_id = ?2
+            ))
+            AND (?3 IS NULL OR audit_log.protein = ?3 OR protein.name = ?3)
+            AND (?4 IS NULL OR audit_log.action = ?4)
+            AND (?5 IS NULL OR audit_log.actor = ?5)
+            AND (?6 IS NULL OR audit_log.batch_id = ?6)
+            AND (?7 IS NULL OR audit_log.created_on >= ?7)
+            AND (?8 IS NULL OR audit_log.created_on < ?8)
+            AND (?9 IS NULL OR audit_log.id < ?9)
+            ORDER BY audit_log.id DESC
+            LIMIT ?10
+            "#,
+        )
+        .bind(scope.sees_everything())
+        .bind(scope.user_id())
+        .bind(&filter.protein)
+        .bind(filter.action.map(|action| action.as_str()))
+        .bind(&filter.actor)
+        .bind(filter.batch)
+        .bind(start)
+        .bind(end)
+        .bind(filter.before)
+        .bind(filter.limit())
+        .fetch_all(&self.pool)
+        .timed("audit_log")
+        .await
+    }
+
+    async fn datasets(&self, protein: &str) -> Result<Vec<Dataset>> {
+        sqlx::query_as(
+            r#"
+            SELECT condition, version, sum(variants) as variants, sum(archived) as archived
+            FROM (
+                SELECT condition, version, variant_count as variants, 0 as archived
+                FROM variant_summary WHERE protein_id = (SELECT id FROM protein WHERE name = ?1)
+                UNION ALL
+                SELECT condition, version, 0, count(*)
+                FROM archived_variant WHERE protein_id = (SELECT id FROM protein WHERE name = ?1)
+                GROUP BY condition, version
+            )
+            GROUP BY condition, version
+            ORDER BY condition, version
+            "#,
+        )
+        .bind(protein)
+        .fetch_all(&self.pool)
+        .timed("datasets")
+        .await
+    }
+
+    async fn upload_batches(&self, protein: &str) -> Result<Vec<UploadBatch>> {
+        sqlx::query_as(
+            r#"
+            SELECT
+                upload_batch.id,
+                protein.name as protein,
+                upload_batch.file_name,
+                upload_batch.sha256,
+                upload_batch.rows_inserted,
+                upload_batch.rows_rejected,
+                upload_batch.columns,
+                upload_batch.actor,
+                upload_batch.created_on
+            FROM upload_batch
+            JOIN protein ON upload_batch.protein_id = protein.id
+            WHERE protein.name = ?1
+            ORDER BY upload_batch.id DESC
+            "#,
+        )
+        .bind(protein)
+        .fetch_all(&self.pool)
+        .timed("upload_batches")
+        .await
+    }
+
+    async fn affected(&self, selection: &Selection) -> Result<Affected> {
+        let mut conn = self.pool.acquire().await?;
+        count(&mut conn, selection).timed("count_affected").await
+    }
+
+    /// Refreshes the summaries of every condition the operation touched,
+    /// as the triggers do in PostgreSQL.
+    async fn apply(
+        &self,
+        operation: Operation,
+        selection: &Selection,
+        actor: &Actor,
+    ) -> Result<Affected> {
+        let mut txn = self.pool.begin().await?;
+        let affected = count(&mut txn, selection).await?;
+        audit::record_sqlite(
+            &mut txn,
+            actor,
+            &Change::dataset(operation, selection, affected),
+        )
+        .await?;
+        let protein_id: Option<i32> = sqlx::query_scalar("SELECT id FROM protein WHERE name = ?1")
+            .bind(&selection.protein)
+            .fetch_optional(&mut *txn)
+            .await?;
+        let conditions: Vec<String> = sqlx::query_scalar(&format!(
+            "SELECT condition FROM variant WHERE {} \
+             UNION SELECT condition FROM archived_variant WHERE {}",
+            selected("variant"),
+            selected("archived_variant")
+        ))
+        .bind(&selection.protein)
+        .bind(selection.condition.as_deref())
+        .bind(selection.version.as_deref())
+        .bind(selection.batch)
+        .fetch_all(&mut *txn)
+        .await?;
+        let from_variants = selected("variant");
+        let from_archive = selected("archived_variant");
+        let statements: Vec<String> = match operation {
+            Operation::Delete if selection.whole_protein() => {
+                vec!["DELETE FROM protein WHERE name = ?1".to_string()]
+            }
+            Operation::Delete => {
+                let mut statements = vec![
+                    format!("DELETE FROM variant WHERE {from_variants}"),
+                    format!("DELETE FROM archived_variant WHERE {from_archive}"),
+                ];
+                if selection.whole_batch() {
+                    statements.push(
+                        "DELETE FROM upload_batch \
+                         WHERE id = ?4 AND protein_id = (SELECT id FROM protein WHERE name = ?1)"
+                            .to_string(),
+                    );
+                }
+                statements
+            }
+            Operation::Archive if selection.whole_protein() => {
+                vec![
+                    "UPDATE protein SET archived_on = CURRENT_TIMESTAMP WHERE name = ?1"
+                        .to_string(),
+                ]
+            }
+            Operation::Archive => vec![
+                format!(
+                    "INSERT INTO archived_variant ({MOVED_COLUMNS}) \
+                     SELECT {MOVED_COLUMNS} FROM variant WHERE {from_variants}"
+                ),
+                format!("DELETE FROM variant WHERE {from_variants}"),
+            ],
+            Operation::Restore => {
+                // Restoring a protein also brings back whatever was archived
+                // under it
+                let mut statements = vec![];
+                if selection.whole_protein() {
+                    statements
+                        .push("UPDATE protein SET archived_on = NULL WHERE name = ?1".to_string());
+                }
+                statements.push(format!(
+                    "INSERT INTO variant ({MOVED_COLUMNS}) \
+                     SELECT {MOVED_COLUMNS} FROM archived_variant WHERE {from_archive}"
+                ));
+                statements.push(format!("DELETE FROM archived_variant WHERE {from_archive}"));
+                statements
+            }
+        };
+        for statement in &statements {
+            bind_selection(sqlx::query(statement), selection)
+                .execute(&mut *txn)
+                .await?;
+        }
+        if let Some(protein_id) = protein_id {
+            for condition in &conditions {
+                ingest::refresh_sqlite_summary(&mut txn, protein_id, condition).await?;
+            }
+        }
+        sqlx::query("UPDATE dataset_revision SET revision = revision + 1")
+            .execute(&mut *txn)
+            .await?;
+        txn.commit().await?;
+        Ok(affected)
+    }
+
+    async fn dataset_revision(&self) -> Result<u64> {
+        let revision: i64 = sqlx::query_scalar("SELECT revision FROM dataset_revision")
+            .fetch_one(&self.pool)
+            .timed("current_revision")
+            .await?;
+        Ok(revision as u64)
+    }
+
+    async fn create_user(
+        &self,
+        username: &str,
+        password_hash: &str,
+        is_admin: bool,
+    ) -> Result<Option<i32>> {
+        sqlx::query_scalar(
+            r#"
+            INSERT INTO app_user (username, password_hash, is_admin) VALUES (?1, ?2, ?3)
+            ON CONFLICT (username) DO NOTHING
+            RETURNING id
+            "#,
+        )
+        .bind(username)
+        .bind(password_hash)
+        .bind(is_admin)
+        .fetch_optional(&self.pool)
+        .timed("create_user")
+        .await
+    }
+
+    async fn users(&self) -> Result<Vec<User>> {
+        sqlx::query_as(
+            "SELECT id, username, is_admin, disabled, created_on FROM app_user ORDER BY username",
+        )
+        .fetch_all(&self.pool)
+        .timed("list_users")
+        .await
+    }
+
+    async fn credentials(&self, username: &str) -> Result<Option<Credentials>> {
+        sqlx::query_as(
+            r#"
+            SELECT id, username, is_admin, disabled, created_on, password_hash
+            FROM app_user WHERE username = ?1
+            "#,
+        )
+        .bind(username)
+        .fetch_optional(&self.pool)
+        .timed("get_credentials")
+        .await
+    }
+
+    async fn update_user(&self, id: i32, update: &UserUpdate) -> Result<bool> {
+        let mut txn = self.pool.begin().await?;
+        let updated = sqlx::query(
+            r#"
+            UPDATE app_user SET
+                password_hash = coalesce(?2, password_hash),
+                is_admin = coalesce(?3, is_admin),
+                disabled = coalesce(?4, disabled)
+            WHERE id = ?1
+            "#,
+        )
+        .bind(id)
+        .bind(&update.password_hash)
+        .bind(update.is_admin)
+        .bind(update.disabled)
+        .execute(&mut *txn)
+        .timed("update_user")
+        .await?
+        .rows_affected();
+        if update.revokes_sessions() {
+            sqlx::query("DELETE FROM user_session WHERE user_id = ?1")
+                .bind(id)
+                .execute(&mut *txn)
+                .timed("revoke_sessions")
+                .await?;
+        }
+        txn.commit().await?;
+        Ok(updated > 0)
+    }
+
+    async fn delete_user(&self, id: i32) -> Result<bool> {
+        let deleted = sqlx::query("DELETE FROM app_user WHERE id = ?1")
+            .bind(id)
+            .execute(&self.pool)
+            .timed("delete_user")
+            .await?
+            .rows_affected();
+        Ok(deleted > 0)
+    }
+
+    async fn create_session(
+        &self,
+        user_id: i32,
+        token_hash: &str,
+        expires_on: NaiveDateTime,
+    ) -> Result<()> {
+        let mut txn = self.pool.begin().await?;
+        sqlx::query("DELETE FROM user_session WHERE expires_on < ?1")
+            .bind(Utc::now().naive_utc())
+            .execute(&mut *txn)
+            .timed("expire_sessions")
+            .await?;
+        sqlx::query(
+            "INSERT INTO user_session (token_hash, user_id, expires_on) VALUES (?1, ?2, ?3)",
+        )
+        .bind(token_hash)
+        .bind(user_id)
+        .bind(expires_on)
+        .execute(&mut *txn)
+        .timed("create_session")
+        .await?;
+        txn.commit().await
+    }
+
+    async fn session(&self, token_hash: &str) -> Result<Option<Session>> {
+        sqlx::query_as(
+            r#"
+            SELECT
+                app_user.id,
+                app_user.username,
+                app_user.is_admin,
+                app_user.disabled,
+                app_user.created_on,
+                user_session.expires_on
+            FROM user_session
+            JOIN app_user ON user_session.user_id = app_user.id
+            WHERE user_session.token_hash = ?1
+            AND NOT app_user.disabled
+            "#,
+        )
+        .bind(token_hash)
+        .fetch_optional(&self.pool)
+        .timed("get_session")
+        .await
+    }
+
+    async fn delete_session(&self, token_hash: &str) -> Result<()> {
+        sqlx::query("DELETE FROM user_session WHERE token_hash = ?1")
+            .bind(token_hash)
+            .execute(&self.pool)
+            .timed("delete_session")
+            .await?;
+        Ok(())
+    }
+
+    async fn create_api_token(
+        &self,
+        user_id: i32,
+        name: &str,
+        token_hash: &str,
+    ) -> Result<Option<i32>> {
+        sqlx::query_scalar(
+            r#"
+            INSERT INTO api_token (user_id, name, token_hash) VALUES (?1, ?2, ?3)
+            ON CONFLICT (user_id, name) DO NOTHING
+            RETURNING id
+            "#,
+        )
+        .bind(user_id)
+        .bind(name)
+        .bind(token_hash)
+        .fetch_optional(&self.pool)
+        .timed("create_api_token")
+        .await
+    }
+
+    async fn api_tokens(&self, user_id: i32) -> Result<Vec<ApiToken>> {
+        sqlx::query_as(
+            r#"
+            SELECT id, name, created_on, last_used_on FROM api_token
+            WHERE user_id = ?1
+            ORDER BY created_on DESC, id DESC
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .timed("list_api_tokens")
+        .await
+    }
+
+    async fn token_user(&self, token_hash: &str) -> Result<Option<User>> {
+        let mut txn = self.pool.begin().await?;
+        let user_id: Option<i32> = sqlx::query_scalar(
+            "UPDATE api_token SET last_used_on = ?2 WHERE token_hash = ?1 RETURNING user_id",
+        )
+        .bind(token_hash)
+        .bind(Utc::now().naive_utc())
+        .fetch_optional(&mut *txn)
+        .timed("use_api_token")
+        .await?;
+        let Some(user_id) = user_id else {
+            return Ok(None);
+        };
+        let user = sqlx::query_as(
+            r#"
+            SELECT id, username, is_admin, disabled, created_on FROM app_user
+            WHERE id = ?1 AND NOT disabled
+            "#,
+        )
+        .bind(user_id)
+        .fetch_optional(&mut *txn)
+        .timed("get_token_user")
+        .await?;
+        txn.commit().await?;
+        Ok(user)
+    }
+
+    async fn delete_api_token(&self, user_id: i32, id: i32) -> Result<bool> {
+        let deleted = sqlx::query("DELETE FROM api_token WHERE user_id = ?1 AND id = ?2")
+            .bind(user_id)
+            .bind(id)
+            .execute(&self.pool)
+            .timed("delete_api_token")
+            .await?
+            .rows_affected();
+        Ok(deleted > 0)
+    }
+
+    async fn protein_access(&self) -> Result<Vec<ProteinAccess>> {
+        sqlx::query_as(
+            r#"
+            SELECT name, project_id, public, archived_on IS NOT NULL as archived
+            FROM protein
+            ORDER BY name
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .timed("protein_access")
+        .await
+    }
+
+    async fn set_protein_access(
+        &self,
+        protein: &str,
+        project_id: Option<i32>,
+        public: bool,
+    ) -> Result<bool> {
+        let updated =
+            sqlx::query("UPDATE protein SET project_id = ?2, public = ?3 WHERE name = ?1")
+                .bind(protein)
+                .bind(project_id)
+                .bind(public)
+                .execute(&self.pool)
+                .timed("set_protein_access")
+                .await?
+                .rows_affected();
+        Ok(updated > 0)
+    }
+
+    async fn create_project(&self, name: &str) -> Result<Option<i32>> {
+        sqlx::query_scalar(
+            "INSERT INTO project (name) VALUES (?1) ON CONFLICT (name) DO NOTHING RETURNING id",
+        )
+        .bind(name)
+        .fetch_optional(&self.pool)
+        .timed("create_project")
+        .await
+    }
+
+    async fn projects(&self) -> Result<Vec<Project>> {
+        sqlx::query_as("SELECT id, name, created_on FROM project ORDER BY name")
+            .fetch_all(&self.pool)
+            .timed("list_projects")
+            .await
+    }
+
+    async fn members(&self, project_id: i32) -> Result<Vec<Member>> {
+        sqlx::query_as(
+            r#"
+            SELECT app_user.id as user_id, app_user.username, project_member.role
+            FROM project_member
+            JOIN app_user ON app_user.id = project_member.user_id
+            WHERE project_member.project_id = ?1
+            ORDER BY app_user.username
+            "#,
+        )
+        .bind(project_id)
+        .fetch_all(&self.pool)
+        .timed("list_members")
+        .await
+    }
+
+    async fn roles(&self, user_id: i32) -> Result<BTreeMap<i32, Role>> {
+        let rows: Vec<(i32, String)> =
+            sqlx::query_as("SELECT project_id, role FROM project_member WHERE user_id = ?1")
+                .bind(user_id)
+                .fetch_all(&self.pool)
+                .timed("user_roles")
+                .await?;
+        rows.into_iter()
+            .map(|(project_id, role)| {
+                let role = role
+                    .parse()
+                    .map_err(|err: String| sqlx::Error::Decode(err.into()))?;
+                Ok((project_id, role))
+            })
+            .collect()
+    }
+
+    async fn set_member(&self, project_id: i32, user_id: i32, role: Option<Role>) -> Result<bool> {
+        let query = match role {
+            Some(role) => sqlx::query(
+                r#"
+                INSERT INTO project_member (project_id, user_id, role) VALUES (?1, ?2, ?3)
+                ON CONFLICT (project_id, user_id) DO UPDATE SET role = excluded.role
+                "#,
+            )
+            .bind(project_id)
+            .bind(user_id)
+            .bind(role.as_str()),
+            None => {
+                sqlx::query("DELETE FROM project_member WHERE project_id = ?1 AND user_id = ?2")
+                    .bind(project_id)
+                    .bind(user_id)
+            }
+        };
+        let changed = query
+            .execute(&self.pool)
+            .timed("set_member")
+            .await?
+            .rows_affected();
+        Ok(changed > 0)
+    }
+
+    async fn applied_migration(&self) -> Result<Option<i64>> {
+        sqlx::query_scalar("SELECT max(version) FROM _sqlx_migrations WHERE success")
+            .fetch_one(&self.pool)
+            .timed("applied_migration")
+            .await
+    }
+
+    fn latest_migration(&self) -> i64 {
+        migrate::latest_sqlite_version()
+    }
+
+    fn pool_stats(&self) -> PoolStats {
+        PoolStats {
+            size: self.pool.size(),
+            idle: self.pool.num_idle() as u32,
+            max: self.pool.options().get_max_connections(),
+        }
+    }
+}
+
+/// Rows of `table`, `variant` or `archived_variant`, in a selection bound
+/// by `bind_selection`.
+fn selected(table: &str) -> String {
+    format!(
+        "{table}.protein_id = (SELECT id FROM protein WHERE name = ?1) \
+         AND (?2 IS NULL OR {table}.condition = ?2) \
+         AND (?3 IS NULL OR {table}.version = ?3) \
+         AND (?4 IS NULL OR {table}.batch_id = ?4)"
+    )
+}
+
+/// Binds protein, condition, version and batch as `?1` to `?4`.
+fn bind_selection<'q>(
+    query: Query<'q, Sqlite, SqliteArguments<'q>>,
+    selection: &'q Selection,
+) -> Query<'q, Sqlite, SqliteArguments<'q>> {
+    query
+        .bind(&selection.protein)
+        .bind(selection.condition.as_deref())
+        .bind(selection.version.as_deref())
+        .bind(selection.batch)
+}
+
+/// Shown and archived variants `selection` covers; none for an unknown
+/// protein.
+async fn count(conn: &mut SqliteConnection, selection: &Selection) -> Result<Affected> {
+    let (variants, archived): (i64, i64) = sqlx::query_as(&format!(
+        "SELECT (SELECT count(*) FROM variant WHERE {}), \
+         (SELECT count(*) FROM archived_variant WHERE {})",
+        selected("variant"),
+        selected("archived_variant")
+    ))
+    .bind(&selection.protein)
+    .bind(selection.condition.as_deref())
+    .bind(selection.version.as_deref())
+    .bind(selection.batch)
+    .fetch_one(conn)
+    .await?;
+    Ok(Affected { variants, archived })
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    use super::*;
+    use crate::repository::testing::{p1_variants, params};
+
+    /// A migrated in-memory database; one connection, since each would get
+    /// its own database.
+    async fn repository() -> SqliteRepository {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        migrate::run_sqlite(&pool).await.unwrap();
+        let repository = SqliteRepository::new(pool);
+        repository
+            .create_protein("P1", None, Some("MA"))
+            .await
+            .unwrap()
+            .unwrap();
+        repository
+            .insert_variants(
+                "P1",
+                &p1_variants(),
+                &SourceFile {
+                    name: "p1.tsv".to_string(),
+                    ..SourceFile::default()
+                },
+                &Actor::command_line("test"),
+            )
+            .await
+            .unwrap();
+        repository
+    }
+
+    #[tokio::test]
+    async fn ranked_page_matches_memory_repository() {
+        let repository = repository().await;
+        let by_p = repository
+            .variants_page(
+                &params(PositionFilter::MostSignificantPValue, Paint::PValue, None),
+                1,
+                2,
+            )
+            .await
+            .unwrap();
+        let aas: Vec<(i32, &str)> = by_p.iter().map(|v| (v.pos, v.aa.as_str())).collect();
+        assert_eq!(aas, [(1, "C"), (2, "A")]);
+        assert_eq!(by_p[0].protein, "P1");
+
+        // Classification has no threshold to filter by
+        let unfiltered = repository
+            .variants_page(
+                &params(PositionFilter::NoOrder, Paint::Classification, Some(0.25)),
+                1,
+                2,
+            )
+            .await
+            .unwrap();
+        assert_eq!(unfiltered.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn ingest_refreshes_summaries_and_revision() {
+        let repository = repository().await;
+        assert_eq!(repository.conditions_for("P1").await.unwrap(), ["c1"]);
+        assert_eq!(repository.max_position("P1", "c1").await.unwrap(), Some(2));
+        let range = repository
+            .range_for("P1", "c1", Paint::Log2FoldChange)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((range.min, range.max, range.max_abs), (-2.0, 1.5, 2.0));
+        // One bump for the new protein, one for the ingest
+        assert_eq!(repository.dataset_revision().await.unwrap(), 2);
+        assert_eq!(
+            repository.create_protein("P1", None, None).await.unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn ingests_are_audited_with_their_batch() {
+        let repository = repository().await;
+        let batch = repository.variant_batch(1).await.unwrap().unwrap();
+        assert_eq!(
+            (batch.protein.as_str(), batch.file_name.as_str()),
+            ("P1", "p1.tsv")
+        );
+        assert_eq!((batch.rows_inserted, batch.actor.as_str()), (4, "cli:test"));
+        let everything = AuditFilter::default();
+        let entries = repository
+            .audit_log(&everything, Scope::Everything)
+            .await
+            .unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].action, audit::Action::Ingest);
+        assert_eq!(entries[0].batch_id, Some(batch.id));
+        assert_eq!(
+            entries[0].details.0["conditions"],
+            serde_json::json!(["c1"])
+        );
+        // P1 is private and has no members
+        let entries = repository
+            .audit_log(&everything, Scope::Public)
+            .await
+            .unwrap();
+        assert!(entries.is_empty());
+
+        repository
+            .record(
+                &Actor::command_line("test"),
+                &Change::new(
+                    audit::Action::RenameProtein,
+                    "P1",
+                    serde_json::json!({"from": "P0", "to": "P1"}),
+                ),
+            )
+            .await
+            .unwrap();
+        let renames = AuditFilter {
+            action: Some(audit::Action::RenameProtein),
+            ..AuditFilter::default()
+        };
+        let entries = repository
+            .audit_log(&renames, Scope::Everything)
+            .await
+            .unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].details.0["from"], "P0");
+        let older = AuditFilter {
+            before: Some(entries[0].id),
+            ..AuditFilter::default()
+        };
+        let entries = repository
+            .audit_log(&older, Scope::Everything)
+            .await
+            .unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].action, audit::Action::Ingest);
+    }
+
+    #[tokio::test]
+    async fn archived_data_is_hidden_until_restored() {
+        let repository = repository().await;
+        let actor = Actor::command_line("test");
+        let condition = Selection {
+            protein: "P1".to_string(),
+            condition: Some("c1".to_string()),
+            ..Selection::default()
+        };
+        let affected = repository.affected(&condition).await.unwrap();
+        assert_eq!(
+            affected,
+            Affected {
+                variants: 4,
+                archived: 0
+            }
+        );
+
+        repository
+            .apply(Operation::Archive, &condition, &actor)
+            .await
+            .unwrap();
+        assert!(repository.conditions_for("P1").await.unwrap().is_empty());
+        assert!(repository.variant_by_id(1).await.unwrap().is_none());
+        let datasets = repository.datasets("P1").await.unwrap();
+        assert_eq!((datasets[0].variants, datasets[0].archived), (0, 4));
+
+        let restored = repository
+            .apply(Operation::Restore, &condition, &actor)
+            .await
+            .unwrap();
+        assert_eq!(restored.by(Operation::Restore), 4);
+        assert_eq!(repository.conditions_for("P1").await.unwrap(), ["c1"]);
+        assert_eq!(repository.max_position("P1", "c1").await.unwrap(), Some(2));
+        assert!(repository.variant_by_id(1).await.unwrap().is_some());
+
+        // A whole protein is only marked, and restored with everything under it
+        let protein = Selection {
+            protein: "P1".to_string(),
+            ..Selection::default()
+        };
+        repository
+            .apply(Operation::Archive, &protein, &actor)
+            .await
+            .unwrap();
+        assert!(repository
+            .list_proteins(Scope::Everything)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(repository.protein_access().await.unwrap()[0].archived);
+        assert!(!repository
+            .protein_visible(Scope::Everything, "P1")
+            .await
+            .unwrap());
+        repository
+            .apply(Operation::Restore, &protein, &actor)
+            .await
+            .unwrap();
+        assert_eq!(
+            repository.list_proteins(Scope::Everything).await.unwrap(),
+            ["P1"]
+        );
+        assert!(repository
+            .protein_visible(Scope::Everything, "P1")
+            .await
+            .unwrap());
+        // Not public and outside any project
+        assert!(!repository
+            .protein_visible(Scope::Public, "P1")
+            .await
+            .unwrap());
+
+        // Deleting the batch takes its variants and the batch itself
+        let batch = Selection {
+            batch: Some(1),
+            ..protein
+        };
+        let deleted = repository
+            .apply(Operation::Delete, &batch, &actor)
+            .await
+            .unwrap();
+        assert_eq!(deleted.by(Operation::Delete), 4);
+        assert!(repository.upload_batches("P1").await.unwrap().is_empty());
+        assert!(repository.conditions_for("P1").await.unwrap().is_empty());
+        let actions: Vec<audit::Action> = repository
+            .audit_log(&AuditFilter::default(), Scope::Everything)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.action)
+            .collect();
+        assert_eq!(
+            actions,
+            [
+                audit::Action::Delete,
+                audit::Action::Restore,
+                audit::Action::Archive,
+                audit::Action::Restore,
+                audit::Action::Archive,
+                audit::Action::Ingest,
+            ]
+        );
+    }
+}
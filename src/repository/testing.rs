@@ -0,0 +1,55 @@
+//! Factories shared by the unit tests of every backend, so they load and
+//! query the same data.
+
+use crate::{Paint, PositionFilter, TableParams, Variant};
+
+pub fn variant(pos: i32, aa: &str, log2_fold_change: f64, p_value: f64) -> Variant {
+    Variant {
+        id: None,
+        chunk: 1,
+        pos,
+        condition: "c1".to_string(),
+        aa: aa.to_string(),
+        log2_fold_change,
+        log2_std_error: 0.1,
+        statistic: log2_fold_change / 0.1,
+        p_value,
+        version: "v1".to_string(),
+        protein: String::new(),
+        created_on: Default::default(),
+    }
+}
+
+/// Two positions of protein P1 in condition c1, for `variants_page` tests.
+pub fn p1_variants() -> [Variant; 4] {
+    [
+        variant(1, "A", 0.5, 0.2),
+        variant(1, "C", -2.0, 0.01),
+        variant(2, "A", 1.5, 0.3),
+        variant(2, "*", -0.1, 0.9),
+    ]
+}
+
+pub fn params(
+    position_filter: PositionFilter,
+    paint: Paint,
+    threshold: Option<f64>,
+) -> TableParams {
+    TableParams {
+        protein: "P1".to_string(),
+        condition: "c1".to_string(),
+        position_filter,
+        paint,
+        operation: None,
+        threshold,
+        page: None,
+        plot: None,
+        classifier: None,
+        effect_cutoff: None,
+        p_cutoff: None,
+        posterior_cutoff: None,
+        gain_z: None,
+        tolerance_metric: None,
+        label_top: None,
+    }
+}
@@ -0,0 +1,611 @@
+//! Login, logout, the account page with personal API tokens, and the user
+//! administration page. `authenticate` runs in front of every route.
+
+use anyhow::Context;
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Path, Query, Request, State},
+    http::{header, request::Parts, HeaderMap, HeaderValue, Method, StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Redirect, Response},
+    Extension, Form,
+};
+use chrono::Utc;
+use dms_viewer::access::Scope;
+use dms_viewer::auth::{self, User, UserUpdate, SESSION_COOKIE};
+use dms_viewer::error::{ApiError, AppError};
+use dms_viewer::AppState;
+use maud::{html, Markup};
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::base;
+
+/// Paths anyone may use, even with `auth.require_login`.
+const PUBLIC_PATHS: [&str; 4] = ["/login", "/logout", "/healthz", "/readyz"];
+
+/// Who sent the request, `None` for an anonymous visitor. Set by
+/// `authenticate` on every request, together with the caller's `Scope`.
+#[derive(Debug, Clone)]
+pub struct Caller(pub Option<User>);
+
+/// A signed-in caller; anonymous requests get 401.
+pub struct SignedIn(pub User);
+
+/// A signed-in administrator; anyone else gets 401 or 403.
+pub struct Admin(pub User);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for SignedIn {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, AppError> {
+        match parts.extensions.get::<Caller>() {
+            Some(Caller(Some(user))) => Ok(SignedIn(user.clone())),
+            _ => Err(AppError::Unauthorized("sign in first".to_string())),
+        }
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Admin {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, AppError> {
+        let SignedIn(user) = SignedIn::from_request_parts(parts, state).await?;
+        if !user.is_admin {
+            return Err(AppError::Forbidden(
+                "only administrators can do this".to_string(),
+            ));
+        }
+        Ok(Admin(user))
+    }
+}
+
+/// Works out the `Caller` from an `Authorization: Bearer` API token or the
+/// session cookie. Requests that change anything need a signed-in caller, and
+/// with `auth.require_login` so does everything outside `PUBLIC_PATHS`.
+pub async fn authenticate(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let caller = match caller(&state, request.headers()).await {
+        Ok(caller) => caller,
+        Err(err) => return ApiError(err).into_response(),
+    };
+    let path = request.uri().path();
+    let public = PUBLIC_PATHS.contains(&path) || path.starts_with("/assets/");
+    let reads = matches!(*request.method(), Method::GET | Method::HEAD);
+    if caller.is_none() && !public && (state.config.auth.require_login || !reads) {
+        return login_required(&request);
+    }
+    request.extensions_mut().insert(Scope::of(caller.as_ref()));
+    request.extensions_mut().insert(Caller(caller));
+    next.run(request).await
+}
+
+async fn caller(state: &AppState, headers: &HeaderMap) -> Result<Option<User>, AppError> {
+    if let Some(token) = auth::bearer_token(headers) {
+        return match state
+            .repository
+            .token_user(&auth::secret_hash(token))
+            .await?
+        {
+            Some(user) => Ok(Some(user)),
+            None => Err(AppError::Unauthorized("invalid API token".to_string())),
+        };
+    }
+    let Some(secret) = auth::cookie(headers, SESSION_COOKIE) else {
+        return Ok(None);
+    };
+    let token_hash = auth::secret_hash(secret);
+    match state.repository.session(&token_hash).await? {
+        Some(session) if session.expires_on > Utc::now().naive_utc() => Ok(Some(session.user)),
+        Some(_) => {
+            state.repository.delete_session(&token_hash).await?;
+            Ok(None)
+        }
+        None => Ok(None),
+    }
+}
+
+/// Sends browsers to the login page and tells everyone else with a 401.
+fn login_required(request: &Request) -> Response {
+    let err = AppError::Unauthorized("sign in first".to_string());
+    let headers = request.headers();
+    if headers.contains_key("HX-Request") {
+        let mut response = err.into_response();
+        response
+            .headers_mut()
+            .insert("HX-Redirect", HeaderValue::from_static("/login"));
+        return response;
+    }
+    let wants_html = headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"));
+    if request.method() == Method::GET && wants_html {
+        return Redirect::to(&login_url(request.uri())).into_response();
+    }
+    ApiError(err).into_response()
+}
+
+fn login_url(next: &Uri) -> String {
+    let next = next.path_and_query().map_or("/", |next| next.as_str());
+    format!("/login?next={}", percent_encode(next))
+}
+
+pub fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+/// Where to go after logging in: a path on this site, the viewer otherwise.
+fn local_path(next: Option<&str>) -> &str {
+    match next {
+        Some(next) if next.starts_with('/') && !next.starts_with("//") => next,
+        _ => "/",
+    }
+}
+
+fn hx_redirect(location: &str) -> HeaderValue {
+    HeaderValue::from_str(location).unwrap_or(HeaderValue::from_static("/"))
+}
+
+/// Runs the argon2 work off the async workers.
+async fn hash_password(password: String) -> Result<String, AppError> {
+    Ok(
+        tokio::task::spawn_blocking(move || auth::hash_password(&password))
+            .await
+            .context("password hashing panicked")??,
+    )
+}
+
+/// Starts a session for `user` and returns the `Set-Cookie` header for it.
+async fn start_session(state: &AppState, user: &User) -> Result<HeaderValue, AppError> {
+    let secret = auth::new_secret();
+    let ttl = state.config.auth.session_ttl;
+    let expires_on = Utc::now().naive_utc()
+        + chrono::Duration::from_std(ttl).context("session_ttl_secs is out of range")?;
+    state
+        .repository
+        .create_session(user.id, &auth::secret_hash(&secret), expires_on)
+        .await?;
+    let cookie = auth::session_cookie(&secret, ttl.as_secs(), state.config.auth.secure_cookies);
+    Ok(HeaderValue::from_str(&cookie).context("unusable session cookie")?)
+}
+
+#[derive(Deserialize)]
+pub struct LoginQuery {
+    next: Option<String>,
+}
+
+pub async fn get_login(
+    Extension(Caller(caller)): Extension<Caller>,
+    Query(query): Query<LoginQuery>,
+) -> Response {
+    let next = local_path(query.next.as_deref());
+    if caller.is_some() {
+        return Redirect::to(next).into_response();
+    }
+    base(html!(
+        main class="account-page" {
+            h2 { "Log in" }
+            div id="error-message" aria-live="polite"{}
+            form class="account-form" hx-post="/login" {
+                input type="hidden" name="next" value=(next);
+                label for="username" { "Username" }
+                input type="text" id="username" name="username" autocomplete="username" required autofocus;
+                label for="password" { "Password" }
+                input type="password" id="password" name="password" autocomplete="current-password" required;
+                button { "Log in" }
+            }
+        }
+    ))
+    .into_response()
+}
+
+#[derive(Deserialize)]
+pub struct LoginForm {
+    username: String,
+    password: String,
+    next: Option<String>,
+}
+
+pub async fn post_login(
+    State(state): State<AppState>,
+    Form(form): Form<LoginForm>,
+) -> Result<Response, AppError> {
+    let credentials = state.repository.credentials(&form.username).await?;
+    let password = form.password;
+    let user = tokio::task::spawn_blocking(move || match credentials {
+        Some(credentials) => (auth::verify_password(&password, &credentials.password_hash)
+            && !credentials.user.disabled)
+            .then_some(credentials.user),
+        None => {
+            auth::verify_nothing(&password);
+            None
+        }
+    })
+    .await
+    .context("password check panicked")?;
+    let Some(user) = user else {
+        warn!("failed login for {}", form.username);
+        return Err(AppError::Unauthorized(
+            "wrong username or password".to_string(),
+        ));
+    };
+    info!("{} logged in", user.username);
+    let cookie = start_session(&state, &user).await?;
+    let mut response = StatusCode::NO_CONTENT.into_response();
+    let headers = response.headers_mut();
+    headers.insert(header::SET_COOKIE, cookie);
+    headers.insert("HX-Redirect", hx_redirect(local_path(form.next.as_deref())));
+    Ok(response)
+}
+
+pub async fn post_logout(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    if let Some(secret) = auth::cookie(&headers, SESSION_COOKIE) {
+        state
+            .repository
+            .delete_session(&auth::secret_hash(secret))
+            .await?;
+    }
+    let mut response = StatusCode::NO_CONTENT.into_response();
+    let cookie = auth::session_cookie("", 0, state.config.auth.secure_cookies);
+    let headers = response.headers_mut();
+    headers.insert(
+        header::SET_COOKIE,
+        HeaderValue::from_str(&cookie).context("unusable session cookie")?,
+    );
+    headers.insert("HX-Redirect", HeaderValue::from_static("/"));
+    Ok(response)
+}
+
+/// Links to the account pages, or to the login page for anonymous visitors.
+pub fn account_bar(caller: Option<&User>) -> Markup {
+    html!(
+        nav id="account-bar" {
+            @match caller {
+                Some(user) => {
+                    span { "Signed in as " strong { (user.username) } }
+                    a href="/account" { "Account" }
+                    a href="/projects" { "Projects" }
+                    a href="/datasets" { "Datasets" }
+                    @if user.is_admin {
+                        a href="/admin/users" { "Users" }
+                    }
+                    button type="button" hx-post="/logout" { "Log out" }
+                }
+                None => a href="/login" { "Log in" },
+            }
+        }
+    )
+}
+
+pub async fn get_account(
+    State(state): State<AppState>,
+    SignedIn(user): SignedIn,
+) -> Result<Markup, AppError> {
+    let tokens = tokens_section(&state, &user, None).await?;
+    Ok(base(html!(
+        main class="account-page" {
+            (account_bar(Some(&user)))
+            h2 { "Account" }
+            div id="error-message" aria-live="polite"{}
+            h3 { "Password" }
+            form class="account-form" hx-post="/account/password" hx-target="#password-message" {
+                label for="current" { "Current password" }
+                input type="password" id="current" name="current" autocomplete="current-password" required;
+                label for="password" { "New password" }
+                input type="password" id="password" name="password" autocomplete="new-password"
+                    minlength=(auth::MIN_PASSWORD_LENGTH) required;
+                button { "Change password" }
+                span id="password-message" {}
+            }
+            h3 { "API tokens" }
+            p {
+                "Send a token as " code { "Authorization: Bearer <token>" }
+                " to use the API and upload files from scripts."
+            }
+            (tokens)
+        }
+    )))
+}
+
+#[derive(Deserialize)]
+pub struct PasswordForm {
+    current: String,
+    password: String,
+}
+
+/// Changes the caller's password. Every session of the user ends, so the
+/// caller gets a fresh one.
+pub async fn post_password(
+    State(state): State<AppState>,
+    SignedIn(user): SignedIn,
+    Form(form): Form<PasswordForm>,
+) -> Result<Response, AppError> {
+    auth::check_password(&form.password)?;
+    let Some(credentials) = state.repository.credentials(&user.username).await? else {
+        return Err(AppError::Unauthorized("sign in first".to_string()));
+    };
+    let current = form.current;
+    let matches = tokio::task::spawn_blocking(move || {
+        auth::verify_password(&current, &credentials.password_hash)
+    })
+    .await
+    .context("password check panicked")?;
+    if !matches {
+        return Err(AppError::BadRequest(
+            "the current password is wrong".to_string(),
+        ));
+    }
+    let update = UserUpdate {
+        password_hash: Some(hash_password(form.password).await?),
+        ..UserUpdate::default()
+    };
+    state.repository.update_user(user.id, &update).await?;
+    info!("{} changed their password", user.username);
+    let cookie = start_session(&state, &user).await?;
+    let mut response = html!("Password changed, other sessions are logged out.").into_response();
+    response.headers_mut().insert(header::SET_COOKIE, cookie);
+    Ok(response)
+}
+
+/// The caller's tokens, showing `created` in full once after it was made.
+async fn tokens_section(
+    state: &AppState,
+    user: &User,
+    created: Option<&str>,
+) -> Result<Markup, AppError> {
+    let tokens = state.repository.api_tokens(user.id).await?;
+    Ok(html!(
+        section id="api-tokens" {
+            @if let Some(token) = created {
+                p class="new-token" {
+                    "Copy the new token now, it will not be shown again: "
+                    code { (token) }
+                }
+            }
+            table class="account-table" {
+                thead { tr { th { "Name" } th { "Created" } th { "Last used" } th {} } }
+                tbody {
+                    @for token in &tokens {
+                        tr {
+                            td { (token.name) }
+                            td { (token.created_on.format("%Y-%m-%d %H:%M")) }
+                            td {
+                                @match token.last_used_on {
+                                    Some(used) => (used.format("%Y-%m-%d %H:%M")),
+                                    None => "never",
+                                }
+                            }
+                            td {
+                                button type="button"
+                                    hx-post=(format!("/account/tokens/{}/delete", token.id))
+                                    hx-target="#api-tokens"
+                                    hx-swap="outerHTML"
+                                    hx-confirm=(format!("Revoke token {}?", token.name))
+                                    { "Revoke" }
+                            }
+                        }
+                    }
+                }
+            }
+            form class="account-form" hx-post="/account/tokens" hx-target="#api-tokens" hx-swap="outerHTML" {
+                label for="token-name" { "New token" }
+                input type="text" id="token-name" name="name" placeholder="e.g. import script"
+                    maxlength=(auth::MAX_NAME_LENGTH) required;
+                button { "Create token" }
+            }
+        }
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct TokenForm {
+    name: String,
+}
+
+pub async fn post_token(
+    State(state): State<AppState>,
+    SignedIn(user): SignedIn,
+    Form(form): Form<TokenForm>,
+) -> Result<Markup, AppError> {
+    let name = form.name.trim();
+    if name.is_empty() || name.chars().count() > auth::MAX_NAME_LENGTH {
+        return Err(AppError::BadRequest(format!(
+            "token names have 1 to {} characters",
+            auth::MAX_NAME_LENGTH
+        )));
+    }
+    let token = auth::new_api_token();
+    if state
+        .repository
+        .create_api_token(user.id, name, &auth::secret_hash(&token))
+        .await?
+        .is_none()
+    {
+        return Err(AppError::BadRequest(format!(
+            "you already have a token called {name}"
+        )));
+    }
+    info!("{} created API token {name}", user.username);
+    tokens_section(&state, &user, Some(&token)).await
+}
+
+pub async fn delete_token(
+    State(state): State<AppState>,
+    SignedIn(user): SignedIn,
+    Path(id): Path<i32>,
+) -> Result<Markup, AppError> {
+    if !state.repository.delete_api_token(user.id, id).await? {
+        return Err(AppError::NotFound(format!("API token {id}")));
+    }
+    tokens_section(&state, &user, None).await
+}
+
+pub async fn get_users(
+    State(state): State<AppState>,
+    Admin(admin): Admin,
+) -> Result<Markup, AppError> {
+    let users = users_section(&state, &admin).await?;
+    Ok(base(html!(
+        main class="account-page" {
+            (account_bar(Some(&admin)))
+            h2 { "Users" }
+            div id="error-message" aria-live="polite"{}
+            (users)
+            h3 { "New user" }
+            form class="account-form" hx-post="/admin/users" hx-target="#users" hx-swap="outerHTML" {
+                label for="username" { "Username" }
+                input type="text" id="username" name="username" maxlength=(auth::MAX_NAME_LENGTH) required;
+                label for="password" { "Password" }
+                input type="password" id="password" name="password" autocomplete="new-password"
+                    minlength=(auth::MIN_PASSWORD_LENGTH) required;
+                label { input type="checkbox" name="is_admin" value="true"; " Administrator" }
+                button { "Create user" }
+            }
+        }
+    )))
+}
+
+async fn users_section(state: &AppState, admin: &User) -> Result<Markup, AppError> {
+    let users = state.repository.users().await?;
+    Ok(html!(
+        table id="users" class="account-table" {
+            thead { tr { th { "User" } th { "Role" } th { "Status" } th { "Created" } th {} } }
+            tbody {
+                @for user in &users {
+                    tr {
+                        td { (user.username) }
+                        td { @if user.is_admin { "administrator" } @else { "user" } }
+                        td { @if user.disabled { "disabled" } @else { "active" } }
+                        td { (user.created_on.format("%Y-%m-%d")) }
+                        td class="account-actions" {
+                            @if user.id != admin.id {
+                                button type="button"
+                                    hx-post=(format!("/admin/users/{}", user.id))
+                                    hx-vals=(format!(r#"{{"is_admin": {}}}"#, !user.is_admin))
+                                    hx-target="#users" hx-swap="outerHTML"
+                                    { @if user.is_admin { "Revoke admin" } @else { "Make admin" } }
+                                button type="button"
+                                    hx-post=(format!("/admin/users/{}", user.id))
+                                    hx-vals=(format!(r#"{{"disabled": {}}}"#, !user.disabled))
+                                    hx-target="#users" hx-swap="outerHTML"
+                                    { @if user.disabled { "Enable" } @else { "Disable" } }
+                                button type="button"
+                                    hx-post=(format!("/admin/users/{}/delete", user.id))
+                                    hx-target="#users" hx-swap="outerHTML"
+                                    hx-confirm=(format!("Delete user {} and their API tokens?", user.username))
+                                    { "Delete" }
+                            }
+                            form hx-post=(format!("/admin/users/{}", user.id)) hx-target="#users" hx-swap="outerHTML" {
+                                input type="password" name="password" placeholder="new password"
+                                    autocomplete="new-password" minlength=(auth::MIN_PASSWORD_LENGTH) required;
+                                button { "Reset password" }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct NewUserForm {
+    username: String,
+    password: String,
+    #[serde(default)]
+    is_admin: bool,
+}
+
+pub async fn post_user(
+    State(state): State<AppState>,
+    Admin(admin): Admin,
+    Form(form): Form<NewUserForm>,
+) -> Result<Markup, AppError> {
+    let username = form.username.trim();
+    auth::check_username(username)?;
+    auth::check_password(&form.password)?;
+    let password_hash = hash_password(form.password).await?;
+    if state
+        .repository
+        .create_user(username, &password_hash, form.is_admin)
+        .await?
+        .is_none()
+    {
+        return Err(AppError::BadRequest(format!(
+            "user {username} already exists"
+        )));
+    }
+    info!("{} created user {username}", admin.username);
+    users_section(&state, &admin).await
+}
+
+#[derive(Deserialize)]
+pub struct UserForm {
+    password: Option<String>,
+    is_admin: Option<bool>,
+    disabled: Option<bool>,
+}
+
+pub async fn update_user(
+    State(state): State<AppState>,
+    Admin(admin): Admin,
+    Path(id): Path<i32>,
+    Form(form): Form<UserForm>,
+) -> Result<Markup, AppError> {
+    if id == admin.id && (form.is_admin == Some(false) || form.disabled == Some(true)) {
+        return Err(AppError::BadRequest(
+            "you cannot lock yourself out; ask another administrator".to_string(),
+        ));
+    }
+    let password_hash = match form.password {
+        Some(password) => {
+            auth::check_password(&password)?;
+            Some(hash_password(password).await?)
+        }
+        None => None,
+    };
+    let update = UserUpdate {
+        password_hash,
+        is_admin: form.is_admin,
+        disabled: form.disabled,
+    };
+    if !state.repository.update_user(id, &update).await? {
+        return Err(AppError::NotFound(format!("user {id}")));
+    }
+    info!("{} updated user {id}", admin.username);
+    users_section(&state, &admin).await
+}
+
+pub async fn delete_user(
+    State(state): State<AppState>,
+    Admin(admin): Admin,
+    Path(id): Path<i32>,
+) -> Result<Markup, AppError> {
+    if id == admin.id {
+        return Err(AppError::BadRequest(
+            "you cannot delete your own account".to_string(),
+        ));
+    }
+    if !state.repository.delete_user(id).await? {
+        return Err(AppError::NotFound(format!("user {id}")));
+    }
+    info!("{} deleted user {id}", admin.username);
+    users_section(&state, &admin).await
+}
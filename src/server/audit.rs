@@ -0,0 +1,148 @@
+//! The audit log, as a page to browse and filter and as JSON: who ingested,
+//! deleted, archived or edited which protein, and from which file. Callers
+//! only see entries about proteins they can see.
+
+use axum::{
+    extract::{Query, State},
+    Extension, Json,
+};
+use dms_viewer::access::Scope;
+use dms_viewer::audit::{Action, AuditEntry, AuditFilter};
+use dms_viewer::error::{ApiError, AppError};
+use dms_viewer::AppState;
+use maud::{html, Markup};
+use serde_json::Value;
+
+use crate::accounts::{account_bar, Caller, SignedIn};
+use crate::base;
+
+pub async fn get_audit(
+    State(state): State<AppState>,
+    Extension(scope): Extension<Scope>,
+    SignedIn(user): SignedIn,
+    Query(filter): Query<AuditFilter>,
+) -> Result<Markup, AppError> {
+    let entries = state.repository.audit_log(&filter, scope).await?;
+    let proteins = state.repository.list_proteins(scope).await?;
+    let text = |value: &Option<String>| value.clone().unwrap_or_default();
+    let day = |value: Option<chrono::NaiveDate>| value.map_or(String::new(), |day| day.to_string());
+    Ok(base(html!(
+        main class="account-page" {
+            (account_bar(Some(&user)))
+            h2 { "Audit log" }
+            p {
+                "Every ingest, delete, archive and protein edit on the proteins you can see, newest first. "
+                "The same entries are available as JSON from " code { "/api/audit" } " with these parameters."
+            }
+            form class="account-form" method="get" action="/audit" {
+                input type="text" name="protein" placeholder="protein" list="audit-proteins"
+                    value=(text(&filter.protein));
+                datalist id="audit-proteins" {
+                    @for protein in &proteins {
+                        option value=(protein) {}
+                    }
+                }
+                select name="action" {
+                    option value="" { "any action" }
+                    @for action in Action::ALL {
+                        option value=(action) selected[filter.action == Some(action)] { (action) }
+                    }
+                }
+                input type="text" name="actor" placeholder="user" value=(text(&filter.actor));
+                label { "From " input type="date" name="since" value=(day(filter.since)); }
+                label { "to " input type="date" name="until" value=(day(filter.until)); }
+                @if let Some(batch) = filter.batch {
+                    input type="hidden" name="batch" value=(batch);
+                }
+                button { "Filter" }
+                a href="/audit" { "Clear" }
+            }
+            @if let Some(batch) = filter.batch {
+                p { "Only upload batch " (batch) "." }
+            }
+            @if entries.is_empty() {
+                p { "No changes match." }
+            } @else {
+                table class="account-table audit-table" {
+                    thead { tr { th { "When (UTC)" } th { "Who" } th { "Action" } th { "Protein" } th { "Batch" } th { "Details" } } }
+                    tbody {
+                        @for entry in &entries {
+                            tr {
+                                td { (entry.created_on.format("%Y-%m-%d %H:%M:%S")) }
+                                td { (entry.actor) }
+                                td { (entry.action) }
+                                td { (entry.protein) }
+                                td {
+                                    @if let Some(batch) = entry.batch_id {
+                                        a href=(format!("/audit?batch={batch}")) { (batch) }
+                                    }
+                                }
+                                td { (details(entry)) }
+                            }
+                        }
+                    }
+                }
+            }
+            @if let Some(last) = entries.last().filter(|_| entries.len() as i64 == filter.limit()) {
+                // The same filter again, starting below the last entry shown
+                form method="get" action="/audit" {
+                    @for (name, value) in [("protein", &filter.protein), ("actor", &filter.actor)] {
+                        @if let Some(value) = value {
+                            input type="hidden" name=(name) value=(value);
+                        }
+                    }
+                    @if let Some(action) = filter.action {
+                        input type="hidden" name="action" value=(action);
+                    }
+                    @for (name, value) in [("since", filter.since), ("until", filter.until)] {
+                        @if let Some(value) = value {
+                            input type="hidden" name=(name) value=(value.to_string());
+                        }
+                    }
+                    @if let Some(batch) = filter.batch {
+                        input type="hidden" name="batch" value=(batch);
+                    }
+                    input type="hidden" name="before" value=(last.id);
+                    button { "Older entries" }
+                }
+            }
+        }
+    )))
+}
+
+pub async fn get_audit_json(
+    State(state): State<AppState>,
+    Extension(scope): Extension<Scope>,
+    Extension(caller): Extension<Caller>,
+    Query(filter): Query<AuditFilter>,
+) -> Result<Json<Vec<AuditEntry>>, ApiError> {
+    if caller.0.is_none() {
+        return Err(AppError::Unauthorized("sign in first".to_string()).into());
+    }
+    Ok(Json(state.repository.audit_log(&filter, scope).await?))
+}
+
+/// The fields of an entry's details, one per line.
+fn details(entry: &AuditEntry) -> Markup {
+    html!(
+        @if let Value::Object(fields) = &entry.details.0 {
+            @for (name, value) in fields {
+                div class="audit-detail" { (name.replace('_', " ")) ": " (show(value)) }
+            }
+        }
+    )
+}
+
+fn show(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Null => "none".to_string(),
+        Value::Array(items) => items.iter().map(show).collect::<Vec<_>>().join(", "),
+        Value::Object(fields) => fields
+            .iter()
+            .map(|(name, value)| format!("{name} → {}", show(value)))
+            .collect::<Vec<_>>()
+            .join(", "),
+        other => other.to_string(),
+    }
+}
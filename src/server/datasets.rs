@@ -0,0 +1,394 @@
+//! Deleting, archiving and restoring data, a protein, condition, version or
+//! upload batch at a time. The page shows what each protein holds; every
+//! operation goes through a confirmation page with the number of variants it
+//! affects. `/api/datasets` does the same for scripts, as a dry run unless
+//! the request says `confirm`. Project admins manage their proteins and site
+//! administrators all of them.
+
+use axum::{
+    extract::{Query, State},
+    Extension, Form, Json,
+};
+use dms_viewer::access::{ProteinAccess, Role, Scope};
+use dms_viewer::audit::Actor;
+use dms_viewer::auth::User;
+use dms_viewer::datasets::{Affected, Dataset, Operation, Selection};
+use dms_viewer::error::{ApiError, AppError};
+use dms_viewer::{empty_string_as_none, validate, AppState};
+use maud::{html, Markup};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::accounts::{account_bar, percent_encode, Caller, SignedIn};
+use crate::base;
+
+/// An operation on a selection, from the page's forms or as JSON.
+#[derive(Debug, Deserialize)]
+pub struct DatasetForm {
+    operation: Operation,
+    protein: String,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    condition: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    version: Option<String>,
+    batch: Option<i32>,
+    /// Only for `/api/datasets`: without it nothing changes.
+    #[serde(default)]
+    confirm: bool,
+}
+
+impl DatasetForm {
+    fn selection(&self) -> Selection {
+        Selection {
+            protein: self.protein.clone(),
+            condition: self.condition.clone(),
+            version: self.version.clone(),
+            batch: self.batch,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DatasetsQuery {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    protein: Option<String>,
+}
+
+/// What an operation covered, or would cover on a dry run.
+#[derive(Debug, Serialize)]
+pub struct DatasetResult {
+    operation: Operation,
+    selection: Selection,
+    /// Shown and archived variants before the operation.
+    counts: Affected,
+    /// Variants deleted, archived or restored.
+    affected: i64,
+    dry_run: bool,
+}
+
+/// Proteins the user may delete and archive data of, archived ones included.
+async fn managed_proteins(state: &AppState, user: &User) -> Result<Vec<ProteinAccess>, AppError> {
+    let proteins = state.repository.protein_access().await?;
+    if user.is_admin {
+        return Ok(proteins);
+    }
+    let roles = state.repository.roles(user.id).await?;
+    Ok(proteins
+        .into_iter()
+        .filter(|protein| {
+            protein
+                .project_id
+                .and_then(|id| roles.get(&id))
+                .is_some_and(|role| *role == Role::Admin)
+        })
+        .collect())
+}
+
+/// `name`, once it is clear that `user` may manage it: 403 for a protein they
+/// only see, 404 for one they do not.
+async fn managed_protein(
+    state: &AppState,
+    user: &User,
+    name: &str,
+) -> Result<ProteinAccess, AppError> {
+    validate::required("protein", name)?;
+    let managed = managed_proteins(state, user).await?;
+    if let Some(protein) = managed.iter().find(|protein| protein.name == name) {
+        return Ok(protein.clone());
+    }
+    let visible = state
+        .repository
+        .list_proteins(Scope::of(Some(user)))
+        .await?;
+    if visible.iter().any(|protein| protein == name) {
+        return Err(AppError::Forbidden(format!(
+            "you need the admin role in the project of {name}"
+        )));
+    }
+    Err(validate::unknown(
+        "protein",
+        name,
+        managed.iter().map(|protein| protein.name.as_str()),
+    ))
+}
+
+pub async fn get_datasets(
+    State(state): State<AppState>,
+    SignedIn(user): SignedIn,
+    Query(query): Query<DatasetsQuery>,
+) -> Result<Markup, AppError> {
+    datasets_page(&state, &user, query.protein.as_deref(), None).await
+}
+
+async fn datasets_page(
+    state: &AppState,
+    user: &User,
+    protein: Option<&str>,
+    notice: Option<String>,
+) -> Result<Markup, AppError> {
+    let proteins = managed_proteins(state, user).await?;
+    let current = match protein {
+        Some(name) => Some(managed_protein(state, user, name).await?),
+        None => None,
+    };
+    let details = match &current {
+        Some(protein) => Some((
+            state.repository.datasets(&protein.name).await?,
+            state.repository.upload_batches(&protein.name).await?,
+        )),
+        None => None,
+    };
+    Ok(base(html!(
+        main class="account-page" {
+            (account_bar(Some(user)))
+            h2 { "Datasets" }
+            p {
+                "Archiving hides data from the viewer until it is restored; deleting removes it for good. "
+                "Each shows how many variants it affects before anything changes, and ends up in the "
+                a href="/audit" { "audit log" } "."
+            }
+            @if let Some(notice) = notice {
+                p class="dataset-notice" { (notice) }
+            }
+            @if proteins.is_empty() {
+                p { "You are not an admin of any project with proteins." }
+            } @else {
+                form class="account-form" method="get" action="/datasets" {
+                    select name="protein" {
+                        @for protein in &proteins {
+                            option value=(protein.name) selected[Some(protein.name.as_str()) == protein_name(&current)] {
+                                (protein.name) @if protein.archived { " (archived)" }
+                            }
+                        }
+                    }
+                    button { "Show" }
+                }
+            }
+            @if let (Some(protein), Some((datasets, batches))) = (&current, &details) {
+                @let whole = Selection { protein: protein.name.clone(), ..Selection::default() };
+                h3 { (protein.name) @if protein.archived { " (archived)" } }
+                (operations(&whole, &[
+                    if protein.archived { Operation::Restore } else { Operation::Archive },
+                    Operation::Delete,
+                ]))
+                @if datasets.is_empty() {
+                    p { "No variants." }
+                } @else {
+                    table class="account-table" {
+                        thead { tr { th { "Condition" } th { "Version" } th { "Shown" } th { "Archived" } th {} } }
+                        tbody {
+                            @for (condition, versions) in by_condition(datasets) {
+                                @let selection = Selection { condition: Some(condition.to_string()), ..whole.clone() };
+                                tr {
+                                    td { strong { (condition) } }
+                                    td { "all versions" }
+                                    td { (versions.iter().map(|dataset| dataset.variants).sum::<i64>()) }
+                                    td { (versions.iter().map(|dataset| dataset.archived).sum::<i64>()) }
+                                    td { (operations(&selection, &Operation::ALL)) }
+                                }
+                                @for dataset in versions {
+                                    @let selection = Selection { version: Some(dataset.version.clone()), ..selection.clone() };
+                                    tr {
+                                        td {}
+                                        td { (dataset.version) }
+                                        td { (dataset.variants) }
+                                        td { (dataset.archived) }
+                                        td { (operations(&selection, &Operation::ALL)) }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+                h4 { "Upload batches" }
+                @if batches.is_empty() {
+                    p { "None; variants loaded before upload batches existed belong to no batch." }
+                } @else {
+                    table class="account-table" {
+                        thead { tr { th { "Batch" } th { "File" } th { "Uploaded (UTC)" } th { "By" } th { "Rows" } th {} } }
+                        tbody {
+                            @for batch in batches {
+                                @let selection = Selection { batch: Some(batch.id), ..whole.clone() };
+                                tr {
+                                    td { a href=(format!("/audit?batch={}", batch.id)) { (batch.id) } }
+                                    td { (batch.file_name) }
+                                    td { (batch.created_on.format("%Y-%m-%d %H:%M")) }
+                                    td { (batch.actor) }
+                                    td { (batch.rows_inserted) }
+                                    td { (operations(&selection, &Operation::ALL)) }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    )))
+}
+
+fn protein_name(protein: &Option<ProteinAccess>) -> Option<&str> {
+    protein.as_ref().map(|protein| protein.name.as_str())
+}
+
+/// Datasets grouped by condition; they come sorted by condition.
+fn by_condition(datasets: &[Dataset]) -> Vec<(&str, Vec<&Dataset>)> {
+    let mut groups: Vec<(&str, Vec<&Dataset>)> = vec![];
+    for dataset in datasets {
+        match groups.last_mut() {
+            Some((condition, versions)) if *condition == dataset.condition => {
+                versions.push(dataset)
+            }
+            _ => groups.push((&dataset.condition, vec![dataset])),
+        }
+    }
+    groups
+}
+
+/// Buttons leading to the confirmation page of each of `offered`.
+fn operations(selection: &Selection, offered: &[Operation]) -> Markup {
+    html!(
+        form class="account-actions" method="get" action="/datasets/confirm" {
+            (selection_inputs(selection))
+            @for operation in offered {
+                button name="operation" value=(operation) { (title(*operation)) }
+            }
+        }
+    )
+}
+
+fn selection_inputs(selection: &Selection) -> Markup {
+    html!(
+        input type="hidden" name="protein" value=(selection.protein);
+        @if let Some(condition) = &selection.condition {
+            input type="hidden" name="condition" value=(condition);
+        }
+        @if let Some(version) = &selection.version {
+            input type="hidden" name="version" value=(version);
+        }
+        @if let Some(batch) = selection.batch {
+            input type="hidden" name="batch" value=(batch);
+        }
+    )
+}
+
+fn title(operation: Operation) -> &'static str {
+    match operation {
+        Operation::Delete => "Delete",
+        Operation::Archive => "Archive",
+        Operation::Restore => "Restore",
+    }
+}
+
+/// The dry run: what an operation would affect, with the button that does it.
+pub async fn get_confirm(
+    State(state): State<AppState>,
+    SignedIn(user): SignedIn,
+    Query(form): Query<DatasetForm>,
+) -> Result<Markup, AppError> {
+    managed_protein(&state, &user, &form.protein).await?;
+    let selection = form.selection();
+    let affected = state.repository.affected(&selection).await?;
+    let count = affected.by(form.operation);
+    let consequence = match form.operation {
+        Operation::Delete if selection.whole_protein() => format!(
+            "This deletes the protein with its {count} variants ({} of them archived) and its upload batches for good.",
+            affected.archived
+        ),
+        Operation::Delete => format!(
+            "This deletes {count} variants ({} of them archived) for good.",
+            affected.archived
+        ),
+        Operation::Archive if selection.whole_protein() => format!(
+            "This hides the protein and its {count} variants from everyone until it is restored."
+        ),
+        Operation::Archive => {
+            format!("This hides {count} variants from everyone until they are restored.")
+        }
+        Operation::Restore if selection.whole_protein() => format!(
+            "This shows the protein again and brings back its {count} archived variants."
+        ),
+        Operation::Restore => format!("This brings back {count} archived variants."),
+    };
+    let back = format!("/datasets?protein={}", percent_encode(&form.protein));
+    Ok(base(html!(
+        main class="account-page" {
+            (account_bar(Some(&user)))
+            h2 { (title(form.operation)) " " (selection) "?" }
+            @if count == 0 && !selection.whole_protein() {
+                p { "Nothing to " (form.operation) "." }
+                a href=(back) { "Back" }
+            } @else {
+                p { (consequence) }
+                form class="account-form" method="post" action="/datasets" {
+                    input type="hidden" name="operation" value=(form.operation);
+                    (selection_inputs(&selection))
+                    button { (title(form.operation)) " " (count) " variants" }
+                    a href=(back) { "Cancel" }
+                }
+            }
+        }
+    )))
+}
+
+pub async fn post_datasets(
+    State(state): State<AppState>,
+    SignedIn(user): SignedIn,
+    Form(form): Form<DatasetForm>,
+) -> Result<Markup, AppError> {
+    let result = apply(&state, &user, &form, true).await?;
+    let shown = match result.operation {
+        Operation::Delete if result.selection.whole_protein() => None,
+        _ => Some(result.selection.protein.as_str()),
+    };
+    let notice = format!(
+        "{}: {} variants {}.",
+        result.selection,
+        result.affected,
+        result.operation.past_tense()
+    );
+    datasets_page(&state, &user, shown, Some(notice)).await
+}
+
+pub async fn post_datasets_json(
+    State(state): State<AppState>,
+    Extension(caller): Extension<Caller>,
+    Json(form): Json<DatasetForm>,
+) -> Result<Json<DatasetResult>, ApiError> {
+    let Some(user) = caller.0 else {
+        return Err(AppError::Unauthorized("sign in first".to_string()).into());
+    };
+    Ok(Json(apply(&state, &user, &form, form.confirm).await?))
+}
+
+/// Applies `form` once `user` may, or with `confirmed` unset only counts.
+async fn apply(
+    state: &AppState,
+    user: &User,
+    form: &DatasetForm,
+    confirmed: bool,
+) -> Result<DatasetResult, AppError> {
+    managed_protein(state, user, &form.protein).await?;
+    let selection = form.selection();
+    let counts = if confirmed {
+        let counts = state
+            .repository
+            .apply(form.operation, &selection, &Actor::user(user))
+            .await?;
+        info!(
+            "{} {} {selection} ({} variants)",
+            user.username,
+            form.operation.past_tense(),
+            counts.by(form.operation)
+        );
+        counts
+    } else {
+        state.repository.affected(&selection).await?
+    };
+    Ok(DatasetResult {
+        operation: form.operation,
+        affected: counts.by(form.operation),
+        selection,
+        counts,
+        dry_run: !confirmed,
+    })
+}
@@ -2,11 +2,18 @@ use axum::http::header;
 use axum::middleware;
 use rand::Rng;
 use serde::Deserialize;
-use std::net::{Ipv4Addr, SocketAddr};
-use std::{collections::HashMap, io::Cursor};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{atomic::Ordering, Arc},
+};
 use tokio::net::TcpListener;
 use tower::ServiceBuilder;
+pub mod accounts;
+pub mod audit;
+pub mod datasets;
+pub mod projects;
 pub mod utils;
+use accounts::{account_bar, authenticate, Caller, SignedIn};
 use axum::extract::Path;
 use axum::{
     body::Bytes,
@@ -14,20 +21,40 @@ use axum::{
     extract::{DefaultBodyLimit, Multipart, Query, State},
     http::{HeaderValue, StatusCode},
     response::IntoResponse,
-    routing::get,
-    Router,
+    routing::{get, post},
+    Extension, Router,
 };
-use chrono::{NaiveDateTime, Utc};
-use csv::Error;
+use clap::{Parser, Subcommand};
+use dms_viewer::access::{Role, Scope};
+use dms_viewer::audit::Actor;
+use dms_viewer::classify::{self, ClassCounts, ClassifierKind, EffectScale, VariantClass};
+use dms_viewer::color::{DivergingScale, Rgb};
+use dms_viewer::config::{self, Backend, Config, ConfigArgs, LogFormat};
+use dms_viewer::error::{ApiError, AppError};
+use dms_viewer::histogram::{self, Histogram};
+use dms_viewer::ingest;
+use dms_viewer::matrix::VariantMatrix;
+use dms_viewer::metrics::{Exposition, METRICS};
+use dms_viewer::migrate;
+use dms_viewer::repository::{PostgresRepository, Repository, SqliteRepository};
+use dms_viewer::substitution::{CellSummary, SubstitutionSummary};
+use dms_viewer::tolerance::{self, PositionTolerance, ToleranceMetric};
+use dms_viewer::validate;
 use dms_viewer::{
-    AppState, Normalizer, Paint, PlotType, PosColor, PositionFilter, TableParams, Variant,
-    VariantColor, GROUPED_AMINO_ACIDS, PAGE_SIZE,
+    empty_string_as_none, AppState, ExportFormat, HeatmapMatrix, HeatmapTrack, Paint, PlotType,
+    PosColor, PositionFilter, TableParams, Variant, VolcanoAxes, VolcanoPoint, GROUPED_AMINO_ACIDS,
+    NO_DATA,
 };
 use maud::{html, Markup, PreEscaped, DOCTYPE};
-use sqlx::{query_as, query_scalar, PgPool};
+use sqlx::PgPool;
 use tower_http::services::ServeDir;
 use tracing::{debug, info, warn};
-use utils::set_static_cache_control;
+use utils::{
+    cache_responses, conditional_get, invalidate_cache_on_ingest, poll_dataset_revision,
+    set_static_cache_control, track_requests,
+};
+
+const TABLE_PARAMS_INCLUDE: &str = "[name='protein'],[name='condition'],[name='position_filter'],[name='paint'],[name='threshold'],[name='classifier'],[name='effect_cutoff'],[name='p_cutoff'],[name='posterior_cutoff'],[name='gain_z'],[name='tolerance_metric'],[name='label_top']";
 
 fn base(content: Markup) -> Markup {
     html! {
@@ -39,15 +66,18 @@ fn base(content: Markup) -> Markup {
                 title{ "DMS Viewer" }
 
                 // Styles
-                link rel="stylesheet" href="assets/style.css"{}
+                link rel="stylesheet" href="/assets/style.css"{}
                 link rel="stylesheet" href="https://cdn.jsdelivr.net/npm/pdbe-molstar@3.3.0/build/pdbe-molstar-light.css"{}
 
                 // Htmx + PDBe Molstar + Alpine
-                script src="assets/htmx.min.js" {}
+                script src="/assets/htmx.min.js" {}
+                script src="/assets/errors.js" {}
                 script src="https://cdn.jsdelivr.net/npm/pdbe-molstar@3.3.0/build/pdbe-molstar-plugin.js"{}
                 script src="//unpkg.com/alpinejs" defer {}
                 script src="https://cdn.jsdelivr.net/npm/d3@7"{}
-                script src="assets/scatter.js"{}
+                script src="/assets/scatter.js"{}
+                script src="/assets/histogram.js"{}
+                script src="/assets/heatmap.js"{}
 
                 meta name="htmx-config" content="{\"responseHandling\": [{\"code\":\".*\", \"swap\": true}]}"{}
 
@@ -59,8 +89,9 @@ fn base(content: Markup) -> Markup {
     }
 }
 #[debug_handler]
-async fn main_content() -> Markup {
+async fn main_content(Extension(Caller(caller)): Extension<Caller>) -> Markup {
     let var_name = html! {
+        (account_bar(caller.as_ref()))
         h1 id = "page-title" {
             span id="page-title-start"{"// "}
             span hx-get="/title?previous=DeepScan" hx-trigger="every 7s" hx-swap="outerHTML swap:1s settle:1s" id="page-title-end" {"DEEPSCAN"}
@@ -71,16 +102,37 @@ async fn main_content() -> Markup {
                 hx-get="/variant_form?plot=heatmap"
                 hx-target="#variant-form"
                 hx-trigger="click"
-                hx-include="[name='protein'],[name='condition'],[name='position_filter'],[name='paint'],[name='threshold']"
+                hx-include=(TABLE_PARAMS_INCLUDE)
                 hx-swap="outerHTML"
                 {"View Heatmap"}
+            button
+                hx-get="/variant_form?plot=table"
+                hx-target="#variant-form"
+                hx-trigger="click"
+                hx-include=(TABLE_PARAMS_INCLUDE)
+                hx-swap="outerHTML"
+                {"View Table"}
             button
                 hx-get="/variant_form?plot=scatter"
                 hx-target="#variant-form"
                 hx-trigger="click"
-                hx-include="[name='protein'],[name='condition'],[name='position_filter'],[name='paint'],[name='threshold']"
+                hx-include=(TABLE_PARAMS_INCLUDE)
                 hx-swap="outerHTML"
-                {"View Scatterplot"}
+                {"View Volcano Plot"}
+            button
+                hx-get="/variant_form?plot=substitution"
+                hx-target="#variant-form"
+                hx-trigger="click"
+                hx-include=(TABLE_PARAMS_INCLUDE)
+                hx-swap="outerHTML"
+                {"View Substitutions"}
+            button
+                hx-get="/variant_form?plot=distribution"
+                hx-target="#variant-form"
+                hx-trigger="click"
+                hx-include=(TABLE_PARAMS_INCLUDE)
+                hx-swap="outerHTML"
+                {"View Distribution"}
 
             form class="selection-form"
                 hx-get="/proteins"
@@ -88,7 +140,18 @@ async fn main_content() -> Markup {
                 id="overall-form"
                 {
                 }
+            @if caller.is_some() {
+                form id="upload-form"
+                    hx-post="/upload"
+                    hx-encoding="multipart/form-data"
+                    hx-include="[name='protein']"
+                    hx-indicator="#upload-indicator"
+                    {
+                        (upload_file_component_with_message(""))
+                    }
+            }
         }
+        div id="error-message" aria-live="polite"{}
         div id="full-view"{
             div id="dms-table-container"
 
@@ -129,10 +192,16 @@ async fn main_content() -> Markup {
                         div {"log2 Std Error: "}
                         div {"z-statistic: "}
                         div {"p-value: "}
+                        div {"Upload: "}
                     }
                     div id="variant-view-body"{}
                 }
                 p id="loading-cell-indicator" class="htmx-indicator" {"Loading..."}
+                div id="classification-summary"
+                    hx-get="/classification"
+                    hx-include=(TABLE_PARAMS_INCLUDE)
+                    hx-trigger="load-condition from:body delay:0.5s, change from:#variant-form delay:0.5s"
+                    {}
             }
             div id="structure"{}
         }
@@ -143,35 +212,59 @@ async fn main_content() -> Markup {
 
 async fn get_plot(
     State(state): State<AppState>,
+    Extension(scope): Extension<Scope>,
     Query(params): Query<TableParams>,
-) -> impl IntoResponse {
-    match params.plot {
+) -> Result<axum::response::Response, AppError> {
+    validate_table_params(&params, scope, &state).await?;
+    let response = match params.plot {
         Some(plot_type) => match plot_type {
-            dms_viewer::PlotType::Scatter => {
-                let mut res = get_scatter_plot(state, params).await.into_response();
-
-                return res;
+            dms_viewer::PlotType::Scatter => get_scatter_plot(state, params).await?,
+            dms_viewer::PlotType::Heatmap => get_canvas_heatmap().into_response(),
+            dms_viewer::PlotType::Table => get_heatmap(&params).await.into_response(),
+            dms_viewer::PlotType::Substitution => {
+                get_substitution_plot(state, params).await?.into_response()
             }
-            dms_viewer::PlotType::Heatmap => {
-                let mut res = get_heatmap().await.into_response();
-
-                return res;
+            dms_viewer::PlotType::Distribution => {
+                get_distribution_plot(state, params).await?.into_response()
             }
         },
-        None => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            html!("Plot Type enum missing"),
-        )
-            .into_response(),
-    }
+        None => return Err(AppError::BadRequest("missing plot type".to_string())),
+    };
+    Ok(response)
 }
 
-async fn get_heatmap() -> impl IntoResponse {
+fn get_canvas_heatmap() -> Markup {
+    html!(
+        #canvas-heatmap
+            x-data="canvasHeatmap()"
+            x-init="load()"
+            data-include=(TABLE_PARAMS_INCLUDE)
+        {
+            div class="canvas-heatmap-controls"{
+                button type="button" x-on:click="zoom(0.5)"{"−"}
+                button type="button" x-on:click="zoom(2)"{"+"}
+                button type="button" title="Export the heatmap as SVG" x-on:click="exportSvg()"{"⤓ SVG"}
+                span class="canvas-heatmap-tooltip" x-text="tooltip"{}
+            }
+            div class="canvas-heatmap-viewport" x-ref="viewport" x-on:scroll="draw()"{
+                div class="canvas-heatmap-spacer" x-ref="spacer"{}
+                canvas x-ref="canvas"
+                    x-on:mousemove="hover($event)"
+                    x-on:mouseleave="tooltip = ''"{}
+            }
+        }
+    )
+}
+
+async fn get_heatmap(params: &TableParams) -> impl IntoResponse {
     (html!(
         table id="dms-table"{
             thead{
                 tr{
                     th{" "}
+                    @if let Some(metric) = params.tolerance_metric {
+                        (tolerance_track_header(params, metric))
+                    }
                     @for amino_acid in &GROUPED_AMINO_ACIDS{
                         th { (amino_acid)}
                     }
@@ -179,7 +272,7 @@ async fn get_heatmap() -> impl IntoResponse {
             }
         tbody id="dms-table-body"
             hx-get="/variants"
-            hx-include="[name='protein'],[name='condition'],[name='position_filter'],[name='paint'],[name='threshold']"
+            hx-include=(TABLE_PARAMS_INCLUDE)
             hx-trigger="load-condition from:body delay:0.25s"
         {
             @for pos in 1..100{ // just to show content while stuff is loading
@@ -197,185 +290,454 @@ async fn get_heatmap() -> impl IntoResponse {
     .into_response()
 }
 
-async fn get_scatter_plot(state: AppState, params: TableParams) -> impl IntoResponse {
+async fn get_scatter_plot(
+    state: AppState,
+    params: TableParams,
+) -> Result<axum::response::Response, AppError> {
     let TableParams {
         ref protein,
         ref condition,
-        page: _,
-        position_filter: _,
-        ref paint,
-        operation: _,
-        threshold,
-        plot: _,
+        label_top,
+        ..
     } = params;
-    let pool = &state.pool;
+    let repository = state.repository.as_ref();
+
+    // The x axis is the scale the classifier's effect cutoff applies to;
+    // p values are always on y.
+    let scale = params.effect_scale();
+    let (x_paint, x_label) = match scale {
+        EffectScale::ZStatistic => (Paint::ZStatistic, "z statistic"),
+        EffectScale::Log2FoldChange => (Paint::Log2FoldChange, "log2 Fold Change"),
+    };
+    let x_of = |variant: &Variant| scale.of(variant);
 
-    let variants = sqlx::query_as!(
-        Variant,
-        r#"SELECT
-            variant.id,
-            variant.chunk,
-            variant.pos,
-            variant.p_value,
-            variant.created_on,
-            variant.log2_fold_change,
-            variant.log2_std_error,
-            variant.statistic,
-            variant.condition,
-            variant.aa,
-            variant.version,
-            protein.name as protein
-        FROM variant
-        JOIN protein ON variant.protein_id = protein.id
-        WHERE protein.name = $1
-        AND variant.condition = $2
-        ORDER BY variant.pos, variant.aa
-        "#,
-        protein,
-        condition,
+    let variants = repository.all_variants(protein, condition).await?;
+    let classes = classify_condition(&params, &variants, repository).await?;
+
+    let Some(min_max) = repository.range_for(protein, condition, x_paint).await? else {
+        return Ok(html!().into_response());
+    };
+    let effect_cutoff = params.effect_cutoff_or_threshold();
+    // Draw the significance line where the BH-adjusted cutoff falls on the
+    // raw p value scale, so it agrees with the point colors.
+    let p_cutoff = params.p_cutoff.unwrap_or(classify::DEFAULT_P_CUTOFF);
+    let p_values: Vec<f64> = variants.iter().map(|variant| variant.p_value).collect();
+    let significance_line = -classify::raw_p_cutoff(&p_values, p_cutoff)
+        .unwrap_or(p_cutoff)
+        .log10();
+
+    let neg_log10_p: Vec<f64> = p_values.iter().map(|p| -p.log10()).collect();
+    let y_max = neg_log10_p
+        .iter()
+        .copied()
+        .filter(|y| y.is_finite())
+        .fold(significance_line, f64::max)
+        .max(1.0)
+        * 1.05;
+    let edge = effect_cutoff.unwrap_or(0.0);
+    let padding = (min_max.max - min_max.min).abs() * 0.05;
+    let axes = VolcanoAxes {
+        x_label: x_label.to_string(),
+        x_min: min_max.min.min(-edge) - padding,
+        x_max: min_max.max.max(edge) + padding,
+        y_max,
+        effect_cutoff,
+        significance_line,
+    };
+
+    let mut ranked: Vec<usize> = (0..variants.len()).collect();
+    ranked.sort_by(|&a, &b| {
+        p_values[a].total_cmp(&p_values[b]).then(
+            x_of(&variants[b])
+                .abs()
+                .total_cmp(&x_of(&variants[a]).abs()),
+        )
+    });
+    let labelled: HashSet<usize> = ranked.into_iter().take(label_top.unwrap_or(0)).collect();
+
+    let points: Vec<VolcanoPoint> = variants
+        .iter()
+        .zip(&classes)
+        .zip(neg_log10_p)
+        .enumerate()
+        .map(|(index, ((variant, class), y))| VolcanoPoint {
+            id: variant.id.unwrap_or_default(),
+            pos: variant.pos,
+            aa: variant.aa.clone(),
+            x: x_of(variant),
+            y: if y.is_finite() { y } else { y_max },
+            color: class.color_hex().to_string(),
+            label: labelled
+                .contains(&index)
+                .then(|| format!("{}{}", variant.pos, variant.aa)),
+        })
+        .collect();
+
+    let axes = serde_json::to_string(&axes)?;
+    let points = serde_json::to_string(&points)?;
+    Ok(html!(
+        #container
+            x-data="scatterPlot()"
+            x-init=(format!("initPlot({}); setData({})", axes, points)) {}
     )
-    .fetch_all(&state.pool)
-    .await
-    .unwrap();
-
-    if let Some(max_abs) = get_max_absolute_value(protein, condition, *paint, pool).await {
-        if let Some(threshold) = threshold {}
-        if let Some(min_max) =
-            get_range_of_variant(protein, condition, Paint::Log2FoldChange, pool).await
-        {
-            let normalizer = Normalizer { max_abs };
+    .into_response())
+}
 
-            let pos_color_pairs: Vec<VariantColor> = variants
-                .iter()
-                .map(|variant| VariantColor {
-                    id: variant.id.unwrap(),
-                    pos: variant.pos,
-                    color: {
-                        match *paint {
-                            Paint::Log2FoldChange => {
-                                normalizer.get_color_hex(variant.log2_fold_change)
+async fn get_distribution_plot(state: AppState, params: TableParams) -> Result<Markup, AppError> {
+    let repository = state.repository.as_ref();
+    let variants = repository
+        .all_variants(&params.protein, &params.condition)
+        .await?;
+    let sequence = repository.protein_sequence(&params.protein).await?;
+    let histogram = Histogram::compute(
+        &variants,
+        params.paint,
+        sequence.as_deref(),
+        histogram::DEFAULT_BINS,
+    );
+    let histogram = serde_json::to_string(&histogram)?;
+    let threshold = serde_json::to_string(&params.threshold)?;
+    Ok(html!(
+        #histogram
+            x-data="histogramPlot()"
+            x-init=(format!("initHistogram({}, {})", histogram, threshold)) {}
+    ))
+}
+
+async fn get_substitution_summary(
+    protein: &str,
+    condition: &str,
+    repository: &dyn Repository,
+) -> Result<SubstitutionSummary, AppError> {
+    let Some(sequence) = repository.protein_sequence(protein).await? else {
+        return Err(AppError::Unprocessable(format!(
+            "protein {protein} has no sequence, so wild-type residues are unknown"
+        )));
+    };
+    let variants = repository.all_variants(protein, condition).await?;
+    Ok(SubstitutionSummary::compute(
+        protein, condition, &variants, &sequence,
+    ))
+}
+
+async fn get_substitution_plot(state: AppState, params: TableParams) -> Result<Markup, AppError> {
+    let summary = get_substitution_summary(
+        &params.protein,
+        &params.condition,
+        state.repository.as_ref(),
+    )
+    .await?;
+    let scale = DivergingScale::new(summary.max_abs());
+    let cell = |cell: &CellSummary, title: String| match cell.mean {
+        Some(mean) => html!(
+            td class="dms-cell"
+                style=(format!("background-color: {}", scale.color(mean)))
+                title=(format!("{title}: mean log2FC {mean:.3} over {} variants", cell.count)){}
+        ),
+        None => html!(td class="dms-cell dms-cell-no-data" title=(format!("{title}: N/A")){}),
+    };
+    Ok(html!(
+        div id="substitution-summary"{
+            table id="substitution-matrix" class="substitution-table"{
+                thead{
+                    tr{
+                        th{"WT \\ Mut"}
+                        @for mutant in &summary.matrix.mutants{
+                            th{(mutant)}
+                        }
+                    }
+                }
+                tbody{
+                    @for (wild_type, row) in summary.matrix.wild_types.iter().zip(&summary.matrix.cells){
+                        tr{
+                            th scope="row"{(wild_type)}
+                            @for (mutant, summary_cell) in summary.matrix.mutants.iter().zip(row){
+                                (cell(summary_cell, format!("{wild_type}→{mutant}")))
+                            }
+                        }
+                    }
+                }
+            }
+            table id="substitution-classes" class="substitution-table"{
+                thead{
+                    tr{
+                        th{"WT \\ Mut"}
+                        @for class in &summary.classes.classes{
+                            th{(class)}
+                        }
+                    }
+                }
+                tbody{
+                    @for (from, row) in summary.classes.classes.iter().zip(&summary.classes.cells){
+                        tr{
+                            th scope="row"{(from)}
+                            @for (to, summary_cell) in summary.classes.classes.iter().zip(row){
+                                (cell(summary_cell, format!("{from}→{to}")))
                             }
-                            Paint::PValue => normalizer.get_color_hex(variant.p_value),
-                            Paint::ZStatistic => normalizer.get_color_hex(variant.statistic),
                         }
-                    },
-                    aa: variant.aa.clone(),
-                    log2_fold_change: variant.log2_fold_change,
-                    log2_std_error: variant.log2_std_error,
-                    statistic: variant.statistic,
-                    p_value: {
-                        let neg_log10_p = -variant.p_value.log10();
-                        neg_log10_p.min(20.0) // Cap at 21 if greater
-                    },
-                })
-                .collect();
-
-            return html!(
-                #container
-                    x-data="scatterPlot()"
-                    x-init=(format!("initPlot({},{},0,21); setData({})",min_max.min,min_max.max, serde_json::to_string(&pos_color_pairs).unwrap())) {}
-            );
+                    }
+                }
+            }
+        }
+    ))
+}
+
+#[derive(Deserialize)]
+struct ProteinConditionQuery {
+    protein: String,
+    condition: String,
+}
+
+async fn get_substitutions_json(
+    State(state): State<AppState>,
+    Extension(scope): Extension<Scope>,
+    Query(query): Query<ProteinConditionQuery>,
+) -> Result<axum::Json<SubstitutionSummary>, ApiError> {
+    validate_dataset(
+        &query.protein,
+        &query.condition,
+        scope,
+        state.repository.as_ref(),
+    )
+    .await?;
+    let summary =
+        get_substitution_summary(&query.protein, &query.condition, state.repository.as_ref())
+            .await?;
+    Ok(axum::Json(summary))
+}
+
+/// Number of steps in the canvas heatmap's color scale.
+const PALETTE_STEPS: u8 = 254;
+
+/// How heatmap cells, scatter points and residues get their color.
+enum CellPainter {
+    Scale(DivergingScale),
+    Classes {
+        by_id: HashMap<i32, VariantClass>,
+        by_position: BTreeMap<i32, ClassCounts>,
+    },
+}
+
+impl CellPainter {
+    fn color(&self, variant: &Variant, value: f64) -> String {
+        match self {
+            CellPainter::Scale(scale) => scale.color(value).hex(),
+            CellPainter::Classes { by_id, .. } => variant
+                .id
+                .and_then(|id| by_id.get(&id))
+                .unwrap_or(&VariantClass::Neutral)
+                .color_hex()
+                .to_string(),
+        }
+    }
+
+    /// Colors the canvas heatmap indexes into.
+    fn palette(&self) -> Vec<String> {
+        match self {
+            CellPainter::Scale(_) => DivergingScale::palette(PALETTE_STEPS)
+                .iter()
+                .map(Rgb::hex)
+                .collect(),
+            CellPainter::Classes { .. } => VariantClass::ALL
+                .iter()
+                .map(|class| class.color_hex().to_string())
+                .collect(),
+        }
+    }
+
+    fn palette_index(&self, variant: &Variant, value: f64) -> u8 {
+        match self {
+            CellPainter::Scale(scale) => scale.palette_index(value, PALETTE_STEPS),
+            CellPainter::Classes { by_id, .. } => {
+                let class = variant
+                    .id
+                    .and_then(|id| by_id.get(&id))
+                    .unwrap_or(&VariantClass::Neutral);
+                VariantClass::ALL.iter().position(|c| c == class).unwrap() as u8
+            }
+        }
+    }
+
+    fn position_title(&self, pos: &i32) -> Option<String> {
+        match self {
+            CellPainter::Scale(_) => None,
+            CellPainter::Classes { by_position, .. } => Some(format_class_counts(
+                &by_position.get(pos).copied().unwrap_or_default(),
+            )),
         }
     }
-    return html!();
+}
+
+async fn classify_condition(
+    params: &TableParams,
+    variants: &[Variant],
+    repository: &dyn Repository,
+) -> Result<Vec<VariantClass>, AppError> {
+    let sequence = repository.protein_sequence(&params.protein).await?;
+    classify::classify(variants, params.classification_rule(), sequence.as_deref())
+        .map_err(|err| AppError::Unprocessable(format!("could not classify variants: {err}")))
+}
+
+/// `variants` must hold the whole condition when painting by classification.
+async fn get_cell_painter(
+    params: &TableParams,
+    variants: &[Variant],
+    repository: &dyn Repository,
+) -> Result<CellPainter, AppError> {
+    match params.paint {
+        Paint::Classification => {
+            let classes = classify_condition(params, variants, repository).await?;
+            Ok(CellPainter::Classes {
+                by_position: classify::counts_by_position(variants, &classes),
+                by_id: variants
+                    .iter()
+                    .zip(classes)
+                    .filter_map(|(variant, class)| variant.id.map(|id| (id, class)))
+                    .collect(),
+            })
+        }
+        paint => {
+            match repository
+                .range_for(&params.protein, &params.condition, paint)
+                .await?
+            {
+                Some(range) => Ok(CellPainter::Scale(DivergingScale::new(range.max_abs))),
+                None => Err(AppError::NotFound(format!(
+                    "no variants for {} in condition {}",
+                    params.protein, params.condition
+                ))),
+            }
+        }
+    }
+}
+
+fn format_class_counts(counts: &ClassCounts) -> String {
+    VariantClass::ALL
+        .iter()
+        .map(|class| format!("{}: {}", class, counts.get(*class)))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+async fn get_classification_summary(
+    State(state): State<AppState>,
+    Extension(scope): Extension<Scope>,
+    Query(params): Query<TableParams>,
+) -> Result<Markup, AppError> {
+    let repository = state.repository.as_ref();
+    validate_table_params(&params, scope, &state).await?;
+    let variants = repository
+        .all_variants(&params.protein, &params.condition)
+        .await?;
+    let classes = classify_condition(&params, &variants, repository).await?;
+    let by_position = classify::counts_by_position(&variants, &classes);
+    let domains = repository.domains(&params.protein).await?;
+    let mut rows: Vec<(String, ClassCounts)> = domains
+        .iter()
+        .map(|domain| {
+            let mut counts = ClassCounts::default();
+            for (_, position_counts) in by_position.range(domain.start_pos..=domain.end_pos) {
+                counts.merge(position_counts);
+            }
+            (
+                format!("{} ({}-{})", domain.name, domain.start_pos, domain.end_pos),
+                counts,
+            )
+        })
+        .collect();
+    let mut total = ClassCounts::default();
+    for class in &classes {
+        total.add(*class);
+    }
+    rows.push(("All positions".to_string(), total));
+    Ok(html!(
+        table id="classification-summary-table"{
+            thead{
+                tr{
+                    th{"Domain"}
+                    @for class in VariantClass::ALL{
+                        th style=(format!("color: {}",class.color_hex())){(class)}
+                    }
+                    th{"Total"}
+                }
+            }
+            tbody{
+                @for (name, counts) in &rows{
+                    tr{
+                        th scope="row"{(name)}
+                        @for class in VariantClass::ALL{
+                            td{(counts.get(class))}
+                        }
+                        td{(counts.total())}
+                    }
+                }
+            }
+        }
+    ))
 }
 
 async fn get_conditions(
     State(state): State<AppState>,
+    Extension(scope): Extension<Scope>,
     Query(params): Query<HashMap<String, String>>,
-) -> impl IntoResponse {
+) -> Result<axum::response::Response, AppError> {
     info!("getting conditions");
-    let protein = params.get("protein").expect("protein not found");
-    let rows = sqlx::query!(
-        r#"
-            SELECT DISTINCT condition
-            FROM variant
-            JOIN protein ON variant.protein_id = protein.id
-            WHERE protein.name = $1;
-            "#,
-        protein
-    )
-    .fetch_all(&state.pool)
-    .await;
+    let protein = params
+        .get("protein")
+        .ok_or_else(|| AppError::BadRequest("missing protein".to_string()))?;
+    validate_protein(protein, scope, state.repository.as_ref()).await?;
+    let conditions = state.repository.conditions_for(protein).await?;
     let pdb_id = match protein.as_str() {
         "GLP1R" => "7ki0",
         "GIPR" => "8wa3",
         "RHO" => "1f88",
         _ => "7s15",
     };
-    match rows {
-        Ok(conditions) => {
-            let mut res = (html! {
-                @for condition in &conditions{
-                    option value=(condition.condition) { (condition.condition) }
-                }
-                script {
-                    (PreEscaped(format!("refresh_and_load_pdb_into_viewer('{}')",pdb_id)))
-                }
-            })
-            .into_response();
-
-            res.headers_mut().insert(
-                "HX-Trigger-After-Settle",
-                HeaderValue::from_static("load-condition"),
-            );
-            return res;
+    let mut res = (html! {
+        @for condition in &conditions{
+            option value=(condition) { (condition) }
         }
-        Err(err) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            html! {
-                p { "Error fetching conditions: " (err) }
-            },
-        )
-            .into_response(),
-    }
+        script {
+            (PreEscaped(format!("refresh_and_load_pdb_into_viewer('{}')",pdb_id)))
+        }
+    })
+    .into_response();
+
+    res.headers_mut().insert(
+        "HX-Trigger-After-Settle",
+        HeaderValue::from_static("load-condition"),
+    );
+    Ok(res)
 }
 
 async fn get_proteins(
-    Query(params): Query<HashMap<String, String>>,
     State(state): State<AppState>,
-) -> impl IntoResponse {
+    Extension(scope): Extension<Scope>,
+) -> Result<Markup, AppError> {
     info!("getting proteins");
 
-    let rows = sqlx::query!("SELECT DISTINCT id, name FROM protein;")
-        .fetch_all(&state.pool)
-        .await;
-    match rows {
-        Ok(proteins) => {
-            let mut res = (html! {
-            div class="selection-form"{
-                #protein-select-div .select-div{
-                    label for="protein" id="protein-select-label"{"Protein"}
-                    select id="protein-select" name="protein"
-                        hx-get="/conditions"
-                        hx-include="this"
-                        hx-target="#condition-select"
-                        hx-trigger="change,load"
-
-                        {
-                            @for protein in &proteins{
-                                option value=(protein.name) { (protein.name) }
-                            }
+    let proteins = state.repository.list_proteins(scope).await?;
+    Ok(html! {
+        div class="selection-form"{
+            #protein-select-div .select-div{
+                label for="protein" id="protein-select-label"{"Protein"}
+                select id="protein-select" name="protein"
+                    hx-get="/conditions"
+                    hx-include="this"
+                    hx-target="#condition-select"
+                    hx-trigger="change,load"
+
+                    {
+                        @for protein in &proteins{
+                            option value=(protein) { (protein) }
                         }
-                }
-                (create_variant_form(None))
+                    }
             }
-
-
-            })
-            .into_response();
-            res.headers_mut().insert(
-                header::CACHE_CONTROL,
-                HeaderValue::from_static("max-age=100"),
-            );
-            return res;
+            (create_variant_form(None))
         }
 
-        Err(err) => (html! {
-            p { "Error fetching proteins: " (err) }
-        })
-        .into_response(),
-    }
+
+    })
 }
 
 async fn get_variant_form(Query(params): Query<TableParams>) -> impl IntoResponse {
@@ -386,6 +748,9 @@ fn create_variant_form(plot: Option<PlotType>) -> Markup {
         Some(plot) => match plot {
             dms_viewer::PlotType::Heatmap => "#dms-table-container",
             dms_viewer::PlotType::Scatter => "#dms-table-container",
+            dms_viewer::PlotType::Substitution => "#dms-table-container",
+            dms_viewer::PlotType::Distribution => "#dms-table-container",
+            dms_viewer::PlotType::Table => "#dms-table-container",
         },
         None => "#dms-table-body",
     };
@@ -410,7 +775,7 @@ fn get_selection_form() -> Markup {
     div class="selection-form"
         hx-get="/threshold"
         hx-trigger="change, load-condition from:body delay:0.25s"
-        hx-include="[name='protein'],[name='condition'],[name='position_filter'],[name='paint']"
+        hx-include="[name='protein'],[name='condition'],[name='position_filter'],[name='paint'],[name='classifier'],[name='effect_cutoff'],[name='p_cutoff'],[name='posterior_cutoff'],[name='gain_z']"
         hx-target="#threshold-slider"
     {
         #condition-select-div .select-div{
@@ -437,8 +802,23 @@ fn get_selection_form() -> Markup {
                 option value=("Log2FoldChange") { ("log2 Fold Change") }
                 option value=("PValue") { ("p value") }
                 option value=("ZStatistic") { ("z statistic") }
+                option value=("Classification") { ("Classification") }
+            }
+        }
+        #tolerance-metric-select-div .select-div{
+            label id="label-tolerance-metric-select" for="tolerance_metric"{"Track"}
+            select id="tolerance-metric-select" name="tolerance_metric"
+            {
+                option value="" { ("None") }
+                @for metric in ToleranceMetric::ALL{
+                    option value=(metric) { (metric.label()) }
+                }
             }
         }
+        #label-top-div .select-div{
+            label id="label-label-top" for="label_top"{"Label top"}
+            input type="number" id="label-top" name="label_top" min="0" max="100" value="0"{}
+        }
         #threshold
             name="threshold"
             x-data="{ threshold_value: 0 }"
@@ -453,352 +833,643 @@ fn get_selection_form() -> Markup {
     )
 }
 
+/// Filtered variants for the whole protein and the painter that colors them,
+/// shared by the JSON matrix and the SVG export.
+async fn get_protein_heatmap(
+    params: &TableParams,
+    scope: Scope,
+    state: &AppState,
+) -> Result<(i32, i32, Vec<Variant>, CellPainter), AppError> {
+    let TableParams {
+        ref protein,
+        ref condition,
+        ref paint,
+        ..
+    } = *params;
+    let repository = state.repository.as_ref();
+    let end = validate_table_params(params, scope, state).await?;
+    let start = 1;
+    let variants = repository.variants_page(params, start, end).await?;
+    let painter = match paint {
+        Paint::Classification => {
+            let all_variants = repository.all_variants(protein, condition).await?;
+            get_cell_painter(params, &all_variants, repository).await?
+        }
+        _ => get_cell_painter(params, &variants, repository).await?,
+    };
+    Ok((start, end, variants, painter))
+}
+
+async fn get_matrix(
+    State(state): State<AppState>,
+    Extension(scope): Extension<Scope>,
+    Query(params): Query<TableParams>,
+) -> Result<axum::Json<HeatmapMatrix>, ApiError> {
+    let TableParams {
+        ref protein,
+        ref condition,
+        paint,
+        ..
+    } = params;
+    let repository = state.repository.as_ref();
+    let (start, end, variants, painter) = get_protein_heatmap(&params, scope, &state).await?;
+    let matrix = VariantMatrix::build(&variants, start, end);
+    // Three decimals is all the tooltip shows, and keeps the payload small.
+    let values = matrix.map(|variant| (paint.value_of(variant) * 1000.0).round() / 1000.0);
+    let ids = matrix
+        .map(|variant| variant.id)
+        .into_iter()
+        .map(Option::flatten)
+        .collect();
+    let colors = matrix
+        .map(|variant| painter.palette_index(variant, paint.value_of(variant)))
+        .into_iter()
+        .map(|color| color.unwrap_or(NO_DATA))
+        .collect();
+
+    let track = match params.tolerance_metric {
+        Some(metric) => {
+            let scores = get_position_tolerance(
+                protein,
+                condition,
+                start,
+                end,
+                params.effect_cutoff,
+                repository,
+            )
+            .await?;
+            let mut track_values = vec![None; (end - start + 1) as usize];
+            for score in &scores {
+                track_values[(score.pos - start) as usize] = Some(score.value(metric));
+            }
+            let scale = match metric {
+                ToleranceMetric::MeanEffect => repository
+                    .range_for(protein, condition, Paint::Log2FoldChange)
+                    .await?
+                    .map_or(0.0, |range| range.max_abs),
+                _ => 1.0,
+            };
+            Some(HeatmapTrack {
+                label: metric.label().to_string(),
+                values: track_values,
+                scale,
+            })
+        }
+        None => None,
+    };
+
+    Ok(axum::Json(HeatmapMatrix {
+        start,
+        end,
+        amino_acids: GROUPED_AMINO_ACIDS
+            .iter()
+            .map(|aa| aa.to_string())
+            .collect(),
+        values,
+        ids,
+        colors,
+        palette: painter.palette(),
+        track,
+    }))
+}
+
+/// Side of one heatmap cell in the SVG export, in pixels.
+const SVG_CELL: usize = 12;
+const SVG_LABEL_WIDTH: usize = 40;
+
+async fn get_matrix_svg(
+    State(state): State<AppState>,
+    Extension(scope): Extension<Scope>,
+    Query(params): Query<TableParams>,
+) -> Result<axum::response::Response, ApiError> {
+    let paint = params.paint;
+    let (start, end, variants, painter) = get_protein_heatmap(&params, scope, &state).await?;
+    let matrix = VariantMatrix::build(&variants, start, end);
+    let width = SVG_LABEL_WIDTH + GROUPED_AMINO_ACIDS.len() * SVG_CELL;
+    let height = SVG_CELL + (end - start + 1) as usize * SVG_CELL;
+    let svg = html!(
+        svg xmlns="http://www.w3.org/2000/svg"
+            width=(width)
+            height=(height)
+            font-family="sans-serif"
+            font-size="9"
+        {
+            @for (col, amino_acid) in GROUPED_AMINO_ACIDS.iter().enumerate() {
+                text
+                    x=(SVG_LABEL_WIDTH + col * SVG_CELL + SVG_CELL / 2)
+                    y=(SVG_CELL - 3)
+                    text-anchor="middle"
+                    {(amino_acid)}
+            }
+            @for (row_index, (pos, row)) in matrix.rows().enumerate() {
+                @let y = SVG_CELL + row_index * SVG_CELL;
+                text x=(SVG_LABEL_WIDTH - 4) y=(y + SVG_CELL - 3) text-anchor="end" {(pos)}
+                @for (col, cell) in row.iter().enumerate() {
+                    @let x = SVG_LABEL_WIDTH + col * SVG_CELL;
+                    @if let Some(variant) = cell {
+                        @let value = paint.value_of(variant);
+                        rect x=(x) y=(y) width=(SVG_CELL - 1) height=(SVG_CELL - 1)
+                            fill=(painter.color(variant, value))
+                        {
+                            title {(format!("{}{} {}: {:.3}", pos, variant.aa, paint, value))}
+                        }
+                    } @else {
+                        rect x=(x) y=(y) width=(SVG_CELL - 1) height=(SVG_CELL - 1) fill="#f1f1f1"{}
+                    }
+                }
+            }
+        }
+    );
+    Ok((
+        [
+            (header::CONTENT_TYPE, "image/svg+xml".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!(
+                    "attachment; filename=\"{}_{}_heatmap.svg\"",
+                    params.protein, params.condition
+                ),
+            ),
+        ],
+        svg.into_string(),
+    )
+        .into_response())
+}
+
+async fn get_cache_stats(State(state): State<AppState>) -> impl IntoResponse {
+    axum::Json(state.cache.stats())
+}
+
+async fn get_metrics(State(state): State<AppState>) -> impl IntoResponse {
+    let mut out = Exposition::new();
+    METRICS.render(&mut out);
+
+    let pool = state.repository.pool_stats();
+    out.gauge(
+        "dms_db_pool_connections",
+        "Open database connections",
+        pool.size as f64,
+    );
+    out.gauge(
+        "dms_db_pool_idle_connections",
+        "Open database connections not in use",
+        pool.idle as f64,
+    );
+    out.gauge(
+        "dms_db_pool_max_connections",
+        "Most connections the pool will open",
+        pool.max as f64,
+    );
+
+    let cache = state.cache.stats();
+    out.counter(
+        "dms_cache_hits_total",
+        "Responses served from the response cache",
+        cache.hits as f64,
+    );
+    out.counter(
+        "dms_cache_misses_total",
+        "Cacheable requests not found in the response cache",
+        cache.misses as f64,
+    );
+    out.counter(
+        "dms_cache_evictions_total",
+        "Entries evicted to stay under the cache size limit",
+        cache.evictions as f64,
+    );
+    out.counter(
+        "dms_cache_invalidations_total",
+        "Entries dropped because their data changed",
+        cache.invalidations as f64,
+    );
+    out.gauge(
+        "dms_cache_entries",
+        "Responses in the response cache",
+        cache.entries as f64,
+    );
+    out.gauge(
+        "dms_cache_bytes",
+        "Body bytes held by the response cache",
+        cache.bytes as f64,
+    );
+    out.gauge(
+        "dms_dataset_revision",
+        "Dataset revision the server last saw",
+        state.revision.load(Ordering::Relaxed) as f64,
+    );
+
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        out.finish(),
+    )
+}
+
+/// Liveness: the process is up and serving requests.
+async fn get_healthz() -> impl IntoResponse {
+    "ok"
+}
+
+/// Readiness: the database answers and has every migration this build
+/// expects.
+async fn get_readyz(State(state): State<AppState>) -> impl IntoResponse {
+    let expected = state.repository.latest_migration();
+    let (database, applied) = match state.repository.applied_migration().await {
+        Ok(applied) => ("ok".to_string(), applied),
+        Err(err) => {
+            warn!("readiness check failed: {err}");
+            (err.to_string(), None)
+        }
+    };
+    let ready = applied.is_some_and(|applied| applied >= expected);
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (
+        status,
+        axum::Json(serde_json::json!({
+            "ready": ready,
+            "database": database,
+            "migration": {"applied": applied, "expected": expected},
+        })),
+    )
+}
+
+/// Checks that `protein` exists and `scope` may see it, suggesting near
+/// misses among the visible proteins when it doesn't. Proteins hidden from
+/// the caller are as unknown as missing ones.
+async fn validate_protein(
+    protein: &str,
+    scope: Scope,
+    repository: &dyn Repository,
+) -> Result<(), AppError> {
+    validate::required("protein", protein)?;
+    let names = repository.list_proteins(scope).await?;
+    if names.iter().any(|name| name == protein) {
+        return Ok(());
+    }
+    Err(validate::unknown(
+        "protein",
+        protein,
+        names.iter().map(String::as_str),
+    ))
+}
+
+/// Checks that `condition` was measured for a `protein` the caller may see
+/// and returns the highest position with data.
+async fn validate_dataset(
+    protein: &str,
+    condition: &str,
+    scope: Scope,
+    repository: &dyn Repository,
+) -> Result<i32, AppError> {
+    validate::required("protein", protein)?;
+    validate::required("condition", condition)?;
+    validate_protein(protein, scope, repository).await?;
+    if let Some(max_pos) = repository.max_position(protein, condition).await? {
+        return Ok(max_pos);
+    }
+    let conditions = repository.conditions_for(protein).await?;
+    Err(validate::unknown(
+        "condition",
+        condition,
+        conditions.iter().map(String::as_str),
+    ))
+}
+
+/// Runs every check on `params` and returns the highest position with data.
+async fn validate_table_params(
+    params: &TableParams,
+    scope: Scope,
+    state: &AppState,
+) -> Result<i32, AppError> {
+    validate::table_params(params)?;
+    let max_pos = validate_dataset(
+        &params.protein,
+        &params.condition,
+        scope,
+        state.repository.as_ref(),
+    )
+    .await?;
+    if let Some(page) = params.page {
+        validate::page_in_range(page, max_pos, state.config.server.page_size)?;
+    }
+    Ok(max_pos)
+}
+
 async fn get_variants(
     State(state): State<AppState>,
+    Extension(scope): Extension<Scope>,
     Query(params): Query<TableParams>,
-) -> impl IntoResponse {
+) -> Result<axum::response::Response, AppError> {
     let TableParams {
         ref protein,
         ref condition,
         page,
         ref position_filter,
         ref paint,
-        operation: _,
-        ref threshold,
-        plot: _,
+        ..
     } = params;
+    let maximum = validate_table_params(&params, scope, &state).await?;
     let page = page.unwrap_or(1);
     info!(
         "Getting variant for protein = {}, condition = {} and page = {} and order={:?}",
         protein, condition, page, position_filter
     );
-    let page_start = (page - 1) * PAGE_SIZE + 1;
-    let mut page_end = page_start + PAGE_SIZE;
-    match sqlx::query!(
-        r#"
-        SELECT max(pos) as maximum FROM variant
-        JOIN protein ON variant.protein_id = protein.id
-        WHERE protein.name = $1
-        AND variant.condition = $2
-        "#,
-        protein,
-        condition
-    )
-    .fetch_one(&state.pool)
-    .await
-    {
-        Ok(record) => match record.maximum {
-            Some(maximum) => {
-                if page_end >= maximum {
-                    page_end = maximum;
-                }
-            }
-            None => {
-                warn!("Could not find maximum")
-            }
-        },
-        Err(_) => {
-            warn!("Could not find maximum");
-        }
-    }
-    let variants: Vec<Variant> = match position_filter {
-        PositionFilter::NoOrder => match threshold {
-            Some(threshold) => sqlx::query_as!(
-                Variant,
-                r#"SELECT
-                    variant.id,
-                    variant.chunk,
-                    variant.pos,
-                    variant.p_value,
-                    variant.created_on,
-                    variant.log2_fold_change,
-                    variant.log2_std_error,
-                    variant.statistic,
-                    variant.condition,
-                    variant.aa,
-                    variant.version,
-                    protein.name as protein
-                FROM variant
-                JOIN protein ON variant.protein_id = protein.id
-                WHERE protein.name = $1
-                AND variant.condition = $2
-                AND case $5
-                    when 'p_value' then variant.p_value < $6
-                    when 'log2_fold_change' then
-                            (case
-                                when variant.log2_fold_change >= 0 then variant.log2_fold_change < $6
-                                else variant.log2_fold_change > $6
-                            end)
-                    when 'statistic' then variant.statistic < $6
-                end
-                AND variant.pos >= $3
-                AND variant.pos <= $4
-                ORDER BY variant.pos, variant.aa
-                "#,
-                protein,
-                condition,
-                page_start,
-                page_end,
-                paint.to_string(),
-                *threshold
-            )
-            .fetch_all(&state.pool)
-            .await
-            .unwrap(),
-            None => sqlx::query_as!(
-                Variant,
-                r#"SELECT
-                                variant.id,
-                                variant.chunk,
-                                variant.pos,
-                                variant.p_value,
-                                variant.created_on,
-                                variant.log2_fold_change,
-                                variant.log2_std_error,
-                                variant.statistic,
-                                variant.condition,
-                                variant.aa,
-                                variant.version,
-                                protein.name as protein
-                            FROM variant
-                            JOIN protein ON variant.protein_id = protein.id
-                            WHERE protein.name = $1
-                            AND variant.condition = $2
-                            AND variant.pos >= $3
-                            AND variant.pos <= $4
-                            ORDER BY variant.pos, variant.aa
-                            "#,
-                protein,
-                condition,
-                page_start,
-                page_end
-            )
-            .fetch_all(&state.pool)
-            .await
-            .unwrap(),
-        },
-        _ => sqlx::query_as!(
-            Variant,
-            r#"
-                WITH ranked_variants AS (
-                    SELECT
-                        variant.id,
-                        variant.chunk,
-                        variant.pos,
-                        variant.p_value,
-                        variant.created_on,
-                        variant.log2_fold_change,
-                        variant.log2_std_error,
-                        variant.statistic,
-                        variant.condition,
-                        variant.aa,
-                        variant.version,
-                        protein.name as protein,
-                        ROW_NUMBER() OVER (
-                            PARTITION BY variant.pos
-                            ORDER BY
-                                CASE $5
-                                    WHEN 'MostSignificantPValue' THEN variant.p_value
-                                    WHEN 'LargestLog2FoldChange' THEN -variant.log2_fold_change
-                                    WHEN 'LargestZStatistic' THEN -variant.statistic
-                                    ELSE NULL
-                                END ASC
-                        ) AS rn
-                    FROM variant
-                    JOIN protein ON variant.protein_id = protein.id
-                    WHERE protein.name = $1
-                    AND variant.condition = $2
-                )
-                SELECT
-                    id,
-                    chunk,
-                    pos,
-                    p_value,
-                    created_on,
-                    log2_fold_change,
-                    log2_std_error,
-                    statistic,
-                    condition,
-                    aa,
-                    version,
-                    protein
-                FROM ranked_variants
-                WHERE rn = 1
-                AND pos >= $3
-                AND pos <= $4;
-                "#,
-            protein,
-            condition,
-            page_start,
-            page_end,
-            position_filter.to_string()
-        )
-        .fetch_all(&state.pool)
-        .await
-        .unwrap(),
-    };
+    let repository = state.repository.as_ref();
+    let page_size = state.config.server.page_size;
+    let page_start = (page - 1) * page_size + 1;
+    let page_end = (page_start + page_size - 1).min(maximum);
+    let has_next_page = page < validate::page_count(maximum, page_size);
+    let variants = repository
+        .variants_page(&params, page_start, page_end)
+        .await?;
     if variants.is_empty() {
         info!("no variants found... perhaps a missing chunk? or a really low threshold")
     }
     let query_length: usize = variants.len();
     info!("{}", query_length);
-    let positions: Vec<i32> = (page_start..page_end).collect();
+    let matrix = VariantMatrix::build(&variants, page_start, page_end);
+    let positions: Vec<i32> = (page_start..=page_end).collect();
     debug!("{:?}", &positions);
-    if let Some(max_abs) = get_max_absolute_value(protein, condition, *paint, &state.pool).await {
-        let normalizer = Normalizer { max_abs };
-        let pos_color_pairs: Vec<PosColor> = variants
+    let painter = match paint {
+        Paint::Classification => {
+            let all_variants = repository.all_variants(protein, condition).await?;
+            get_cell_painter(&params, &all_variants, repository).await?
+        }
+        _ => get_cell_painter(&params, &variants, repository).await?,
+    };
+    let pos_color_pairs: Vec<PosColor> = match &painter {
+        CellPainter::Classes { by_position, .. } => by_position
+            .range(page_start..=page_end)
+            .map(|(pos, counts)| PosColor {
+                pos: *pos,
+                color: counts.dominant().color_hex().to_string(),
+            })
+            .collect(),
+        CellPainter::Scale(_) => variants
             .iter()
             .map(|variant| PosColor {
                 pos: variant.pos,
-                color: normalizer.get_color_hex(variant.log2_fold_change),
+                color: painter.color(variant, variant.log2_fold_change),
             })
-            .collect();
-        let mut res = (
-                    StatusCode::OK,
-                    html!(
-                        @for pos in &positions{
-                            tr{
-                                th scope="row"{(pos)}
-                                @for amino_acid in &GROUPED_AMINO_ACIDS{
-                                    @let end_of_row = (pos == &(page_end - 15)) && (&amino_acid == &GROUPED_AMINO_ACIDS.last().unwrap());
-                                    (get_variant_cell(&variants, amino_acid, pos, &params, end_of_row,&normalizer))
-                                }
+            .collect(),
+    };
+    let (tolerance, track_scale) = match params.tolerance_metric {
+        Some(metric) => {
+            let scores = get_position_tolerance(
+                protein,
+                condition,
+                page_start,
+                page_end,
+                params.effect_cutoff,
+                repository,
+            )
+            .await?;
+            let scale = match metric {
+                ToleranceMetric::MeanEffect => repository
+                    .range_for(protein, condition, Paint::Log2FoldChange)
+                    .await?
+                    .map_or(0.0, |range| range.max_abs),
+                _ => 1.0,
+            };
+            (
+                scores.into_iter().map(|score| (score.pos, score)).collect(),
+                Some((metric, scale)),
+            )
+        }
+        None => (HashMap::new(), None),
+    };
+    Ok((
+                StatusCode::OK,
+                html!(
+                    @for pos in &positions{
+                        tr{
+                            th scope="row" title=[painter.position_title(pos)]{(pos)}
+                            @if let Some((metric, scale)) = track_scale {
+                                (tolerance_track_cell(tolerance.get(pos), metric, scale))
+                            }
+                            @for amino_acid in &GROUPED_AMINO_ACIDS{
+                                @let end_of_row = has_next_page && *pos == page_end - 15 && *amino_acid == *GROUPED_AMINO_ACIDS.last().unwrap();
+                                (get_variant_cell(matrix.get(*pos, amino_acid), amino_acid, pos, &params, end_of_row,&painter))
                             }
                         }
-                        script {(PreEscaped(format!("colorVariants({})",serde_json::json!(pos_color_pairs))))}
+                    }
+                    script {(PreEscaped(format!("colorVariants({})",serde_json::json!(pos_color_pairs))))}
 
 
-                    )
-                    ).into_response();
-        res.headers_mut().insert(
-            header::CACHE_CONTROL,
-            HeaderValue::from_static("max-age=100"),
-        );
-        return res;
-    } else {
-        warn!("error");
-        (StatusCode::INTERNAL_SERVER_ERROR, html!(div{"eeeee"})).into_response()
-    }
+                )
+                ).into_response())
 }
-async fn get_max_absolute_value(
+async fn get_position_tolerance(
     protein: &str,
     condition: &str,
-    paint: Paint,
-    pool: &PgPool,
-) -> Option<f64> {
-    match sqlx::query_scalar!(
-        r#"
-                select
-                    max(abs(
-                        case $3
-                            when 'p_value' then variant.p_value
-                            when 'log2_fold_change' then variant.log2_fold_change
-                            when 'statistic' then variant.statistic
-                        end
-                    ))
-                from variant
-                join protein on variant.protein_id = protein.id
-                where protein.name = $1 and variant.condition = $2;"#,
-        protein,
-        condition,
-        paint.to_string()
+    page_start: i32,
+    page_end: i32,
+    effect_cutoff: Option<f64>,
+    repository: &dyn Repository,
+) -> Result<Vec<PositionTolerance>, sqlx::Error> {
+    let variants = repository
+        .variants_in_range(protein, condition, page_start, page_end)
+        .await?;
+    let sequence = repository.protein_sequence(protein).await?;
+    Ok(tolerance::position_tolerance(
+        &variants,
+        sequence.as_deref(),
+        effect_cutoff.unwrap_or(classify::DEFAULT_EFFECT_CUTOFF),
+    ))
+}
+
+fn tolerance_track_header(params: &TableParams, metric: ToleranceMetric) -> Markup {
+    html!(
+        th class="tolerance-track" title=(metric.label()){
+            "Tol."
+            form action="/tolerance" method="get" target="_blank"{
+                input type="hidden" name="protein" value=(params.protein){}
+                input type="hidden" name="condition" value=(params.condition){}
+                input type="hidden" name="format" value="Tsv"{}
+                @if let Some(effect_cutoff) = params.effect_cutoff {
+                    input type="hidden" name="effect_cutoff" value=(effect_cutoff){}
+                }
+                button type="submit" title="Export tolerance scores as TSV"{"⤓"}
+            }
+            button
+                title="Color the structure by this track"
+                hx-get="/tolerance/structure"
+                hx-include=(TABLE_PARAMS_INCLUDE)
+                hx-target="#tolerance-structure"
+                {"◐"}
+            div id="tolerance-structure" style="display: none;"{}
+        }
     )
-    .fetch_one(pool)
-    .await
-    {
-        Ok(max_abs_option) => max_abs_option,
-        Err(_) => None,
+}
+
+fn tolerance_track_cell(
+    score: Option<&PositionTolerance>,
+    metric: ToleranceMetric,
+    scale: f64,
+) -> Markup {
+    match score {
+        Some(score) => {
+            let value = score.value(metric);
+            let width = if scale > 0.0 {
+                (value.abs() / scale * 100.0).min(100.0)
+            } else {
+                0.0
+            };
+            let color = match metric {
+                ToleranceMetric::MeanEffect => DivergingScale::new(scale).color(value).hex(),
+                _ => "#4A6FA5".to_string(),
+            };
+            html!(
+                td class="tolerance-track" title=(format!("{}: {:.3} ({} missense)", metric.label(), value, score.missense)){
+                    div class="tolerance-bar" style=(format!("width: {:.1}%; background-color: {}", width, color)){}
+                }
+            )
+        }
+        None => html!(td class="tolerance-track" title=(format!("{}: N/A", metric.label())){}),
     }
 }
 
-struct MinMax {
-    min: f64,
-    max: f64,
+#[derive(Deserialize)]
+struct ToleranceQuery {
+    protein: String,
+    condition: String,
+    effect_cutoff: Option<f64>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    tolerance_metric: Option<ToleranceMetric>,
+    format: Option<ExportFormat>,
 }
 
-async fn get_range_of_variant(
-    protein: &str,
-    condition: &str,
-    paint: Paint,
-    pool: &PgPool,
-) -> Option<MinMax> {
-    match sqlx::query!(
-        r#"
-                select
-                    max(
-                        case $3
-                            when 'p_value' then variant.p_value
-                            when 'log2_fold_change' then variant.log2_fold_change
-                            when 'statistic' then variant.statistic
-                        end
-                    ) as max,
-                    min(
-                        case $3
-                            when 'p_value' then variant.p_value
-                            when 'log2_fold_change' then variant.log2_fold_change
-                            when 'statistic' then variant.statistic
-                        end
-                    ) as min
-                from variant
-                join protein on variant.protein_id = protein.id
-                where protein.name = $1 and variant.condition = $2;"#,
-        protein,
-        condition,
-        paint.to_string()
+async fn validate_tolerance_query(
+    query: &ToleranceQuery,
+    scope: Scope,
+    repository: &dyn Repository,
+) -> Result<(), AppError> {
+    validate::finite("effect_cutoff", query.effect_cutoff)?;
+    validate_dataset(&query.protein, &query.condition, scope, repository).await?;
+    Ok(())
+}
+
+async fn get_tolerance(
+    State(state): State<AppState>,
+    Extension(scope): Extension<Scope>,
+    Query(query): Query<ToleranceQuery>,
+) -> Result<axum::response::Response, ApiError> {
+    validate_tolerance_query(&query, scope, state.repository.as_ref()).await?;
+    let scores = get_position_tolerance(
+        &query.protein,
+        &query.condition,
+        1,
+        i32::MAX,
+        query.effect_cutoff,
+        state.repository.as_ref(),
+    )
+    .await?;
+    let response = match query.format.unwrap_or(ExportFormat::Json) {
+        ExportFormat::Json => axum::Json(scores).into_response(),
+        ExportFormat::Tsv => {
+            let mut writer = csv::WriterBuilder::new()
+                .delimiter(b'\t')
+                .from_writer(vec![]);
+            for score in &scores {
+                writer.serialize(score)?;
+            }
+            let body = writer
+                .into_inner()
+                .map_err(|err| AppError::Internal(err.into_error().into()))?;
+            (
+                [
+                    (
+                        header::CONTENT_TYPE,
+                        "text/tab-separated-values".to_string(),
+                    ),
+                    (
+                        header::CONTENT_DISPOSITION,
+                        format!(
+                            "attachment; filename=\"{}_{}_tolerance.tsv\"",
+                            query.protein, query.condition
+                        ),
+                    ),
+                ],
+                body,
+            )
+                .into_response()
+        }
+    };
+    Ok(response)
+}
+
+async fn get_tolerance_structure_colors(
+    State(state): State<AppState>,
+    Extension(scope): Extension<Scope>,
+    Query(query): Query<ToleranceQuery>,
+) -> Result<Markup, AppError> {
+    validate_tolerance_query(&query, scope, state.repository.as_ref()).await?;
+    let metric = query
+        .tolerance_metric
+        .unwrap_or(ToleranceMetric::FractionTolerated);
+    let scores = get_position_tolerance(
+        &query.protein,
+        &query.condition,
+        1,
+        i32::MAX,
+        query.effect_cutoff,
+        state.repository.as_ref(),
+    )
+    .await?;
+    // Color every metric on a diverging scale around its midpoint, so the
+    // least tolerant positions end up red.
+    let (center, max_abs) = match metric {
+        ToleranceMetric::MeanEffect => (
+            0.0,
+            scores
+                .iter()
+                .map(|score| score.mean_effect.abs())
+                .fold(0.0, f64::max),
+        ),
+        _ => (0.5, 0.5),
+    };
+    let scale = DivergingScale::new(max_abs);
+    let pos_color_pairs: Vec<PosColor> = scores
+        .iter()
+        .map(|score| PosColor {
+            pos: score.pos,
+            color: scale.color(score.value(metric) - center).hex(),
+        })
+        .collect();
+    Ok(
+        html!(script {(PreEscaped(format!("colorVariants({})",serde_json::json!(pos_color_pairs))))}),
     )
-    .fetch_one(pool)
-    .await
-    {
-        Ok(max_abs_option) => Some(MinMax {
-            min: max_abs_option.min?,
-            max: max_abs_option.max?,
-        }),
-        Err(_) => None,
-    }
 }
 
 fn get_variant_cell(
-    variants: &[Variant],
+    variant: Option<&Variant>,
     amino_acid: &str,
     pos: &i32,
     params: &TableParams,
     end_of_row: bool,
-    normalizer: &Normalizer,
+    painter: &CellPainter,
 ) -> Markup {
     if end_of_row {
         info!("reached end of row")
     };
-    for variant in variants {
-        if amino_acid == &variant.aa && pos == &variant.pos {
-            let color = normalizer.get_color_hex(variant.log2_fold_change);
-            if end_of_row {
-                info!("emitting end of row td");
-                return html!((format_variant_cell(
-                    Some(variant.log2_fold_change),
-                    pos,
-                    amino_acid,
-                    variant.id,
-                    Some(color.clone())
-                ))(format_invisible_lazy_load_cell(&params)));
-            } else {
-                return format_variant_cell(
-                    Some(variant.log2_fold_change),
-                    pos,
-                    amino_acid,
-                    variant.id,
-                    Some(color),
-                );
-            }
-        }
-    }
+    let cell = match variant {
+        Some(variant) => format_variant_cell(
+            Some(variant.log2_fold_change),
+            pos,
+            amino_acid,
+            variant.id,
+            Some(painter.color(variant, variant.log2_fold_change)),
+        ),
+        None => format_variant_cell(None, pos, amino_acid, None, None),
+    };
     if end_of_row {
-        info!("emitting end of row td after not finding ");
-
-        return html!((format_variant_cell(None, pos, amino_acid, None, None))(
-            format_invisible_lazy_load_cell(&params)
-        ));
+        info!("emitting end of row td");
+        return html!((cell)(format_invisible_lazy_load_cell(params)));
     }
-    return html!((format_variant_cell(None, pos, amino_acid, None, None)));
+    cell
 }
 
 fn format_variant_cell(
@@ -826,24 +1497,24 @@ fn format_variant_cell(
             }
         }
     }
-    return html!(
+    html!(
         td
         id=(format!("{pos}{amino_acid}"))
         class="dms-cell dms-cell-no-data"
-        title=(format!("log2FC: {:.3}, {}{}","N/A",pos,amino_acid)){});
+        title=(format!("log2FC: {:.3}, {}{}","N/A",pos,amino_acid)){})
 }
 
 fn format_invisible_lazy_load_cell(params: &TableParams) -> Markup {
-    return html!(
+    html!(
         td
         id="invisible-lazy-load-cell"
         hx-trigger="intersect once"
         hx-target="#dms-table-body"
         hx-indicator="#loading-cell-indicator"
-        hx-include="[name='protein'],[name='condition'],[name='position_filter'],[name='paint'],[name='threshold']"
+        hx-include=(TABLE_PARAMS_INCLUDE)
         hx-get=(format!("/variants?page={}",params.page.unwrap_or(1)+1))
         hx-swap="beforeend"
-        {});
+        {})
 }
 
 fn upload_file_component_with_message(message: &str) -> Markup {
@@ -857,46 +1528,51 @@ fn upload_file_component_with_message(message: &str) -> Markup {
 }
 
 #[debug_handler]
-async fn upload_file(State(state): State<AppState>, mut multipart: Multipart) -> impl IntoResponse {
+async fn upload_file(
+    State(state): State<AppState>,
+    Extension(scope): Extension<Scope>,
+    SignedIn(user): SignedIn,
+    mut multipart: Multipart,
+) -> Result<axum::response::Response, AppError> {
     info!("Uploading file");
     let mut protein: Option<String> = None;
-    let mut file: Option<Bytes> = None;
-    while let Some(field) = multipart.next_field().await.unwrap() {
+    let mut file: Option<(String, Bytes)> = None;
+    while let Some(field) = multipart.next_field().await? {
         if let Some(field_name) = field.name() {
             if field_name == "protein" {
                 info!("{:?}", field);
-                protein = Some(field.text().await.unwrap());
+                protein = Some(field.text().await?);
             } else if field_name == "file" {
-                file = Some(field.bytes().await.unwrap().clone().clone());
+                let file_name = field.file_name().unwrap_or("upload.tsv").to_string();
+                file = Some((file_name, field.bytes().await?));
             }
         }
     }
-    match protein {
+    let response = match protein {
         Some(protein) => {
             // Proceed if file exists, otherwise return an error
-            if let Some(file_data) = file {
-                let (variants, errors) = read_tsv(file_data, &protein);
-
-                match insert(&state.pool, &variants, &protein).await {
-                    Ok(result) => {
-                        let mut res = upload_file_component_with_message(&format!(
-                            "File successfully uploaded. {result} rows affected with {} errors",
-                            errors.len()
-                        ))
-                        .into_response();
-                        res.headers_mut().insert(
-                            "HX-Trigger-After-Settle",
-                            HeaderValue::from_static("load-condition"),
-                        );
-
-                        res
-                    }
-                    Err(err) => (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        upload_file_component_with_message(&err.to_string()),
-                    )
-                        .into_response(),
-                }
+            validate_protein(&protein, scope, state.repository.as_ref()).await?;
+            projects::require_role(&state, &user, &protein, Role::Uploader).await?;
+            if let Some((file_name, file_data)) = file {
+                let (variants, errors, source) =
+                    ingest::read_source(&file_name, &file_data, &protein);
+
+                let batch = state
+                    .repository
+                    .insert_variants(&protein, &variants, &source, &Actor::user(&user))
+                    .await?;
+                let mut res = upload_file_component_with_message(&format!(
+                    "File successfully uploaded. {} rows affected with {} errors",
+                    batch.rows_inserted,
+                    errors.len()
+                ))
+                .into_response();
+                res.headers_mut().insert(
+                    "HX-Trigger-After-Settle",
+                    HeaderValue::from_static("load-condition"),
+                );
+
+                res
             } else {
                 // No file uploaded
                 (
@@ -914,87 +1590,52 @@ async fn upload_file(State(state): State<AppState>, mut multipart: Multipart) ->
             )
                 .into_response()
         }
-    }
-}
-fn read_tsv(file_contents: Bytes, protein: &str) -> (Vec<Variant>, Vec<Error>) {
-    info!("reading file");
-    let cursor = Cursor::new(file_contents);
-    let mut reader = csv::ReaderBuilder::new()
-        .delimiter(b'\t') // Specify TSV format
-        .has_headers(true)
-        .from_reader(cursor);
-    let created_on = Utc::now().naive_utc();
-    let mut variants = vec![];
-    let mut errors = vec![];
-    for result in reader.deserialize::<Variant>() {
-        match result {
-            Ok(mut variant) => {
-                variant.protein = protein.to_string();
-                variant.created_on = created_on;
-                variants.push(variant);
-            }
-            Err(e) => {
-                errors.push(e);
-            }
-        }
-    }
-    (variants, errors)
+    };
+    Ok(response)
 }
 
 async fn get_variant_by_id(
     State(state): State<AppState>,
+    Extension(scope): Extension<Scope>,
     Path(id): Path<i32>,
     Query(_params): Query<HashMap<String, String>>,
-) -> impl IntoResponse {
+) -> Result<Markup, AppError> {
     // info!("Acquired variant {id}");
-    let pool = &state.pool;
-    if let Ok(variant) = query_as!(
-        Variant,
-        r#"
-        SELECT
-                variant.id,
-                variant.chunk,
-                variant.pos,
-                variant.p_value,
-                variant.created_on,
-                variant.log2_fold_change,
-                variant.log2_std_error,
-                variant.statistic,
-                variant.condition,
-                variant.aa,
-                variant.version,
-                protein.name as protein
-            FROM variant
-            JOIN protein ON variant.protein_id = protein.id
-            WHERE variant.id = $1;
-    "#,
-        id
-    )
-    .fetch_one(pool)
-    .await
+    let not_found = || AppError::NotFound(format!("variant {id}"));
+    let variant = state
+        .repository
+        .variant_by_id(id)
+        .await?
+        .ok_or_else(not_found)?;
+    if !state
+        .repository
+        .list_proteins(scope)
+        .await?
+        .contains(&variant.protein)
     {
-        let mut res = html!(
-            div{(variant.protein)}
-            div{(variant.condition)}
-            div{(variant.pos)}
-            div{(variant.aa)}
-            div{(format!("{:.3}",variant.log2_fold_change))}
-            div{(format!("{:.3}",variant.log2_std_error))}
-            div{(format!("{:.3}",variant.statistic))}
-            div{(format!("{:.5}",variant.p_value))}
-
-            script {(PreEscaped(format!("focusVariant({})",variant.pos)))}
-        )
-        .into_response();
-        res.headers_mut().insert(
-            header::CACHE_CONTROL,
-            HeaderValue::from_static("max-age=100"),
-        );
-        return res;
-    } else {
-        warn!("Variant not found!");
-        return html!("Variant not found!").into_response();
+        return Err(not_found());
     }
+    let batch = state.repository.variant_batch(id).await?;
+    Ok(html!(
+        div{(variant.protein)}
+        div{(variant.condition)}
+        div{(variant.pos)}
+        div{(variant.aa)}
+        div{(format!("{:.3}",variant.log2_fold_change))}
+        div{(format!("{:.3}",variant.log2_std_error))}
+        div{(format!("{:.3}",variant.statistic))}
+        div{(format!("{:.5}",variant.p_value))}
+        div{
+            @match batch {
+                Some(batch) => a href=(format!("/audit?batch={}", batch.id))
+                    title=(format!("{} rows by {} on {}", batch.rows_inserted, batch.actor, batch.created_on.format("%Y-%m-%d")))
+                    { (batch.file_name) },
+                None => "unknown",
+            }
+        }
+
+        script {(PreEscaped(format!("focusVariant({})",variant.pos)))}
+    ))
 }
 
 #[derive(Deserialize)]
@@ -1016,133 +1657,38 @@ async fn get_many_variants_by_id(Query(query): Query<IdQuery>) -> impl IntoRespo
     (response).into_response()
 }
 
-#[derive(sqlx::FromRow)]
-struct Id {
-    id: i32,
-}
-async fn insert(pool: &PgPool, variants: &[Variant], protein: &str) -> Result<u64, sqlx::Error> {
-    info!("Inserting {} variants into db", variants.len());
-    let mut txn = pool.begin().await?;
-    let chunks: Vec<i32> = variants.iter().map(|v| v.chunk).collect();
-    let positions: Vec<i32> = variants.iter().map(|v| v.pos).collect();
-    let conditions: Vec<String> = variants.iter().map(|v| v.condition.clone()).collect();
-    let aas: Vec<String> = variants.iter().map(|v| v.aa.clone()).collect();
-    let log2_fold_changes: Vec<f64> = variants.iter().map(|v| v.log2_fold_change).collect();
-    let log2_std_errors: Vec<f64> = variants.iter().map(|v| v.log2_std_error).collect();
-    let statistics: Vec<f64> = variants.iter().map(|v| v.statistic).collect();
-    let p_values: Vec<f64> = variants.iter().map(|v| v.p_value).collect();
-    let versions: Vec<String> = variants.iter().map(|v| v.version.clone()).collect();
-    let created_ons: Vec<NaiveDateTime> = variants.iter().map(|v| v.created_on).collect();
-    let protein_id: i32 = query_scalar!("SELECT id FROM protein WHERE name = $1", protein)
-        .fetch_one(&mut *txn) // Fetch one record, assuming the protein exists
-        .await?;
-    info!("Found protein {} at id {}", protein, protein_id);
-    let sql = r#"
-            INSERT INTO variant
-            (
-                chunk,
-                pos,
-                condition,
-                aa,
-                log2_fold_change,
-                log2_std_error,
-                statistic,
-                p_value,
-                version,
-                protein_id,
-                created_on
-            )
-            SELECT * FROM UNNEST(
-                $1::INT8[],
-                $2::INT8[],
-                $3::VARCHAR(30)[],
-                $4::VARCHAR(30)[],
-                $5::DOUBLE PRECISION[],
-                $6::DOUBLE PRECISION[],
-                $7::DOUBLE PRECISION[],
-                $8::DOUBLE PRECISION[],
-                $9::VARCHAR(30)[],
-                $10::INT8[],
-                $11::TIMESTAMP[]
-            ) RETURNING id;
-        "#;
-    let rows: Vec<Id> = query_as::<_, Id>(sql)
-        .bind(chunks)
-        .bind(positions)
-        .bind(conditions)
-        .bind(aas)
-        .bind(log2_fold_changes)
-        .bind(log2_std_errors)
-        .bind(statistics)
-        .bind(p_values)
-        .bind(versions)
-        .bind(vec![protein_id; variants.len()]) // A vector filled with the protein_id for all rows
-        .bind(created_ons)
-        .fetch_all(pool)
-        .await?;
-
-    txn.commit().await?;
-    let rows_affected = rows.into_iter().map(|r| r.id).len() as u64;
-    info!("rows affected {}", rows_affected);
-    Ok(rows_affected)
-}
-
 async fn get_threshold_for_paint_by(
     State(state): State<AppState>,
+    Extension(scope): Extension<Scope>,
     Query(params): Query<TableParams>,
-) -> impl IntoResponse {
+) -> Result<axum::response::Response, AppError> {
     let TableParams {
         ref protein,
         ref condition,
-        page: _,
-        position_filter,
+        ref position_filter,
         ref paint,
-        operation: _,
-        threshold: _,
-        plot: _,
+        ..
     } = params;
-    let pool = &state.pool;
-    match position_filter {
+    let repository = state.repository.as_ref();
+    validate_table_params(&params, scope, &state).await?;
+    if let Paint::Classification = paint {
+        return Ok(classifier_controls(&params).into_response());
+    }
+    let response = match position_filter {
         PositionFilter::NoOrder => {
-            let rows = sqlx::query!(
-                r#"
-                select
-                    max(
-                        case $3
-                            when 'p_value' then variant.p_value
-                            when 'log2_fold_change' then variant.log2_fold_change
-                            when 'statistic' then variant.statistic
-                        end
-                    ) as "max_value!",
-                    min(
-                        case $3
-                            when 'p_value' then variant.p_value
-                            when 'log2_fold_change' then variant.log2_fold_change
-                            when 'statistic' then variant.statistic
-                        end
-                    ) as "min_value!"
-                from variant
-                join protein on variant.protein_id = protein.id
-                where protein.name = $1 and variant.condition = $2;
-                "#,
-                protein,
-                condition,
-                paint.to_string()
-            )
-            .fetch_one(pool)
-            .await;
+            let rows = repository.range_for(protein, condition, *paint).await?;
             match rows {
-                Ok(row) => {
-                    let step = (row.max_value - row.min_value) / 50.0;
-                    return (html!(
+                Some(range) => {
+                    let step = (range.max - range.min) / 50.0;
+                    (html!(
                         label for="threshold"{"Threshold"}
                             div style="display: flex; align-items: center;" {
                                 input
                                     type="range"
                                     id="threshold"
                                     name="threshold"
-                                    min=(format!("{:.3}",row.min_value))
-                                    max=(format!("{:.3}",row.max_value))
+                                    min=(format!("{:.3}",range.min))
+                                    max=(format!("{:.3}",range.max))
                                     step=(format!("{:.3}",step))
                                     x-model="threshold_value"
                                     {}
@@ -1151,20 +1697,53 @@ async fn get_threshold_for_paint_by(
 
 
                     ))
-                    .into_response();
+                    .into_response()
                 }
-                Err(e) => {
-                    return (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        html!(div{(format!("no max or min found, {}",e))}),
-                    )
-                        .into_response();
+                None => {
+                    return Err(AppError::NotFound(format!(
+                        "no {paint} range for {protein} in {condition}"
+                    )))
                 }
             }
         }
         _ => (html!()).into_response(),
-    }
+    };
+    Ok(response)
 }
+fn classifier_controls(params: &TableParams) -> Markup {
+    let classifier = params.classifier.unwrap_or(ClassifierKind::Threshold);
+    html!(
+        label for="classifier"{"Classifier"}
+        div style="display: flex; align-items: center; gap: 10px;" {
+            select id="classifier-select" name="classifier"{
+                @for kind in [ClassifierKind::Threshold, ClassifierKind::Mixture]{
+                    option value=(kind) selected[kind == classifier] {
+                        @match kind {
+                            ClassifierKind::Threshold => "Effect size + adjusted p",
+                            ClassifierKind::Mixture => "Synonymous/nonsense mixture",
+                        }
+                    }
+                }
+            }
+            @if let ClassifierKind::Threshold = classifier {
+                label for="effect_cutoff"{"|log2FC| ≥"}
+                input type="number" id="effect_cutoff" name="effect_cutoff" step="0.1" min="0"
+                    value=(params.effect_cutoff.unwrap_or(classify::DEFAULT_EFFECT_CUTOFF)){}
+                label for="p_cutoff"{"adj. p <"}
+                input type="number" id="p_cutoff" name="p_cutoff" step="0.01" min="0" max="1"
+                    value=(params.p_cutoff.unwrap_or(classify::DEFAULT_P_CUTOFF)){}
+            } @else {
+                label for="posterior_cutoff"{"P(nonsense) ≥"}
+                input type="number" id="posterior_cutoff" name="posterior_cutoff" step="0.05" min="0" max="1"
+                    value=(params.posterior_cutoff.unwrap_or(classify::DEFAULT_POSTERIOR_CUTOFF)){}
+                label for="gain_z"{"gain z >"}
+                input type="number" id="gain_z" name="gain_z" step="0.1" min="0"
+                    value=(params.gain_z.unwrap_or(classify::DEFAULT_GAIN_Z)){}
+            }
+        }
+    )
+}
+
 #[derive(Debug, serde::Deserialize)]
 pub struct TitleQuery {
     previous: Option<String>,
@@ -1187,52 +1766,177 @@ async fn get_title(Query(query): Query<TitleQuery>) -> impl IntoResponse {
 
     let random_number: usize = rng.gen_range(0..filtered_titles.len());
     let new_title = filtered_titles.get(random_number).unwrap();
-    return html!(
+    html!(
         span
             hx-get=(format!("/title?previous={new_title}"))
             hx-trigger="every 5s"
             hx-swap="outerHTML swap:1s settle:1s"
             id="page-title-end" {(new_title.to_uppercase())}
     )
-    .into_response();
+    .into_response()
+}
+
+/// DeepScan deep mutational scanning viewer.
+#[derive(Parser, Debug)]
+#[command(version)]
+struct Cli {
+    #[command(flatten)]
+    config: ConfigArgs,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Start the web server (the default)
+    Serve,
+    /// Apply pending database migrations and exit
+    Migrate,
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    tracing_subscriber::fmt::init();
+    config::load_dotenv()?;
+    let cli = Cli::parse();
+    let config = Config::load(&cli.config)?;
+    match config.server.log_format {
+        LogFormat::Text => tracing_subscriber::fmt().init(),
+        LogFormat::Json => tracing_subscriber::fmt().json().init(),
+    }
     info!("Welcome to DeepScan!");
-    let state = AppState::from_env().await?;
-    let listener =
-        TcpListener::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, state.env.port))).await?;
-    info!("loaded");
-    let app = Router::new()
-        .route("/", get(main_content))
+    info!("effective configuration:\n{config}");
+    let command = cli.command.unwrap_or(Command::Serve);
+    let migrate = matches!(command, Command::Migrate) || config.database.migrate_on_start;
+    // Postgres tells running servers about changes; SQLite has to be polled
+    let (repository, applied, listen): (Arc<dyn Repository>, _, Option<PgPool>) =
+        match config.database.backend() {
+            Some(Backend::Sqlite) => {
+                let pool = config.database.connect_sqlite().await?;
+                let applied = if migrate {
+                    migrate::run_sqlite(&pool).await?
+                } else {
+                    vec![]
+                };
+                (Arc::new(SqliteRepository::new(pool)), applied, None)
+            }
+            _ => {
+                let pool = config.database.connect().await?;
+                let applied = if migrate {
+                    migrate::run(&pool).await?
+                } else {
+                    vec![]
+                };
+                let repository = Arc::new(PostgresRepository::new(pool.clone()));
+                (repository, applied, Some(pool))
+            }
+        };
+    if let Command::Migrate = command {
+        info!(
+            "{} migration(s) applied, schema at version {}",
+            applied.len(),
+            repository.latest_migration()
+        );
+        return Ok(());
+    }
+    let state = AppState::new(config, repository);
+    let listener = TcpListener::bind(state.config.server.bind).await?;
+    info!("listening on {}", state.config.server.bind);
+    state.revision.store(
+        state.repository.dataset_revision().await?,
+        Ordering::Release,
+    );
+    match listen {
+        Some(pool) => tokio::spawn(invalidate_cache_on_ingest(state.clone(), pool)),
+        None => tokio::spawn(poll_dataset_revision(state.clone())),
+    };
+    let assets_dir = state.config.server.assets_dir.clone();
+    // Responses that depend only on `TableParams` and the stored variants
+    let cached = Router::new()
         .route("/variants", get(get_variants))
         .route("/plot", get(get_plot))
+        .route("/threshold", get(get_threshold_for_paint_by))
+        .route("/api/matrix", get(get_matrix))
+        .route("/api/matrix.svg", get(get_matrix_svg))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            cache_responses,
+        ));
+    let app = Router::new()
+        .merge(cached)
+        .route("/", get(main_content))
         .route("/variant_form", get(get_variant_form))
         // .route("/heatmap", get(get_heatmap))
         .route("/proteins", get(get_proteins))
         .route("/conditions", get(get_conditions))
-        // .route("/upload", post(upload_file))
+        .route("/upload", post(upload_file))
+        .route(
+            "/login",
+            get(accounts::get_login).post(accounts::post_login),
+        )
+        .route("/logout", post(accounts::post_logout))
+        .route("/account", get(accounts::get_account))
+        .route("/account/password", post(accounts::post_password))
+        .route("/account/tokens", post(accounts::post_token))
+        .route("/account/tokens/:id/delete", post(accounts::delete_token))
+        .route(
+            "/admin/users",
+            get(accounts::get_users).post(accounts::post_user),
+        )
+        .route("/admin/users/:id", post(accounts::update_user))
+        .route("/admin/users/:id/delete", post(accounts::delete_user))
+        .route(
+            "/projects",
+            get(projects::get_projects).post(projects::post_project),
+        )
+        .route("/projects/:id/members", post(projects::post_member))
+        .route(
+            "/projects/:id/members/:user_id/delete",
+            post(projects::delete_member),
+        )
+        .route(
+            "/projects/:id/proteins",
+            post(projects::post_protein_access),
+        )
+        .route("/audit", get(audit::get_audit))
+        .route("/api/audit", get(audit::get_audit_json))
+        .route(
+            "/datasets",
+            get(datasets::get_datasets).post(datasets::post_datasets),
+        )
+        .route("/datasets/confirm", get(datasets::get_confirm))
+        .route("/api/datasets", post(datasets::post_datasets_json))
         .route("/variant/:id", get(get_variant_by_id))
         .route("/variant", get(get_many_variants_by_id))
-        .route("/threshold", get(get_threshold_for_paint_by))
+        .route("/classification", get(get_classification_summary))
+        .route("/tolerance", get(get_tolerance))
+        .route("/tolerance/structure", get(get_tolerance_structure_colors))
+        .route("/api/substitutions", get(get_substitutions_json))
+        .route("/api/cache", get(get_cache_stats))
+        .route("/metrics", get(get_metrics))
+        .route("/healthz", get(get_healthz))
+        .route("/readyz", get(get_readyz))
         .route("/title", get(get_title))
         // .route("/scatter", get(get_scatter_plot))
-        .layer(DefaultBodyLimit::max(1024 * 1024 * 100000))
+        .layer(DefaultBodyLimit::max(
+            state.config.server.upload_limit_bytes,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            conditional_get,
+        ))
+        .layer(middleware::from_fn_with_state(state.clone(), authenticate))
         .with_state(state)
         .nest_service(
             "/assets",
             ServiceBuilder::new()
                 .layer(middleware::from_fn(set_static_cache_control))
                 .service(
-                    ServeDir::new("assets")
+                    ServeDir::new(assets_dir)
                         .precompressed_br()
                         .precompressed_gzip(),
                 ),
-        );
-    axum::serve(listener, app.into_make_service())
-        .await
-        .unwrap();
+        )
+        .layer(middleware::from_fn(track_requests));
+    axum::serve(listener, app.into_make_service()).await?;
     Ok(())
 }
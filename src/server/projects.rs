@@ -0,0 +1,348 @@
+//! The projects page: who belongs to which project with what role, and which
+//! of a project's proteins are public. Site administrators create projects
+//! and move proteins between them; project admins manage their members and
+//! publish their proteins.
+
+use axum::{
+    extract::{Path, State},
+    Form,
+};
+use dms_viewer::access::{self, Project, ProteinAccess, Role};
+use dms_viewer::audit::{Action, Actor, Change};
+use dms_viewer::auth::{self, User};
+use dms_viewer::error::AppError;
+use dms_viewer::AppState;
+use maud::{html, Markup};
+use serde::Deserialize;
+use serde_json::json;
+use tracing::info;
+
+use crate::accounts::{account_bar, Admin, SignedIn};
+use crate::base;
+
+/// The user's role in the project of `protein`. Site administrators count as
+/// admins of every project; proteins outside a project have no members.
+pub async fn protein_role(
+    state: &AppState,
+    user: &User,
+    protein: &str,
+) -> Result<Option<Role>, AppError> {
+    if user.is_admin {
+        return Ok(Some(Role::Admin));
+    }
+    let project_id = state
+        .repository
+        .protein_access()
+        .await?
+        .into_iter()
+        .find(|access| access.name == protein)
+        .and_then(|access| access.project_id);
+    let Some(project_id) = project_id else {
+        return Ok(None);
+    };
+    Ok(state
+        .repository
+        .roles(user.id)
+        .await?
+        .get(&project_id)
+        .copied())
+}
+
+/// Fails with 403 unless the user has at least `needed` for `protein`.
+pub async fn require_role(
+    state: &AppState,
+    user: &User,
+    protein: &str,
+    needed: Role,
+) -> Result<(), AppError> {
+    if protein_role(state, user, protein).await? < Some(needed) {
+        return Err(AppError::Forbidden(format!(
+            "you need the {needed} role in the project of {protein}"
+        )));
+    }
+    Ok(())
+}
+
+/// Project `id`, once it is clear that `user` may manage it.
+async fn require_project_admin(
+    state: &AppState,
+    user: &User,
+    id: i32,
+) -> Result<Project, AppError> {
+    let Some(project) = state
+        .repository
+        .projects()
+        .await?
+        .into_iter()
+        .find(|project| project.id == id)
+    else {
+        return Err(AppError::NotFound(format!("project {id}")));
+    };
+    if !user.is_admin && state.repository.roles(user.id).await?.get(&id) != Some(&Role::Admin) {
+        return Err(AppError::Forbidden(
+            "only project admins can do this".to_string(),
+        ));
+    }
+    Ok(project)
+}
+
+pub async fn get_projects(
+    State(state): State<AppState>,
+    SignedIn(user): SignedIn,
+) -> Result<Markup, AppError> {
+    let projects = projects_section(&state, &user).await?;
+    Ok(base(html!(
+        main class="account-page" {
+            (account_bar(Some(&user)))
+            h2 { "Projects" }
+            p {
+                "Members see a project's proteins; uploaders can also add files and project admins "
+                "manage members and publish proteins to everyone, including visitors who are not signed in."
+            }
+            div id="error-message" aria-live="polite"{}
+            (projects)
+            @if user.is_admin {
+                h3 { "New project" }
+                form class="account-form" hx-post="/projects" hx-target="#projects" hx-swap="outerHTML" {
+                    label for="project-name" { "Name" }
+                    input type="text" id="project-name" name="name" maxlength=(auth::MAX_NAME_LENGTH) required;
+                    button { "Create project" }
+                }
+            }
+        }
+    )))
+}
+
+/// Every project the user belongs to, or all of them for administrators.
+async fn projects_section(state: &AppState, user: &User) -> Result<Markup, AppError> {
+    let roles = state.repository.roles(user.id).await?;
+    let proteins = state.repository.protein_access().await?;
+    let mut projects = vec![];
+    for project in state.repository.projects().await? {
+        let role = if user.is_admin {
+            Some(Role::Admin)
+        } else {
+            roles.get(&project.id).copied()
+        };
+        if let Some(role) = role {
+            let members = state.repository.members(project.id).await?;
+            projects.push((project, role, members));
+        }
+    }
+    Ok(html!(
+        section id="projects" {
+            @if projects.is_empty() {
+                p { "You are not a member of any project yet." }
+            }
+            @for (project, role, members) in &projects {
+                @let manage = *role == Role::Admin;
+                @let held: Vec<&ProteinAccess> = proteins
+                    .iter()
+                    .filter(|protein| protein.project_id == Some(project.id))
+                    .collect();
+                h3 { (project.name) }
+                table class="account-table" {
+                    thead { tr { th { "Member" } th { "Role" } th {} } }
+                    tbody {
+                        @for member in members {
+                            tr {
+                                td { (member.username) }
+                                td { (member.role) }
+                                td class="account-actions" {
+                                    @if manage && (user.is_admin || member.user_id != user.id) {
+                                        form hx-post=(format!("/projects/{}/members", project.id))
+                                            hx-target="#projects" hx-swap="outerHTML"
+                                        {
+                                            input type="hidden" name="username" value=(member.username);
+                                            (role_select(member.role))
+                                            button { "Change" }
+                                        }
+                                        button type="button"
+                                            hx-post=(format!("/projects/{}/members/{}/delete", project.id, member.user_id))
+                                            hx-target="#projects" hx-swap="outerHTML"
+                                            hx-confirm=(format!("Remove {} from {}?", member.username, project.name))
+                                            { "Remove" }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+                @if manage {
+                    form class="account-form" hx-post=(format!("/projects/{}/members", project.id))
+                        hx-target="#projects" hx-swap="outerHTML"
+                    {
+                        input type="text" name="username" placeholder="username"
+                            maxlength=(auth::MAX_NAME_LENGTH) required;
+                        (role_select(Role::Viewer))
+                        button { "Add member" }
+                    }
+                }
+                table class="account-table" {
+                    thead { tr { th { "Protein" } th { "Visible to" } th {} } }
+                    tbody {
+                        @for protein in &held {
+                            tr {
+                                td { (protein.name) }
+                                td { @if protein.public { "everyone" } @else { "members" } }
+                                td class="account-actions" {
+                                    @if manage {
+                                        button type="button"
+                                            hx-post=(format!("/projects/{}/proteins", project.id))
+                                            hx-vals=(serde_json::json!({"protein": protein.name, "public": !protein.public}).to_string())
+                                            hx-target="#projects" hx-swap="outerHTML"
+                                            { @if protein.public { "Unpublish" } @else { "Publish" } }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+                @if user.is_admin {
+                    form class="account-form" hx-post=(format!("/projects/{}/proteins", project.id))
+                        hx-target="#projects" hx-swap="outerHTML"
+                    {
+                        select name="protein" required {
+                            @for protein in proteins.iter().filter(|protein| protein.project_id != Some(project.id)) {
+                                option value=(protein.name) { (protein.name) }
+                            }
+                        }
+                        label { input type="checkbox" name="public" value="true"; " Public" }
+                        button { "Move to " (project.name) }
+                    }
+                }
+            }
+        }
+    ))
+}
+
+fn role_select(selected: Role) -> Markup {
+    html!(
+        select name="role" {
+            @for role in Role::ALL {
+                option value=(role) selected[role == selected] { (role) }
+            }
+        }
+    )
+}
+
+#[derive(Deserialize)]
+pub struct NewProjectForm {
+    name: String,
+}
+
+pub async fn post_project(
+    State(state): State<AppState>,
+    Admin(admin): Admin,
+    Form(form): Form<NewProjectForm>,
+) -> Result<Markup, AppError> {
+    let name = form.name.trim();
+    access::check_project_name(name)?;
+    if state.repository.create_project(name).await?.is_none() {
+        return Err(AppError::BadRequest(format!(
+            "project {name} already exists"
+        )));
+    }
+    info!("{} created project {name}", admin.username);
+    projects_section(&state, &admin).await
+}
+
+#[derive(Deserialize)]
+pub struct MemberForm {
+    username: String,
+    role: Role,
+}
+
+/// Adds a member or changes their role.
+pub async fn post_member(
+    State(state): State<AppState>,
+    SignedIn(user): SignedIn,
+    Path(id): Path<i32>,
+    Form(form): Form<MemberForm>,
+) -> Result<Markup, AppError> {
+    require_project_admin(&state, &user, id).await?;
+    let username = form.username.trim();
+    let Some(member) = state.repository.credentials(username).await? else {
+        return Err(AppError::NotFound(format!("user {username}")));
+    };
+    if member.user.id == user.id && !user.is_admin {
+        return Err(AppError::BadRequest(
+            "ask another project admin to change your own role".to_string(),
+        ));
+    }
+    state
+        .repository
+        .set_member(id, member.user.id, Some(form.role))
+        .await?;
+    info!(
+        "{} made {username} {} of project {id}",
+        user.username, form.role
+    );
+    projects_section(&state, &user).await
+}
+
+pub async fn delete_member(
+    State(state): State<AppState>,
+    SignedIn(user): SignedIn,
+    Path((id, user_id)): Path<(i32, i32)>,
+) -> Result<Markup, AppError> {
+    require_project_admin(&state, &user, id).await?;
+    if user_id == user.id && !user.is_admin {
+        return Err(AppError::BadRequest(
+            "ask another project admin to remove you".to_string(),
+        ));
+    }
+    if !state.repository.set_member(id, user_id, None).await? {
+        return Err(AppError::NotFound(format!(
+            "member {user_id} of project {id}"
+        )));
+    }
+    info!("{} removed user {user_id} from project {id}", user.username);
+    projects_section(&state, &user).await
+}
+
+#[derive(Deserialize)]
+pub struct ProteinAccessForm {
+    protein: String,
+    #[serde(default)]
+    public: bool,
+}
+
+/// Publishes or hides one of the project's proteins. Administrators can also
+/// move a protein here from another project.
+pub async fn post_protein_access(
+    State(state): State<AppState>,
+    SignedIn(user): SignedIn,
+    Path(id): Path<i32>,
+    Form(form): Form<ProteinAccessForm>,
+) -> Result<Markup, AppError> {
+    let project = require_project_admin(&state, &user, id).await?;
+    let current = state
+        .repository
+        .protein_access()
+        .await?
+        .into_iter()
+        .find(|access| access.name == form.protein)
+        .filter(|access| user.is_admin || access.project_id == Some(id))
+        .ok_or_else(|| AppError::NotFound(format!("protein {} in project {id}", form.protein)))?;
+    state
+        .repository
+        .set_protein_access(&current.name, Some(id), form.public)
+        .await?;
+    state
+        .repository
+        .record(
+            &Actor::user(&user),
+            &Change::new(
+                Action::ProteinAccess,
+                &current.name,
+                json!({"project": project.name, "public": form.public}),
+            ),
+        )
+        .await?;
+    info!(
+        "{} set {} to project {id}, public = {}",
+        user.username, current.name, form.public
+    );
+    projects_section(&state, &user).await
+}
@@ -1,15 +1,291 @@
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
 use axum::{
-    extract::Request,
-    http::{header, HeaderValue},
+    body::{to_bytes, Body},
+    extract::{MatchedPath, Query, Request, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
     middleware::Next,
-    response::Response,
+    response::{IntoResponse, Response},
+};
+use dms_viewer::{
+    access::Scope,
+    cache::{CacheKey, CachedResponse},
+    metrics::METRICS,
+    AppState, TableParams,
 };
+use serde::Deserialize;
+use sqlx::{postgres::PgListener, PgPool};
+use tracing::{info, warn};
+
+/// How clients may cache each route. Change caching here, not in handlers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CachePolicy {
+    /// Files under `/assets`.
+    Static,
+    /// Anything derived from the database: revalidated on every use, with an
+    /// ETag that changes with the dataset revision and the caller's scope.
+    /// Private, since what a caller may see depends on who they are.
+    Revalidate,
+    /// Responses that differ on every request.
+    NoStore,
+}
+
+impl CachePolicy {
+    pub fn for_path(path: &str) -> Self {
+        match path {
+            "/title" | "/api/cache" | "/metrics" | "/healthz" | "/readyz" => CachePolicy::NoStore,
+            // Pages that depend on who is signed in, and changes
+            "/" | "/login" | "/logout" | "/upload" | "/audit" | "/api/audit" => {
+                CachePolicy::NoStore
+            }
+            _ if path.starts_with("/account")
+                || path.starts_with("/admin/")
+                || path.starts_with("/projects")
+                || path.starts_with("/datasets")
+                || path == "/api/datasets" =>
+            {
+                CachePolicy::NoStore
+            }
+            _ if path.starts_with("/assets/") => CachePolicy::Static,
+            _ => CachePolicy::Revalidate,
+        }
+    }
+
+    fn cache_control(&self) -> HeaderValue {
+        HeaderValue::from_static(match self {
+            CachePolicy::Static => "public, max-age=3600",
+            CachePolicy::Revalidate => "private, no-cache",
+            CachePolicy::NoStore => "no-store",
+        })
+    }
+}
 
 pub async fn set_static_cache_control(request: Request, next: Next) -> Response {
     let mut response = next.run(request).await;
-    response.headers_mut().insert(
-        header::CACHE_CONTROL,
-        HeaderValue::from_static("public, max-age=3600"),
+    response
+        .headers_mut()
+        .insert(header::CACHE_CONTROL, CachePolicy::Static.cache_control());
+    response
+}
+
+/// Applies `CachePolicy::for_path`, answering `If-None-Match` with 304 Not
+/// Modified while the dataset revision is unchanged. The build version is
+/// part of the tag so a deploy never serves old markup, and the caller's
+/// scope so signing in or out never revalidates what another caller saw.
+pub async fn conditional_get(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let policy = CachePolicy::for_path(request.uri().path());
+    if policy != CachePolicy::Revalidate {
+        let mut response = next.run(request).await;
+        response
+            .headers_mut()
+            .insert(header::CACHE_CONTROL, policy.cache_control());
+        return response;
+    }
+    let scope = request
+        .extensions()
+        .get::<Scope>()
+        .copied()
+        .unwrap_or_default();
+    let etag = format!(
+        "\"{}-{}{}\"",
+        env!("CARGO_PKG_VERSION"),
+        state.revision.load(Ordering::Acquire),
+        scope.tag()
     );
+    let etag = HeaderValue::from_str(&etag).unwrap();
+    if if_none_match(request.headers(), &etag) {
+        let mut response = StatusCode::NOT_MODIFIED.into_response();
+        response.headers_mut().insert(header::ETAG, etag);
+        response
+            .headers_mut()
+            .insert(header::CACHE_CONTROL, policy.cache_control());
+        return response;
+    }
+    let mut response = next.run(request).await;
+    if response.status().is_success() {
+        response.headers_mut().insert(header::ETAG, etag);
+    }
+    response
+        .headers_mut()
+        .insert(header::CACHE_CONTROL, policy.cache_control());
     response
 }
+
+fn if_none_match(headers: &HeaderMap, etag: &HeaderValue) -> bool {
+    let Some(tags) = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return false;
+    };
+    let etag = etag.to_str().unwrap();
+    tags.split(',')
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == etag)
+}
+
+pub async fn track_requests(request: Request, next: Next) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| match CachePolicy::for_path(request.uri().path()) {
+            // `nest_service` doesn't record a matched path.
+            CachePolicy::Static => "/assets".to_string(),
+            _ => "unmatched".to_string(),
+        });
+    let method = request.method().to_string();
+    let start = Instant::now();
+    let response = next.run(request).await;
+    METRICS.observe_request(&route, &method, response.status().as_u16(), start.elapsed());
+    response
+}
+
+/// Serves repeated requests from `AppState::cache`, keyed on the normalized
+/// `TableParams`. Requests without valid table parameters pass through, and
+/// so do those for a protein the caller may not see, for the handler to
+/// refuse: a cached response is the same for everyone allowed to see it.
+pub async fn cache_responses(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Ok(Query(params)) = Query::<TableParams>::try_from_uri(request.uri()) else {
+        return next.run(request).await;
+    };
+    let scope = request
+        .extensions()
+        .get::<Scope>()
+        .copied()
+        .unwrap_or_default();
+    if !matches!(
+        state
+            .repository
+            .protein_visible(scope, &params.protein)
+            .await,
+        Ok(true)
+    ) {
+        return next.run(request).await;
+    }
+    let key = CacheKey {
+        request: format!("{}?{}", request.uri().path(), params.cache_key()),
+        protein: params.protein,
+        condition: params.condition,
+    };
+    if let Some(cached) = state.cache.get(&key) {
+        let mut response = Response::new(Body::from(cached.body));
+        *response.status_mut() = cached.status;
+        *response.headers_mut() = cached.headers;
+        return response;
+    }
+    let response = next.run(request).await;
+    if !response.status().is_success() {
+        return response;
+    }
+    let (parts, body) = response.into_parts();
+    let body = match to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(err) => {
+            warn!("could not buffer response for the cache: {err}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    state.cache.insert(
+        key,
+        CachedResponse {
+            status: parts.status,
+            headers: parts.headers.clone(),
+            body: body.clone(),
+        },
+    );
+    Response::from_parts(parts, Body::from(body))
+}
+
+#[derive(Deserialize)]
+struct IngestNotification {
+    protein: Option<String>,
+    condition: Option<String>,
+    revision: Option<u64>,
+}
+
+/// Follows the `variant_ingest` channel: drops cached responses for every
+/// (protein, condition) the database reports as changed and moves the
+/// dataset revision forward. After a reconnect anything may have been
+/// missed, so the whole cache goes.
+pub async fn invalidate_cache_on_ingest(state: AppState, pool: PgPool) {
+    loop {
+        let mut listener = match PgListener::connect_with(&pool).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                warn!("could not listen for ingest notifications: {err}");
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                continue;
+            }
+        };
+        if let Err(err) = listener.listen("variant_ingest").await {
+            warn!("could not listen for ingest notifications: {err}");
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            continue;
+        }
+        match state.repository.dataset_revision().await {
+            Ok(revision) => {
+                if state.revision.swap(revision, Ordering::AcqRel) != revision {
+                    state.cache.clear();
+                }
+            }
+            Err(err) => warn!("could not read the dataset revision: {err}"),
+        }
+        loop {
+            match listener.recv().await {
+                Ok(notification) => {
+                    match serde_json::from_str::<IngestNotification>(notification.payload()) {
+                        Ok(ingest) => {
+                            info!(
+                                "invalidating cache for {:?} in {:?}",
+                                ingest.protein, ingest.condition
+                            );
+                            match ingest.condition {
+                                Some(condition) => state
+                                    .cache
+                                    .invalidate(ingest.protein.as_deref(), &condition),
+                                None => state.cache.clear(),
+                            }
+                            if let Some(revision) = ingest.revision {
+                                state.revision.fetch_max(revision, Ordering::AcqRel);
+                            }
+                        }
+                        Err(err) => warn!("unreadable ingest notification: {err}"),
+                    }
+                }
+                Err(err) => {
+                    warn!("lost ingest notifications: {err}");
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// `invalidate_cache_on_ingest` for databases without notifications: checks
+/// the dataset revision every few seconds and drops the whole cache when it
+/// moved, whoever wrote the change.
+pub async fn poll_dataset_revision(state: AppState) {
+    let mut interval = tokio::time::interval(Duration::from_secs(2));
+    loop {
+        interval.tick().await;
+        match state.repository.dataset_revision().await {
+            Ok(revision) => {
+                if state.revision.swap(revision, Ordering::AcqRel) != revision {
+                    info!("dataset revision {revision}, invalidating cache");
+                    state.cache.clear();
+                }
+            }
+            Err(err) => warn!("could not read the dataset revision: {err}"),
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use serde::{Deserialize, Serialize};
+
+use crate::matrix::VariantMatrix;
+use crate::{wild_type_at, Variant, GROUPED_AMINO_ACIDS};
+
+/// Physicochemical classes, following the blocks of `GROUPED_AMINO_ACIDS`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResidueClass {
+    Hydrophobic,
+    Special,
+    Polar,
+    NegativelyCharged,
+    PositivelyCharged,
+    Stop,
+}
+
+impl ResidueClass {
+    pub const ALL: [ResidueClass; 6] = [
+        ResidueClass::Hydrophobic,
+        ResidueClass::Special,
+        ResidueClass::Polar,
+        ResidueClass::NegativelyCharged,
+        ResidueClass::PositivelyCharged,
+        ResidueClass::Stop,
+    ];
+
+    pub fn of(aa: &str) -> Option<Self> {
+        let class = match aa {
+            "I" | "V" | "L" | "F" | "C" | "M" | "A" | "W" => ResidueClass::Hydrophobic,
+            "G" | "P" => ResidueClass::Special,
+            "T" | "S" | "Y" | "H" | "N" | "Q" => ResidueClass::Polar,
+            "D" | "E" => ResidueClass::NegativelyCharged,
+            "K" | "R" => ResidueClass::PositivelyCharged,
+            "*" => ResidueClass::Stop,
+            _ => return None,
+        };
+        Some(class)
+    }
+
+    fn index(&self) -> usize {
+        ResidueClass::ALL.iter().position(|c| c == self).unwrap()
+    }
+}
+
+impl std::fmt::Display for ResidueClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let output = match self {
+            ResidueClass::Hydrophobic => "hydrophobic",
+            ResidueClass::Special => "special",
+            ResidueClass::Polar => "polar",
+            ResidueClass::NegativelyCharged => "negative",
+            ResidueClass::PositivelyCharged => "positive",
+            ResidueClass::Stop => "stop",
+        };
+        write!(f, "{}", output)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct CellSummary {
+    pub mean: Option<f64>,
+    pub count: usize,
+}
+
+#[derive(Default)]
+struct Accumulator {
+    sum: f64,
+    count: usize,
+}
+
+impl Accumulator {
+    fn add(&mut self, value: f64) {
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn summary(&self) -> CellSummary {
+        CellSummary {
+            mean: (self.count > 0).then(|| self.sum / self.count as f64),
+            count: self.count,
+        }
+    }
+}
+
+/// Mean log2 fold change per wild-type → mutant pair. Rows are wild-type
+/// residues and columns mutant residues, both in `GROUPED_AMINO_ACIDS` order;
+/// rows skip the stop codon.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SubstitutionMatrix {
+    pub wild_types: Vec<String>,
+    pub mutants: Vec<String>,
+    pub cells: Vec<Vec<CellSummary>>,
+}
+
+/// The same summary collapsed onto `ResidueClass`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ClassSummary {
+    pub classes: Vec<ResidueClass>,
+    pub cells: Vec<Vec<CellSummary>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SubstitutionSummary {
+    pub protein: String,
+    pub condition: String,
+    pub matrix: SubstitutionMatrix,
+    pub classes: ClassSummary,
+}
+
+impl SubstitutionSummary {
+    /// Variants whose position falls outside `sequence`, or whose residues
+    /// are not one of the 21 codes, are skipped, and so are synonymous ones,
+    /// which substitute nothing; a duplicated (pos, aa) cell counts once.
+    pub fn compute(protein: &str, condition: &str, variants: &[Variant], sequence: &str) -> Self {
+        let wild_types: Vec<&str> = GROUPED_AMINO_ACIDS
+            .iter()
+            .copied()
+            .filter(|aa| *aa != "*")
+            .collect();
+        let mut residues = accumulators(wild_types.len(), GROUPED_AMINO_ACIDS.len());
+        let mut classes = accumulators(ResidueClass::ALL.len(), ResidueClass::ALL.len());
+        for variant in VariantMatrix::from_variants(variants).iter() {
+            let Some(wild_type) = wild_type_at(sequence, variant.pos) else {
+                continue;
+            };
+            let wild_type = wild_type.to_string();
+            if variant.aa == wild_type {
+                continue;
+            }
+            let (Some(row), Some(col)) = (
+                wild_types.iter().position(|aa| *aa == wild_type),
+                GROUPED_AMINO_ACIDS.iter().position(|aa| *aa == variant.aa),
+            ) else {
+                continue;
+            };
+            residues[row][col].add(variant.log2_fold_change);
+            if let (Some(from), Some(to)) =
+                (ResidueClass::of(&wild_type), ResidueClass::of(&variant.aa))
+            {
+                classes[from.index()][to.index()].add(variant.log2_fold_change);
+            }
+        }
+        Self {
+            protein: protein.to_string(),
+            condition: condition.to_string(),
+            matrix: SubstitutionMatrix {
+                wild_types: wild_types.iter().map(|aa| aa.to_string()).collect(),
+                mutants: GROUPED_AMINO_ACIDS
+                    .iter()
+                    .map(|aa| aa.to_string())
+                    .collect(),
+                cells: summarize(&residues),
+            },
+            classes: ClassSummary {
+                classes: ResidueClass::ALL.to_vec(),
+                cells: summarize(&classes),
+            },
+        }
+    }
+
+    /// Largest absolute mean over both tables, for a shared color scale.
+    pub fn max_abs(&self) -> f64 {
+        self.matrix
+            .cells
+            .iter()
+            .chain(&self.classes.cells)
+            .flatten()
+            .filter_map(|cell| cell.mean)
+            .fold(0.0, |acc, mean| acc.max(mean.abs()))
+    }
+}
+
+fn accumulators(rows: usize, cols: usize) -> Vec<Vec<Accumulator>> {
+    (0..rows)
+        .map(|_| (0..cols).map(|_| Accumulator::default()).collect())
+        .collect()
+}
+
+fn summarize(table: &[Vec<Accumulator>]) -> Vec<Vec<CellSummary>> {
+    table
+        .iter()
+        .map(|row| row.iter().map(Accumulator::summary).collect())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variant(pos: i32, aa: &str, log2_fold_change: f64) -> Variant {
+        Variant {
+            id: None,
+            chunk: 1,
+            pos,
+            condition: "c1".to_string(),
+            aa: aa.to_string(),
+            log2_fold_change,
+            log2_std_error: 0.1,
+            statistic: log2_fold_change / 0.1,
+            p_value: 0.5,
+            version: "v1".to_string(),
+            protein: "P1".to_string(),
+            created_on: Default::default(),
+        }
+    }
+
+    fn cell(summary: &SubstitutionSummary, wild_type: &str, mutant: &str) -> CellSummary {
+        let matrix = &summary.matrix;
+        let row = matrix.wild_types.iter().position(|aa| aa == wild_type);
+        let col = matrix.mutants.iter().position(|aa| aa == mutant);
+        matrix.cells[row.unwrap()][col.unwrap()]
+    }
+
+    fn class_cell(
+        summary: &SubstitutionSummary,
+        from: ResidueClass,
+        to: ResidueClass,
+    ) -> CellSummary {
+        summary.classes.cells[from.index()][to.index()]
+    }
+
+    #[test]
+    fn averages_each_wild_type_to_mutant_pair() {
+        let variants = [
+            variant(1, "A", -1.0),
+            // Only the first variant of a cell counts
+            variant(1, "A", 9.0),
+            variant(1, "M", 0.5),
+            variant(1, "X", 0.5),
+            variant(2, "*", -4.0),
+            variant(3, "K", 2.0),
+            variant(4, "A", -3.0),
+            variant(5, "A", 1.0),
+            variant(6, "A", 1.0),
+        ];
+        let summary = SubstitutionSummary::compute("P1", "c1", &variants, "MKDM*");
+        assert_eq!(summary.matrix.wild_types.len(), 20);
+        assert_eq!(summary.matrix.mutants.len(), 21);
+
+        let m_to_a = cell(&summary, "M", "A");
+        assert_eq!((m_to_a.mean, m_to_a.count), (Some(-2.0), 2));
+        let k_to_stop = cell(&summary, "K", "*");
+        assert_eq!((k_to_stop.mean, k_to_stop.count), (Some(-4.0), 1));
+        let d_to_k = cell(&summary, "D", "K");
+        assert_eq!((d_to_k.mean, d_to_k.count), (Some(2.0), 1));
+        let counted: usize = summary.matrix.cells.iter().flatten().map(|c| c.count).sum();
+        assert_eq!(counted, 4);
+
+        let class = class_cell(
+            &summary,
+            ResidueClass::Hydrophobic,
+            ResidueClass::Hydrophobic,
+        );
+        assert_eq!((class.mean, class.count), (Some(-2.0), 2));
+        let class = class_cell(
+            &summary,
+            ResidueClass::PositivelyCharged,
+            ResidueClass::Stop,
+        );
+        assert_eq!((class.mean, class.count), (Some(-4.0), 1));
+        let class = class_cell(
+            &summary,
+            ResidueClass::NegativelyCharged,
+            ResidueClass::PositivelyCharged,
+        );
+        assert_eq!((class.mean, class.count), (Some(2.0), 1));
+        assert_eq!(summary.max_abs(), 4.0);
+    }
+
+    #[test]
+    fn skips_synonymous_variants_and_stop_codons_in_the_sequence() {
+        let variants = [variant(1, "M", 0.5), variant(2, "A", -1.0)];
+        let summary = SubstitutionSummary::compute("P1", "c1", &variants, "M*");
+        // The synonymous M → M leaves the diagonal empty
+        let synonymous = cell(&summary, "M", "M");
+        assert_eq!((synonymous.mean, synonymous.count), (None, 0));
+        // A stop in the sequence is not a wild-type row
+        assert!(!summary.matrix.wild_types.contains(&"*".to_string()));
+        assert!(summary
+            .classes
+            .cells
+            .iter()
+            .flatten()
+            .all(|cell| cell.count == 0 && cell.mean.is_none()));
+        assert_eq!(summary.max_abs(), 0.0);
+    }
+}
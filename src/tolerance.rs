@@ -0,0 +1,192 @@
+use serde::{Deserialize, Serialize};
+
+use crate::matrix::VariantMatrix;
+use crate::{wild_type_at, MutationType, Variant};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToleranceMetric {
+    #[serde(alias = "mean_effect")]
+    MeanEffect,
+    #[serde(alias = "fraction_tolerated")]
+    FractionTolerated,
+    #[serde(alias = "entropy")]
+    Entropy,
+}
+
+impl ToleranceMetric {
+    pub const ALL: [ToleranceMetric; 3] = [
+        ToleranceMetric::MeanEffect,
+        ToleranceMetric::FractionTolerated,
+        ToleranceMetric::Entropy,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ToleranceMetric::MeanEffect => "Mean missense log2FC",
+            ToleranceMetric::FractionTolerated => "Fraction tolerated",
+            ToleranceMetric::Entropy => "Fitness entropy",
+        }
+    }
+}
+
+impl std::fmt::Display for ToleranceMetric {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let output = match self {
+            ToleranceMetric::MeanEffect => "MeanEffect",
+            ToleranceMetric::FractionTolerated => "FractionTolerated",
+            ToleranceMetric::Entropy => "Entropy",
+        };
+        write!(f, "{}", output)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct PositionTolerance {
+    pub pos: i32,
+    /// Number of missense substitutions measured at this position.
+    pub missense: usize,
+    /// Mean log2 fold change over missense substitutions.
+    pub mean_effect: f64,
+    /// Share of missense substitutions with log2FC above `-tolerated_cutoff`.
+    pub fraction_tolerated: f64,
+    /// Shannon entropy of the fitness distribution (2^log2FC, normalized to
+    /// sum to one) over missense and synonymous substitutions, divided by
+    /// log2(n) so it lies in [0, 1]. Evenly fit positions score close to 1.
+    pub entropy: f64,
+}
+
+impl PositionTolerance {
+    pub fn value(&self, metric: ToleranceMetric) -> f64 {
+        match metric {
+            ToleranceMetric::MeanEffect => self.mean_effect,
+            ToleranceMetric::FractionTolerated => self.fraction_tolerated,
+            ToleranceMetric::Entropy => self.entropy,
+        }
+    }
+}
+
+/// Per-position tolerance scores, ordered by position. Positions without any
+/// missense measurement are left out. Nonsense variants never count, and
+/// only the first variant of a duplicated (pos, aa) cell does.
+pub fn position_tolerance(
+    variants: &[Variant],
+    sequence: Option<&str>,
+    tolerated_cutoff: f64,
+) -> Vec<PositionTolerance> {
+    let matrix = VariantMatrix::from_variants(variants);
+    matrix
+        .rows()
+        .filter_map(|(pos, row)| {
+            let wild_type = sequence.and_then(|sequence| wild_type_at(sequence, pos));
+            let mut missense = Vec::new();
+            let mut synonymous = Vec::new();
+            for variant in row.iter().flatten() {
+                match MutationType::of(&variant.aa, wild_type) {
+                    MutationType::Missense => missense.push(variant.log2_fold_change),
+                    MutationType::Synonymous => synonymous.push(variant.log2_fold_change),
+                    MutationType::Nonsense => {}
+                }
+            }
+            if missense.is_empty() {
+                return None;
+            }
+            let n = missense.len() as f64;
+            let tolerated = missense
+                .iter()
+                .filter(|&&effect| effect > -tolerated_cutoff)
+                .count();
+            Some(PositionTolerance {
+                pos,
+                missense: missense.len(),
+                mean_effect: missense.iter().sum::<f64>() / n,
+                fraction_tolerated: tolerated as f64 / n,
+                entropy: normalized_entropy(missense.iter().chain(&synonymous)),
+            })
+        })
+        .collect()
+}
+
+fn normalized_entropy<'a>(effects: impl Iterator<Item = &'a f64>) -> f64 {
+    let fitness: Vec<f64> = effects.map(|effect| effect.exp2()).collect();
+    let total: f64 = fitness.iter().sum();
+    if fitness.len() < 2 || total <= 0.0 || !total.is_finite() {
+        return 0.0;
+    }
+    let entropy: f64 = fitness
+        .iter()
+        .map(|f| f / total)
+        .filter(|&p| p > 0.0)
+        .map(|p| -p * p.log2())
+        .sum();
+    entropy / (fitness.len() as f64).log2()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variant(pos: i32, aa: &str, log2_fold_change: f64) -> Variant {
+        Variant {
+            id: None,
+            chunk: 1,
+            pos,
+            condition: "c1".to_string(),
+            aa: aa.to_string(),
+            log2_fold_change,
+            log2_std_error: 0.1,
+            statistic: log2_fold_change / 0.1,
+            p_value: 0.5,
+            version: "v1".to_string(),
+            protein: "P1".to_string(),
+            created_on: Default::default(),
+        }
+    }
+
+    #[test]
+    fn scores_missense_substitutions_per_position() {
+        let variants = [
+            variant(1, "A", -1.0),
+            variant(1, "C", 1.0),
+            variant(1, "M", 0.0),
+            variant(1, "*", -3.0),
+            // Nothing but a stop codon, then nothing at all at 4
+            variant(2, "*", -3.0),
+            variant(3, "A", -2.0),
+            // Only the first variant of a cell counts
+            variant(3, "A", 5.0),
+            variant(5, "D", 0.0),
+            variant(5, "E", 0.0),
+        ];
+        let scores = position_tolerance(&variants, Some("MKTAY"), 0.5);
+        let positions: Vec<i32> = scores.iter().map(|score| score.pos).collect();
+        assert_eq!(positions, [1, 3, 5]);
+
+        // Missense -1 and 1; the synonymous M joins them only for the
+        // entropy, over fitness 0.5, 2 and 1
+        let first = scores[0];
+        assert_eq!(first.missense, 2);
+        assert_eq!(first.mean_effect, 0.0);
+        assert_eq!(first.fraction_tolerated, 0.5);
+        assert!((first.entropy - 0.8699155297736259).abs() < 1e-12);
+
+        // A single variant has nothing to spread over
+        let single = scores[1];
+        assert_eq!(single.missense, 1);
+        assert_eq!(single.mean_effect, -2.0);
+        assert_eq!(single.fraction_tolerated, 0.0);
+        assert_eq!(single.entropy, 0.0);
+
+        // Equal fitness is as even as it gets
+        assert_eq!(scores[2].fraction_tolerated, 1.0);
+        assert!((scores[2].entropy - 1.0).abs() < 1e-12);
+        assert_eq!(scores[2].value(ToleranceMetric::Entropy), scores[2].entropy);
+    }
+
+    #[test]
+    fn without_a_sequence_every_substitution_is_missense() {
+        let variants = [variant(1, "M", 0.0), variant(1, "A", -1.0)];
+        assert_eq!(position_tolerance(&variants, None, 0.5)[0].missense, 2);
+        assert_eq!(position_tolerance(&variants, Some("M"), 0.5)[0].missense, 1);
+        assert!(position_tolerance(&[], None, 0.5).is_empty());
+    }
+}
@@ -0,0 +1,232 @@
+use crate::{error::AppError, TableParams};
+
+/// Most suggestions offered for an unknown name.
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Levenshtein distance between `a` and `b`, ignoring case.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.to_lowercase().chars().collect();
+    let b: Vec<char> = b.to_lowercase().chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for (i, a_char) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != b_char);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
+
+/// Candidates close to `name`, nearest first: those within a few edits, or
+/// that contain it ignoring case.
+pub fn suggestions<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    let needle = name.to_lowercase();
+    let max_distance = (name.chars().count() / 3).max(2);
+    let mut close: Vec<(usize, &str)> = candidates
+        .into_iter()
+        .filter_map(|candidate| {
+            let distance = edit_distance(name, candidate);
+            let contains = !needle.is_empty() && candidate.to_lowercase().contains(&needle);
+            (distance <= max_distance || contains).then_some((distance, candidate))
+        })
+        .collect();
+    close.sort();
+    close.dedup();
+    close
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, candidate)| candidate.to_string())
+        .collect()
+}
+
+/// `NotFound`-style error for `name`, suggesting the closest `candidates`.
+pub fn unknown<'a>(
+    what: &'static str,
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> AppError {
+    AppError::Unknown {
+        what,
+        name: name.to_string(),
+        suggestions: suggestions(name, candidates),
+    }
+}
+
+pub fn required(what: &str, value: &str) -> Result<(), AppError> {
+    if value.trim().is_empty() {
+        return Err(AppError::BadRequest(format!("missing {what}")));
+    }
+    Ok(())
+}
+
+pub fn finite(name: &str, value: Option<f64>) -> Result<(), AppError> {
+    match value {
+        Some(value) if !value.is_finite() => Err(AppError::BadRequest(format!(
+            "{name} must be a finite number, got {value}"
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Number of `page_size` pages needed to show positions `1..=max_pos`.
+pub fn page_count(max_pos: i32, page_size: i32) -> i32 {
+    (max_pos.max(0) + page_size - 1) / page_size
+}
+
+pub fn page_in_range(page: i32, max_pos: i32, page_size: i32) -> Result<(), AppError> {
+    let pages = page_count(max_pos, page_size);
+    if page < 1 || page > pages {
+        return Err(AppError::BadRequest(format!(
+            "page {page} is out of range, expected 1 to {pages}"
+        )));
+    }
+    Ok(())
+}
+
+/// Checks on `TableParams` that don't need the database. Whether the protein,
+/// condition and page exist is up to the caller.
+pub fn table_params(params: &TableParams) -> Result<(), AppError> {
+    required("protein", &params.protein)?;
+    required("condition", &params.condition)?;
+    finite("threshold", params.threshold)?;
+    finite("effect_cutoff", params.effect_cutoff)?;
+    finite("p_cutoff", params.p_cutoff)?;
+    finite("posterior_cutoff", params.posterior_cutoff)?;
+    finite("gain_z", params.gain_z)?;
+    match params.page {
+        Some(page) if page < 1 => Err(AppError::BadRequest(format!(
+            "page must be at least 1, got {page}"
+        ))),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Paint, PositionFilter};
+
+    fn params() -> TableParams {
+        TableParams {
+            protein: "P1".to_string(),
+            condition: "c1".to_string(),
+            position_filter: PositionFilter::NoOrder,
+            paint: Paint::PValue,
+            operation: None,
+            threshold: None,
+            page: None,
+            plot: None,
+            classifier: None,
+            effect_cutoff: None,
+            p_cutoff: None,
+            posterior_cutoff: None,
+            gain_z: None,
+            tolerance_metric: None,
+            label_top: None,
+        }
+    }
+
+    fn bad_request(result: Result<(), AppError>) -> String {
+        match result {
+            Err(AppError::BadRequest(message)) => message,
+            other => panic!("expected a bad request, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn edit_distance_ignores_case() {
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("GLP1R", "glp1r"), 0);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("abc", ""), 3);
+    }
+
+    #[test]
+    fn suggests_near_misses_nearest_first() {
+        let candidates = ["INSR", "glp2r", "GCGR", "GLP1R", "GLP1R", "ADRB2"];
+        assert_eq!(suggestions("GLP1", candidates), ["GLP1R", "glp2r"]);
+        // Containing the name counts however far apart they are
+        assert_eq!(suggestions("lp", ["GLP1R_HUMAN"]), ["GLP1R_HUMAN"]);
+        assert!(suggestions("TP53", candidates).is_empty());
+        assert!(suggestions("", ["LONGNAME"]).is_empty());
+
+        let many = ["c1", "c2", "c3", "c4", "c10"];
+        assert_eq!(suggestions("c", many), ["c1", "c2", "c3"]);
+
+        let AppError::Unknown {
+            what,
+            name,
+            suggestions,
+        } = unknown("protein", "GLP1", candidates)
+        else {
+            panic!("expected an unknown name");
+        };
+        assert_eq!((what, name.as_str()), ("protein", "GLP1"));
+        assert_eq!(suggestions, ["GLP1R", "glp2r"]);
+    }
+
+    #[test]
+    fn pages_must_hold_positions() {
+        assert_eq!(page_count(0, 50), 0);
+        assert_eq!(page_count(50, 50), 1);
+        assert_eq!(page_count(51, 50), 2);
+        assert!(page_in_range(1, 51, 50).is_ok());
+        assert!(page_in_range(2, 51, 50).is_ok());
+        assert_eq!(
+            bad_request(page_in_range(3, 51, 50)),
+            "page 3 is out of range, expected 1 to 2"
+        );
+        assert!(page_in_range(0, 51, 50).is_err());
+        // Nothing to page through
+        assert!(page_in_range(1, 0, 50).is_err());
+    }
+
+    #[test]
+    fn table_params_need_names_finite_numbers_and_a_positive_page() {
+        assert!(table_params(&params()).is_ok());
+        assert_eq!(
+            bad_request(table_params(&TableParams {
+                protein: " ".to_string(),
+                ..params()
+            })),
+            "missing protein"
+        );
+        assert_eq!(
+            bad_request(table_params(&TableParams {
+                condition: String::new(),
+                ..params()
+            })),
+            "missing condition"
+        );
+        assert_eq!(
+            bad_request(table_params(&TableParams {
+                threshold: Some(f64::NAN),
+                ..params()
+            })),
+            "threshold must be a finite number, got NaN"
+        );
+        assert_eq!(
+            bad_request(table_params(&TableParams {
+                gain_z: Some(f64::INFINITY),
+                ..params()
+            })),
+            "gain_z must be a finite number, got inf"
+        );
+        assert_eq!(
+            bad_request(table_params(&TableParams {
+                page: Some(0),
+                ..params()
+            })),
+            "page must be at least 1, got 0"
+        );
+        assert!(table_params(&TableParams {
+            page: Some(1),
+            effect_cutoff: Some(-1.0),
+            ..params()
+        })
+        .is_ok());
+    }
+}
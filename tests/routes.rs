@@ -0,0 +1,1409 @@
+//! Every route of the `server` binary against a throwaway database.
+//!
+//! `sqlx::test` creates a fresh, migrated database per test on the Postgres
+//! at `DATABASE_URL` (the user needs `CREATEDB`). The fixtures in
+//! `tests/fixtures/` are loaded through `ingest`, the same path uploads and
+//! `deepscan-admin import` take, then the server is started against that
+//! database on a free port:
+//!
+//! ```bash
+//! DATABASE_URL=postgres://postgres@localhost/postgres cargo test --test routes
+//! ```
+//!
+//! Expected table contents come from `MemoryRepository` holding the same
+//! variants, so the SQL and the in-memory semantics are checked against each
+//! other.
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use dms_viewer::access::Role;
+use dms_viewer::audit::Actor;
+use dms_viewer::auth;
+use dms_viewer::classify::{self, ClassCounts};
+use dms_viewer::ingest::{self, SourceFile};
+use dms_viewer::matrix::VariantMatrix;
+use dms_viewer::repository::{MemoryRepository, PostgresRepository, Repository};
+use dms_viewer::tolerance;
+use dms_viewer::{Paint, PositionFilter, TableParams, Variant};
+use reqwest::StatusCode;
+use sqlx::PgPool;
+
+/// Twelve positions with every amino acid in `c1`, six positions with five in
+/// `c2` (version `v2`) and one unparseable row.
+const ALPHA: &str = "alpha.tsv";
+/// Three positions in `c1` and no sequence, so no substitution summary.
+const BETA: &str = "beta.tsv";
+const ALPHA_SEQUENCE: &str = "MKTAYIAKQRQI";
+
+const FILTERS: [PositionFilter; 4] = [
+    PositionFilter::NoOrder,
+    PositionFilter::MostSignificantPValue,
+    PositionFilter::LargestLog2FoldChange,
+    PositionFilter::LargestZStatistic,
+];
+const PAINTS: [Paint; 4] = [
+    Paint::Log2FoldChange,
+    Paint::PValue,
+    Paint::ZStatistic,
+    Paint::Classification,
+];
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+/// Reads a fixture the way an upload does.
+fn read_fixture(name: &str, protein: &str) -> (Vec<Variant>, usize) {
+    let (variants, _, source) = read_source(name, protein);
+    (variants, source.rows_rejected as usize)
+}
+
+fn read_source(name: &str, protein: &str) -> (Vec<Variant>, Vec<csv::Error>, SourceFile) {
+    let data = std::fs::read(fixture(name)).unwrap();
+    ingest::read_source(name, &data, protein)
+}
+
+/// Loads both fixtures into `pool` and the same variants into a
+/// `MemoryRepository`, which the tests use as the expected answer. Both
+/// proteins are public and outside any project.
+async fn load_fixtures(pool: &PgPool) -> MemoryRepository {
+    let postgres = PostgresRepository::new(pool.clone());
+    let memory = MemoryRepository::new();
+    for (protein, file, pdb_id, sequence) in [
+        ("ALPHA", ALPHA, Some("7ki0"), Some(ALPHA_SEQUENCE)),
+        ("BETA", BETA, None, None),
+    ] {
+        let id = postgres.create_protein(protein, pdb_id, sequence).await;
+        assert!(id.unwrap().is_some(), "{protein} already exists");
+        assert!(postgres
+            .set_protein_access(protein, None, true)
+            .await
+            .unwrap());
+        memory.add_protein(protein, sequence);
+        let (variants, _, source) = read_source(file, protein);
+        let actor = Actor::command_line("fixtures");
+        let batch = ingest::insert(pool, &variants, protein, &source, &actor)
+            .await
+            .unwrap();
+        assert_eq!(batch.rows_inserted, variants.len() as i64);
+        memory
+            .insert_variants(protein, &variants, &source, &actor)
+            .await
+            .unwrap();
+    }
+    memory
+}
+
+/// The `server` binary on a free port, killed when dropped.
+struct Server {
+    child: Child,
+    base: String,
+    log: PathBuf,
+    client: reqwest::Client,
+}
+
+impl Server {
+    /// Starts the server against the database behind `pool` and waits until
+    /// `/readyz` answers 200.
+    async fn start(pool: &PgPool) -> Self {
+        Self::start_with(pool, &[]).await
+    }
+
+    /// `start` with extra environment variables.
+    async fn start_with(pool: &PgPool, env: &[(&str, &str)]) -> Self {
+        let mut url = url::Url::parse(&std::env::var("DATABASE_URL").unwrap()).unwrap();
+        url.set_path(pool.connect_options().get_database().unwrap());
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let log = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("server-{port}.log"));
+        // A clean environment and working directory, so no `.env`,
+        // `dms-viewer.toml` or stray variable changes the configuration
+        let child = Command::new(env!("CARGO_BIN_EXE_server"))
+            .env_clear()
+            .current_dir(env!("CARGO_TARGET_TMPDIR"))
+            .env("DATABASE_URL", url.as_str())
+            .env("BIND_ADDRESS", format!("127.0.0.1:{port}"))
+            .env(
+                "ASSETS_DIR",
+                Path::new(env!("CARGO_MANIFEST_DIR")).join("assets"),
+            )
+            .env("DB_MAX_CONNECTIONS", "4")
+            .envs(env.iter().copied())
+            .stdout(File::create(&log).unwrap())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let mut server = Self {
+            child,
+            base: format!("http://127.0.0.1:{port}"),
+            log,
+            client: reqwest::Client::new(),
+        };
+        for _ in 0..100 {
+            if let Some(status) = server.child.try_wait().unwrap() {
+                panic!("server exited with {status}:\n{}", server.log());
+            }
+            if let Ok(response) = server.client.get(server.url("/readyz")).send().await {
+                if response.status() == StatusCode::OK {
+                    return server;
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("server did not become ready:\n{}", server.log());
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.base)
+    }
+
+    fn log(&self) -> String {
+        std::fs::read_to_string(&self.log).unwrap_or_default()
+    }
+
+    async fn get(&self, path: &str) -> (StatusCode, String) {
+        let response = self.client.get(self.url(path)).send().await.unwrap();
+        (response.status(), response.text().await.unwrap())
+    }
+
+    /// `get` with extra headers, e.g. a session cookie or an API token.
+    async fn get_as(&self, path: &str, headers: &[(&str, &str)]) -> (StatusCode, String) {
+        let mut request = self.client.get(self.url(path));
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let response = request.send().await.unwrap();
+        (response.status(), response.text().await.unwrap())
+    }
+
+    /// Posts an urlencoded form.
+    async fn post_form(
+        &self,
+        path: &str,
+        form: &str,
+        headers: &[(&str, &str)],
+    ) -> reqwest::Response {
+        let mut request = self
+            .client
+            .post(self.url(path))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(form.to_string());
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        request.send().await.unwrap()
+    }
+
+    /// Posts `body` as JSON and parses the JSON response.
+    async fn post_json(
+        &self,
+        path: &str,
+        body: &serde_json::Value,
+        bearer: Option<&str>,
+    ) -> (StatusCode, serde_json::Value) {
+        let mut request = self
+            .client
+            .post(self.url(path))
+            .header("Content-Type", "application/json")
+            .body(body.to_string());
+        if let Some(bearer) = bearer {
+            request = request.header("Authorization", bearer);
+        }
+        let response = request.send().await.unwrap();
+        let status = response.status();
+        let text = response.text().await.unwrap();
+        (status, serde_json::from_str(&text).unwrap_or_default())
+    }
+
+    /// Logs in and returns the `Cookie` header for the new session.
+    async fn login(&self, username: &str, password: &str) -> String {
+        let response = self
+            .post_form(
+                "/login",
+                &format!("username={username}&password={password}&next=/account"),
+                &[],
+            )
+            .await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(response.headers()["HX-Redirect"], "/account");
+        let cookie = response.headers()["Set-Cookie"].to_str().unwrap();
+        assert!(cookie.contains("HttpOnly"), "{cookie}");
+        cookie.split(';').next().unwrap().to_string()
+    }
+
+    /// `get`, failing the test unless the status is `expected`.
+    async fn expect(&self, path: &str, expected: StatusCode) -> String {
+        let (status, body) = self.get(path).await;
+        assert_eq!(status, expected, "GET {path}:\n{body}");
+        body
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn params(
+    protein: &str,
+    condition: &str,
+    position_filter: PositionFilter,
+    paint: Paint,
+    threshold: Option<f64>,
+) -> TableParams {
+    TableParams {
+        protein: protein.to_string(),
+        condition: condition.to_string(),
+        position_filter,
+        paint,
+        operation: None,
+        threshold,
+        page: None,
+        plot: None,
+        classifier: None,
+        effect_cutoff: None,
+        p_cutoff: None,
+        posterior_cutoff: None,
+        gain_z: None,
+        tolerance_metric: None,
+        label_top: None,
+    }
+}
+
+fn query(params: &TableParams) -> String {
+    let mut query = format!(
+        "protein={}&condition={}&position_filter={}&paint={}",
+        params.protein, params.condition, params.position_filter, params.paint
+    );
+    if let Some(threshold) = params.threshold {
+        query.push_str(&format!("&threshold={threshold}"));
+    }
+    query
+}
+
+/// A threshold that keeps some but not all variants of the fixtures.
+fn threshold_for(paint: Paint) -> f64 {
+    match paint {
+        Paint::PValue => 0.3,
+        Paint::Log2FoldChange => 0.5,
+        Paint::ZStatistic => 2.0,
+        Paint::Classification => 0.5,
+    }
+}
+
+/// `(id, title)` of every table cell holding a variant, e.g.
+/// `("3A", "log2FC: 0.123, 3A")`.
+fn data_cells(body: &str) -> BTreeSet<(String, String)> {
+    body.split("<td")
+        .filter(|cell| cell.contains(r#"class="dms-cell-data dms-cell""#))
+        .map(|cell| (attribute(cell, "id"), attribute(cell, "title")))
+        .collect()
+}
+
+fn attribute(element: &str, name: &str) -> String {
+    let start = element
+        .find(&format!(r#" {name}=""#))
+        .unwrap_or_else(|| panic!("no {name} in {element}"))
+        + name.len()
+        + 3;
+    let end = start + element[start..].find('"').unwrap();
+    element[start..end].to_string()
+}
+
+fn expected_cells(variants: &[Variant]) -> BTreeSet<(String, String)> {
+    variants
+        .iter()
+        .map(|variant| {
+            let id = format!("{}{}", variant.pos, variant.aa);
+            let title = format!("log2FC: {:.3}, {id}", variant.log2_fold_change);
+            (id, title)
+        })
+        .collect()
+}
+
+#[test]
+fn fixtures_parse_with_one_bad_row() {
+    let (alpha, alpha_errors) = read_fixture(ALPHA, "ALPHA");
+    assert_eq!((alpha.len(), alpha_errors), (12 * 21 + 6 * 5, 1));
+    let (beta, beta_errors) = read_fixture(BETA, "BETA");
+    assert_eq!((beta.len(), beta_errors), (9, 0));
+}
+
+#[sqlx::test]
+async fn proteins_and_conditions(pool: PgPool) {
+    load_fixtures(&pool).await;
+    let server = Server::start(&pool).await;
+
+    let proteins = server.expect("/proteins", StatusCode::OK).await;
+    assert!(proteins.contains(r#"<option value="ALPHA">ALPHA</option>"#));
+    assert!(proteins.contains(r#"<option value="BETA">BETA</option>"#));
+    assert!(proteins.contains(r#"id="protein-select""#));
+
+    let response = server
+        .client
+        .get(server.url("/conditions?protein=ALPHA"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()["HX-Trigger-After-Settle"],
+        "load-condition"
+    );
+    let conditions = response.text().await.unwrap();
+    assert!(conditions
+        .starts_with(r#"<option value="c1">c1</option><option value="c2">c2</option><script>"#));
+    let beta = server
+        .expect("/conditions?protein=BETA", StatusCode::OK)
+        .await;
+    assert!(!beta.contains("c2"));
+
+    let unknown = server
+        .expect("/conditions?protein=ALPA", StatusCode::NOT_FOUND)
+        .await;
+    assert!(unknown.contains(r#"class="error-message""#));
+    assert!(unknown.contains("did you mean ALPHA?"));
+    server.expect("/conditions", StatusCode::BAD_REQUEST).await;
+}
+
+#[sqlx::test]
+async fn variants_for_every_filter_paint_and_threshold(pool: PgPool) {
+    let memory = load_fixtures(&pool).await;
+    let server = Server::start(&pool).await;
+    for (protein, condition, positions) in
+        [("ALPHA", "c1", 12), ("ALPHA", "c2", 6), ("BETA", "c1", 3)]
+    {
+        for position_filter in FILTERS {
+            for paint in PAINTS {
+                for threshold in [None, Some(threshold_for(paint))] {
+                    let params = params(protein, condition, position_filter, paint, threshold);
+                    let path = format!("/variants?{}&page=1", query(&params));
+                    let body = server.expect(&path, StatusCode::OK).await;
+                    let expected = memory.variants_page(&params, 1, positions).await.unwrap();
+                    assert_eq!(data_cells(&body), expected_cells(&expected), "GET {path}");
+                    assert_eq!(
+                        body.matches(r#"<th scope="row""#).count(),
+                        positions as usize,
+                        "GET {path} should list every position"
+                    );
+                    assert_eq!(
+                        body.matches("<td").count(),
+                        positions as usize * 21,
+                        "GET {path}"
+                    );
+                    assert!(body.contains("colorVariants("), "GET {path}");
+                }
+            }
+        }
+    }
+}
+
+#[sqlx::test]
+async fn ranked_filters_keep_one_variant_per_position(pool: PgPool) {
+    load_fixtures(&pool).await;
+    let server = Server::start(&pool).await;
+    for position_filter in &FILTERS[1..] {
+        let params = params("ALPHA", "c1", *position_filter, Paint::PValue, None);
+        let body = server
+            .expect(&format!("/variants?{}", query(&params)), StatusCode::OK)
+            .await;
+        let positions: Vec<String> = data_cells(&body)
+            .into_iter()
+            .map(|(id, _)| id.trim_end_matches(char::is_alphabetic).to_string())
+            .collect();
+        assert_eq!(positions.len(), 12, "{position_filter}");
+        assert_eq!(
+            positions.iter().collect::<BTreeSet<_>>().len(),
+            12,
+            "{position_filter}"
+        );
+    }
+}
+
+#[sqlx::test]
+async fn variants_rejects_bad_parameters(pool: PgPool) {
+    load_fixtures(&pool).await;
+    let server = Server::start(&pool).await;
+    let base = "protein=ALPHA&position_filter=NoOrder&paint=p_value";
+    let unknown = server
+        .expect(
+            &format!("/variants?{base}&condition=c3"),
+            StatusCode::NOT_FOUND,
+        )
+        .await;
+    assert!(unknown.contains("did you mean c1, c2?"), "{unknown}");
+    for bad in ["page=0", "page=2", "threshold=NaN"] {
+        let body = server
+            .expect(
+                &format!("/variants?{base}&condition=c1&{bad}"),
+                StatusCode::BAD_REQUEST,
+            )
+            .await;
+        assert!(body.contains(r#"class="error-message""#), "{bad}: {body}");
+    }
+    server
+        .expect(
+            "/variants?protein=ALPHA&condition=c1&position_filter=Best&paint=p_value",
+            StatusCode::BAD_REQUEST,
+        )
+        .await;
+}
+
+#[sqlx::test]
+async fn plots(pool: PgPool) {
+    load_fixtures(&pool).await;
+    let server = Server::start(&pool).await;
+    let table = "protein=ALPHA&condition=c1&position_filter=NoOrder&paint=log2_fold_change";
+
+    let scatter = server
+        .expect(&format!("/plot?{table}&plot=Scatter"), StatusCode::OK)
+        .await;
+    assert!(scatter.contains("log2 Fold Change"), "{scatter}");
+    let heatmap = server
+        .expect(&format!("/plot?{table}&plot=Heatmap"), StatusCode::OK)
+        .await;
+    assert!(heatmap.contains(r#"id="canvas-heatmap""#));
+    let skeleton = server
+        .expect(&format!("/plot?{table}&plot=Table"), StatusCode::OK)
+        .await;
+    assert!(skeleton.contains(r#"id="dms-table-body""#));
+    let distribution = server
+        .expect(&format!("/plot?{table}&plot=Distribution"), StatusCode::OK)
+        .await;
+    assert!(!distribution.is_empty());
+    let substitutions = server
+        .expect(&format!("/plot?{table}&plot=Substitution"), StatusCode::OK)
+        .await;
+    assert!(!substitutions.is_empty());
+
+    // BETA has no sequence to take wild-type residues from
+    let beta = "protein=BETA&condition=c1&position_filter=NoOrder&paint=log2_fold_change";
+    server
+        .expect(
+            &format!("/plot?{beta}&plot=Substitution"),
+            StatusCode::UNPROCESSABLE_ENTITY,
+        )
+        .await;
+    server
+        .expect(&format!("/plot?{table}"), StatusCode::BAD_REQUEST)
+        .await;
+}
+
+#[sqlx::test]
+async fn index_and_variant_form(pool: PgPool) {
+    load_fixtures(&pool).await;
+    let server = Server::start(&pool).await;
+    let index = server.expect("/", StatusCode::OK).await;
+    assert!(index.contains(r#"id="page-title""#), "{index}");
+    assert!(index.contains(r#"hx-get="/variant_form?plot=heatmap""#));
+    assert!(index.contains(r#"hx-get="/proteins""#), "{index}");
+    // Uploading needs a login, so anonymous visitors get no upload form
+    assert!(!index.contains(r#"id="upload-form""#), "{index}");
+
+    let table = "protein=ALPHA&condition=c1&position_filter=NoOrder&paint=log2_fold_change";
+    for plot in ["heatmap", "table", "scatter"] {
+        let form = server
+            .expect(
+                &format!("/variant_form?{table}&plot={plot}"),
+                StatusCode::OK,
+            )
+            .await;
+        assert!(form.contains(r#"id="variant-form""#), "{plot}: {form}");
+        assert!(
+            form.contains(&format!(r#"hx-get="/plot?plot={plot}""#)),
+            "{plot}: {form}"
+        );
+        assert!(form.contains(r#"name="paint""#), "{plot}: {form}");
+    }
+}
+
+#[sqlx::test]
+async fn heatmap_matrix_and_svg(pool: PgPool) {
+    let memory = load_fixtures(&pool).await;
+    let server = Server::start(&pool).await;
+    for paint in PAINTS {
+        for threshold in [None, Some(threshold_for(paint))] {
+            let params = params("ALPHA", "c1", PositionFilter::NoOrder, paint, threshold);
+            let path = format!("/api/matrix?{}", query(&params));
+            let body = server.expect(&path, StatusCode::OK).await;
+            let matrix: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(matrix["start"], 1, "GET {path}");
+            assert_eq!(matrix["end"], 12, "GET {path}");
+
+            let variants = memory.variants_page(&params, 1, 12).await.unwrap();
+            let expected = VariantMatrix::build(&variants, 1, 12);
+            let ids: Vec<Option<i32>> = serde_json::from_value(matrix["ids"].clone()).unwrap();
+            let expected_ids: Vec<Option<i32>> = expected
+                .map(|variant| variant.id)
+                .into_iter()
+                .map(Option::flatten)
+                .collect();
+            assert_eq!(ids, expected_ids, "GET {path}");
+            // Rounded to three decimals like the tooltip
+            let values: Vec<Option<f64>> =
+                serde_json::from_value(matrix["values"].clone()).unwrap();
+            let expected_values =
+                expected.map(|variant| (paint.value_of(variant) * 1000.0).round() / 1000.0);
+            assert_eq!(values, expected_values, "GET {path}");
+            assert_eq!(
+                matrix["colors"].as_array().unwrap().len(),
+                ids.len(),
+                "GET {path}"
+            );
+        }
+    }
+
+    let params = params("ALPHA", "c1", PositionFilter::NoOrder, Paint::PValue, None);
+    let response = server
+        .client
+        .get(server.url(&format!("/api/matrix.svg?{}", query(&params))))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["Content-Type"], "image/svg+xml");
+    assert!(response.headers()["Content-Disposition"]
+        .to_str()
+        .unwrap()
+        .contains("ALPHA_c1_heatmap.svg"));
+    let svg = response.text().await.unwrap();
+    assert!(svg.starts_with("<svg"), "{svg}");
+    let variants = memory.variants_page(&params, 1, 12).await.unwrap();
+    assert_eq!(svg.matches("<title>").count(), variants.len());
+    assert_eq!(svg.matches("<rect").count(), 12 * 21);
+
+    server
+        .expect(
+            "/api/matrix?protein=ALPA&condition=c1&position_filter=NoOrder&paint=p_value",
+            StatusCode::NOT_FOUND,
+        )
+        .await;
+}
+
+#[sqlx::test]
+async fn classification_summary(pool: PgPool) {
+    let memory = load_fixtures(&pool).await;
+    let server = Server::start(&pool).await;
+    let params = params(
+        "ALPHA",
+        "c1",
+        PositionFilter::NoOrder,
+        Paint::Classification,
+        None,
+    );
+    let body = server
+        .expect(
+            &format!("/classification?{}", query(&params)),
+            StatusCode::OK,
+        )
+        .await;
+    assert!(body.contains(r#"id="classification-summary-table""#));
+    let row = &body[body.find("All positions").unwrap()..];
+    let cells: Vec<usize> = row
+        .split("<td>")
+        .skip(1)
+        .take(4)
+        .map(|cell| cell[..cell.find('<').unwrap()].parse().unwrap())
+        .collect();
+
+    let variants = memory.all_variants("ALPHA", "c1").await.unwrap();
+    let classes = classify::classify(
+        &variants,
+        params.classification_rule(),
+        Some(ALPHA_SEQUENCE),
+    )
+    .unwrap();
+    let mut expected = ClassCounts::default();
+    for class in classes {
+        expected.add(class);
+    }
+    assert_eq!(
+        cells,
+        [
+            expected.deleterious,
+            expected.neutral,
+            expected.gain_of_function,
+            variants.len(),
+        ],
+        "{body}"
+    );
+
+    // The mixture needs a sequence, which BETA does not have
+    server
+        .expect(
+            "/classification?protein=BETA&condition=c1&position_filter=NoOrder\
+             &paint=classification&classifier=Mixture",
+            StatusCode::UNPROCESSABLE_ENTITY,
+        )
+        .await;
+}
+
+#[sqlx::test]
+async fn tolerance_scores_and_structure_colors(pool: PgPool) {
+    let memory = load_fixtures(&pool).await;
+    let server = Server::start(&pool).await;
+    let variants = memory.all_variants("ALPHA", "c1").await.unwrap();
+    let expected = tolerance::position_tolerance(
+        &variants,
+        Some(ALPHA_SEQUENCE),
+        classify::DEFAULT_EFFECT_CUTOFF,
+    );
+
+    let body = server
+        .expect("/tolerance?protein=ALPHA&condition=c1", StatusCode::OK)
+        .await;
+    let scores: serde_json::Value = serde_json::from_str(&body).unwrap();
+    let scores = scores.as_array().unwrap();
+    assert_eq!(scores.len(), expected.len());
+    for (score, expected) in scores.iter().zip(&expected) {
+        assert_eq!(score["pos"], expected.pos);
+        assert_eq!(score["missense"], expected.missense);
+        for (field, value) in [
+            ("mean_effect", expected.mean_effect),
+            ("fraction_tolerated", expected.fraction_tolerated),
+            ("entropy", expected.entropy),
+        ] {
+            assert!(
+                (score[field].as_f64().unwrap() - value).abs() < 1e-9,
+                "{field} at {}: {score}",
+                expected.pos
+            );
+        }
+    }
+
+    let response = server
+        .client
+        .get(server.url("/tolerance?protein=ALPHA&condition=c1&format=Tsv"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/tab-separated-values"
+    );
+    let tsv = response.text().await.unwrap();
+    let mut lines = tsv.lines();
+    assert_eq!(
+        lines.next(),
+        Some("pos\tmissense\tmean_effect\tfraction_tolerated\tentropy")
+    );
+    assert_eq!(lines.count(), expected.len());
+
+    let colors = server
+        .expect(
+            "/tolerance/structure?protein=ALPHA&condition=c1&tolerance_metric=Entropy",
+            StatusCode::OK,
+        )
+        .await;
+    assert!(colors.starts_with("<script>colorVariants("), "{colors}");
+    for score in &expected {
+        assert!(
+            colors.contains(&format!(r#""pos":{}}}"#, score.pos)),
+            "{colors}"
+        );
+    }
+    server
+        .expect(
+            "/tolerance?protein=ALPHA&condition=c3",
+            StatusCode::NOT_FOUND,
+        )
+        .await;
+    server
+        .expect(
+            "/tolerance/structure?protein=ALPHA&condition=c1&effect_cutoff=NaN",
+            StatusCode::BAD_REQUEST,
+        )
+        .await;
+}
+
+#[sqlx::test]
+async fn threshold_controls(pool: PgPool) {
+    let memory = load_fixtures(&pool).await;
+    let server = Server::start(&pool).await;
+    for paint in [Paint::Log2FoldChange, Paint::PValue, Paint::ZStatistic] {
+        let params = params("ALPHA", "c1", PositionFilter::NoOrder, paint, None);
+        let body = server
+            .expect(&format!("/threshold?{}", query(&params)), StatusCode::OK)
+            .await;
+        let range = memory
+            .range_for("ALPHA", "c1", paint)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(body.contains(r#"type="range""#), "{paint}: {body}");
+        assert!(
+            body.contains(&format!(r#"min="{:.3}""#, range.min)),
+            "{paint}: {body}"
+        );
+        assert!(
+            body.contains(&format!(r#"max="{:.3}""#, range.max)),
+            "{paint}: {body}"
+        );
+    }
+    for position_filter in &FILTERS[1..] {
+        let params = params("ALPHA", "c1", *position_filter, Paint::PValue, None);
+        let body = server
+            .expect(&format!("/threshold?{}", query(&params)), StatusCode::OK)
+            .await;
+        assert_eq!(body, "", "{position_filter}");
+    }
+    let params = params(
+        "ALPHA",
+        "c1",
+        PositionFilter::NoOrder,
+        Paint::Classification,
+        None,
+    );
+    let classifier = server
+        .expect(&format!("/threshold?{}", query(&params)), StatusCode::OK)
+        .await;
+    assert!(classifier.contains(r#"id="classifier-select""#));
+}
+
+#[sqlx::test]
+async fn variant_by_id(pool: PgPool) {
+    load_fixtures(&pool).await;
+    let server = Server::start(&pool).await;
+    let variant: Variant = sqlx::query_as(
+        r#"
+        SELECT variant.*, protein.name AS protein FROM variant
+        JOIN protein ON variant.protein_id = protein.id
+        WHERE protein.name = 'ALPHA' AND condition = 'c2' AND pos = 4 AND aa = 'F'
+        "#,
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    let id = variant.id.unwrap();
+    let body = server
+        .expect(&format!("/variant/{id}"), StatusCode::OK)
+        .await;
+    for field in [
+        "ALPHA".to_string(),
+        "c2".to_string(),
+        "4".to_string(),
+        "F".to_string(),
+        format!("{:.3}", variant.log2_fold_change),
+        format!("{:.5}", variant.p_value),
+    ] {
+        assert!(
+            body.contains(&format!("<div>{field}</div>")),
+            "{field}: {body}"
+        );
+    }
+    assert!(body.contains("focusVariant(4)"));
+    let missing = server
+        .expect(&format!("/variant/{}", id + 10_000), StatusCode::NOT_FOUND)
+        .await;
+    assert!(missing.contains(r#"class="error-message""#));
+    server
+        .expect("/variant/first", StatusCode::BAD_REQUEST)
+        .await;
+}
+
+async fn create_user(pool: &PgPool, username: &str, password: &str, is_admin: bool) -> i32 {
+    let hash = auth::hash_password(password).unwrap();
+    PostgresRepository::new(pool.clone())
+        .create_user(username, &hash, is_admin)
+        .await
+        .unwrap()
+        .unwrap()
+}
+
+/// Multipart body uploading `file` for `protein`, with its content type.
+fn upload_body(protein: &str, file: &str) -> (String, Vec<u8>) {
+    let boundary = "dms-viewer-test";
+    let mut body = format!(
+        "--{boundary}\r\nContent-Disposition: form-data; name=\"protein\"\r\n\r\n{protein}\r\n\
+         --{boundary}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{file}\"\r\n\
+         Content-Type: text/tab-separated-values\r\n\r\n"
+    )
+    .into_bytes();
+    body.extend_from_slice(&std::fs::read(fixture(file)).unwrap());
+    body.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+    (format!("multipart/form-data; boundary={boundary}"), body)
+}
+
+async fn beta_rows(pool: &PgPool) -> i64 {
+    sqlx::query_scalar(
+        "SELECT count(*) FROM variant JOIN protein ON variant.protein_id = protein.id \
+         WHERE protein.name = 'BETA'",
+    )
+    .fetch_one(pool)
+    .await
+    .unwrap()
+}
+
+#[sqlx::test]
+async fn uploads_need_a_login(pool: PgPool) {
+    load_fixtures(&pool).await;
+    let user = create_user(&pool, "ada", "analytical engine", false).await;
+    let token = auth::new_api_token();
+    let repository = PostgresRepository::new(pool.clone());
+    repository
+        .create_api_token(user, "import script", &auth::secret_hash(&token))
+        .await
+        .unwrap();
+    let lab = repository.create_project("lab").await.unwrap().unwrap();
+    repository
+        .set_protein_access("BETA", Some(lab), true)
+        .await
+        .unwrap();
+    let server = Server::start(&pool).await;
+    let upload = |authorization: Option<String>| {
+        let (content_type, body) = upload_body("BETA", BETA);
+        let mut request = server
+            .client
+            .post(server.url("/upload"))
+            .header("Content-Type", content_type)
+            .body(body);
+        if let Some(authorization) = authorization {
+            request = request.header("Authorization", authorization);
+        }
+        request.send()
+    };
+
+    let response = upload(None).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = upload(Some("Bearer dms_not_a_token".to_string()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(beta_rows(&pool).await, 9);
+
+    // Signing in is not enough: uploading takes the uploader role
+    let response = upload(Some(format!("Bearer {token}"))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    repository
+        .set_member(lab, user, Some(Role::Viewer))
+        .await
+        .unwrap();
+    let response = upload(Some(format!("Bearer {token}"))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert_eq!(beta_rows(&pool).await, 9);
+    repository
+        .set_member(lab, user, Some(Role::Uploader))
+        .await
+        .unwrap();
+    let response = upload(Some(format!("Bearer {token}"))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.text().await.unwrap();
+    assert!(body.contains("9 rows affected"), "{body}");
+    assert_eq!(beta_rows(&pool).await, 18);
+}
+
+#[sqlx::test]
+async fn uploads_are_audited(pool: PgPool) {
+    load_fixtures(&pool).await;
+    let user = create_user(&pool, "ada", "analytical engine", false).await;
+    let token = auth::new_api_token();
+    let repository = PostgresRepository::new(pool.clone());
+    repository
+        .create_api_token(user, "import script", &auth::secret_hash(&token))
+        .await
+        .unwrap();
+    let lab = repository.create_project("lab").await.unwrap().unwrap();
+    repository
+        .set_protein_access("BETA", Some(lab), true)
+        .await
+        .unwrap();
+    repository
+        .set_member(lab, user, Some(Role::Uploader))
+        .await
+        .unwrap();
+    let server = Server::start(&pool).await;
+    let bearer = format!("Bearer {token}");
+    let (content_type, body) = upload_body("BETA", BETA);
+    let response = server
+        .client
+        .post(server.url("/upload"))
+        .header("Content-Type", content_type)
+        .header("Authorization", bearer.as_str())
+        .body(body)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let (status, _) = server.get("/api/audit").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, body) = server
+        .get_as(
+            "/api/audit?action=ingest",
+            &[("Authorization", bearer.as_str())],
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let entries: serde_json::Value = serde_json::from_str(&body).unwrap();
+    let entries = entries.as_array().unwrap();
+    // Newest first: the upload, then the two fixtures loaded from the CLI
+    assert_eq!(entries.len(), 3, "{body}");
+    let upload = &entries[0];
+    let (_, _, source) = read_source(BETA, "BETA");
+    assert_eq!(upload["actor"], "ada");
+    assert_eq!(upload["protein"], "BETA");
+    assert_eq!(upload["details"]["file_name"], BETA);
+    assert_eq!(upload["details"]["sha256"], source.sha256.as_str());
+    assert_eq!(upload["details"]["rows_inserted"], 9);
+    assert_eq!(entries[1]["actor"], "cli:fixtures");
+
+    // Every new variant links back to the batch and its entry
+    let batch = upload["batch_id"].as_i64().unwrap();
+    let variant: i32 = sqlx::query_scalar("SELECT id FROM variant WHERE batch_id = $1 LIMIT 1")
+        .bind(batch as i32)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    let body = server
+        .expect(&format!("/variant/{variant}"), StatusCode::OK)
+        .await;
+    assert!(body.contains(&format!("/audit?batch={batch}")), "{body}");
+    let (status, body) = server
+        .get_as(
+            &format!("/audit?batch={batch}"),
+            &[("Authorization", bearer.as_str())],
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains(BETA), "{body}");
+    let (_, body) = server
+        .get_as(
+            "/api/audit?action=delete",
+            &[("Authorization", bearer.as_str())],
+        )
+        .await;
+    assert_eq!(body, "[]");
+}
+
+#[sqlx::test]
+async fn login_sessions_and_tokens(pool: PgPool) {
+    create_user(&pool, "ada", "analytical engine", false).await;
+    let server = Server::start(&pool).await;
+
+    let response = server
+        .post_form("/login", "username=ada&password=difference+engine", &[])
+        .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(response.headers().get("Set-Cookie").is_none());
+    let response = server
+        .post_form("/login", "username=nobody&password=analytical+engine", &[])
+        .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let (status, _) = server.get("/account").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let cookie = server.login("ada", "analytical+engine").await;
+    let session = [("Cookie", cookie.as_str())];
+    let (status, body) = server.get_as("/account", &session).await;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    assert!(body.contains("Signed in as <strong>ada</strong>"), "{body}");
+    let (_, body) = server.get_as("/", &session).await;
+    assert!(body.contains(r#"id="upload-form""#), "{body}");
+
+    // A new token is shown once and then works as a bearer token
+    let response = server
+        .post_form("/account/tokens", "name=import+script", &session)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.text().await.unwrap();
+    let token = body
+        .split(auth::TOKEN_PREFIX)
+        .nth(1)
+        .map(|rest| format!("{}{}", auth::TOKEN_PREFIX, &rest[..64]))
+        .expect(&body);
+    let bearer = format!("Bearer {token}");
+    let (status, body) = server
+        .get_as("/account", &[("Authorization", bearer.as_str())])
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("import script"), "{body}");
+    assert!(!body.contains(&token), "{body}");
+    let response = server
+        .post_form("/account/tokens", "name=import+script", &session)
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // Logging out ends the session but not the token
+    let response = server.post_form("/logout", "", &session).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert!(response.headers()["Set-Cookie"]
+        .to_str()
+        .unwrap()
+        .contains("Max-Age=0"));
+    let (status, _) = server.get_as("/account", &session).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = server
+        .get_as("/account", &[("Authorization", bearer.as_str())])
+        .await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[sqlx::test]
+async fn admin_page_needs_an_administrator(pool: PgPool) {
+    create_user(&pool, "root", "correct horse", true).await;
+    let ada = create_user(&pool, "ada", "analytical engine", false).await;
+    let server = Server::start(&pool).await;
+    let ada_cookie = server.login("ada", "analytical+engine").await;
+    let ada_session = [("Cookie", ada_cookie.as_str())];
+    let (status, _) = server.get_as("/admin/users", &ada_session).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let response = server
+        .post_form(
+            "/admin/users",
+            "username=eve&password=12345678",
+            &ada_session,
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let root_cookie = server.login("root", "correct+horse").await;
+    let root = [("Cookie", root_cookie.as_str())];
+    let (status, body) = server.get_as("/admin/users", &root).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("<td>ada</td>"), "{body}");
+
+    let response = server
+        .post_form("/admin/users", "username=grace&password=short", &root)
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = server
+        .post_form(
+            "/admin/users",
+            "username=grace&password=compiler+one&is_admin=true",
+            &root,
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.text().await.unwrap();
+    assert!(body.contains("<td>grace</td>"), "{body}");
+    server.login("grace", "compiler+one").await;
+
+    // Disabling ada ends her session and blocks new logins
+    let response = server
+        .post_form(&format!("/admin/users/{ada}"), "disabled=true", &root)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let (status, _) = server.get_as("/account", &ada_session).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let response = server
+        .post_form("/login", "username=ada&password=analytical+engine", &[])
+        .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    // Administrators cannot lock themselves out
+    let users = PostgresRepository::new(pool.clone()).users().await.unwrap();
+    let root_id = users
+        .iter()
+        .find(|user| user.username == "root")
+        .unwrap()
+        .id;
+    let response = server
+        .post_form(&format!("/admin/users/{root_id}"), "is_admin=false", &root)
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = server
+        .post_form(&format!("/admin/users/{root_id}/delete"), "", &root)
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = server
+        .post_form(&format!("/admin/users/{ada}/delete"), "", &root)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.text().await.unwrap();
+    assert!(!body.contains("<td>ada</td>"), "{body}");
+}
+
+#[sqlx::test]
+async fn require_login_hides_the_data(pool: PgPool) {
+    load_fixtures(&pool).await;
+    create_user(&pool, "ada", "analytical engine", false).await;
+    let server = Server::start_with(&pool, &[("REQUIRE_LOGIN", "true")]).await;
+    let (status, _) = server.get("/proteins").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = server.get_as("/proteins", &[("HX-Request", "true")]).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let response = server
+        .client
+        .get(server.url("/variants?protein=ALPHA&condition=c1"))
+        .header("Accept", "text/html")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.url().path(), "/login");
+    assert_eq!(
+        response.url().query(),
+        Some("next=/variants%3Fprotein%3DALPHA%26condition%3Dc1")
+    );
+    server.expect("/healthz", StatusCode::OK).await;
+
+    let cookie = server.login("ada", "analytical+engine").await;
+    let (status, body) = server.get_as("/proteins", &[("Cookie", &cookie)]).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("ALPHA"), "{body}");
+}
+
+#[sqlx::test]
+async fn projects_hide_unpublished_proteins(pool: PgPool) {
+    load_fixtures(&pool).await;
+    let repository = PostgresRepository::new(pool.clone());
+    let lab = repository.create_project("lab").await.unwrap().unwrap();
+    repository
+        .set_protein_access("ALPHA", Some(lab), false)
+        .await
+        .unwrap();
+    create_user(&pool, "root", "correct horse", true).await;
+    let ada = create_user(&pool, "ada", "analytical engine", false).await;
+    let grace = create_user(&pool, "grace", "compiler one", false).await;
+    repository
+        .set_member(lab, grace, Some(Role::Admin))
+        .await
+        .unwrap();
+    let alpha_variant: i32 = sqlx::query_scalar(
+        "SELECT variant.id FROM variant JOIN protein ON variant.protein_id = protein.id          WHERE protein.name = 'ALPHA' LIMIT 1",
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    let server = Server::start(&pool).await;
+    let grace_cookie = server.login("grace", "compiler+one").await;
+    let grace_session = [("Cookie", grace_cookie.as_str())];
+    let ada_cookie = server.login("ada", "analytical+engine").await;
+    let ada_session = [("Cookie", ada_cookie.as_str())];
+    let root_cookie = server.login("root", "correct+horse").await;
+    let root = [("Cookie", root_cookie.as_str())];
+    let variants =
+        "/variants?protein=ALPHA&condition=c1&position_filter=NoOrder&paint=log2_fold_change";
+
+    // Members see the protein; a cached answer for them is not served to others
+    let (_, body) = server.get_as("/proteins", &grace_session).await;
+    assert!(body.contains("ALPHA"), "{body}");
+    let (status, _) = server.get_as(variants, &grace_session).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = server.get_as(variants, &root).await;
+    assert_eq!(status, StatusCode::OK);
+    for session in [&[][..], &ada_session[..]] {
+        let (_, body) = server.get_as("/proteins", session).await;
+        assert!(!body.contains("ALPHA") && body.contains("BETA"), "{body}");
+        let (status, body) = server.get_as(variants, session).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(!body.contains("ALPHA?"), "{body}");
+        let (status, _) = server
+            .get_as("/api/substitutions?protein=ALPHA&condition=c1", session)
+            .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = server
+            .get_as(&format!("/variant/{alpha_variant}"), session)
+            .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+    let public = server
+        .client
+        .get(server.url("/proteins"))
+        .send()
+        .await
+        .unwrap();
+    let member = server
+        .client
+        .get(server.url("/proteins"))
+        .header("Cookie", &grace_cookie)
+        .send()
+        .await
+        .unwrap();
+    assert_ne!(public.headers()["ETag"], member.headers()["ETag"]);
+
+    // Viewers see it but cannot upload or manage the project
+    let response = server
+        .post_form(
+            &format!("/projects/{lab}/members"),
+            "username=ada&role=viewer",
+            &ada_session,
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = server
+        .post_form(
+            &format!("/projects/{lab}/members"),
+            "username=ada&role=viewer",
+            &grace_session,
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let (status, _) = server.get_as(variants, &ada_session).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, body) = server.get_as("/projects", &ada_session).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(
+        body.contains("<h3>lab</h3>") && !body.contains("Publish"),
+        "{body}"
+    );
+    let response = server
+        .post_form(
+            &format!("/projects/{lab}/proteins"),
+            "protein=ALPHA&public=true",
+            &ada_session,
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = server
+        .post_form(
+            &format!("/projects/{ada}/members/{grace}/delete"),
+            "",
+            &grace_session,
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // Project admins publish to everyone, administrators move proteins
+    let response = server
+        .post_form(
+            &format!("/projects/{lab}/proteins"),
+            "protein=BETA&public=false",
+            &grace_session,
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = server
+        .post_form(
+            &format!("/projects/{lab}/proteins"),
+            "protein=ALPHA&public=true",
+            &grace_session,
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    server.expect(variants, StatusCode::OK).await;
+    let response = server
+        .post_form(&format!("/projects/{lab}/proteins"), "protein=BETA", &root)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let (_, body) = server.get("/proteins").await;
+    assert!(body.contains("ALPHA") && !body.contains("BETA"), "{body}");
+}
+
+#[sqlx::test]
+async fn datasets_archive_delete_and_restore(pool: PgPool) {
+    load_fixtures(&pool).await;
+    let repository = PostgresRepository::new(pool.clone());
+    let mut bearers = vec![];
+    for (name, is_admin) in [("root", true), ("ada", false)] {
+        let user = create_user(&pool, name, "analytical engine", is_admin).await;
+        let token = auth::new_api_token();
+        repository
+            .create_api_token(user, "cleanup script", &auth::secret_hash(&token))
+            .await
+            .unwrap();
+        bearers.push(format!("Bearer {token}"));
+    }
+    let server = Server::start(&pool).await;
+    let root = bearers[0].as_str();
+    let c2 = serde_json::json!({"operation": "archive", "protein": "ALPHA", "condition": "c2"});
+
+    let (status, _) = server.post_json("/api/datasets", &c2, None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    // ALPHA is public, but only administrators manage its data
+    let (status, _) = server
+        .post_json("/api/datasets", &c2, Some(bearers[1].as_str()))
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    // Without confirm it is a dry run
+    let (status, result) = server.post_json("/api/datasets", &c2, Some(root)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(result["dry_run"], true);
+    let count = result["affected"].as_i64().unwrap();
+    assert!(count > 0, "{result}");
+    assert_eq!(result["counts"]["archived"], 0);
+    let conditions = server
+        .expect("/conditions?protein=ALPHA", StatusCode::OK)
+        .await;
+    assert!(conditions.contains("c2"));
+
+    let mut confirmed = c2.clone();
+    confirmed["confirm"] = true.into();
+    let (_, result) = server
+        .post_json("/api/datasets", &confirmed, Some(root))
+        .await;
+    assert_eq!(result["dry_run"], false);
+    assert_eq!(result["affected"].as_i64(), Some(count));
+    let conditions = server
+        .expect("/conditions?protein=ALPHA", StatusCode::OK)
+        .await;
+    assert!(conditions.contains("c1") && !conditions.contains("c2"));
+
+    // The page lists the archived condition with a restore button
+    let (status, page) = server
+        .get_as("/datasets?protein=ALPHA", &[("Authorization", root)])
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(page.contains("c2") && page.contains("Restore"), "{page}");
+    let (status, confirm) = server
+        .get_as(
+            "/datasets/confirm?operation=restore&protein=ALPHA&condition=c2",
+            &[("Authorization", root)],
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(confirm.contains(&count.to_string()), "{confirm}");
+
+    let restore = serde_json::json!({
+        "operation": "restore", "protein": "ALPHA", "condition": "c2", "confirm": true,
+    });
+    let (_, result) = server
+        .post_json("/api/datasets", &restore, Some(root))
+        .await;
+    assert_eq!(result["affected"].as_i64(), Some(count));
+    let conditions = server
+        .expect("/conditions?protein=ALPHA", StatusCode::OK)
+        .await;
+    assert!(conditions.contains("c2"));
+
+    // An archived protein is gone from every view until restored
+    let beta = serde_json::json!({"operation": "archive", "protein": "BETA", "confirm": true});
+    let (status, _) = server.post_json("/api/datasets", &beta, Some(root)).await;
+    assert_eq!(status, StatusCode::OK);
+    let proteins = server.expect("/proteins", StatusCode::OK).await;
+    assert!(proteins.contains("ALPHA") && !proteins.contains("BETA"));
+    server
+        .expect("/conditions?protein=BETA", StatusCode::NOT_FOUND)
+        .await;
+    assert_eq!(beta_rows(&pool).await, 9);
+
+    let delete = serde_json::json!({"operation": "delete", "protein": "BETA", "confirm": true});
+    let (_, result) = server.post_json("/api/datasets", &delete, Some(root)).await;
+    assert_eq!(result["affected"], 9);
+    assert_eq!(beta_rows(&pool).await, 0);
+
+    let (_, body) = server
+        .get_as("/api/audit?action=delete", &[("Authorization", root)])
+        .await;
+    let entries: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(entries.as_array().unwrap().len(), 1, "{body}");
+    assert_eq!(entries[0]["actor"], "root", "{body}");
+}
+
+#[sqlx::test]
+async fn health_and_metrics(pool: PgPool) {
+    load_fixtures(&pool).await;
+    let server = Server::start(&pool).await;
+    assert_eq!(server.expect("/healthz", StatusCode::OK).await, "ok");
+    let ready = server.expect("/readyz", StatusCode::OK).await;
+    assert!(ready.contains(r#""ready":true"#), "{ready}");
+    server.expect("/proteins", StatusCode::OK).await;
+    let metrics = server.expect("/metrics", StatusCode::OK).await;
+    assert!(metrics.contains(r#"query="list_proteins""#), "{metrics}");
+    assert!(
+        metrics.contains("dms_db_pool_max_connections 4"),
+        "{metrics}"
+    );
+}